@@ -1,14 +1,16 @@
-use std::net::ToSocketAddrs;
+use std::io::Write;
+use std::process;
+use std::time::Duration;
 
 use futures::stream::Stream;
 use log::error;
 use structopt::StructOpt;
 use tokio::prelude::*;
 
-use meilies::reqresp::Request;
+use meilies::reqresp::{Request, Response};
 use meilies::resp::{FromResp, RespValue};
-use meilies::stream::Stream as EsStream;
-use meilies_client::{paired_connect, sub_connect};
+use meilies::stream::{BinaryEncoding, EventData, EventNumber, Stream as EsStream, StreamName};
+use meilies_client::{paired_connect, sub_connect, ServerAddr};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "meilies-cli", about = "A basic cli for MeiliES.", author)]
@@ -21,23 +23,93 @@ struct Opt {
     #[structopt(short = "p", long = "port", default_value = "6480")]
     port: u16,
 
-    /// Command and arguments that will be sent to the server.
+    /// How event data that is not valid UTF-8 is printed: base64, hex or escape.
+    #[structopt(long = "binary", default_value = "escape")]
+    binary: BinaryEncoding,
+
+    /// Command and arguments that will be sent to the server,
+    /// `tail <stream> <n>` prints the last n events of a stream and exits,
+    /// `ping` prints the round-trip time and exits with 1 if the server does not answer.
     cmd_args: Vec<String>,
 }
 
+/// How long `ping` waits for the server, connecting included.
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Print a response, the data of events is printed as text using the binary encoding,
+/// followed by the `key=value` headers of the event.
+fn print_response(response: Response, binary: BinaryEncoding) {
+    match response {
+        Response::Event {
+            stream,
+            number,
+            event_name,
+            event_data,
+            headers,
+            redacted,
+            ..
+        } => {
+            let mut headers: Vec<_> = headers.into_iter().collect();
+            headers.sort();
+            let headers: String = headers
+                .into_iter()
+                .map(|(key, value)| format!(" {}={}", key, value))
+                .collect();
+
+            let data = if redacted {
+                String::from("<redacted>")
+            } else {
+                event_data.to_text(binary)
+            };
+
+            println!("{} {} {} {}{}", stream, number.0, event_name, data, headers)
+        }
+        response => println!("{:?}", response),
+    }
+}
+
+/// Print the last `count` events of a stream and exit.
+fn tail(
+    addr: ServerAddr,
+    stream: StreamName,
+    count: u64,
+    binary: BinaryEncoding,
+) -> impl Future<Item = (), Error = ()> {
+    paired_connect(addr)
+        .map_err(|e| error!("{}", e))
+        .and_then(|conn| conn.last_event_number(stream).map_err(|e| error!("{}", e)))
+        .and_then(move |(stream, last, conn)| {
+            let to = last.map_or(0, |n| n.0 + 1);
+            let from = to.saturating_sub(count);
+            conn.read_range(stream, EventNumber(from), EventNumber(to))
+                .map_err(|e| error!("{}", e))
+        })
+        .map(move |(events, _conn)| {
+            for (number, name, data) in events {
+                println!("{:>8}  {:<20}  {}", number.0, name, data.to_text(binary));
+            }
+        })
+}
+
 fn main() {
     let _ = stderrlog::new().verbosity(2).init();
 
     let opt = Opt::from_args();
-    let addr = (opt.hostname.as_str(), opt.port);
-    let addr = match addr
-        .to_socket_addrs()
-        .map(|addrs| addrs.filter(|a| a.is_ipv4()).next())
-    {
-        Ok(Some(addr)) => addr,
-        Ok(None) => return error!("impossible to dns resolve addr; {:?}", addr),
-        Err(e) => return error!("error parsing addr; {}", e),
-    };
+    let addr = ServerAddr::host(opt.hostname.clone(), opt.port);
+
+    if opt.cmd_args.first().map(String::as_str) == Some("tail") {
+        let stream = opt.cmd_args.get(1).map(|s| StreamName::new(s.to_owned()));
+        let count = opt.cmd_args.get(2).map(|s| s.parse::<u64>());
+
+        return match (stream, count, opt.cmd_args.len()) {
+            (Some(Ok(stream)), Some(Ok(count)), 3) => {
+                tokio::run(tail(addr, stream, count, opt.binary))
+            }
+            (Some(Err(e)), _, _) => error!("invalid stream name; {}", e),
+            (_, Some(Err(e)), _) => error!("invalid number of events; {}", e),
+            (_, _, _) => error!("usage: tail <stream> <n>"),
+        };
+    }
 
     let args = opt
         .cmd_args
@@ -50,16 +122,21 @@ fn main() {
         Err(e) => return error!("{}", e),
     };
 
+    let binary = opt.binary;
     let fut = match command {
-        Request::SubscribeAll { range } => {
+        Request::SubscribeAll { range, ordered } => {
             let fut = sub_connect(addr)
                 .map_err(|e| error!("{}", e))
                 .and_then(move |(mut ctrl, msgs)| {
-                    ctrl.subscribe_to(EsStream::all(range));
+                    if ordered {
+                        ctrl.subscribe_all_ordered(range);
+                    } else {
+                        ctrl.subscribe_to(EsStream::all(range));
+                    }
 
                     msgs.for_each(move |msg| {
                         match msg {
-                            Ok(response) => println!("{:?}", response),
+                            Ok(response) => print_response(response, binary),
                             Err(error) => eprintln!("Error: {}", error),
                         }
                         future::ok(())
@@ -73,17 +150,46 @@ fn main() {
 
             Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
         }
-        Request::Subscribe { streams } => {
+        Request::Subscribe {
+            streams,
+            require_existing,
+        } => {
             let fut = sub_connect(addr)
                 .map_err(|e| error!("{}", e))
-                .and_then(|(mut ctrl, msgs)| {
+                .and_then(move |(mut ctrl, msgs)| {
                     for stream in streams {
-                        ctrl.subscribe_to(stream);
+                        if require_existing {
+                            ctrl.subscribe_to_existing(stream);
+                        } else {
+                            ctrl.subscribe_to(stream);
+                        }
                     }
 
                     msgs.for_each(move |msg| {
                         match msg {
-                            Ok(response) => println!("{:?}", response),
+                            Ok(response) => print_response(response, binary),
+                            Err(error) => eprintln!("Error: {}", error),
+                        }
+                        future::ok(())
+                    })
+                    .map_err(|e| error!("{:?}", e))
+                })
+                .and_then(|_| {
+                    println!("Connection closed by the server");
+                    Err(())
+                });
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::SubscribePrefix { prefix, range } => {
+            let fut = sub_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(move |(mut ctrl, msgs)| {
+                    ctrl.subscribe_prefix(prefix, range);
+
+                    msgs.for_each(move |msg| {
+                        match msg {
+                            Ok(response) => print_response(response, binary),
                             Err(error) => eprintln!("Error: {}", error),
                         }
                         future::ok(())
@@ -101,6 +207,8 @@ fn main() {
             stream,
             event_name,
             event_data,
+            dedup_id: None,
+            ..
         } => {
             let fut = paired_connect(addr)
                 .map_err(|e| error!("{}", e))
@@ -112,6 +220,57 @@ fn main() {
 
             Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
         }
+        Request::Publish {
+            stream,
+            event_name,
+            event_data,
+            dedup_id: Some(dedup_id),
+            ..
+        } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(|conn| {
+                    conn.publish_with_dedup_id(stream, event_name, event_data, dedup_id)
+                        .map_err(|e| error!("{}", e))
+                })
+                .map(|(number, _conn)| println!("Event {} of the stream", number.0));
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::PublishMulti { events } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(|conn| conn.publish_multi(events).map_err(|e| error!("{}", e)))
+                .map(|(events, _conn)| {
+                    for (stream, number) in events {
+                        println!("Event {} of the stream {}", number.0, stream)
+                    }
+                });
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::PublishChunk {
+            stream,
+            event_name,
+            chunk,
+            last,
+        } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(move |conn| {
+                    conn.publish_chunk(stream, event_name, chunk, last)
+                        .map_err(|e| error!("{}", e))
+                })
+                .map(move |_conn| {
+                    if last {
+                        println!("Event sent to the stream")
+                    } else {
+                        println!("Chunk sent")
+                    }
+                });
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
         Request::LastEventNumber { stream } => {
             let fut = paired_connect(addr)
                 .map_err(|e| error!("{}", e))
@@ -120,6 +279,19 @@ fn main() {
 
             Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
         }
+        Request::LastEvent { stream } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(|conn| conn.last_event(stream).map_err(|e| error!("{}", e)))
+                .map(move |(event, _conn)| match event {
+                    Some((number, name, data)) => {
+                        println!("{} {} {}", number.0, name, data.to_text(binary))
+                    }
+                    None => println!("{:?}", event),
+                });
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
         Request::StreamNames => {
             let fut = paired_connect(addr)
                 .map_err(|e| error!("{}", e))
@@ -128,6 +300,245 @@ fn main() {
 
             Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
         }
+        Request::StreamSize { stream } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(|conn| conn.stream_size(stream).map_err(|e| error!("{}", e)))
+                .map(|(stream, bytes, _conn)| println!("{} - {} bytes", stream, bytes));
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::TotalSize => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(|conn| conn.total_size().map_err(|e| error!("{}", e)))
+                .map(|(bytes, _conn)| println!("{} bytes", bytes));
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::ListSubscriptions => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(|conn| conn.list_subscriptions().map_err(|e| error!("{}", e)))
+                .map(|(subscriptions, _conn)| {
+                    for (stream, count) in subscriptions {
+                        println!("{}: {}", stream, count);
+                    }
+                });
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::Ping => {
+            let fut = paired_connect(addr)
+                .map_err(|e| e.to_string())
+                .and_then(|conn| conn.ping().map_err(|e| e.to_string()))
+                .timeout(PING_TIMEOUT)
+                .then(|result| match result {
+                    Ok((rtt, _conn)) => {
+                        println!("PONG in {:.2?}", rtt);
+                        Ok(())
+                    }
+                    Err(e) => {
+                        match e.into_inner() {
+                            Some(e) => error!("{}", e),
+                            None => error!("no answer after {:?}", PING_TIMEOUT),
+                        }
+                        process::exit(1)
+                    }
+                });
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::Info => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(|conn| conn.info().map_err(|e| error!("{}", e)))
+                .map(|(info, _conn)| {
+                    println!("version: {}", info.version);
+                    println!("uptime: {:?}", info.uptime);
+                    println!("streams: {}", info.streams);
+                    println!("published events: {}", info.published_events);
+                    println!("features: {}", info.features.join(", "));
+                });
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::Export { stream } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(|conn| conn.export(stream).map_err(|e| error!("{}", e)))
+                .and_then(|(blob, _conn)| {
+                    let stdout = std::io::stdout();
+                    let mut stdout = stdout.lock();
+                    stdout.write_all(&blob).map_err(|e| error!("{}", e))
+                });
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::Verify { stream } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(|conn| {
+                    conn.request(Request::Verify { stream })
+                        .map_err(|e| error!("{}", e))
+                })
+                .map(|(response, _conn)| match response {
+                    Response::Verified {
+                        stream,
+                        events,
+                        corrupted,
+                        missing,
+                    } => {
+                        println!("{}: {} events checked", stream, events);
+                        for number in corrupted {
+                            println!("event {} is corrupted", number.0);
+                        }
+                        for (from, to) in missing {
+                            println!("events {} to {} are missing", from.0, to.0 - 1);
+                        }
+                    }
+                    response => println!("{:?}", response),
+                });
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::SaveSnapshot {
+            stream,
+            number,
+            data,
+        } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(move |conn| {
+                    conn.save_snapshot(stream, number, data)
+                        .map_err(|e| error!("{}", e))
+                })
+                .map(|_conn| println!("Snapshot saved"));
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::GetSnapshot { stream } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(|conn| conn.get_snapshot(stream).map_err(|e| error!("{}", e)))
+                .map(move |(snapshot, _conn)| match snapshot {
+                    Some((number, data)) => {
+                        println!("{} {}", number.0, EventData(data).to_text(binary))
+                    }
+                    None => println!("{:?}", snapshot),
+                });
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::CreateSnapshot { stream } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(|conn| conn.create_snapshot(stream).map_err(|e| error!("{}", e)))
+                .map(move |((number, data), _conn)| {
+                    println!("{} {}", number.0, EventData(data).to_text(binary))
+                });
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::Compact { stream } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(|conn| {
+                    conn.request(Request::Compact { stream })
+                        .map_err(|e| error!("{}", e))
+                })
+                .map(|(response, _conn)| match response {
+                    Response::Compacted {
+                        stream,
+                        events,
+                        bytes,
+                    } => println!(
+                        "{}: {} events deleted, {} bytes reclaimed",
+                        stream, events, bytes
+                    ),
+                    response => println!("{:?}", response),
+                });
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::Page {
+            stream,
+            from,
+            limit,
+        } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(move |conn| conn.page(stream, from, limit).map_err(|e| error!("{}", e)))
+                .map(move |((events, next), _conn)| {
+                    for event in events {
+                        print_response(event, binary);
+                    }
+                    match next {
+                        Some(next) => println!("next page at {}", next.0),
+                        None => println!("end of stream"),
+                    }
+                });
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::ConfigureStream { stream, settings } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(move |conn| {
+                    conn.configure_stream(stream, settings)
+                        .map_err(|e| error!("{}", e))
+                })
+                .map(|_conn| println!("Stream configured"));
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::WaitFor { stream, number } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(move |conn| {
+                    conn.wait_for(stream, number, None)
+                        .map_err(|e| error!("{}", e))
+                })
+                .map(move |_conn| println!("Stream reached event {}", number.0));
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::CreateGroup { stream, group } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(|conn| {
+                    conn.create_group(stream, group)
+                        .map_err(|e| error!("{}", e))
+                })
+                .map(|_conn| println!("Group created"));
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::RedactEvent { stream, number } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(move |conn| {
+                    conn.redact_event(stream, number)
+                        .map_err(|e| error!("{}", e))
+                })
+                .map(move |_conn| println!("Event {} redacted", number.0));
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
+        Request::Claim { .. } | Request::Ack { .. } => {
+            return error!("events can only be acknowledged on the connection that claimed them")
+        }
+        Request::Pause { .. } | Request::Resume { .. } => {
+            return error!("streams can only be paused on the connection that subscribed to them")
+        }
+        Request::Import { stream, blob } => {
+            let fut = paired_connect(addr)
+                .map_err(|e| error!("{}", e))
+                .and_then(|conn| conn.import(stream, blob).map_err(|e| error!("{}", e)))
+                .map(|_conn| println!("Events imported in the stream"));
+
+            Box::new(fut) as Box<dyn Future<Item = (), Error = ()> + Send>
+        }
     };
 
     tokio::run(fut);
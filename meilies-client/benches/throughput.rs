@@ -0,0 +1,104 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use futures::future::{self, Future, Loop};
+use tokio::runtime::Runtime;
+
+use meilies::stream::{EventData, EventName, EventNumber, StreamName};
+use meilies_client::{paired_connect_with_nodelay, PairedConnection};
+use meilies_server::Server;
+
+const EVENTS: u64 = 1000;
+
+/// Start a server using a temporary database and connect to it,
+/// `TCP_NODELAY` is set on both sides of the connection if `nodelay` is true.
+fn setup(nodelay: bool) -> (Runtime, PairedConnection) {
+    let server = Server::builder()
+        .listen("127.0.0.1:0".parse().unwrap())
+        .temporary(true)
+        .nodelay(nodelay)
+        .build()
+        .unwrap();
+
+    let addr = server.local_addrs().unwrap()[0];
+    let mut runtime = Runtime::new().unwrap();
+    runtime.spawn(server.run());
+
+    let conn = runtime
+        .block_on(paired_connect_with_nodelay(addr, nodelay))
+        .unwrap();
+    (runtime, conn)
+}
+
+fn publish_events(
+    conn: PairedConnection,
+    stream: StreamName,
+    count: u64,
+) -> impl Future<Item = PairedConnection, Error = String> {
+    let event_name = EventName::new("event".to_owned()).unwrap();
+
+    future::loop_fn((conn, 0), move |(conn, i)| {
+        if i == count {
+            return future::Either::A(future::ok(Loop::Break(conn)));
+        }
+
+        let event_data = EventData(i.to_be_bytes().to_vec());
+        let fut = conn
+            .publish(stream.clone(), event_name.clone(), event_data)
+            .map(move |conn| Loop::Continue((conn, i + 1)))
+            .map_err(|e| e.to_string());
+
+        future::Either::B(fut)
+    })
+}
+
+/// Every publish waits for the acknowledgement of the previous one,
+/// the `nagle` variant shows the latency added when `TCP_NODELAY` is not set.
+///
+/// On loopback with a single core, 1000 publishes take 27 to 29ms with and without
+/// `TCP_NODELAY`, the difference is within the noise: each publish is a single write
+/// sent when nothing is left unacknowledged, Nagle's algorithm has nothing to hold back.
+/// It delays the writes that follow an unacknowledged one, like pipelined requests.
+fn publish(c: &mut Criterion) {
+    let stream = StreamName::new("publish".to_owned()).unwrap();
+
+    let mut group = c.benchmark_group("publish");
+    group.throughput(Throughput::Elements(EVENTS));
+    for &(name, nodelay) in &[("sequential", true), ("sequential-nagle", false)] {
+        let (mut runtime, conn) = setup(nodelay);
+        let mut conn = Some(conn);
+
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let fut = publish_events(conn.take().unwrap(), stream.clone(), EVENTS);
+                conn = Some(runtime.block_on(fut).unwrap());
+            })
+        });
+    }
+    group.finish();
+}
+
+fn catch_up(c: &mut Criterion) {
+    let (mut runtime, conn) = setup(true);
+    let stream = StreamName::new("catch-up".to_owned()).unwrap();
+
+    let fut = publish_events(conn, stream.clone(), EVENTS);
+    let mut conn = Some(runtime.block_on(fut).unwrap());
+
+    let mut group = c.benchmark_group("subscribe");
+    group.throughput(Throughput::Elements(EVENTS));
+    group.bench_function("catch-up", |b| {
+        b.iter(|| {
+            let to = EventNumber(EVENTS);
+            let fut = conn
+                .take()
+                .unwrap()
+                .read_range(stream.clone(), EventNumber(0), to);
+            let (events, paired) = runtime.block_on(fut).unwrap();
+            assert_eq!(events.len() as u64, EVENTS);
+            conn = Some(paired);
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, publish, catch_up);
+criterion_main!(benches);
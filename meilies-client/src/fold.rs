@@ -0,0 +1,194 @@
+use std::net::SocketAddr;
+use std::{fmt, io};
+
+use futures::Future;
+use meilies::stream::{EventData, EventName, EventNumber, ReadRange};
+use meilies::stream::{Stream as EsStream, StreamName};
+
+use crate::paired::{paired_connect, PairedConnectionError};
+
+#[derive(Debug)]
+pub enum FoldError {
+    Connection(tokio_retry::Error<io::Error>),
+    Request(PairedConnectionError),
+}
+
+impl fmt::Display for FoldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FoldError::Connection(error) => write!(f, "connection error: {}", error),
+            FoldError::Request(error) => write!(f, "request error: {}", error),
+        }
+    }
+}
+
+/// Fold the events of a stream into a state, the events are read on a paired connection.
+///
+/// An unbounded range is folded up to the last event published when the fold starts,
+/// a stream read from its end has no event to fold and the initial state is returned.
+pub fn fold<S, F>(
+    addr: SocketAddr,
+    stream: EsStream,
+    init: S,
+    f: F,
+) -> impl Future<Item = S, Error = FoldError>
+where
+    F: FnMut(S, (EventNumber, EventName, EventData)) -> S,
+{
+    let (from, to) = match stream.range {
+        ReadRange::ReadFromUntil(from, to) => (from, to),
+        ReadRange::ReadFrom(from) => (from, u64::max_value()),
+        ReadRange::ReadFromEnd => (0, 0),
+    };
+
+    paired_connect(addr)
+        .map_err(FoldError::Connection)
+        .and_then(move |conn| {
+            conn.read_range(stream.name, EventNumber(from), EventNumber(to))
+                .map_err(FoldError::Request)
+        })
+        .map(move |(events, _conn)| events.into_iter().fold(init, f))
+}
+
+/// Fold the events of a stream starting from its latest snapshot,
+/// only the events published after the snapshot are read.
+///
+/// `decode_snapshot` builds the initial state from the data of the snapshot, it is given
+/// `None` if the stream has no snapshot, the events are then folded from the first one.
+/// Returns the state and the number of the last event it includes, if any.
+pub fn fold_from_snapshot<S, D, F>(
+    addr: SocketAddr,
+    stream: StreamName,
+    decode_snapshot: D,
+    f: F,
+) -> impl Future<Item = (S, Option<EventNumber>), Error = FoldError>
+where
+    D: FnOnce(Option<Vec<u8>>) -> S,
+    F: FnMut(S, (EventNumber, EventName, EventData)) -> S,
+{
+    paired_connect(addr)
+        .map_err(FoldError::Connection)
+        .and_then(move |conn| {
+            conn.get_snapshot(stream.clone())
+                .map_err(FoldError::Request)
+                .and_then(move |(snapshot, conn)| {
+                    let (number, data) = match snapshot {
+                        Some((number, data)) => (Some(number), Some(data)),
+                        None => (None, None),
+                    };
+
+                    let state = decode_snapshot(data);
+                    let from = number.map_or(EventNumber(0), EventNumber::next);
+                    let to = EventNumber(u64::max_value());
+
+                    conn.read_range(stream, from, to)
+                        .map_err(FoldError::Request)
+                        .map(move |(events, _conn)| {
+                            let last = events.last().map(|(number, _, _)| *number).or(number);
+                            let state = events.into_iter().fold(state, f);
+                            (state, last)
+                        })
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::{self, Loop};
+    use meilies_server::Server;
+    use tokio::runtime::Runtime;
+
+    fn sum(sum: u64, (_, _, data): (EventNumber, EventName, EventData)) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&data.0);
+        sum + u64::from_be_bytes(bytes)
+    }
+
+    /// Start a server and publish the numbers 1 to 10 in a stream, as the events 0 to 9.
+    fn setup(stream: StreamName) -> (Runtime, SocketAddr) {
+        let server = Server::builder()
+            .listen("127.0.0.1:0".parse().unwrap())
+            .temporary(true)
+            .build()
+            .unwrap();
+
+        let addr = server.local_addrs().unwrap()[0];
+        let mut runtime = Runtime::new().unwrap();
+        runtime.spawn(server.run());
+
+        let event_name = EventName::new("added".to_owned()).unwrap();
+
+        let publish = paired_connect(addr)
+            .map_err(|e| e.to_string())
+            .and_then(move |conn| {
+                future::loop_fn((conn, 1u64), move |(conn, i)| {
+                    let event_data = EventData(i.to_be_bytes().to_vec());
+                    conn.publish(stream.clone(), event_name.clone(), event_data)
+                        .map_err(|e| e.to_string())
+                        .map(move |conn| match i {
+                            10 => Loop::Break(()),
+                            _ => Loop::Continue((conn, i + 1)),
+                        })
+                })
+            });
+        runtime.block_on(publish).unwrap();
+
+        (runtime, addr)
+    }
+
+    #[test]
+    fn fold_integer_events_into_a_sum() {
+        let stream = StreamName::new("numbers".to_owned()).unwrap();
+        let (mut runtime, addr) = setup(stream.clone());
+
+        let all = EsStream::new(stream.clone(), ReadRange::ReadFrom(0));
+        assert_eq!(runtime.block_on(fold(addr, all, 0, sum)).unwrap(), 55);
+
+        // the events 2 to 4, the numbers start at zero
+        let bounded = EsStream::new(stream.clone(), ReadRange::ReadFromUntil(2, 5));
+        assert_eq!(
+            runtime.block_on(fold(addr, bounded, 0, sum)).unwrap(),
+            3 + 4 + 5
+        );
+
+        let from_end = EsStream::new(stream, ReadRange::ReadFromEnd);
+        assert_eq!(runtime.block_on(fold(addr, from_end, 0, sum)).unwrap(), 0);
+    }
+
+    #[test]
+    fn fold_from_the_latest_snapshot() {
+        let stream = StreamName::new("snapshotted".to_owned()).unwrap();
+        let (mut runtime, addr) = setup(stream.clone());
+
+        let decode = |data: Option<Vec<u8>>| match data {
+            Some(data) => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(&data);
+                u64::from_be_bytes(bytes)
+            }
+            None => 0,
+        };
+
+        let fut = fold_from_snapshot(addr, stream.clone(), decode, sum);
+        assert_eq!(runtime.block_on(fut).unwrap(), (55, Some(EventNumber(9))));
+
+        // the snapshot pretends that the events 0 to 4 sum up to 1000
+        let snapshot = 1000u64.to_be_bytes().to_vec();
+        let save = paired_connect(addr)
+            .map_err(|e| e.to_string())
+            .and_then(move |conn| {
+                conn.save_snapshot(stream.clone(), EventNumber(4), snapshot)
+                    .map_err(|e| e.to_string())
+                    .map(|_| stream)
+            });
+        let stream = runtime.block_on(save).unwrap();
+
+        let fut = fold_from_snapshot(addr, stream, decode, sum);
+        let expected = 1000 + 6 + 7 + 8 + 9 + 10;
+        assert_eq!(
+            runtime.block_on(fut).unwrap(),
+            (expected, Some(EventNumber(9)))
+        );
+    }
+}
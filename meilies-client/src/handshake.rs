@@ -0,0 +1,154 @@
+use std::net::ToSocketAddrs;
+use std::{fmt, io};
+
+use futures::{Future, Sink, Stream};
+use meilies::reqresp::{Request, RequestMsgError, Response, ResponseMsgError, ServerError};
+
+use super::{connect_first, resolve, ClientConnection};
+
+/// The step at which opening a connection with `open_connection` failed.
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The address could not be resolved, or it resolved to no address at all.
+    Resolve(io::Error),
+    /// The address was resolved but the connection could not be established.
+    Connect(io::Error),
+    /// The connection was established but the server did not answer the ping.
+    Handshake(HandshakeError),
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ConnectError::*;
+
+        match self {
+            Resolve(error) => write!(f, "address resolution failed: {}", error),
+            Connect(error) => write!(f, "connection failed: {}", error),
+            Handshake(error) => write!(f, "handshake failed: {}", error),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    /// The server answered the ping with an error, like when it has too many connections.
+    ServerSide(ServerError),
+    ConnectionClosed,
+    RequestMsgError(RequestMsgError),
+    ResponseMsgError(ResponseMsgError),
+    InvalidServerResponse(Response),
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use HandshakeError::*;
+
+        match self {
+            ServerSide(error) => write!(f, "server side error: {}", error),
+            ConnectionClosed => write!(f, "connection closed"),
+            RequestMsgError(error) => write!(f, "invalid Request: {}", error),
+            ResponseMsgError(error) => write!(f, "invalid Response received: {}", error),
+            InvalidServerResponse(response) => {
+                write!(f, "invalid server response received: {:?}", response)
+            }
+        }
+    }
+}
+
+/// Open a framed connection with a server using RESP, the address is resolved,
+/// each resolved address is tried in order until one accepts the connection
+/// and the server must answer a ping before the connection is returned.
+///
+/// The address is resolved on a thread of its own, the executor is never blocked.
+/// Unlike `connect` the error tells which one of these steps failed.
+pub fn open_connection<A>(addr: A) -> impl Future<Item = ClientConnection, Error = ConnectError>
+where
+    A: ToSocketAddrs + Send + 'static,
+{
+    resolve(addr)
+        .and_then(|addrs| {
+            if addrs.is_empty() {
+                let kind = io::ErrorKind::AddrNotAvailable;
+                return Err(io::Error::new(kind, "no address"));
+            }
+            Ok(addrs)
+        })
+        .map_err(ConnectError::Resolve)
+        .and_then(|addrs| connect_first(addrs, true).map_err(ConnectError::Connect))
+        .and_then(|connection| handshake(connection).map_err(ConnectError::Handshake))
+}
+
+/// Check that a server is answering on the connection by sending it a ping.
+fn handshake(
+    connection: ClientConnection,
+) -> impl Future<Item = ClientConnection, Error = HandshakeError> {
+    use HandshakeError::*;
+
+    connection
+        .send(Request::Ping)
+        .map_err(RequestMsgError)
+        .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+        .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+            Ok(Response::Pong) => Ok(connection),
+            Ok(response) => Err(InvalidServerResponse(response)),
+            Err(error) => Err(ServerSide(error)),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::Socket;
+    use tokio::prelude::FutureExt;
+
+    #[test]
+    fn closed_port_is_a_connect_error() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+        // the port is free again once the listener is dropped
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let fut = open_connection(addr).timeout(Duration::from_secs(5));
+        match runtime.block_on(fut) {
+            Err(e) => match e.into_inner() {
+                Some(ConnectError::Connect(_)) => (),
+                otherwise => panic!("unexpected error {:?}", otherwise),
+            },
+            Ok(_) => panic!("connected to a closed port"),
+        }
+    }
+
+    #[test]
+    fn dead_address_falls_back_to_the_next_one() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let dead = listener.local_addr().unwrap();
+        drop(listener);
+
+        // the connection is accepted by the backlog without calling accept
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let live = listener.local_addr().unwrap();
+
+        let fut = connect_first(vec![dead, live], true).timeout(Duration::from_secs(5));
+        let connection = runtime.block_on(fut).unwrap();
+        match connection.get_ref() {
+            Socket::Tcp(socket) => assert_eq!(socket.peer_addr().unwrap(), live),
+            Socket::Unix(_) => panic!("connected to a unix socket"),
+        }
+    }
+
+    #[test]
+    fn no_address_is_a_resolve_error() {
+        let addrs: &[std::net::SocketAddr] = &[];
+        match open_connection(addrs).wait() {
+            Err(ConnectError::Resolve(_)) => (),
+            Err(otherwise) => panic!("unexpected error {:?}", otherwise),
+            Ok(_) => panic!("connected without an address"),
+        }
+    }
+}
@@ -1,34 +1,159 @@
 use std::io;
-use std::net::SocketAddr;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::Path;
+use std::thread;
 use std::time::Duration;
 
+use futures::future::{self, Either, Loop};
 use futures::stream::{SplitSink, SplitStream};
+use futures::sync::oneshot::{self, Canceled};
 use futures::Future;
 use log::warn;
 use meilies::reqresp::ClientCodec;
 use tokio::codec::{Decoder, Framed};
-use tokio::net::TcpStream;
+use tokio::net::{TcpStream, UnixStream};
 
+mod fold;
+mod handshake;
+mod next;
 mod paired;
+mod pool;
+mod socket;
 mod steel_connection;
 mod sub;
 
-pub use self::paired::{paired_connect, PairedConnection};
-use self::steel_connection::{retry_strategy, SteelConnection};
-pub use self::sub::{sub_connect, ProtocolError, SubController, SubStream};
+pub use self::fold::{fold, fold_from_snapshot, FoldError};
+pub use self::handshake::{open_connection, ConnectError, HandshakeError};
+pub use self::next::{next_event, NextEventError};
+pub use self::paired::{paired_connect, paired_connect_with_nodelay};
+pub use self::paired::{PairedConnection, PairedConnectionError, ServerInfo};
+pub use self::pool::{PairedPool, PairedPoolError};
+pub use self::socket::{ParseServerAddrError, ServerAddr, Socket};
+use self::steel_connection::SteelConnection;
+pub use self::steel_connection::{ConnectionState, RetryPolicy};
+pub use self::sub::{
+    sub_connect, sub_connect_unix, sub_connect_with_config, sub_connect_with_keepalive,
+};
+pub use self::sub::{Dedup, Delivery, DrainToAllCaughtUp, KeepAlive, ProtocolError};
+pub use self::sub::{SubConnectConfig, SubController, SubMetrics, SubStream};
 
-pub type ClientConnection = Framed<TcpStream, ClientCodec>;
-pub type ClientConnectionWriter = SplitSink<Framed<TcpStream, ClientCodec>>;
-pub type ClientConnectionReader = SplitStream<Framed<TcpStream, ClientCodec>>;
+pub type ClientConnection = Framed<Socket, ClientCodec>;
+pub type ClientConnectionWriter = SplitSink<Framed<Socket, ClientCodec>>;
+pub type ClientConnectionReader = SplitStream<Framed<Socket, ClientCodec>>;
 
 /// Open a framed connection with a server using RESP
+#[deprecated(
+    note = "use `open_connection`, its error tells resolution, connection and handshake failures apart"
+)]
 pub fn connect(addr: &SocketAddr) -> impl Future<Item = ClientConnection, Error = io::Error> {
-    TcpStream::connect(addr).map(|socket| {
+    connect_tcp(addr, true)
+}
+
+/// Without `TCP_NODELAY` the small request and response messages of a paired connection
+/// can be held back by Nagle's algorithm waiting for the delayed ACK of the peer.
+fn connect_tcp(
+    addr: &SocketAddr,
+    nodelay: bool,
+) -> impl Future<Item = ClientConnection, Error = io::Error> {
+    TcpStream::connect(addr).map(move |socket| {
         let duration = Duration::from_millis(50);
         if let Err(e) = socket.set_keepalive(Some(duration)) {
             warn!("set_keepalive error; {}", e);
         }
 
-        ClientCodec::default().framed(socket)
+        if let Err(e) = socket.set_nodelay(nodelay) {
+            warn!("set_nodelay error; {}", e);
+        }
+
+        ClientCodec::default().framed(Socket::Tcp(socket))
     })
 }
+
+/// Open a framed connection with a server using RESP, trying each address the host
+/// resolves to, IPv4 and IPv6 alike, in order until one of them accepts the connection.
+///
+/// The host is resolved on a thread of its own, the executor is never blocked.
+/// The error is the one of the last address tried.
+pub fn resolve_and_connect(
+    host: &str,
+    port: u16,
+) -> impl Future<Item = ClientConnection, Error = io::Error> {
+    resolve((host.to_owned(), port)).and_then(|addrs| connect_first(addrs, true))
+}
+
+/// Resolve an address on a dedicated thread, the resolver of the system
+/// is blocking and would stall every other task of the executor.
+fn resolve<A>(addr: A) -> impl Future<Item = Vec<SocketAddr>, Error = io::Error>
+where
+    A: ToSocketAddrs + Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    let spawned = thread::Builder::new()
+        .name(String::from("meilies-resolver"))
+        .spawn(move || {
+            let addrs = addr.to_socket_addrs().map(Iterator::collect);
+            let _ = sender.send(addrs);
+        });
+
+    if let Err(e) = spawned {
+        return Either::A(future::err(e));
+    }
+
+    Either::B(receiver.then(|result| match result {
+        Ok(addrs) => addrs,
+        Err(Canceled) => Err(io::Error::new(
+            io::ErrorKind::Other,
+            "resolver thread panicked",
+        )),
+    }))
+}
+
+fn connect_first(
+    addrs: Vec<SocketAddr>,
+    nodelay: bool,
+) -> impl Future<Item = ClientConnection, Error = io::Error> {
+    let no_address = io::Error::new(io::ErrorKind::AddrNotAvailable, "no address to connect to");
+
+    future::loop_fn(
+        (addrs.into_iter(), no_address),
+        move |(mut addrs, last_error)| match addrs.next() {
+            Some(addr) => Either::A(
+                connect_tcp(&addr, nodelay).then(move |result| match result {
+                    Ok(connection) => Ok(Loop::Break(connection)),
+                    Err(e) => {
+                        warn!("connection to {} failed; {}", addr, e);
+                        Ok(Loop::Continue((addrs, e)))
+                    }
+                }),
+            ),
+            None => Either::B(future::err(last_error)),
+        },
+    )
+}
+
+/// Open a framed connection with a server listening on a unix socket using RESP
+pub fn connect_unix(path: &Path) -> impl Future<Item = ClientConnection, Error = io::Error> {
+    UnixStream::connect(path).map(|socket| ClientCodec::default().framed(Socket::Unix(socket)))
+}
+
+/// Open a framed connection with a server using RESP, whatever the kind of its address
+pub fn connect_addr(addr: &ServerAddr) -> impl Future<Item = ClientConnection, Error = io::Error> {
+    connect_addr_with_nodelay(addr, true)
+}
+
+/// Open a framed connection with a server, `TCP_NODELAY` is set on the TCP sockets
+/// if `nodelay` is true, it has no effect on unix sockets.
+fn connect_addr_with_nodelay(
+    addr: &ServerAddr,
+    nodelay: bool,
+) -> impl Future<Item = ClientConnection, Error = io::Error> {
+    match addr {
+        ServerAddr::Tcp(addr) => Either::A(connect_tcp(addr, nodelay)),
+        ServerAddr::Host(host, port) => {
+            let connection =
+                resolve((host.clone(), *port)).and_then(move |addrs| connect_first(addrs, nodelay));
+            Either::B(Either::A(connection))
+        }
+        ServerAddr::Unix(path) => Either::B(Either::B(connect_unix(path))),
+    }
+}
@@ -0,0 +1,78 @@
+use std::net::SocketAddr;
+use std::{fmt, io};
+
+use futures::{Future, Stream};
+use meilies::reqresp::{Response, ServerError};
+use meilies::stream::{EventData, EventName, EventNumber, ReadRange};
+use meilies::stream::{Stream as EsStream, StreamName};
+
+use crate::paired::{paired_connect, PairedConnectionError};
+use crate::sub::{sub_connect, ProtocolError};
+
+#[derive(Debug)]
+pub enum NextEventError {
+    Connection(tokio_retry::Error<io::Error>),
+    Request(PairedConnectionError),
+    Protocol(ProtocolError),
+    ServerSide(ServerError),
+    ConnectionClosed,
+}
+
+impl fmt::Display for NextEventError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NextEventError::Connection(error) => write!(f, "connection error: {}", error),
+            NextEventError::Request(error) => write!(f, "request error: {}", error),
+            NextEventError::Protocol(error) => write!(f, "protocol error: {}", error),
+            NextEventError::ServerSide(error) => write!(f, "server side error: {}", error),
+            NextEventError::ConnectionClosed => f.write_str("connection closed"),
+        }
+    }
+}
+
+/// Wait for the next event published to a stream and return it, the subscription
+/// is closed once the event is received.
+///
+/// The last event number of the stream is read when the future is first polled
+/// and the subscription starts right after it, this way an event published while
+/// the subscription is being opened is not missed.
+pub fn next_event(
+    addr: SocketAddr,
+    stream: StreamName,
+) -> impl Future<Item = (EventNumber, EventName, EventData), Error = NextEventError> {
+    paired_connect(addr)
+        .map_err(NextEventError::Connection)
+        .and_then(move |conn| {
+            conn.last_event_number(stream)
+                .map_err(NextEventError::Request)
+        })
+        .and_then(move |(stream, number, _conn)| {
+            let from = number.map_or(0, |n| n.next().0);
+            sub_connect(addr)
+                .map_err(NextEventError::Connection)
+                .map(move |(controller, sub_stream)| (stream, from, controller, sub_stream))
+        })
+        .and_then(|(stream, from, mut controller, sub_stream)| {
+            controller.subscribe_to(EsStream::new(stream, ReadRange::ReadFrom(from)));
+
+            sub_stream
+                .map_err(NextEventError::Protocol)
+                .and_then(|response| match response {
+                    Ok(Response::Event {
+                        number,
+                        event_name,
+                        event_data,
+                        ..
+                    }) => Ok(Some((number, event_name, event_data))),
+                    Ok(_) => Ok(None),
+                    Err(error) => Err(NextEventError::ServerSide(error)),
+                })
+                .filter_map(|event| event)
+                .into_future()
+                .map_err(|(error, _)| error)
+                .and_then(move |(event, _)| {
+                    controller.close();
+                    event.ok_or(NextEventError::ConnectionClosed)
+                })
+        })
+}
@@ -1,23 +1,48 @@
-use std::net::SocketAddr;
-use std::{fmt, io};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::{cmp, fmt, io};
 
+use futures::future::{self, Either, Loop};
 use futures::{Future, Sink, Stream};
 use log::warn;
-use meilies::reqresp::{Request, RequestMsgError};
-use meilies::reqresp::{Response, ResponseMsgError};
-use meilies::stream::{EventData, EventName, EventNumber, StreamName};
+use meilies::reqresp::{ErrorCode, Request, RequestMsgError};
+use meilies::reqresp::{Response, ResponseMsgError, ServerError};
+use meilies::stream::Stream as EsStream;
+use meilies::stream::{EventData, EventName, EventNumber, StreamName, StreamSettings};
+use tokio::prelude::FutureExt;
 use tokio_retry::Retry;
 
-use super::{connect, SteelConnection};
+use super::{connect_addr_with_nodelay, ServerAddr, SteelConnection};
 use crate::steel_connection::retry_strategy;
 
 /// Open a framed paired connection with a server.
-pub fn paired_connect(
-    addr: SocketAddr,
+pub fn paired_connect<A: Into<ServerAddr>>(
+    addr: A,
 ) -> impl Future<Item = PairedConnection, Error = tokio_retry::Error<io::Error>> {
     PairedConnection::connect(addr)
 }
 
+/// Open a framed paired connection with a server, `TCP_NODELAY` is set on its
+/// TCP connections if `nodelay` is true, which `paired_connect` always does.
+pub fn paired_connect_with_nodelay<A: Into<ServerAddr>>(
+    addr: A,
+    nodelay: bool,
+) -> impl Future<Item = PairedConnection, Error = tokio_retry::Error<io::Error>> {
+    PairedConnection::connect_to(addr.into(), nodelay)
+}
+
+/// The version of a server and a few statistics, see `PairedConnection::info`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub version: String,
+    pub uptime: Duration,
+    pub streams: u64,
+    /// The number of events ever published, including the ones removed since.
+    pub published_events: u64,
+    pub features: Vec<String>,
+}
+
 /// A paired connection returns a response to each message send, it is sequential.
 /// This connection is used to publish events to streams.
 pub struct PairedConnection {
@@ -26,11 +51,14 @@ pub struct PairedConnection {
 
 #[derive(Debug)]
 pub enum PairedConnectionError {
-    ServerSide(String),
+    ServerSide(ServerError),
     ConnectionClosed,
     RequestMsgError(RequestMsgError),
     ResponseMsgError(ResponseMsgError),
     InvalidServerResponse(Response),
+    /// The server did not answer in time, the connection is dropped
+    /// because the response could still be received later.
+    TimedOut(Duration),
 }
 
 impl fmt::Display for PairedConnectionError {
@@ -45,19 +73,48 @@ impl fmt::Display for PairedConnectionError {
             InvalidServerResponse(response) => {
                 write!(f, "invalid server response received: {:?}", response)
             }
+            TimedOut(timeout) => write!(f, "no response received in {:.2?}", timeout),
+        }
+    }
+}
+
+impl PairedConnectionError {
+    /// The code of the error sent by the server, if any.
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            PairedConnectionError::ServerSide(error) => error.code,
+            _ => None,
         }
     }
 }
 
 impl PairedConnection {
     /// Open a framed paired connection with a server.
-    pub fn connect(
-        addr: SocketAddr,
+    ///
+    /// A `ServerAddr::Host` is resolved on each connection and
+    /// every address it resolves to is tried, see `resolve_and_connect`.
+    pub fn connect<A: Into<ServerAddr>>(
+        addr: A,
+    ) -> impl Future<Item = PairedConnection, Error = tokio_retry::Error<io::Error>> {
+        PairedConnection::connect_to(addr.into(), true)
+    }
+
+    /// Open a framed paired connection with a server listening on a unix socket.
+    pub fn connect_unix<P: Into<PathBuf>>(
+        path: P,
+    ) -> impl Future<Item = PairedConnection, Error = tokio_retry::Error<io::Error>> {
+        PairedConnection::connect_to(ServerAddr::Unix(path.into()), true)
+    }
+
+    fn connect_to(
+        addr: ServerAddr,
+        nodelay: bool,
     ) -> impl Future<Item = PairedConnection, Error = tokio_retry::Error<io::Error>> {
         Retry::spawn(retry_strategy(), move || {
             warn!("Connecting to {}", addr);
-            connect(&addr).map(move |connection| {
-                let connection = SteelConnection::new(addr, connection);
+            let addr = addr.clone();
+            connect_addr_with_nodelay(&addr, nodelay).map(move |connection| {
+                let connection = SteelConnection::new(addr, connection).nodelay(nodelay);
                 PairedConnection { connection }
             })
         })
@@ -69,6 +126,18 @@ impl PairedConnection {
         stream: StreamName,
         event_name: EventName,
         event_data: EventData,
+    ) -> impl Future<Item = PairedConnection, Error = PairedConnectionError> {
+        self.publish_with_headers(stream, event_name, event_data, HashMap::new())
+    }
+
+    /// Publish an event along with headers, the subscribers receive
+    /// the headers with the event.
+    pub fn publish_with_headers(
+        self,
+        stream: StreamName,
+        event_name: EventName,
+        event_data: EventData,
+        headers: HashMap<String, String>,
     ) -> impl Future<Item = PairedConnection, Error = PairedConnectionError> {
         use PairedConnectionError::*;
 
@@ -76,6 +145,37 @@ impl PairedConnection {
             stream,
             event_name,
             event_data,
+            dedup_id: None,
+            headers,
+        };
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::Ok) => Ok(PairedConnection { connection }),
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Publish a part of an event, the server accumulates the chunks
+    /// and publishes the event when it receives the last one.
+    pub fn publish_chunk(
+        self,
+        stream: StreamName,
+        event_name: EventName,
+        chunk: Vec<u8>,
+        last: bool,
+    ) -> impl Future<Item = PairedConnection, Error = PairedConnectionError> {
+        use PairedConnectionError::*;
+
+        let command = Request::PublishChunk {
+            stream,
+            event_name,
+            chunk,
+            last,
         };
 
         self.connection
@@ -89,6 +189,94 @@ impl PairedConnection {
             })
     }
 
+    /// Publish an event in chunks of at most `chunk_size` bytes,
+    /// the data of a big event is never encoded in a single frame.
+    pub fn publish_chunked(
+        self,
+        stream: StreamName,
+        event_name: EventName,
+        event_data: EventData,
+        chunk_size: usize,
+    ) -> impl Future<Item = PairedConnection, Error = PairedConnectionError> {
+        let chunk_size = cmp::max(chunk_size, 1);
+        let data = event_data.0;
+
+        future::loop_fn((self, 0), move |(conn, offset)| {
+            let end = cmp::min(offset + chunk_size, data.len());
+            let last = end == data.len();
+            let chunk = data[offset..end].to_vec();
+
+            conn.publish_chunk(stream.clone(), event_name.clone(), chunk, last)
+                .map(move |conn| {
+                    if last {
+                        Loop::Break(conn)
+                    } else {
+                        Loop::Continue((conn, end))
+                    }
+                })
+        })
+    }
+
+    /// Publish an event to a stream only if no event was published with the same dedup id,
+    /// returns the number of the event, the one of the previous event if it is a duplicate.
+    ///
+    /// Retrying such a publish after a timeout can not append the same event twice.
+    pub fn publish_with_dedup_id(
+        self,
+        stream: StreamName,
+        event_name: EventName,
+        event_data: EventData,
+        dedup_id: String,
+    ) -> impl Future<Item = (EventNumber, PairedConnection), Error = PairedConnectionError> {
+        use PairedConnectionError::*;
+
+        let command = Request::Publish {
+            stream,
+            event_name,
+            event_data,
+            dedup_id: Some(dedup_id),
+            headers: HashMap::new(),
+        };
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::Published { number, .. }) => {
+                    Ok((number, PairedConnection { connection }))
+                }
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Publish events to several streams atomically, either all the events are
+    /// appended or none of them is, returns the numbers given to the events in order.
+    pub fn publish_multi(
+        self,
+        events: Vec<(StreamName, EventName, EventData)>,
+    ) -> impl Future<
+        Item = (Vec<(StreamName, EventNumber)>, PairedConnection),
+        Error = PairedConnectionError,
+    > {
+        use PairedConnectionError::*;
+
+        let command = Request::PublishMulti { events };
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::PublishedMulti { events }) => {
+                    Ok((events, PairedConnection { connection }))
+                }
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
     /// Request the last event number that the stream is at.
     ///
     /// Returns `None` if the stream does not contain any event.
@@ -116,6 +304,43 @@ impl PairedConnection {
             })
     }
 
+    /// Request the last event of a stream.
+    ///
+    /// Returns `None` if the stream does not contain any event.
+    pub fn last_event(
+        self,
+        stream: StreamName,
+    ) -> impl Future<
+        Item = (
+            Option<(EventNumber, EventName, EventData)>,
+            PairedConnection,
+        ),
+        Error = PairedConnectionError,
+    > {
+        use PairedConnectionError::*;
+
+        let command = Request::LastEvent { stream };
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::Event {
+                    number,
+                    event_name,
+                    event_data,
+                    ..
+                }) => {
+                    let event = (number, event_name, event_data);
+                    Ok((Some(event), PairedConnection { connection }))
+                }
+                Ok(Response::Nil) => Ok((None, PairedConnection { connection })),
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
     /// Request the list of stream names
     ///
     /// Returns an empty Vec if the database does not contain any stream.
@@ -139,4 +364,547 @@ impl PairedConnection {
                 Err(error) => Err(ServerSide(error)),
             })
     }
+
+    /// Request the number of bytes used by the events of a stream.
+    pub fn stream_size(
+        self,
+        stream: StreamName,
+    ) -> impl Future<Item = (StreamName, u64, PairedConnection), Error = PairedConnectionError>
+    {
+        use PairedConnectionError::*;
+
+        let command = Request::StreamSize { stream };
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::StreamSize { stream, bytes }) => {
+                    Ok((stream, bytes, PairedConnection { connection }))
+                }
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Request the number of bytes used by the events of all the streams.
+    pub fn total_size(
+        self,
+    ) -> impl Future<Item = (u64, PairedConnection), Error = PairedConnectionError> {
+        use PairedConnectionError::*;
+
+        let command = Request::TotalSize;
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::TotalSize { bytes }) => Ok((bytes, PairedConnection { connection })),
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Check that the server is alive, returns the round-trip time of the ping.
+    pub fn ping(
+        self,
+    ) -> impl Future<Item = (Duration, PairedConnection), Error = PairedConnectionError> {
+        use PairedConnectionError::*;
+
+        future::lazy(move || {
+            let start = Instant::now();
+            self.connection
+                .send(Request::Ping)
+                .map_err(RequestMsgError)
+                .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+                .and_then(
+                    move |(first, connection)| match first.ok_or(ConnectionClosed)? {
+                        Ok(Response::Pong) => {
+                            Ok((start.elapsed(), PairedConnection { connection }))
+                        }
+                        Ok(response) => Err(InvalidServerResponse(response)),
+                        Err(error) => Err(ServerSide(error)),
+                    },
+                )
+        })
+    }
+
+    /// Request the version of the server, its uptime, its number of streams,
+    /// the number of events ever published and its enabled features.
+    pub fn info(
+        self,
+    ) -> impl Future<Item = (ServerInfo, PairedConnection), Error = PairedConnectionError> {
+        use PairedConnectionError::*;
+
+        let command = Request::Info;
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::Info {
+                    version,
+                    uptime,
+                    streams,
+                    published_events,
+                    features,
+                }) => {
+                    let info = ServerInfo {
+                        version,
+                        uptime: Duration::from_secs(uptime),
+                        streams,
+                        published_events,
+                        features,
+                    };
+                    Ok((info, PairedConnection { connection }))
+                }
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Request the number of active subscriptions of each stream.
+    pub fn list_subscriptions(
+        self,
+    ) -> impl Future<Item = (Vec<(StreamName, u64)>, PairedConnection), Error = PairedConnectionError>
+    {
+        use PairedConnectionError::*;
+
+        let command = Request::ListSubscriptions;
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::Subscriptions { subscriptions }) => {
+                    Ok((subscriptions, PairedConnection { connection }))
+                }
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Send any request that is answered by a single response and return it as is.
+    ///
+    /// Subscription requests must not be sent on a paired connection,
+    /// the server would push events that are never read.
+    pub fn request(
+        self,
+        request: Request,
+    ) -> impl Future<Item = (Response, PairedConnection), Error = PairedConnectionError> {
+        use PairedConnectionError::*;
+
+        self.connection
+            .send(request)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(response) => Ok((response, PairedConnection { connection })),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Read the events of a stream in the given range (`to` is exclusive) and return them.
+    ///
+    /// The range is clamped to the last event of the stream when the request is made,
+    /// so the returned future completes even if the stream has less events than asked.
+    /// The read ends when the server reports the end of the history, the corrupted
+    /// events that it skips are missing from the returned events.
+    pub fn read_range(
+        self,
+        stream: StreamName,
+        from: EventNumber,
+        to: EventNumber,
+    ) -> impl Future<
+        Item = (Vec<(EventNumber, EventName, EventData)>, PairedConnection),
+        Error = PairedConnectionError,
+    > {
+        use PairedConnectionError::*;
+
+        self.last_event_number(stream)
+            .and_then(move |(stream, last, paired)| {
+                let end = last.map_or(EventNumber::zero(), EventNumber::next);
+                let to = cmp::min(to, end);
+
+                if from >= to {
+                    return Either::A(future::ok((Vec::new(), paired)));
+                }
+
+                let command = Request::Subscribe {
+                    streams: vec![EsStream::history_until(stream, from.0, to.0)],
+                    require_existing: false,
+                };
+
+                let fut = paired
+                    .connection
+                    .send(command)
+                    .map_err(RequestMsgError)
+                    .and_then(move |connection| {
+                        future::loop_fn((Vec::new(), connection), move |(mut events, conn)| {
+                            conn.into_future()
+                                .map_err(|(e, _)| ResponseMsgError(e))
+                                .and_then(move |(msg, conn)| match msg.ok_or(ConnectionClosed)? {
+                                    Ok(Response::Subscribed { .. }) => {
+                                        Ok(Loop::Continue((events, conn)))
+                                    }
+                                    Ok(Response::Event {
+                                        number,
+                                        event_name,
+                                        event_data,
+                                        ..
+                                    }) => {
+                                        events.push((number, event_name, event_data));
+                                        Ok(Loop::Continue((events, conn)))
+                                    }
+                                    Ok(Response::EndOfStream { .. }) => {
+                                        let paired = PairedConnection { connection: conn };
+                                        Ok(Loop::Break((events, paired)))
+                                    }
+                                    Ok(response) => Err(InvalidServerResponse(response)),
+                                    Err(error) => Err(ServerSide(error)),
+                                })
+                        })
+                    });
+
+                Either::B(fut)
+            })
+    }
+
+    /// Export all the events of a stream as a binary blob that can be given to `import`.
+    pub fn export(
+        self,
+        stream: StreamName,
+    ) -> impl Future<Item = (Vec<u8>, PairedConnection), Error = PairedConnectionError> {
+        use PairedConnectionError::*;
+
+        let command = Request::Export { stream };
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|connection| {
+                future::loop_fn((Vec::new(), connection), |(mut blob, conn)| {
+                    conn.into_future()
+                        .map_err(|(e, _)| ResponseMsgError(e))
+                        .and_then(move |(msg, conn)| match msg.ok_or(ConnectionClosed)? {
+                            Ok(Response::Exported { blob: batch, .. }) => {
+                                blob.extend_from_slice(&batch);
+                                Ok(Loop::Continue((blob, conn)))
+                            }
+                            Ok(Response::Ok) => {
+                                let paired = PairedConnection { connection: conn };
+                                Ok(Loop::Break((blob, paired)))
+                            }
+                            Ok(response) => Err(InvalidServerResponse(response)),
+                            Err(error) => Err(ServerSide(error)),
+                        })
+                })
+            })
+    }
+
+    /// Import the events of a blob produced by `export` in a stream.
+    pub fn import(
+        self,
+        stream: StreamName,
+        blob: Vec<u8>,
+    ) -> impl Future<Item = PairedConnection, Error = PairedConnectionError> {
+        use PairedConnectionError::*;
+
+        let command = Request::Import { stream, blob };
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::Ok) => Ok(PairedConnection { connection }),
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Replace the settings of a stream, the stream is created if it does not exist yet.
+    pub fn configure_stream(
+        self,
+        stream: StreamName,
+        settings: StreamSettings,
+    ) -> impl Future<Item = PairedConnection, Error = PairedConnectionError> {
+        use PairedConnectionError::*;
+
+        let command = Request::ConfigureStream { stream, settings };
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::Ok) => Ok(PairedConnection { connection }),
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Create a consumer group that reads a stream from its first event,
+    /// nothing is changed if the group already exists.
+    pub fn create_group(
+        self,
+        stream: StreamName,
+        group: String,
+    ) -> impl Future<Item = PairedConnection, Error = PairedConnectionError> {
+        use PairedConnectionError::*;
+
+        let command = Request::CreateGroup { stream, group };
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::Ok) => Ok(PairedConnection { connection }),
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Claim the next event of the group that no other consumer claimed,
+    /// `None` is returned when every event of the stream is claimed.
+    ///
+    /// The event must be acknowledged on this connection, it is claimed
+    /// again by another consumer if the connection is closed before.
+    pub fn claim(
+        self,
+        stream: StreamName,
+        group: String,
+    ) -> impl Future<
+        Item = (
+            Option<(EventNumber, EventName, EventData)>,
+            PairedConnection,
+        ),
+        Error = PairedConnectionError,
+    > {
+        use PairedConnectionError::*;
+
+        let command = Request::Claim { stream, group };
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::Event {
+                    number,
+                    event_name,
+                    event_data,
+                    ..
+                }) => {
+                    let event = (number, event_name, event_data);
+                    Ok((Some(event), PairedConnection { connection }))
+                }
+                Ok(Response::Nil) => Ok((None, PairedConnection { connection })),
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Acknowledge an event claimed on this connection, it is never claimed again.
+    pub fn ack(
+        self,
+        stream: StreamName,
+        group: String,
+        number: EventNumber,
+    ) -> impl Future<Item = PairedConnection, Error = PairedConnectionError> {
+        use PairedConnectionError::*;
+
+        let command = Request::Ack {
+            stream,
+            group,
+            number,
+        };
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::Ok) => Ok(PairedConnection { connection }),
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Replace an event of a stream by a tombstone, the event keeps its number
+    /// and its name but is read redacted, without its data and its headers.
+    pub fn redact_event(
+        self,
+        stream: StreamName,
+        number: EventNumber,
+    ) -> impl Future<Item = PairedConnection, Error = PairedConnectionError> {
+        use PairedConnectionError::*;
+
+        let command = Request::RedactEvent { stream, number };
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::Ok) => Ok(PairedConnection { connection }),
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Store the state of a stream folded up to the event `number` included,
+    /// it replaces the previous snapshot of the stream.
+    pub fn save_snapshot(
+        self,
+        stream: StreamName,
+        number: EventNumber,
+        data: Vec<u8>,
+    ) -> impl Future<Item = PairedConnection, Error = PairedConnectionError> {
+        use PairedConnectionError::*;
+
+        let command = Request::SaveSnapshot {
+            stream,
+            number,
+            data,
+        };
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::Ok) => Ok(PairedConnection { connection }),
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Request the latest snapshot of a stream and the number of the last event it includes.
+    ///
+    /// Returns `None` if no snapshot of the stream has been saved.
+    pub fn get_snapshot(
+        self,
+        stream: StreamName,
+    ) -> impl Future<
+        Item = (Option<(EventNumber, Vec<u8>)>, PairedConnection),
+        Error = PairedConnectionError,
+    > {
+        use PairedConnectionError::*;
+
+        let command = Request::GetSnapshot { stream };
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::Snapshot { number, data, .. }) => {
+                    Ok((Some((number, data)), PairedConnection { connection }))
+                }
+                Ok(Response::Nil) => Ok((None, PairedConnection { connection })),
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Ask the server to fold the events published since the latest snapshot of a stream,
+    /// returns the new snapshot and the number of the last event it includes.
+    ///
+    /// The server must have a snapshot function registered for the stream.
+    pub fn create_snapshot(
+        self,
+        stream: StreamName,
+    ) -> impl Future<Item = ((EventNumber, Vec<u8>), PairedConnection), Error = PairedConnectionError>
+    {
+        use PairedConnectionError::*;
+
+        let command = Request::CreateSnapshot { stream };
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::Snapshot { number, data, .. }) => {
+                    Ok(((number, data), PairedConnection { connection }))
+                }
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Request at most `limit` events of a stream starting at `from` in a single response,
+    /// the server caps the limit and can send less events than asked.
+    ///
+    /// Returns the events, as `Response::Event`s, and the number the next page starts at,
+    /// `None` if the end of the stream has been reached.
+    pub fn page(
+        self,
+        stream: StreamName,
+        from: EventNumber,
+        limit: u64,
+    ) -> impl Future<
+        Item = ((Vec<Response>, Option<EventNumber>), PairedConnection),
+        Error = PairedConnectionError,
+    > {
+        use PairedConnectionError::*;
+
+        let command = Request::Page {
+            stream,
+            from,
+            limit,
+        };
+
+        self.connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::Page { events, next, .. }) => {
+                    Ok(((events, next), PairedConnection { connection }))
+                }
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            })
+    }
+
+    /// Wait until the last event number of a stream reaches `number`,
+    /// the events are not sent, only the readiness of the stream.
+    ///
+    /// The connection is dropped if the server does not answer before the timeout.
+    pub fn wait_for(
+        self,
+        stream: StreamName,
+        number: EventNumber,
+        timeout: Option<Duration>,
+    ) -> impl Future<Item = PairedConnection, Error = PairedConnectionError> {
+        use PairedConnectionError::*;
+
+        let command = Request::WaitFor { stream, number };
+
+        let wait = self
+            .connection
+            .send(command)
+            .map_err(RequestMsgError)
+            .and_then(|framed| framed.into_future().map_err(|(e, _)| ResponseMsgError(e)))
+            .and_then(|(first, connection)| match first.ok_or(ConnectionClosed)? {
+                Ok(Response::Ok) => Ok(PairedConnection { connection }),
+                Ok(response) => Err(InvalidServerResponse(response)),
+                Err(error) => Err(ServerSide(error)),
+            });
+
+        match timeout {
+            Some(duration) => Either::A(
+                wait.timeout(duration)
+                    .map_err(move |error| error.into_inner().unwrap_or(TimedOut(duration))),
+            ),
+            None => Either::B(wait),
+        }
+    }
 }
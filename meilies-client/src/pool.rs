@@ -0,0 +1,103 @@
+use std::sync::{Arc, Mutex};
+use std::{fmt, io};
+
+use futures::future::{self, Either};
+use futures::Future;
+use meilies::stream::{EventData, EventName, EventNumber, StreamName};
+
+use crate::paired::{PairedConnection, PairedConnectionError};
+use crate::socket::ServerAddr;
+
+/// A pool of paired connections that can be shared between tasks.
+///
+/// Connections are handed out by `acquire` and given back with `release`,
+/// a connection that failed is simply not given back, a new one will be opened lazily.
+#[derive(Clone)]
+pub struct PairedPool {
+    addr: ServerAddr,
+    size: usize,
+    idle: Arc<Mutex<Vec<PairedConnection>>>,
+}
+
+#[derive(Debug)]
+pub enum PairedPoolError {
+    Connection(tokio_retry::Error<io::Error>),
+    Request(PairedConnectionError),
+}
+
+impl fmt::Display for PairedPoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PairedPoolError::Connection(error) => write!(f, "connection error: {}", error),
+            PairedPoolError::Request(error) => write!(f, "request error: {}", error),
+        }
+    }
+}
+
+impl PairedPool {
+    /// Create a pool that keeps at most `size` idle connections to the server.
+    pub fn new<A: Into<ServerAddr>>(addr: A, size: usize) -> PairedPool {
+        PairedPool {
+            addr: addr.into(),
+            size,
+            idle: Arc::new(Mutex::new(Vec::with_capacity(size))),
+        }
+    }
+
+    /// Take an idle connection from the pool or open a new one if there is none.
+    pub fn acquire(
+        &self,
+    ) -> impl Future<Item = PairedConnection, Error = tokio_retry::Error<io::Error>> {
+        let idle = self.idle.lock().unwrap().pop();
+
+        match idle {
+            Some(connection) => Either::A(future::ok(connection)),
+            None => Either::B(PairedConnection::connect(self.addr.clone())),
+        }
+    }
+
+    /// Give back a connection to the pool, it is dropped if the pool is already full.
+    pub fn release(&self, connection: PairedConnection) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.size {
+            idle.push(connection);
+        }
+    }
+
+    /// Publish an event to a stream using a connection of the pool.
+    pub fn publish(
+        &self,
+        stream: StreamName,
+        event_name: EventName,
+        event_data: EventData,
+    ) -> impl Future<Item = (), Error = PairedPoolError> {
+        let pool = self.clone();
+
+        self.acquire()
+            .map_err(PairedPoolError::Connection)
+            .and_then(|conn| {
+                conn.publish(stream, event_name, event_data)
+                    .map_err(PairedPoolError::Request)
+            })
+            .map(move |conn| pool.release(conn))
+    }
+
+    /// Request the last event number of a stream using a connection of the pool.
+    pub fn last_event_number(
+        &self,
+        stream: StreamName,
+    ) -> impl Future<Item = (StreamName, Option<EventNumber>), Error = PairedPoolError> {
+        let pool = self.clone();
+
+        self.acquire()
+            .map_err(PairedPoolError::Connection)
+            .and_then(|conn| {
+                conn.last_event_number(stream)
+                    .map_err(PairedPoolError::Request)
+            })
+            .map(move |(stream, number, conn)| {
+                pool.release(conn);
+                (stream, number)
+            })
+    }
+}
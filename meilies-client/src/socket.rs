@@ -0,0 +1,157 @@
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::num::ParseIntError;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::{error, fmt};
+
+use futures::Poll;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, UnixStream};
+
+/// The address of a server, either a TCP address, a host name
+/// resolved on each connection or the path of a unix socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerAddr {
+    Tcp(SocketAddr),
+    Host(String, u16),
+    Unix(PathBuf),
+}
+
+impl ServerAddr {
+    /// The address of a server that is resolved each time a connection is opened,
+    /// every resolved address is tried in order, see `resolve_and_connect`.
+    pub fn host<H: Into<String>>(host: H, port: u16) -> ServerAddr {
+        ServerAddr::Host(host.into(), port)
+    }
+}
+
+impl fmt::Display for ServerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServerAddr::Tcp(addr) => write!(f, "{}", addr),
+            ServerAddr::Host(host, port) if host.contains(':') => write!(f, "[{}]:{}", host, port),
+            ServerAddr::Host(host, port) => write!(f, "{}:{}", host, port),
+            ServerAddr::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseServerAddrError {
+    MissingPort,
+    InvalidPort(ParseIntError),
+}
+
+impl fmt::Display for ParseServerAddrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseServerAddrError::MissingPort => write!(f, "missing port"),
+            ParseServerAddrError::InvalidPort(e) => write!(f, "invalid port; {}", e),
+        }
+    }
+}
+
+impl error::Error for ParseServerAddrError {}
+
+/// Parse a `host:port` address, the host is kept to be resolved on each connection
+/// unless it is an IP address, an IPv6 host is written between brackets.
+impl FromStr for ServerAddr {
+    type Err = ParseServerAddrError;
+
+    fn from_str(s: &str) -> Result<ServerAddr, ParseServerAddrError> {
+        if let Ok(addr) = s.parse() {
+            return Ok(ServerAddr::Tcp(addr));
+        }
+
+        let colon = s.rfind(':').ok_or(ParseServerAddrError::MissingPort)?;
+        let (host, port) = (&s[..colon], &s[colon + 1..]);
+        let port = port.parse().map_err(ParseServerAddrError::InvalidPort)?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+
+        Ok(ServerAddr::host(host, port))
+    }
+}
+
+impl From<SocketAddr> for ServerAddr {
+    fn from(addr: SocketAddr) -> ServerAddr {
+        ServerAddr::Tcp(addr)
+    }
+}
+
+/// A socket connected to a server, either over TCP or a unix socket.
+pub enum Socket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(socket) => socket.read(buf),
+            Socket::Unix(socket) => socket.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(socket) => socket.write(buf),
+            Socket::Unix(socket) => socket.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Socket::Tcp(socket) => socket.flush(),
+            Socket::Unix(socket) => socket.flush(),
+        }
+    }
+}
+
+impl AsyncRead for Socket {}
+
+impl AsyncWrite for Socket {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match self {
+            Socket::Tcp(socket) => AsyncWrite::shutdown(socket),
+            Socket::Unix(socket) => AsyncWrite::shutdown(socket),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_server_addr() {
+        let addr: ServerAddr = "127.0.0.1:6480".parse().unwrap();
+        assert_eq!(
+            addr,
+            ServerAddr::Tcp(SocketAddr::from(([127, 0, 0, 1], 6480)))
+        );
+
+        let addr: ServerAddr = "localhost:6480".parse().unwrap();
+        assert_eq!(addr, ServerAddr::host("localhost", 6480));
+        assert_eq!(addr.to_string(), "localhost:6480");
+
+        let addr: ServerAddr = "[fe80::1%eth0]:6480".parse().unwrap();
+        assert_eq!(addr, ServerAddr::host("fe80::1%eth0", 6480));
+        assert_eq!(addr.to_string(), "[fe80::1%eth0]:6480");
+
+        for invalid in &[
+            "localhost",
+            "localhost:",
+            "localhost:port",
+            "localhost:65536",
+        ] {
+            assert!(
+                invalid.parse::<ServerAddr>().is_err(),
+                "{} was parsed",
+                invalid
+            );
+        }
+    }
+}
@@ -1,35 +1,103 @@
-use std::net::SocketAddr;
+use std::time::Duration;
 use std::{io, mem};
 
 use futures::{Async, AsyncSink, Future, Sink, Stream};
 use log::{error, info, warn};
-use meilies::reqresp::{Request, RequestMsgError, Response, ResponseMsgError};
+use meilies::reqresp::{Request, RequestMsgError, Response, ResponseMsgError, ServerError};
 use tokio_retry::Error as TrError;
 use tokio_retry::{strategy::FibonacciBackoff, Retry};
 
-use super::{connect, ClientConnection};
+use super::{connect_addr_with_nodelay, ClientConnection, ServerAddr};
+
+/// Whether a connection is usable or is being reestablished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    Reconnecting,
+    /// Every reconnection attempt failed, the connection is not retried anymore.
+    Disconnected,
+}
+
+/// How many times and how often a lost connection is retried,
+/// the delays between the attempts follow a Fibonacci backoff.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub first_delay: Duration,
+    pub max_retries: usize,
+}
+
+impl RetryPolicy {
+    /// The delays between the attempts.
+    pub fn strategy(&self) -> std::iter::Take<FibonacciBackoff> {
+        let millis = self.first_delay.as_millis() as u64;
+        FibonacciBackoff::from_millis(millis).take(self.max_retries)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            first_delay: Duration::from_millis(100),
+            max_retries: 50,
+        }
+    }
+}
 
 /// A connection that try to reconnect when disconnected.
 ///
 /// It will keep the stream states (e.g. the stream position).
 pub struct SteelConnection {
-    addr: SocketAddr,
+    addr: ServerAddr,
     reconnected: bool,
+    retry: RetryPolicy,
+    nodelay: bool,
     conn_state: ConnState,
+    on_state_change: Option<Box<dyn Fn(ConnectionState) + Send>>,
 }
 
 enum ConnState {
     Connected(ClientConnection),
     Connecting(Box<Future<Item = ClientConnection, Error = io::Error> + Send>),
+    Disconnected,
 }
 
 impl SteelConnection {
     /// Create a new steel connection.
-    pub fn new(addr: SocketAddr, connection: ClientConnection) -> SteelConnection {
+    pub fn new(addr: ServerAddr, connection: ClientConnection) -> SteelConnection {
+        SteelConnection::with_retry(addr, connection, RetryPolicy::default())
+    }
+
+    /// Create a new steel connection that gives up reconnecting
+    /// once the attempts of the retry policy are exhausted.
+    pub fn with_retry(
+        addr: ServerAddr,
+        connection: ClientConnection,
+        retry: RetryPolicy,
+    ) -> SteelConnection {
         SteelConnection {
             addr,
             reconnected: false,
+            retry,
+            nodelay: true,
             conn_state: ConnState::Connected(connection),
+            on_state_change: None,
+        }
+    }
+
+    /// Set `TCP_NODELAY` on the connections opened to reconnect, enabled by default.
+    pub fn nodelay(mut self, nodelay: bool) -> SteelConnection {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Drop the current connection and start reconnecting,
+    /// used when the server is considered dead.
+    pub fn reconnect(&mut self) {
+        let was_reconnecting = self.connection_state() == ConnectionState::Reconnecting;
+        let retry = retry_future(self.addr.clone(), self.retry, self.nodelay);
+        self.conn_state = ConnState::Connecting(retry);
+        if !was_reconnecting {
+            self.notify(ConnectionState::Reconnecting);
         }
     }
 
@@ -37,19 +105,66 @@ impl SteelConnection {
     pub fn has_been_reconnected(&mut self) -> bool {
         mem::replace(&mut self.reconnected, false)
     }
+
+    /// Returns whether the connection is currently usable or being reestablished.
+    pub fn connection_state(&self) -> ConnectionState {
+        match self.conn_state {
+            ConnState::Connected(_) => ConnectionState::Connected,
+            ConnState::Connecting(_) => ConnectionState::Reconnecting,
+            ConnState::Disconnected => ConnectionState::Disconnected,
+        }
+    }
+
+    /// Call `f` every time the connection is lost or reestablished,
+    /// it replaces the previously registered function.
+    pub fn on_state_change<F>(&mut self, f: F)
+    where
+        F: Fn(ConnectionState) + Send + 'static,
+    {
+        self.on_state_change = Some(Box::new(f));
+    }
+
+    fn notify(&self, state: ConnectionState) {
+        if let Some(f) = &self.on_state_change {
+            f(state);
+        }
+    }
+
+    fn set_connected(&mut self, connection: ClientConnection) {
+        info!("Successfully reconnected to {}", self.addr);
+        self.reconnected = true;
+        self.conn_state = ConnState::Connected(connection);
+        self.notify(ConnectionState::Connected);
+    }
+
+    fn set_disconnected(&mut self, error: &io::Error) {
+        error!("Giving up reconnecting to {}; {}", self.addr, error);
+        self.conn_state = ConnState::Disconnected;
+        self.notify(ConnectionState::Disconnected);
+    }
+}
+
+/// The error returned when sending on a connection that gave up reconnecting.
+fn disconnected_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotConnected,
+        "reconnection attempts exhausted",
+    )
 }
 
-/// The retry strategy used to reconnect.
+/// The retry strategy used to reconnect by default.
 pub fn retry_strategy() -> std::iter::Take<FibonacciBackoff> {
-    FibonacciBackoff::from_millis(100).take(50)
+    RetryPolicy::default().strategy()
 }
 
 fn retry_future(
-    addr: SocketAddr,
+    addr: ServerAddr,
+    retry: RetryPolicy,
+    nodelay: bool,
 ) -> Box<Future<Item = ClientConnection, Error = io::Error> + Send> {
-    let retry = Retry::spawn(retry_strategy(), move || {
+    let retry = Retry::spawn(retry.strategy(), move || {
         warn!("Reconnecting to {}", addr);
-        connect(&addr)
+        connect_addr_with_nodelay(&addr, nodelay)
     })
     .map_err(|error| match error {
         TrError::OperationError(e) => e,
@@ -60,7 +175,7 @@ fn retry_future(
 }
 
 impl Stream for SteelConnection {
-    type Item = Result<Response, String>;
+    type Item = Result<Response, ServerError>;
     type Error = ResponseMsgError;
 
     fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
@@ -68,7 +183,7 @@ impl Stream for SteelConnection {
             ConnState::Connected(connection) => match connection.poll() {
                 Ok(Async::Ready(None)) => {
                     error!("Connection closed with {}", self.addr);
-                    self.conn_state = ConnState::Connecting(retry_future(self.addr));
+                    self.reconnect();
                     self.poll()
                 }
                 Err(error) => {
@@ -78,7 +193,7 @@ impl Stream for SteelConnection {
                     match error {
                         RespMsgError(IoError(e)) => {
                             error!("Connection error with {}; {}", self.addr, e);
-                            self.conn_state = ConnState::Connecting(retry_future(self.addr));
+                            self.reconnect();
                             self.poll()
                         }
                         otherwise => Err(otherwise),
@@ -88,14 +203,16 @@ impl Stream for SteelConnection {
             },
             ConnState::Connecting(connect) => match connect.poll() {
                 Ok(Async::Ready(connection)) => {
-                    info!("Successfully reconnected to {}", self.addr);
-                    self.reconnected = true;
-                    self.conn_state = ConnState::Connected(connection);
+                    self.set_connected(connection);
                     self.poll()
                 }
                 Ok(Async::NotReady) => Ok(Async::NotReady),
-                Err(error) => Err(error.into()),
+                Err(error) => {
+                    self.set_disconnected(&error);
+                    Err(error.into())
+                }
             },
+            ConnState::Disconnected => Ok(Async::Ready(None)),
         }
     }
 }
@@ -116,14 +233,16 @@ impl Sink for SteelConnection {
             }
             ConnState::Connecting(connect) => match connect.poll() {
                 Ok(Async::Ready(connection)) => {
-                    info!("Successfully reconnected to {}", self.addr);
-                    self.reconnected = true;
-                    self.conn_state = ConnState::Connected(connection);
+                    self.set_connected(connection);
                     self.start_send(item)
                 }
                 Ok(Async::NotReady) => Ok(AsyncSink::NotReady(item)),
-                Err(error) => Err(error.into()),
+                Err(error) => {
+                    self.set_disconnected(&error);
+                    Err(error.into())
+                }
             },
+            ConnState::Disconnected => Err(disconnected_error().into()),
         }
     }
 
@@ -137,7 +256,7 @@ impl Sink for SteelConnection {
                     match error {
                         RespMsgError(IoError(e)) => {
                             error!("Connection error with {}; {}", self.addr, e);
-                            self.conn_state = ConnState::Connecting(retry_future(self.addr));
+                            self.reconnect();
                             self.poll_complete()
                         }
                         otherwise => Err(otherwise),
@@ -147,14 +266,117 @@ impl Sink for SteelConnection {
             },
             ConnState::Connecting(connect) => match connect.poll() {
                 Ok(Async::Ready(connection)) => {
-                    info!("Successfully reconnected to {}", self.addr);
-                    self.reconnected = true;
-                    self.conn_state = ConnState::Connected(connection);
+                    self.set_connected(connection);
                     self.poll_complete()
                 }
                 Ok(Async::NotReady) => Ok(Async::NotReady),
-                Err(error) => Err(error.into()),
+                Err(error) => {
+                    self.set_disconnected(&error);
+                    Err(error.into())
+                }
             },
+            ConnState::Disconnected => Err(disconnected_error().into()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use futures::future;
+    use tokio::net::TcpListener;
+    use tokio::prelude::FutureExt;
+
+    use crate::connect_tcp;
+
+    #[test]
+    fn observe_a_disconnection() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // the server closes the first connection and keeps the second one
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(first, incoming)| {
+                drop(first);
+                incoming.into_future().map_err(|(e, _)| e)
+            })
+            .map(|(second, _)| second);
+
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let client = {
+            let states = states.clone();
+            connect_tcp(&addr, true).and_then(move |connection| {
+                let mut steel = SteelConnection::new(ServerAddr::Tcp(addr), connection);
+                assert_eq!(steel.connection_state(), ConnectionState::Connected);
+
+                let observed = states.clone();
+                steel.on_state_change(move |state| observed.lock().unwrap().push(state));
+
+                future::poll_fn(move || {
+                    steel
+                        .poll()
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+                    match states.lock().unwrap().len() {
+                        2 => Ok(Async::Ready(steel.connection_state())),
+                        _ => Ok(Async::NotReady),
+                    }
+                })
+            })
+        };
+
+        let fut = server.join(client).timeout(Duration::from_secs(5));
+        let (_second, state) = runtime.block_on(fut).unwrap();
+
+        assert_eq!(state, ConnectionState::Connected);
+        let expected = vec![ConnectionState::Reconnecting, ConnectionState::Connected];
+        assert_eq!(*states.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn give_up_after_the_retries() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // the server accepts a single connection and stops listening
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .map(|(first, _)| drop(first));
+
+        let retry = RetryPolicy {
+            first_delay: Duration::from_millis(10),
+            max_retries: 2,
+        };
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let client = {
+            let states = states.clone();
+            connect_tcp(&addr, true).and_then(move |connection| {
+                let mut steel =
+                    SteelConnection::with_retry(ServerAddr::Tcp(addr), connection, retry);
+                steel.on_state_change(move |state| states.lock().unwrap().push(state));
+
+                future::poll_fn(move || match steel.poll() {
+                    Ok(Async::NotReady) => Ok(Async::NotReady),
+                    Ok(Async::Ready(_)) => panic!("the connection must not end without an error"),
+                    Err(_) => Ok(Async::Ready(steel.poll())),
+                })
+            })
+        };
+
+        let fut = server.join(client).timeout(Duration::from_secs(5));
+        let (_, after_error) = runtime.block_on(fut).unwrap();
+
+        assert!(matches!(after_error, Ok(Async::Ready(None))));
+        let expected = vec![ConnectionState::Reconnecting, ConnectionState::Disconnected];
+        assert_eq!(*states.lock().unwrap(), expected);
+    }
+}
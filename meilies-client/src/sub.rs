@@ -1,23 +1,219 @@
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::{fmt, io};
 
 use futures::stream::SplitStream;
-use futures::{Async, AsyncSink, Future, Poll, Sink, Stream};
+use futures::task::AtomicTask;
+use futures::{try_ready, Async, AsyncSink, Future, Poll, Sink, Stream};
 use log::{error, warn};
-use meilies::reqresp::{Request, RequestMsgError, Response, ResponseMsgError};
+use meilies::reqresp::{Request, RequestMsgError, Response, ResponseMsgError, ServerError};
 use meilies::resp::RespMsgError;
-use meilies::stream::{Stream as EsStream, StreamName};
+use meilies::stream::ALL_STREAMS_PREFIX;
+use meilies::stream::{EventData, EventNumber, ReadRange, Stream as EsStream, StreamName};
 use tokio::sync::mpsc;
+use tokio::timer::Interval;
 use tokio_retry::Retry;
 
-use super::{connect, retry_strategy, SteelConnection};
+use super::{connect_addr_with_nodelay, ConnectionState, RetryPolicy, ServerAddr, SteelConnection};
+
+/// The keepalive configuration of a sub connection.
+///
+/// A ping is sent to the server at each interval, if no message is received
+/// during `max_missed` intervals the connection is considered dead and is reestablished.
+/// It detects half-open connections that the TCP keepalive does not.
+#[derive(Debug, Clone, Copy)]
+pub struct KeepAlive {
+    pub interval: Duration,
+    pub max_missed: u32,
+}
+
+impl Default for KeepAlive {
+    fn default() -> KeepAlive {
+        KeepAlive {
+            interval: Duration::from_secs(20),
+            max_missed: 3,
+        }
+    }
+}
+
+/// What happens to the events published while a sub connection is reconnecting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// The streams are resumed right after the last event delivered, no event is lost.
+    ///
+    /// An event can be delivered twice if the connection is lost between the moment
+    /// it is handled and the moment it is received, `SubStream::dedup` filters them.
+    AtLeastOnce,
+    /// The streams are resumed from their end, the events published during
+    /// the disconnection are lost but none is delivered twice.
+    ///
+    /// Bounded ranges are always resumed right after the last event delivered,
+    /// reading from the end would make them unbounded.
+    AtMostOnce,
+}
+
+impl Default for Delivery {
+    fn default() -> Delivery {
+        Delivery::AtLeastOnce
+    }
+}
+
+/// The configuration of a sub connection.
+#[derive(Debug, Clone, Copy)]
+pub struct SubConnectConfig {
+    keepalive: KeepAlive,
+    delivery: Delivery,
+    retry: RetryPolicy,
+    nodelay: bool,
+}
+
+impl Default for SubConnectConfig {
+    fn default() -> SubConnectConfig {
+        SubConnectConfig {
+            keepalive: KeepAlive::default(),
+            delivery: Delivery::default(),
+            retry: RetryPolicy::default(),
+            nodelay: true,
+        }
+    }
+}
+
+impl SubConnectConfig {
+    pub fn new() -> SubConnectConfig {
+        SubConnectConfig::default()
+    }
+
+    /// Specify how to detect a dead connection.
+    pub fn keepalive(mut self, keepalive: KeepAlive) -> SubConnectConfig {
+        self.keepalive = keepalive;
+        self
+    }
+
+    /// Specify from where the streams are resumed after a reconnection,
+    /// the default is `Delivery::AtLeastOnce`.
+    pub fn delivery(mut self, delivery: Delivery) -> SubConnectConfig {
+        self.delivery = delivery;
+        self
+    }
+
+    /// Specify how many times the connection is retried before the `SubStream`
+    /// ends with an error, the default is 50 attempts starting after 100ms.
+    pub fn retry(mut self, retry: RetryPolicy) -> SubConnectConfig {
+        self.retry = retry;
+        self
+    }
+
+    /// Set `TCP_NODELAY` on the TCP connections, enabled by default, the events
+    /// and the acknowledgements are not held back by Nagle's algorithm.
+    pub fn nodelay(mut self, nodelay: bool) -> SubConnectConfig {
+        self.nodelay = nodelay;
+        self
+    }
+}
 
 #[derive(Debug, Default)]
 struct StreamContext {
     reconnected: bool,
     position_start: Option<u64>,
     position_end: Option<u64>,
+    from_time: Option<u64>,
+    history_only: bool,
+    paused: bool,
+}
+
+impl StreamContext {
+    /// Remember the range requested by a new subscription to this stream.
+    fn subscribed(&mut self, range: ReadRange, from_time: Option<u64>) {
+        self.position_start = range.from();
+        self.position_end = range.to();
+        self.from_time = from_time;
+    }
+
+    /// Remember that an event has been delivered, a resubscription will start right after it.
+    ///
+    /// This is also true for streams that were subscribed from the end: once an event
+    /// has been received we know where to restart and the events sent during the gap
+    /// must not be lost.
+    fn delivered(&mut self, number: EventNumber) {
+        self.position_start = Some(number.0 + 1);
+        self.from_time = None;
+    }
+
+    /// Remember that the server sent every event of a history only subscription.
+    fn ended(&mut self, number: EventNumber) {
+        self.position_start = Some(number.0);
+        self.position_end = Some(number.0);
+    }
+
+    /// Returns `true` if every event of a bounded range has already been delivered.
+    fn is_complete(&self) -> bool {
+        match (self.position_start, self.position_end) {
+            (Some(start), Some(end)) => start >= end,
+            _ => false,
+        }
+    }
+
+    /// The stream to subscribe to when the connection has been reestablished.
+    ///
+    /// A subscription started from a time that did not receive any event yet is resumed
+    /// from the same time, the server resolves it again. A history only subscription
+    /// that did not end yet reads until the end of the stream at the time it is resumed.
+    fn resume_stream(&self, name: StreamName, delivery: Delivery) -> EsStream {
+        match (delivery, self.position_end) {
+            (Delivery::AtMostOnce, None) if !self.history_only => {
+                EsStream::new(name, ReadRange::ReadFromEnd)
+            }
+            (_, _) => EsStream {
+                from_time: self.from_time,
+                history_only: self.history_only,
+                ..EsStream::new_from_to(name, self.position_start, self.position_end)
+            },
+        }
+    }
+}
+
+/// Counters updated as a sub connection receives messages, see `SubStream::metrics`.
+///
+/// The counters are shared with the connection, they can be read from another
+/// thread to export the throughput of a consumer without wrapping its stream.
+#[derive(Debug, Clone, Default)]
+pub struct SubMetrics(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    events: AtomicU64,
+    event_bytes: AtomicU64,
+    reconnects: AtomicU64,
+}
+
+impl SubMetrics {
+    /// The number of events received, including those sent again after a reconnection.
+    pub fn events(&self) -> u64 {
+        self.0.events.load(Ordering::Relaxed)
+    }
+
+    /// The number of bytes of event data received.
+    pub fn event_bytes(&self) -> u64 {
+        self.0.event_bytes.load(Ordering::Relaxed)
+    }
+
+    /// The number of times the connection has been reestablished.
+    pub fn reconnects(&self) -> u64 {
+        self.0.reconnects.load(Ordering::Relaxed)
+    }
+
+    fn event_received(&self, data: &EventData) {
+        self.0.events.fetch_add(1, Ordering::Relaxed);
+        let bytes = data.len() as u64;
+        self.0.event_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn reconnected(&self) {
+        self.0.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 /// A tokio Stream that reconnect when the connection is lost.
@@ -26,24 +222,92 @@ struct StreamContext {
 pub struct EventStream {
     state: HashMap<StreamName, StreamContext>,
     connection: SteelConnection,
+    keepalive: KeepAlive,
+    delivery: Delivery,
+    interval: Interval,
+    last_message: Instant,
+    /// A ping could not be sent because the connection was busy, it is sent at the next poll.
+    ping_pending: bool,
+    metrics: SubMetrics,
 }
 
 impl EventStream {
     fn connect(
-        addr: SocketAddr,
+        addr: ServerAddr,
+        config: SubConnectConfig,
     ) -> impl Future<Item = EventStream, Error = tokio_retry::Error<io::Error>> {
-        Retry::spawn(retry_strategy(), move || {
+        let SubConnectConfig {
+            keepalive,
+            delivery,
+            retry,
+            nodelay,
+        } = config;
+
+        Retry::spawn(retry.strategy(), move || {
             warn!("Connecting to {}", addr);
-            connect(&addr).map(move |connection| {
-                let connection = SteelConnection::new(addr, connection);
+            let addr = addr.clone();
+            connect_addr_with_nodelay(&addr, nodelay).map(move |connection| {
+                let connection = SteelConnection::with_retry(addr, connection, retry);
+                let connection = connection.nodelay(nodelay);
+                let start = Instant::now() + keepalive.interval;
                 EventStream {
                     state: HashMap::new(),
                     connection,
+                    keepalive,
+                    delivery,
+                    interval: Interval::new(start, keepalive.interval),
+                    last_message: Instant::now(),
+                    ping_pending: false,
+                    metrics: SubMetrics::default(),
                 }
             })
         })
     }
 
+    /// Send a ping, the server answers with a pong that is not returned to the user,
+    /// it only proves that the connection is alive.
+    fn send_ping(&mut self) -> Result<(), ProtocolError> {
+        let sent = self
+            .connection
+            .start_send(Request::Ping)
+            .map_err(ProtocolError::RequestMsgError)?;
+        self.ping_pending = sent.is_not_ready();
+
+        self.connection
+            .poll_complete()
+            .map_err(ProtocolError::RequestMsgError)?;
+
+        Ok(())
+    }
+
+    fn poll_keepalive(&mut self) -> Result<(), ProtocolError> {
+        if self.ping_pending {
+            self.send_ping()?;
+        }
+
+        loop {
+            match self.interval.poll() {
+                Ok(Async::Ready(Some(_))) => (),
+                Ok(_) => return Ok(()),
+                Err(e) => {
+                    let error = RespMsgError::IoError(io::Error::new(io::ErrorKind::Other, e));
+                    return Err(ProtocolError::ResponseMsgError(
+                        ResponseMsgError::RespMsgError(error),
+                    ));
+                }
+            }
+
+            let timeout = self.keepalive.interval * self.keepalive.max_missed;
+            if self.last_message.elapsed() >= timeout {
+                warn!("No message received since {:.2?}, reconnecting", timeout);
+                self.connection.reconnect();
+                self.last_message = Instant::now();
+            } else {
+                self.send_ping()?;
+            }
+        }
+    }
+
     fn send_stream_subscriptions(&mut self) -> Result<(), ProtocolError> {
         // Now that a new connection has been successfully established
         // we can re-send our subscriptions with the appropriate event number.
@@ -52,16 +316,42 @@ impl EventStream {
 
         for (name, context) in &mut self.state {
             context.reconnected = true;
-            let stream = EsStream::new_from_to(
-                name.clone(),
-                context.position_start.into(),
-                context.position_end.into(),
-            );
-            streams.push(stream);
+            if !context.is_complete() {
+                streams.push(context.resume_stream(name.clone(), self.delivery));
+            }
+        }
+
+        if streams.is_empty() {
+            return Ok(());
         }
 
-        let subscription = Request::Subscribe { streams };
-        self.start_send(subscription)?;
+        // the streams paused on the previous connection stay paused
+        let paused: Vec<_> = streams
+            .iter()
+            .filter(|stream| self.state.get(&stream.name).map_or(false, |c| c.paused))
+            .map(|stream| stream.name.clone())
+            .collect();
+
+        // the server only accepts a `$all.<prefix>` stream alone in its subscription
+        let (prefixes, streams): (Vec<_>, Vec<_>) = streams
+            .into_iter()
+            .partition(|stream| stream.name.as_str().starts_with(ALL_STREAMS_PREFIX));
+
+        let subscriptions = prefixes
+            .into_iter()
+            .map(|stream| vec![stream])
+            .chain(Some(streams).filter(|streams| !streams.is_empty()));
+
+        for streams in subscriptions {
+            let subscription = Request::Subscribe {
+                streams,
+                require_existing: false,
+            };
+            self.start_send(subscription)?;
+        }
+        for stream in paused {
+            self.start_send(Request::Pause { stream })?;
+        }
         self.poll_complete()?;
 
         Ok(())
@@ -69,16 +359,32 @@ impl EventStream {
 }
 
 impl Stream for EventStream {
-    type Item = Result<Response, String>;
+    type Item = Result<Response, ServerError>;
     type Error = ProtocolError;
 
     fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        self.poll_keepalive()?;
+
         let result = match self.connection.poll() {
             Ok(Async::Ready(Some(item))) => {
+                self.last_message = Instant::now();
+
                 match &item {
-                    Ok(Response::Event { stream, number, .. }) => {
-                        self.state.entry(stream.clone()).or_default().position_start =
-                            Some(number.0 + 1);
+                    Ok(Response::Pong) => return self.poll(),
+                    Ok(Response::Event {
+                        stream,
+                        number,
+                        event_data,
+                        ..
+                    }) => {
+                        self.metrics.event_received(event_data);
+                        self.state
+                            .entry(stream.clone())
+                            .or_default()
+                            .delivered(*number);
+                    }
+                    Ok(Response::EndOfStream { stream, number }) => {
+                        self.state.entry(stream.clone()).or_default().ended(*number);
                     }
                     Ok(Response::Subscribed { stream }) => {
                         // if we were already subscribed to a stream and we are reconnecting
@@ -111,11 +417,21 @@ impl Sink for EventStream {
         &mut self,
         item: Self::SinkItem,
     ) -> Result<AsyncSink<Self::SinkItem>, Self::SinkError> {
-        if let Request::Subscribe { streams } = &item {
-            for EsStream { name, range } in streams {
-                self.state.entry(name.clone()).or_default().position_start = range.from();
-                self.state.entry(name.clone()).or_default().position_end = range.to();
+        match &item {
+            Request::Subscribe { streams, .. } => {
+                for stream in streams {
+                    let context = self.state.entry(stream.name.clone()).or_default();
+                    context.subscribed(stream.range, stream.from_time);
+                    context.history_only = stream.history_only;
+                }
             }
+            Request::Pause { stream } => {
+                self.state.entry(stream.clone()).or_default().paused = true
+            }
+            Request::Resume { stream } => {
+                self.state.entry(stream.clone()).or_default().paused = false
+            }
+            _otherwise => (),
         }
 
         let result = self.connection.start_send(item);
@@ -139,12 +455,56 @@ impl Sink for EventStream {
 }
 
 /// Open a sup connection with a server.
-pub fn sub_connect(
-    addr: SocketAddr,
+///
+/// A `ServerAddr::Host` is resolved on each connection and
+/// every address it resolves to is tried, see `resolve_and_connect`.
+pub fn sub_connect<A: Into<ServerAddr>>(
+    addr: A,
+) -> impl Future<Item = (SubController, SubStream), Error = tokio_retry::Error<io::Error>> {
+    sub_connect_to(addr.into(), SubConnectConfig::default())
+}
+
+/// Open a sup connection with a server, specifying how to detect a dead connection.
+pub fn sub_connect_with_keepalive<A: Into<ServerAddr>>(
+    addr: A,
+    keepalive: KeepAlive,
+) -> impl Future<Item = (SubController, SubStream), Error = tokio_retry::Error<io::Error>> {
+    sub_connect_to(addr.into(), SubConnectConfig::new().keepalive(keepalive))
+}
+
+/// Open a sup connection with a server using the given configuration.
+pub fn sub_connect_with_config<A: Into<ServerAddr>>(
+    addr: A,
+    config: SubConnectConfig,
 ) -> impl Future<Item = (SubController, SubStream), Error = tokio_retry::Error<io::Error>> {
-    EventStream::connect(addr)
+    sub_connect_to(addr.into(), config)
+}
+
+/// Open a sup connection with a server listening on a unix socket.
+pub fn sub_connect_unix<P: Into<PathBuf>>(
+    path: P,
+) -> impl Future<Item = (SubController, SubStream), Error = tokio_retry::Error<io::Error>> {
+    sub_connect_to(ServerAddr::Unix(path.into()), SubConnectConfig::default())
+}
+
+fn sub_connect_to(
+    addr: ServerAddr,
+    config: SubConnectConfig,
+) -> impl Future<Item = (SubController, SubStream), Error = tokio_retry::Error<io::Error>> {
+    EventStream::connect(addr, config)
         .map_err(|e| dbg!(e))
-        .map(|connection| {
+        .map(|mut connection| {
+            let state = Arc::new(Mutex::new(ConnectionState::Connected));
+            let observed = state.clone();
+            let metrics = connection.metrics.clone();
+            let observed_metrics = metrics.clone();
+            connection.connection.on_state_change(move |new_state| {
+                if new_state == ConnectionState::Connected {
+                    observed_metrics.reconnected();
+                }
+                *observed.lock().unwrap() = new_state;
+            });
+
             let (writer, reader) = connection.split();
             let (sender, receiver) = mpsc::unbounded_channel();
 
@@ -153,24 +513,73 @@ pub fn sub_connect(
                     let error = RespMsgError::IoError(io::Error::new(io::ErrorKind::BrokenPipe, e));
                     ProtocolError::RequestMsgError(RequestMsgError::RespMsgError(error))
                 })
-                .map(Into::into)
+                .take_while(|command: &Command| Ok(!command.is_close()))
+                .filter_map(|command| match command {
+                    Command::Request(request) => Some(request),
+                    Command::Close => None,
+                })
                 .forward(writer)
                 .map_err(|e| error!("{:?}", e))
                 .map(|_| ());
 
             tokio::spawn(x);
 
-            let controller = SubController { sender };
-            let sub_stream = SubStream { connection: reader };
+            let closed = Arc::new(Closed::default());
+            let controller = SubController {
+                sender,
+                closed: closed.clone(),
+            };
+            let sub_stream = SubStream {
+                connection: Some(reader),
+                closed,
+                state,
+                metrics,
+            };
 
             (controller, sub_stream)
         })
 }
 
+/// The commands sent by a `SubController` to the connection task.
+#[derive(Debug)]
+enum Command {
+    Request(Request),
+    Close,
+}
+
+impl Command {
+    fn is_close(&self) -> bool {
+        match self {
+            Command::Close => true,
+            Command::Request(_) => false,
+        }
+    }
+}
+
+/// Shared by a `SubController` and its `SubStream`, set once the controller is closed.
+#[derive(Default)]
+struct Closed {
+    flag: AtomicBool,
+    task: AtomicTask,
+}
+
+impl Closed {
+    fn close(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+        self.task.notify();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.task.register();
+        self.flag.load(Ordering::SeqCst)
+    }
+}
+
 /// A sub controller control which streams to connect to.
 #[derive(Clone)]
 pub struct SubController {
-    sender: mpsc::UnboundedSender<Request>,
+    sender: mpsc::UnboundedSender<Command>,
+    closed: Arc<Closed>,
 }
 
 impl SubController {
@@ -178,17 +587,93 @@ impl SubController {
     pub fn subscribe_to(&mut self, stream: EsStream) {
         let command = Request::Subscribe {
             streams: vec![stream],
+            require_existing: false,
+        };
+
+        if let Err(e) = self.sender.try_send(Command::Request(command)) {
+            error!("{}", e);
+        }
+    }
+
+    /// Ask the server to send events of the given stream,
+    /// the server answers with an error if the stream does not contain any event.
+    pub fn subscribe_to_existing(&mut self, stream: EsStream) {
+        let command = Request::Subscribe {
+            streams: vec![stream],
+            require_existing: true,
         };
 
-        if let Err(e) = self.sender.try_send(command) {
+        if let Err(e) = self.sender.try_send(Command::Request(command)) {
             error!("{}", e);
         }
     }
+
+    /// Ask the server to send events of every stream whose name starts with the prefix,
+    /// including the streams created later on.
+    ///
+    /// After a reconnection the streams already seen are resumed
+    /// but those created during the disconnection are not.
+    pub fn subscribe_prefix(&mut self, prefix: String, range: ReadRange) {
+        let command = Request::SubscribePrefix { prefix, range };
+
+        if let Err(e) = self.sender.try_send(Command::Request(command)) {
+            error!("{}", e);
+        }
+    }
+
+    /// Ask the server to send the events of every stream in the order they were published,
+    /// the range is a range of global numbers, the server must be started with global ordering.
+    ///
+    /// After a reconnection the streams already seen are resumed one by one,
+    /// the global order is not kept across the reconnection.
+    pub fn subscribe_all_ordered(&mut self, range: ReadRange) {
+        let command = Request::SubscribeAll {
+            range,
+            ordered: true,
+        };
+
+        if let Err(e) = self.sender.try_send(Command::Request(command)) {
+            error!("{}", e);
+        }
+    }
+
+    /// Ask the server to stop sending the events of a stream without ending its subscription,
+    /// the server answers with `Response::Ok` and the stream stays paused after a reconnection.
+    pub fn pause(&mut self, stream: StreamName) {
+        let command = Request::Pause { stream };
+
+        if let Err(e) = self.sender.try_send(Command::Request(command)) {
+            error!("{}", e);
+        }
+    }
+
+    /// Ask the server to send the events of a paused stream again, from where it stopped.
+    pub fn resume(&mut self, stream: StreamName) {
+        let command = Request::Resume { stream };
+
+        if let Err(e) = self.sender.try_send(Command::Request(command)) {
+            error!("{}", e);
+        }
+    }
+
+    /// Stop sending requests and close the connection,
+    /// the `SubStream` ends once it has released its half of the connection.
+    pub fn close(&mut self) {
+        if let Err(e) = self.sender.try_send(Command::Close) {
+            error!("{}", e);
+        }
+        self.closed.close();
+    }
 }
 
 /// A tokio Stream that returns every event received on all subscribed streams.
 pub struct SubStream {
-    connection: SplitStream<EventStream>,
+    /// Released when the controller is closed, the connection is dropped
+    /// with the half owned by the connection task.
+    connection: Option<SplitStream<EventStream>>,
+    closed: Arc<Closed>,
+    state: Arc<Mutex<ConnectionState>>,
+    metrics: SubMetrics,
 }
 
 #[derive(Debug)]
@@ -207,10 +692,457 @@ impl fmt::Display for ProtocolError {
 }
 
 impl Stream for SubStream {
-    type Item = Result<Response, String>;
+    type Item = Result<Response, ServerError>;
     type Error = ProtocolError;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        self.connection.poll()
+        if self.closed.is_closed() {
+            self.connection = None;
+        }
+
+        match &mut self.connection {
+            Some(connection) => connection.poll(),
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+impl SubStream {
+    /// Returns whether the connection is currently usable or being reestablished,
+    /// useful to show that the events may be late while reconnecting.
+    pub fn connection_state(&self) -> ConnectionState {
+        *self.state.lock().unwrap()
+    }
+
+    /// The counters of the events received and of the reconnections,
+    /// the returned handle keeps being updated as the stream is polled.
+    pub fn metrics(&self) -> SubMetrics {
+        self.metrics.clone()
+    }
+
+    /// Drop the events whose number is not greater than the last one delivered for
+    /// their stream, the events of each stream are then returned in increasing order
+    /// even if the server sends some of them again after a reconnection.
+    pub fn dedup(self) -> Dedup<SubStream> {
+        Dedup::new(self)
+    }
+
+    /// End the stream once the server sent `Response::AllCaughtUp`, every stream of
+    /// a bounded `$all` subscription has then sent its historical events.
+    pub fn drain_to_all_caught_up(self) -> DrainToAllCaughtUp<SubStream> {
+        DrainToAllCaughtUp::new(self)
+    }
+}
+
+/// A stream that ends after `Response::AllCaughtUp`, see `SubStream::drain_to_all_caught_up`.
+pub struct DrainToAllCaughtUp<S> {
+    stream: S,
+    done: bool,
+}
+
+impl<S> DrainToAllCaughtUp<S> {
+    pub fn new(stream: S) -> DrainToAllCaughtUp<S> {
+        DrainToAllCaughtUp {
+            stream,
+            done: false,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S> Stream for DrainToAllCaughtUp<S>
+where
+    S: Stream<Item = Result<Response, ServerError>>,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.done {
+            return Ok(Async::Ready(None));
+        }
+
+        match try_ready!(self.stream.poll()) {
+            Some(Ok(Response::AllCaughtUp)) | None => {
+                self.done = true;
+                Ok(Async::Ready(None))
+            }
+            item => Ok(Async::Ready(item)),
+        }
+    }
+}
+
+/// A stream that drops the events that were already delivered, see `SubStream::dedup`.
+pub struct Dedup<S> {
+    stream: S,
+    last_numbers: HashMap<StreamName, EventNumber>,
+}
+
+impl<S> Dedup<S> {
+    pub fn new(stream: S) -> Dedup<S> {
+        Dedup {
+            stream,
+            last_numbers: HashMap::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S> Stream for Dedup<S>
+where
+    S: Stream<Item = Result<Response, ServerError>>,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let item = try_ready!(self.stream.poll());
+
+            if let Some(Ok(Response::Event { stream, number, .. })) = &item {
+                match self.last_numbers.get_mut(stream) {
+                    Some(last) if number <= last => continue,
+                    Some(last) => *last = *number,
+                    None => {
+                        self.last_numbers.insert(stream.clone(), *number);
+                    }
+                }
+            }
+
+            return Ok(Async::Ready(item));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use meilies::reqresp::ServerCodec;
+    use meilies::stream::{EventData, EventName};
+    use tokio::codec::Decoder;
+    use tokio::net::TcpListener;
+    use tokio::prelude::FutureExt;
+
+    #[test]
+    fn resume_read_from_end_after_event() {
+        let name = StreamName::new("from-end".to_owned()).unwrap();
+        let mut context = StreamContext::default();
+
+        context.subscribed(ReadRange::ReadFromEnd, None);
+        let stream = context.resume_stream(name.clone(), Delivery::AtLeastOnce);
+        assert_eq!(stream.range, ReadRange::ReadFromEnd);
+
+        context.delivered(EventNumber(3));
+        context.delivered(EventNumber(4));
+
+        // the connection is lost here, the resubscription must not re-tail the stream
+        let stream = context.resume_stream(name, Delivery::AtLeastOnce);
+        assert_eq!(stream.range, ReadRange::ReadFrom(5));
+    }
+
+    #[test]
+    fn resume_bounded_range() {
+        let name = StreamName::new("bounded".to_owned()).unwrap();
+        let mut context = StreamContext::default();
+
+        context.subscribed(ReadRange::ReadFromUntil(2, 6), None);
+        context.delivered(EventNumber(2));
+        context.delivered(EventNumber(3));
+
+        // already delivered events are not requested again and the upper bound is kept
+        let stream = context.resume_stream(name.clone(), Delivery::AtLeastOnce);
+        assert_eq!(stream.range, ReadRange::ReadFromUntil(4, 6));
+        assert!(!context.is_complete());
+
+        context.delivered(EventNumber(4));
+        context.delivered(EventNumber(5));
+
+        // the last event of the range has been delivered, nothing to resubscribe to
+        assert!(context.is_complete());
+    }
+
+    #[test]
+    fn resume_at_most_once() {
+        let name = StreamName::new("at-most-once".to_owned()).unwrap();
+        let mut context = StreamContext::default();
+
+        context.subscribed(ReadRange::ReadFrom(0), None);
+        context.delivered(EventNumber(0));
+        context.delivered(EventNumber(1));
+
+        // the events published during the disconnection are skipped
+        let stream = context.resume_stream(name.clone(), Delivery::AtMostOnce);
+        assert_eq!(stream.range, ReadRange::ReadFromEnd);
+
+        let stream = context.resume_stream(name.clone(), Delivery::AtLeastOnce);
+        assert_eq!(stream.range, ReadRange::ReadFrom(2));
+
+        // a bounded range keeps its end whatever the delivery mode
+        context.subscribed(ReadRange::ReadFromUntil(0, 10), None);
+        context.delivered(EventNumber(3));
+        let stream = context.resume_stream(name, Delivery::AtMostOnce);
+        assert_eq!(stream.range, ReadRange::ReadFromUntil(4, 10));
+    }
+
+    #[test]
+    fn resume_from_time() {
+        let name = StreamName::new("since".to_owned()).unwrap();
+        let mut context = StreamContext::default();
+
+        // the time is resolved again until an event is delivered
+        context.subscribed(ReadRange::ReadFrom(0), Some(1_500_000_000_000));
+        let stream = context.resume_stream(name.clone(), Delivery::AtLeastOnce);
+        assert_eq!(stream, EsStream::since(name.clone(), 1_500_000_000_000));
+
+        context.delivered(EventNumber(7));
+        let stream = context.resume_stream(name.clone(), Delivery::AtLeastOnce);
+        assert_eq!(stream, EsStream::new(name, ReadRange::ReadFrom(8)));
+    }
+
+    #[test]
+    fn resume_history_only() {
+        let name = StreamName::new("history".to_owned()).unwrap();
+        let mut context = StreamContext::default();
+
+        context.subscribed(ReadRange::ReadFrom(0), None);
+        context.history_only = true;
+        context.delivered(EventNumber(2));
+
+        // the subscription is still history only, whatever the delivery mode
+        let stream = context.resume_stream(name.clone(), Delivery::AtMostOnce);
+        assert_eq!(stream, EsStream::history(name, 3));
+
+        // nothing is resubscribed once the server sent the end of the stream
+        context.ended(EventNumber(5));
+        assert!(context.is_complete());
+    }
+
+    #[test]
+    fn dedup_drops_redelivered_events() {
+        let event = |stream: &str, number| {
+            Ok(Response::Event {
+                stream: StreamName::new(stream.to_owned()).unwrap(),
+                number: EventNumber(number),
+                event_name: EventName::new("event".to_owned()).unwrap(),
+                event_data: EventData(Vec::new()),
+                global: None,
+                headers: Default::default(),
+                redacted: false,
+                timestamp: None,
+            })
+        };
+
+        let responses = vec![
+            event("a", 0),
+            event("a", 1),
+            event("b", 1),
+            event("a", 1),
+            event("a", 0),
+            Err(ServerError::from(String::from("error"))),
+            event("b", 1),
+            event("a", 2),
+            event("b", 2),
+        ];
+
+        let stream = futures::stream::iter_ok::<_, ()>(responses);
+        let delivered = Dedup::new(stream).collect().wait().unwrap();
+
+        let expected = vec![
+            event("a", 0),
+            event("a", 1),
+            event("b", 1),
+            Err(ServerError::from(String::from("error"))),
+            event("a", 2),
+            event("b", 2),
+        ];
+        assert_eq!(delivered, expected);
+    }
+
+    #[test]
+    fn drain_ends_after_all_caught_up() {
+        let caught_up = |stream: &str| {
+            Ok(Response::CaughtUp {
+                stream: StreamName::new(stream.to_owned()).unwrap(),
+                number: EventNumber(0),
+            })
+        };
+
+        let responses = vec![
+            caught_up("a"),
+            caught_up("b"),
+            Ok(Response::AllCaughtUp),
+            caught_up("c"),
+        ];
+
+        let stream = futures::stream::iter_ok::<_, ()>(responses);
+        let drained = DrainToAllCaughtUp::new(stream).collect().wait().unwrap();
+        assert_eq!(drained, vec![caught_up("a"), caught_up("b")]);
+    }
+
+    #[test]
+    fn close_ends_the_connection() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // the server side reads until the client closes the connection
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(socket, _)| tokio::io::read_to_end(socket.unwrap(), Vec::new()));
+
+        // the controller and the stream are kept alive, only `close` can end the connection
+        let client = sub_connect(addr)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))
+            .and_then(|(mut controller, sub_stream)| {
+                controller.close();
+                sub_stream
+                    .collect()
+                    .map(move |responses| (controller, responses))
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+            });
+
+        let fut = server.join(client).timeout(Duration::from_secs(5));
+        let ((_, data), (_controller, responses)) = runtime.block_on(fut).unwrap();
+
+        assert!(data.is_empty());
+        assert!(responses.is_empty());
+    }
+
+    #[test]
+    fn metrics_count_received_events() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = StreamName::new("metrics".to_owned()).unwrap();
+
+        let responses: Vec<_> = (0..3)
+            .map(|number| {
+                Ok(Response::Event {
+                    stream: stream.clone(),
+                    number: EventNumber(number),
+                    event_name: EventName::new("event".to_owned()).unwrap(),
+                    event_data: EventData(b"data".to_vec()),
+                    global: None,
+                    headers: Default::default(),
+                    redacted: false,
+                    timestamp: None,
+                })
+            })
+            .collect();
+
+        // the server sends the events as soon as the client is connected
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(socket, _)| {
+                ServerCodec::default()
+                    .framed(socket.unwrap())
+                    .send_all(futures::stream::iter_ok::<_, io::Error>(responses))
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+            });
+
+        let client = sub_connect(addr)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))
+            .and_then(|(controller, sub_stream)| {
+                let metrics = sub_stream.metrics();
+                assert_eq!(metrics.events(), 0);
+
+                sub_stream
+                    .take(3)
+                    .fold(Vec::new(), move |mut seen, _| {
+                        seen.push(metrics.events());
+                        Ok::<_, ProtocolError>(seen)
+                    })
+                    .map(|seen| (controller, seen))
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+            });
+
+        let fut = server.join(client).timeout(Duration::from_secs(5));
+        let (_, (_controller, seen)) = runtime.block_on(fut).unwrap();
+
+        assert_eq!(seen, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn keepalive_reconnects_silent_server() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // the server accepts the connections but never answers
+        let server = listener.incoming().take(2).collect();
+
+        let keepalive = KeepAlive {
+            interval: Duration::from_millis(50),
+            max_missed: 2,
+        };
+        let client = sub_connect_with_keepalive(addr, keepalive)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))
+            .map(|(controller, sub_stream)| {
+                tokio::spawn(sub_stream.for_each(|_| Ok(())).map_err(|_| ()));
+                controller
+            });
+
+        let fut = server.join(client).timeout(Duration::from_secs(5));
+        let (sockets, _controller) = runtime.block_on(fut).unwrap();
+
+        assert_eq!(sockets.len(), 2);
+    }
+
+    #[test]
+    fn keepalive_pings_are_not_returned() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream_names = Response::StreamNames {
+            streams: vec![StreamName::new("orders".to_owned()).unwrap()],
+        };
+
+        // the server answers the first ping and then sends stream names
+        let responses = vec![Ok(Response::Pong), Ok(stream_names.clone())];
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(socket, _)| {
+                ServerCodec::default()
+                    .framed(socket.unwrap())
+                    .into_future()
+                    .map_err(|(e, _)| io::Error::new(io::ErrorKind::Other, e.to_string()))
+            })
+            .and_then(|(request, framed)| {
+                framed
+                    .send_all(futures::stream::iter_ok::<_, io::Error>(responses))
+                    .map(move |framed| (request, framed))
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+            });
+
+        let keepalive = KeepAlive {
+            interval: Duration::from_millis(50),
+            max_missed: 100,
+        };
+        let client = sub_connect_with_keepalive(addr, keepalive)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))
+            .and_then(|(controller, sub_stream)| {
+                sub_stream
+                    .into_future()
+                    .map(|(response, _)| (controller, response))
+                    .map_err(|(e, _)| io::Error::new(io::ErrorKind::Other, e.to_string()))
+            });
+
+        let fut = server.join(client).timeout(Duration::from_secs(5));
+        let ((request, _framed), (_controller, response)) = runtime.block_on(fut).unwrap();
+
+        assert_eq!(request.unwrap(), Request::Ping);
+        assert_eq!(response, Some(Ok(stream_names)));
     }
 }
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use futures::future::{self, Future};
+use futures::Stream;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::service::service_fn;
+use hyper::{Body, HeaderMap, Method, Request, Response, Server, StatusCode};
+use log::{error, info};
+use structopt::StructOpt;
+
+use meilies::stream::{EventData, EventName, EventNumber, ReadRange};
+use meilies::stream::{Stream as EsStream, StreamName};
+use meilies_client::{PairedPool, ServerAddr};
+
+mod sse;
+mod ws;
+
+use self::sse::EventSource;
+use self::ws::Backpressure;
+
+type ResponseFuture = Box<dyn Future<Item = Response<Body>, Error = hyper::Error> + Send>;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "meilies-gateway",
+    about = "An HTTP gateway to MeiliES using Server-Sent Events or WebSockets.",
+    author
+)]
+struct Opt {
+    /// MeiliES server hostname.
+    #[structopt(short = "h", long = "hostname", default_value = "127.0.0.1")]
+    hostname: String,
+
+    /// MeiliES server port.
+    #[structopt(short = "p", long = "port", default_value = "6480")]
+    port: u16,
+
+    /// The address the HTTP gateway listens on.
+    #[structopt(short = "l", long = "listen", default_value = "127.0.0.1:6490")]
+    listen: SocketAddr,
+
+    /// The number of idle connections kept open to publish events.
+    #[structopt(long = "pool-size", default_value = "4")]
+    pool_size: usize,
+
+    /// Serve Server-Sent Events over HTTP (sse) or JSON messages over WebSockets (ws).
+    #[structopt(long = "mode", default_value = "sse")]
+    mode: Mode,
+
+    /// What to do with the events a WebSocket client reads too slowly: drop or buffer.
+    #[structopt(long = "backpressure", default_value = "buffer")]
+    backpressure: Backpressure,
+
+    /// The number of messages buffered for each WebSocket client.
+    #[structopt(long = "buffer-size", default_value = "1024")]
+    buffer_size: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Mode {
+    Sse,
+    Ws,
+}
+
+#[derive(Debug)]
+struct ParseModeError(String);
+
+impl fmt::Display for ParseModeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid mode {:?}, expected sse or ws", self.0)
+    }
+}
+
+impl FromStr for Mode {
+    type Err = ParseModeError;
+
+    fn from_str(s: &str) -> Result<Mode, Self::Err> {
+        match s {
+            "sse" => Ok(Mode::Sse),
+            "ws" => Ok(Mode::Ws),
+            _ => Err(ParseModeError(s.to_owned())),
+        }
+    }
+}
+
+fn text_response(status: StatusCode, text: String) -> ResponseFuture {
+    let response = Response::builder()
+        .status(status)
+        .body(Body::from(text))
+        .unwrap();
+    Box::new(future::ok(response))
+}
+
+/// Decode the `%XX` escapes of a part of an URL, a `+` is a space in a query string.
+fn percent_decode(text: &str, plus_as_space: bool) -> Result<String, String> {
+    let hex = |byte: Option<u8>| byte.and_then(|byte| (byte as char).to_digit(16));
+
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut input = text.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'%' => match (hex(input.next()), hex(input.next())) {
+                (Some(high), Some(low)) => bytes.push((high * 16 + low) as u8),
+                _ => return Err(format!("invalid percent escape in {:?}", text)),
+            },
+            b'+' if plus_as_space => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+    }
+
+    String::from_utf8(bytes).map_err(|_| format!("{:?} is not UTF-8 once decoded", text))
+}
+
+fn query_params(query: Option<&str>) -> Result<HashMap<String, String>, String> {
+    query
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut split = pair.splitn(2, '=');
+            let key = percent_decode(split.next().unwrap_or(""), true)?;
+            let value = percent_decode(split.next().unwrap_or(""), true)?;
+            Ok((key, value))
+        })
+        .collect()
+}
+
+/// The number of the last event received by a browser reconnecting an `EventSource`,
+/// it is sent in the `Last-Event-ID` header.
+fn last_event_id(headers: &HeaderMap) -> Result<Option<EventNumber>, String> {
+    match headers.get("last-event-id").map(|id| id.to_str()) {
+        Some(Ok(id)) => match id.trim().parse() {
+            Ok(number) => Ok(Some(EventNumber(number))),
+            Err(e) => Err(format!("invalid Last-Event-ID header; {}", e)),
+        },
+        Some(Err(e)) => Err(format!("invalid Last-Event-ID header; {}", e)),
+        None => Ok(None),
+    }
+}
+
+/// Map the `from` and `to` query parameters to a read range,
+/// the `to` bound is exclusive like everywhere else.
+fn read_range(params: &HashMap<String, String>) -> Result<ReadRange, String> {
+    let parse = |key: &str| match params.get(key) {
+        Some(value) => value
+            .parse::<u64>()
+            .map(Some)
+            .map_err(|e| format!("invalid {} parameter; {}", key, e)),
+        None => Ok(None),
+    };
+
+    match (parse("from")?, parse("to")?) {
+        (Some(from), Some(to)) if from >= to => {
+            Err(String::from("the to parameter must be greater than from"))
+        }
+        (from, Some(to)) => Ok(ReadRange::ReadFromUntil(from.unwrap_or(0), to)),
+        (Some(from), None) => Ok(ReadRange::ReadFrom(from)),
+        (None, None) => Ok(ReadRange::ReadFromEnd),
+    }
+}
+
+fn subscribe(
+    addr: ServerAddr,
+    stream: StreamName,
+    range: ReadRange,
+    last_event_id: Option<EventNumber>,
+) -> ResponseFuture {
+    let events = EventSource::new(addr, EsStream::new(stream, range), last_event_id);
+    let response = Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(Body::wrap_stream(events))
+        .unwrap();
+    Box::new(future::ok(response))
+}
+
+fn publish(
+    pool: PairedPool,
+    stream: StreamName,
+    event_name: EventName,
+    body: Body,
+) -> ResponseFuture {
+    let fut = body.concat2().and_then(move |data| {
+        let event_data = EventData(data.to_vec());
+        pool.publish(stream, event_name, event_data).then(|result| {
+            let response = match result {
+                Ok(()) => Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .body(Body::empty()),
+                Err(e) => {
+                    error!("{}", e);
+                    Response::builder()
+                        .status(StatusCode::BAD_GATEWAY)
+                        .body(Body::from(e.to_string()))
+                }
+            };
+            Ok(response.unwrap())
+        })
+    });
+
+    Box::new(fut)
+}
+
+/// `GET /streams/<stream>?from=<n>&to=<n>` streams the events of a stream,
+/// `POST /streams/<stream>?event=<name>` publishes the body as an event.
+///
+/// A browser that reconnects resumes after the event of its `Last-Event-ID` header.
+fn handle(request: Request<Body>, addr: ServerAddr, pool: PairedPool) -> ResponseFuture {
+    let name = match request.uri().path().strip_prefix("/streams/") {
+        Some(name) => percent_decode(name, false),
+        None => return text_response(StatusCode::NOT_FOUND, String::from("not found")),
+    };
+
+    let stream = match name.and_then(|name| StreamName::new(name).map_err(|e| e.to_string())) {
+        Ok(stream) => stream,
+        Err(e) => {
+            let message = format!("invalid stream name; {}", e);
+            return text_response(StatusCode::BAD_REQUEST, message);
+        }
+    };
+
+    let params = match query_params(request.uri().query()) {
+        Ok(params) => params,
+        Err(message) => return text_response(StatusCode::BAD_REQUEST, message),
+    };
+
+    match *request.method() {
+        Method::GET => match (read_range(&params), last_event_id(request.headers())) {
+            (Ok(range), Ok(last_event_id)) => subscribe(addr, stream, range, last_event_id),
+            (Err(message), _) | (_, Err(message)) => {
+                text_response(StatusCode::BAD_REQUEST, message)
+            }
+        },
+        Method::POST => {
+            let event_name = match params.get("event").map(|e| EventName::new(e.to_owned())) {
+                Some(Ok(event_name)) => event_name,
+                Some(Err(e)) => {
+                    let message = format!("invalid event name; {}", e);
+                    return text_response(StatusCode::BAD_REQUEST, message);
+                }
+                None => {
+                    let message = String::from("missing event parameter");
+                    return text_response(StatusCode::BAD_REQUEST, message);
+                }
+            };
+            publish(pool, stream, event_name, request.into_body())
+        }
+        _ => text_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            String::from("method not allowed"),
+        ),
+    }
+}
+
+fn main() {
+    let _ = stderrlog::new().verbosity(2).init();
+
+    let opt = Opt::from_args();
+    let addr = ServerAddr::host(opt.hostname, opt.port);
+    let pool = PairedPool::new(addr.clone(), opt.pool_size);
+
+    if opt.mode == Mode::Ws {
+        let server = ws::serve(&opt.listen, addr, pool, opt.backpressure, opt.buffer_size);
+        return match server {
+            Ok(server) => {
+                info!("listening for WebSockets on {}", opt.listen);
+                tokio::run(server)
+            }
+            Err(e) => error!("impossible to listen on {}; {}", opt.listen, e),
+        };
+    }
+
+    let new_service = move || {
+        let (addr, pool) = (addr.clone(), pool.clone());
+        service_fn(move |request| handle(request, addr.clone(), pool.clone()))
+    };
+
+    let server = match Server::try_bind(&opt.listen) {
+        Ok(builder) => builder.serve(new_service),
+        Err(e) => return error!("impossible to listen on {}; {}", opt.listen, e),
+    };
+
+    info!("listening on {}", opt.listen);
+    tokio::run(server.map_err(|e| error!("server error; {}", e)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn read_range_from_params() {
+        let range = |pairs| read_range(&params(pairs));
+
+        assert_eq!(range(&[]), Ok(ReadRange::ReadFromEnd));
+        assert_eq!(range(&[("from", "3")]), Ok(ReadRange::ReadFrom(3)));
+        assert_eq!(range(&[("to", "5")]), Ok(ReadRange::ReadFromUntil(0, 5)));
+        assert_eq!(
+            range(&[("from", "3"), ("to", "5")]),
+            Ok(ReadRange::ReadFromUntil(3, 5))
+        );
+
+        assert!(range(&[("from", "5"), ("to", "5")]).is_err());
+        assert!(range(&[("from", "-1")]).is_err());
+        assert!(range(&[("to", "end")]).is_err());
+    }
+
+    #[test]
+    fn query_params_are_decoded() {
+        assert_eq!(query_params(None), Ok(HashMap::new()));
+        assert_eq!(
+            query_params(Some("from=3&&to")),
+            Ok(params(&[("from", "3"), ("to", "")]))
+        );
+        assert_eq!(
+            query_params(Some("event=order%20created&note=a+b%2Bc%3D")),
+            Ok(params(&[("event", "order created"), ("note", "a b+c=")]))
+        );
+        assert_eq!(
+            query_params(Some("event=caf%C3%A9")),
+            Ok(params(&[("event", "café")]))
+        );
+
+        assert!(query_params(Some("event=%2")).is_err());
+        assert!(query_params(Some("event=%zz")).is_err());
+        assert!(query_params(Some("event=%FF")).is_err());
+    }
+
+    #[test]
+    fn path_is_decoded_without_plus_as_space() {
+        assert_eq!(
+            percent_decode("my%20stream", false),
+            Ok(String::from("my stream"))
+        );
+        assert_eq!(percent_decode("a+b", false), Ok(String::from("a+b")));
+        assert_eq!(percent_decode("a%3Ab", false), Ok(String::from("a:b")));
+    }
+
+    #[test]
+    fn last_event_id_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(last_event_id(&headers), Ok(None));
+
+        headers.insert("last-event-id", "41".parse().unwrap());
+        assert_eq!(last_event_id(&headers), Ok(Some(EventNumber(41))));
+
+        headers.insert("last-event-id", "forty-one".parse().unwrap());
+        assert!(last_event_id(&headers).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+use std::io;
+
+use futures::{Async, Future, Poll, Stream};
+use log::{error, warn};
+use meilies::reqresp::Response;
+use meilies::stream::Stream as EsStream;
+use meilies::stream::{EventData, EventName, EventNumber, ReadRange};
+use meilies_client::{sub_connect, ServerAddr, SubController, SubStream};
+
+type Connecting = Box<
+    dyn Future<Item = (SubController, SubStream), Error = tokio_retry::Error<io::Error>> + Send,
+>;
+
+enum State {
+    Connecting(Connecting),
+    /// The controller is kept, dropping it would close the subscription.
+    Streaming {
+        _controller: SubController,
+        sub_stream: SubStream,
+    },
+    Finished,
+}
+
+/// A stream of Server-Sent Events chunks following a MeiliES stream.
+///
+/// The upstream connection is reopened when it is lost, the subscription
+/// is resumed from the event following the last one that was sent.
+/// The stream ends if it can not be reopened, browsers then reconnect
+/// with the number of the last event they received.
+pub struct EventSource {
+    addr: ServerAddr,
+    stream: EsStream,
+    state: State,
+}
+
+impl EventSource {
+    /// The events following `last_event_id` are streamed if there is one,
+    /// nothing is streamed if the range was already entirely sent.
+    pub fn new(
+        addr: ServerAddr,
+        stream: EsStream,
+        last_event_id: Option<EventNumber>,
+    ) -> EventSource {
+        let mut source = EventSource {
+            addr,
+            stream,
+            state: State::Finished,
+        };
+
+        if let Some(number) = last_event_id {
+            source.delivered(number);
+        }
+        if !source.is_complete() {
+            source.state = State::Connecting(Box::new(sub_connect(source.addr.clone())));
+        }
+
+        source
+    }
+
+    fn delivered(&mut self, number: EventNumber) {
+        self.stream.range = match self.stream.range {
+            ReadRange::ReadFromUntil(_, to) => ReadRange::ReadFromUntil(number.0 + 1, to),
+            ReadRange::ReadFrom(_) | ReadRange::ReadFromEnd => ReadRange::ReadFrom(number.0 + 1),
+        };
+    }
+
+    fn is_complete(&self) -> bool {
+        match self.stream.range {
+            ReadRange::ReadFromUntil(from, to) => from >= to,
+            _ => false,
+        }
+    }
+}
+
+impl Stream for EventSource {
+    type Item = String;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let state = match &mut self.state {
+                State::Connecting(connecting) => match connecting.poll() {
+                    Ok(Async::Ready((mut controller, sub_stream))) => {
+                        controller.subscribe_to(self.stream.clone());
+                        State::Streaming {
+                            _controller: controller,
+                            sub_stream,
+                        }
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        error!("impossible to connect to {}; {}", self.addr, e);
+                        return Ok(Async::Ready(None));
+                    }
+                },
+                State::Streaming { sub_stream, .. } => match sub_stream.poll() {
+                    Ok(Async::Ready(Some(Ok(Response::Event {
+                        number,
+                        event_name,
+                        event_data,
+                        ..
+                    })))) => {
+                        self.delivered(number);
+                        if self.is_complete() {
+                            self.state = State::Finished;
+                        }
+                        let chunk = event_chunk(number, &event_name, &event_data);
+                        return Ok(Async::Ready(Some(chunk)));
+                    }
+                    Ok(Async::Ready(Some(Ok(_)))) => continue,
+                    Ok(Async::Ready(Some(Err(e)))) => {
+                        return Ok(Async::Ready(Some(error_chunk(&e.to_string()))));
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(None)) => {
+                        warn!("connection to {} closed, reconnecting", self.addr);
+                        State::Connecting(Box::new(sub_connect(self.addr.clone())))
+                    }
+                    Err(e) => {
+                        warn!("connection to {} lost, reconnecting; {}", self.addr, e);
+                        State::Connecting(Box::new(sub_connect(self.addr.clone())))
+                    }
+                },
+                State::Finished => return Ok(Async::Ready(None)),
+            };
+
+            self.state = state;
+        }
+    }
+}
+
+/// The name of the stream is not sent, the client already knows it from the URL.
+///
+/// The data is JSON encoded like in the WebSocket mode, a string when it is valid UTF-8
+/// and `{"base64": "<data>"}` otherwise, the line breaks of the event name are replaced.
+fn event_chunk(number: EventNumber, name: &EventName, data: &EventData) -> String {
+    let name: String = lines(name.as_str()).collect::<Vec<_>>().join(" ");
+    let mut chunk = format!("id: {}\nevent: {}\n", number.0, name);
+    push_data(&mut chunk, &serde_json::to_string(data).unwrap());
+    chunk
+}
+
+fn error_chunk(error: &str) -> String {
+    let mut chunk = String::from("event: error\n");
+    push_data(&mut chunk, error);
+    chunk
+}
+
+/// The lines of a text as SSE reads them, separated by `\r\n`, `\r` or `\n`.
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = Some(text);
+    std::iter::from_fn(move || {
+        let text = rest?;
+        match text.find(&['\r', '\n'][..]) {
+            Some(index) => {
+                let separator = if text[index..].starts_with("\r\n") {
+                    2
+                } else {
+                    1
+                };
+                rest = Some(&text[index + separator..]);
+                Some(&text[..index])
+            }
+            None => {
+                rest = None;
+                Some(text)
+            }
+        }
+    })
+}
+
+/// Every line of the text is sent as a data field, a blank line ends the event.
+fn push_data(chunk: &mut String, text: &str) {
+    for line in lines(text) {
+        chunk.push_str("data: ");
+        chunk.push_str(line);
+        chunk.push('\n');
+    }
+    chunk.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use meilies::stream::StreamName;
+
+    fn finished_source(range: ReadRange) -> EventSource {
+        let name = StreamName::new("orders".to_owned()).unwrap();
+        EventSource {
+            addr: ServerAddr::host("localhost", 6480),
+            stream: EsStream::new(name, range),
+            state: State::Finished,
+        }
+    }
+
+    #[test]
+    fn delivered_resumes_after_the_event() {
+        let mut source = finished_source(ReadRange::ReadFromEnd);
+        source.delivered(EventNumber(4));
+        assert_eq!(source.stream.range, ReadRange::ReadFrom(5));
+        assert!(!source.is_complete());
+
+        let mut source = finished_source(ReadRange::ReadFrom(0));
+        source.delivered(EventNumber(0));
+        assert_eq!(source.stream.range, ReadRange::ReadFrom(1));
+
+        let mut source = finished_source(ReadRange::ReadFromUntil(0, 10));
+        source.delivered(EventNumber(8));
+        assert_eq!(source.stream.range, ReadRange::ReadFromUntil(9, 10));
+        assert!(!source.is_complete());
+        source.delivered(EventNumber(9));
+        assert!(source.is_complete());
+    }
+
+    #[test]
+    fn every_line_is_a_data_field() {
+        let mut chunk = String::new();
+        push_data(&mut chunk, "single");
+        assert_eq!(chunk, "data: single\n\n");
+
+        let mut chunk = String::new();
+        push_data(&mut chunk, "first\nsecond\n");
+        assert_eq!(chunk, "data: first\ndata: second\ndata: \n\n");
+
+        let mut chunk = String::new();
+        push_data(&mut chunk, "");
+        assert_eq!(chunk, "data: \n\n");
+
+        // a lone carriage return also ends a line, it can not inject a field
+        let mut chunk = String::new();
+        push_data(&mut chunk, "x\rid: 999\r\nevent: y");
+        assert_eq!(chunk, "data: x\ndata: id: 999\ndata: event: y\n\n");
+    }
+
+    #[test]
+    fn event_chunk_carries_the_event_number() {
+        let name = EventName::new("created".to_owned()).unwrap();
+
+        let chunk = event_chunk(EventNumber(7), &name, &EventData(b"a\rid: 9".to_vec()));
+        assert_eq!(chunk, "id: 7\nevent: created\ndata: \"a\\rid: 9\"\n\n");
+
+        let chunk = event_chunk(EventNumber(8), &name, &EventData(vec![0xff, 0x00]));
+        assert_eq!(
+            chunk,
+            "id: 8\nevent: created\ndata: {\"base64\":\"/wA=\"}\n\n"
+        );
+
+        let name = EventName::new("created\rid: 9".to_owned()).unwrap();
+        let chunk = event_chunk(EventNumber(9), &name, &EventData(b"a".to_vec()));
+        assert_eq!(chunk, "id: 9\nevent: created id: 9\ndata: \"a\"\n\n");
+
+        assert_eq!(error_chunk("boom"), "event: error\ndata: boom\n\n");
+    }
+
+    #[test]
+    fn sent_range_is_not_reopened() {
+        let name = StreamName::new("orders".to_owned()).unwrap();
+        let stream = EsStream::new(name, ReadRange::ReadFromUntil(0, 10));
+        let addr = ServerAddr::host("localhost", 6480);
+
+        let mut source = EventSource::new(addr, stream, Some(EventNumber(9)));
+        assert_eq!(source.poll().unwrap(), Async::Ready(None));
+    }
+}
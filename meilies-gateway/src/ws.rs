@@ -0,0 +1,345 @@
+//! The WebSocket mode of the gateway.
+//!
+//! Every text or binary frame sent by the browser must contain a JSON encoded `Request`,
+//! every frame sent back contains a JSON encoded `Result<Response, String>`,
+//! `{"Ok": <response>}` or `{"Err": "<error message>"}`.
+//!
+//! ```json
+//! {"Subscribe":{"streams":[{"name":"orders","range":{"ReadFrom":0}}],"require_existing":false}}
+//! {"Publish":{"stream":"orders","event_name":"created","event_data":"{\"id\":42}"}}
+//! {"Ok":{"Event":{"stream":"orders","number":0,"event_name":"created","event_data":"{\"id\":42}"}}}
+//! ```
+//!
+//! Event data that is not valid UTF-8 is written as `{"base64": "<data>"}`.
+//!
+//! The responses of the requests other than subscriptions can be pushed back in another
+//! order than the requests were sent, an `Export` is refused as it has several responses.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use futures::{future, Future, Sink, Stream};
+use log::{error, warn};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, Sender};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{accept_async, WebSocketStream};
+
+use meilies::reqresp::{Request, Response};
+use meilies::stream::Stream as EsStream;
+use meilies_client::{sub_connect, PairedPool, ServerAddr, SubController, SubStream};
+
+type UnitFuture = Box<dyn Future<Item = (), Error = ()> + Send>;
+
+/// What to do with the events of a subscription when the browser reads them too slowly.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Drop the events that do not fit in the buffer.
+    Drop,
+    /// Stop reading events from the server until the buffer has room again.
+    Buffer,
+}
+
+#[derive(Debug)]
+pub struct ParseBackpressureError(String);
+
+impl fmt::Display for ParseBackpressureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid backpressure policy {:?}, expected drop or buffer",
+            self.0
+        )
+    }
+}
+
+impl FromStr for Backpressure {
+    type Err = ParseBackpressureError;
+
+    fn from_str(s: &str) -> Result<Backpressure, Self::Err> {
+        match s {
+            "drop" => Ok(Backpressure::Drop),
+            "buffer" => Ok(Backpressure::Buffer),
+            _ => Err(ParseBackpressureError(s.to_owned())),
+        }
+    }
+}
+
+fn frame(result: Result<Response, String>) -> Message {
+    Message::Text(serde_json::to_string(&result).unwrap())
+}
+
+/// The request sent in a text or binary frame, the other frames carry none.
+fn decode_request(message: &Message) -> Option<serde_json::Result<Request>> {
+    match message {
+        Message::Text(text) => Some(serde_json::from_str(text)),
+        Message::Binary(bytes) => Some(serde_json::from_slice(bytes)),
+        _ => None,
+    }
+}
+
+fn send_frame(sender: Sender<Message>, result: Result<Response, String>) -> UnitFuture {
+    let fut = sender
+        .send(frame(result))
+        .map(drop)
+        .map_err(|e| error!("{}", e));
+    Box::new(fut)
+}
+
+fn forward_events(
+    sub_stream: SubStream,
+    sender: Sender<Message>,
+    backpressure: Backpressure,
+) -> UnitFuture {
+    let frames = sub_stream
+        .map_err(|e| error!("{}", e))
+        .map(|result| frame(result.map_err(|e| e.to_string())));
+
+    match backpressure {
+        Backpressure::Buffer => {
+            let sender = sender.sink_map_err(|e| error!("{}", e));
+            Box::new(frames.forward(sender).map(drop))
+        }
+        Backpressure::Drop => {
+            let mut sender = sender;
+            Box::new(
+                frames.for_each(move |message| match sender.try_send(message) {
+                    Ok(()) => Ok(()),
+                    Err(ref e) if e.is_full() => {
+                        warn!("client too slow, dropping an event");
+                        Ok(())
+                    }
+                    Err(e) => {
+                        error!("{}", e);
+                        Err(())
+                    }
+                }),
+            )
+        }
+    }
+}
+
+/// Whether the server answers a request with a single response, a paired connection
+/// is only released to the pool once the response of its request has been read.
+fn answered_once(request: &Request) -> bool {
+    !matches!(request, Request::Export { .. })
+}
+
+/// Subscriptions are sent through the sub connection, every other request
+/// is sent on a paired connection and its response is pushed back.
+///
+/// The paired requests are spawned, a slow one like `WaitFor` does not delay the
+/// following frames, their responses can therefore be pushed back in another order.
+fn handle_message(
+    message: Message,
+    controller: &mut SubController,
+    pool: &PairedPool,
+    sender: Sender<Message>,
+) -> UnitFuture {
+    let request = match decode_request(&message) {
+        Some(request) => request,
+        None => return Box::new(future::ok(())),
+    };
+
+    match request {
+        Ok(Request::SubscribeAll {
+            range,
+            ordered: false,
+        }) => controller.subscribe_to(EsStream::all(range)),
+        Ok(Request::SubscribeAll {
+            range,
+            ordered: true,
+        }) => controller.subscribe_all_ordered(range),
+        Ok(Request::Subscribe {
+            streams,
+            require_existing,
+        }) => {
+            for stream in streams {
+                if require_existing {
+                    controller.subscribe_to_existing(stream);
+                } else {
+                    controller.subscribe_to(stream);
+                }
+            }
+        }
+        Ok(Request::SubscribePrefix { prefix, range }) => {
+            controller.subscribe_prefix(prefix, range)
+        }
+        Ok(ref request) if !answered_once(request) => {
+            let message = "requests answered with several responses are not supported";
+            return send_frame(sender, Err(message.to_owned()));
+        }
+        Ok(request) => {
+            let pool = pool.clone();
+            let fut = pool
+                .acquire()
+                .map_err(|e| e.to_string())
+                .and_then(|conn| conn.request(request).map_err(|e| e.to_string()))
+                .map(move |(response, conn)| {
+                    pool.release(conn);
+                    response
+                })
+                .then(move |result| send_frame(sender, result));
+            tokio::spawn(fut);
+        }
+        Err(e) => return send_frame(sender, Err(format!("invalid request; {}", e))),
+    }
+
+    Box::new(future::ok(()))
+}
+
+fn handle_connection(
+    websocket: WebSocketStream<TcpStream>,
+    addr: ServerAddr,
+    pool: PairedPool,
+    backpressure: Backpressure,
+    buffer_size: usize,
+) -> UnitFuture {
+    let (ws_sink, ws_stream) = websocket.split();
+    let (sender, receiver) = mpsc::channel(buffer_size);
+
+    let writer = receiver
+        .map_err(|e| error!("{}", e))
+        .forward(ws_sink.sink_map_err(|e| error!("{}", e)))
+        .map(drop);
+    tokio::spawn(writer);
+
+    let fut = sub_connect(addr).map_err(|e| error!("{}", e)).and_then(
+        move |(mut controller, sub_stream)| {
+            tokio::spawn(forward_events(sub_stream, sender.clone(), backpressure));
+
+            let mut closer = controller.clone();
+            ws_stream
+                .map_err(|e| error!("{}", e))
+                .for_each(move |message| {
+                    handle_message(message, &mut controller, &pool, sender.clone())
+                })
+                .then(move |result| {
+                    closer.close();
+                    result
+                })
+        },
+    );
+
+    Box::new(fut)
+}
+
+/// Accept WebSocket connections, each one is bridged to the MeiliES server at `addr`.
+pub fn serve(
+    listen: &SocketAddr,
+    addr: ServerAddr,
+    pool: PairedPool,
+    backpressure: Backpressure,
+    buffer_size: usize,
+) -> io::Result<impl Future<Item = (), Error = ()>> {
+    let listener = TcpListener::bind(listen)?;
+
+    let server = listener
+        .incoming()
+        .map_err(|e| error!("error accepting a connection; {}", e))
+        .for_each(move |socket| {
+            let (addr, pool) = (addr.clone(), pool.clone());
+            let connection = accept_async(socket)
+                .map_err(|e| error!("websocket handshake failed; {}", e))
+                .and_then(move |websocket| {
+                    handle_connection(websocket, addr, pool, backpressure, buffer_size)
+                });
+
+            tokio::spawn(connection);
+            Ok(())
+        });
+
+    Ok(server)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use meilies::stream::{EventData, EventName, EventNumber, ReadRange, StreamName};
+    use serde_json::{json, Value};
+
+    fn frame_json(result: Result<Response, String>) -> Value {
+        match frame(result) {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            message => panic!("expected a text frame, got {:?}", message),
+        }
+    }
+
+    fn event(event_data: &[u8]) -> Response {
+        Response::Event {
+            stream: StreamName::new("orders".to_owned()).unwrap(),
+            number: EventNumber(0),
+            event_name: EventName::new("created".to_owned()).unwrap(),
+            event_data: EventData(event_data.to_vec()),
+            global: None,
+            headers: Default::default(),
+            redacted: false,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn responses_are_framed_as_json_results() {
+        assert_eq!(frame_json(Ok(Response::Pong)), json!({ "Ok": "Pong" }));
+        assert_eq!(frame_json(Err("boom".to_owned())), json!({ "Err": "boom" }));
+
+        let frame = frame_json(Ok(event(br#"{"id":42}"#)));
+        let event = &frame["Ok"]["Event"];
+        assert_eq!(event["stream"], "orders");
+        assert_eq!(event["number"], 0);
+        assert_eq!(event["event_name"], "created");
+        assert_eq!(event["event_data"], r#"{"id":42}"#);
+    }
+
+    #[test]
+    fn binary_event_data_is_framed_as_base64() {
+        let frame = frame_json(Ok(event(&[0xff, 0x00])));
+        assert_eq!(
+            frame["Ok"]["Event"]["event_data"],
+            json!({ "base64": "/wA=" })
+        );
+    }
+
+    #[test]
+    fn requests_are_decoded_from_text_and_binary_frames() {
+        let json = r#"{"Subscribe":{"streams":[{"name":"orders","range":{"ReadFrom":0}}],"require_existing":false}}"#;
+        let expected = Request::Subscribe {
+            streams: vec![EsStream::new(
+                StreamName::new("orders".to_owned()).unwrap(),
+                ReadRange::ReadFrom(0),
+            )],
+            require_existing: false,
+        };
+
+        let text = decode_request(&Message::Text(json.to_owned()));
+        assert_eq!(text.unwrap().unwrap(), expected);
+
+        let binary = decode_request(&Message::Binary(json.as_bytes().to_vec()));
+        assert_eq!(binary.unwrap().unwrap(), expected);
+
+        let invalid = decode_request(&Message::Text(String::from(r#"{"Publish":{}}"#)));
+        assert!(invalid.unwrap().is_err());
+
+        assert!(decode_request(&Message::Ping(Vec::new())).is_none());
+    }
+
+    #[test]
+    fn export_is_not_answered_once() {
+        let stream = StreamName::new("orders".to_owned()).unwrap();
+        assert!(answered_once(&Request::Ping));
+        assert!(answered_once(&Request::StreamNames));
+        assert!(!answered_once(&Request::Export { stream }));
+    }
+
+    #[test]
+    fn parse_backpressure() {
+        assert_eq!("drop".parse::<Backpressure>().unwrap(), Backpressure::Drop);
+        assert_eq!(
+            "buffer".parse::<Backpressure>().unwrap(),
+            Backpressure::Buffer
+        );
+        assert!("block".parse::<Backpressure>().is_err());
+    }
+}
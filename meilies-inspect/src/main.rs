@@ -1,12 +1,17 @@
-use std::io::{Error, ErrorKind, Write};
-use std::net::ToSocketAddrs;
+use std::cmp;
+use std::fs;
+use std::io::{self, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::thread;
 
-use futures::future::{self, Future};
+use futures::future::{self, Either, Future};
 use futures::stream::Stream;
+use futures::sync::oneshot;
 use meilies::reqresp::Response;
-use meilies::stream::Stream as EsStream;
-use meilies_client::sub_connect;
+use meilies::stream::StreamName;
+use meilies::stream::{EventData, EventName, EventNumber, ReadRange, Stream as EsStream};
+use meilies_client::{sub_connect, ServerAddr};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -28,6 +33,38 @@ struct Opt {
     #[structopt(long = "stream", parse(try_from_str))]
     stream: EsStream,
 
+    /// Number of the first event to inspect, replaces the range of the stream argument.
+    #[structopt(long = "from")]
+    from: Option<u64>,
+
+    /// Number of the event where the inspection stops (exclusive),
+    /// replaces the range of the stream argument.
+    #[structopt(long = "to")]
+    to: Option<u64>,
+
+    /// Log the events on which the command fails and continue with the next ones.
+    #[structopt(long = "continue-on-error")]
+    continue_on_error: bool,
+
+    /// Abort once the command has failed on this many events, used with `--continue-on-error`.
+    #[structopt(long = "max-failures")]
+    max_failures: Option<usize>,
+
+    /// File where the number of the last successfully processed event is saved,
+    /// the inspection resumes after this event when the file exists.
+    ///
+    /// With `--continue-on-error` the offset stops before the first failed event,
+    /// the events that follow it are inspected again when the inspection resumes.
+    #[structopt(long = "offset-file", parse(from_os_str))]
+    offset_file: Option<PathBuf>,
+
+    /// Number of commands executed in parallel.
+    ///
+    /// The results are handled in the order of the events, a failing event
+    /// is reported once all the previous events have been processed.
+    #[structopt(long = "concurrency", default_value = "1")]
+    concurrency: usize,
+
     /// Command and arguments that will interpret the event data piped in stdin.
     ///
     /// `MEILIES_STREAM_NAME` contains the stream name.
@@ -43,70 +80,166 @@ where
     future::err(Error::new(ErrorKind::Other, error))
 }
 
+/// Execute the command with the event data piped in stdin.
+fn run_command(
+    command: &str,
+    stream: StreamName,
+    number: EventNumber,
+    event_name: EventName,
+    event_data: EventData,
+) -> io::Result<()> {
+    let mut child = Command::new("/bin/bash")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .env("MEILIES_STREAM_NAME", stream.into_inner())
+        .env("MEILIES_EVENT_NAME", event_name.into_inner())
+        .env("MEILIES_EVENT_NUMBER", number.0.to_string())
+        .spawn()?;
+
+    let data = event_data.0.as_slice();
+    child.stdin.as_mut().unwrap().write_all(data)?;
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            "command execution was not successful",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Read the number of the last processed event saved in the offset file, if any.
+fn read_offset(path: &Path) -> io::Result<Option<EventNumber>> {
+    match fs::read_to_string(path) {
+        Ok(content) => match content.trim().parse() {
+            Ok(number) => Ok(Some(EventNumber(number))),
+            Err(e) => Err(Error::new(ErrorKind::InvalidData, e)),
+        },
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Save the number of the last processed event, the file is replaced atomically.
+fn write_offset(path: &Path, number: EventNumber) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, number.0.to_string())?;
+    fs::rename(tmp_path, path)
+}
+
+/// Execute the command in a dedicated thread, the future resolves once the command exited.
+fn spawn_command(
+    command: String,
+    stream: StreamName,
+    number: EventNumber,
+    event_name: EventName,
+    event_data: EventData,
+) -> impl Future<Item = io::Result<()>, Error = io::Error> {
+    let (sender, receiver) = oneshot::channel();
+
+    thread::spawn(move || {
+        let result = run_command(&command, stream, number, event_name, event_data);
+        let _ = sender.send(result);
+    });
+
+    receiver.map_err(|e| Error::new(ErrorKind::Other, e))
+}
+
 fn main() {
     let Opt {
         hostname,
         port,
         stream,
+        from,
+        to,
+        continue_on_error,
+        max_failures,
+        offset_file,
+        concurrency,
         command,
     } = Opt::from_args();
 
-    let addr = (hostname.as_str(), port);
-    let addr = match addr
-        .to_socket_addrs()
-        .map(|addrs| addrs.filter(|a| a.is_ipv4()).next())
-    {
-        Ok(Some(addr)) => addr,
-        Ok(None) => panic!("impossible to dns resolve addr; {:?}", addr),
-        Err(e) => panic!("error parsing addr; {}", e),
+    if concurrency == 0 {
+        return eprintln!("--concurrency must be greater than 0");
+    }
+
+    let stream = match (from, to) {
+        (Some(from), Some(to)) if from >= to => {
+            return eprintln!("--from ({}) must be lower than --to ({})", from, to);
+        }
+        (Some(from), Some(to)) => EsStream::new(stream.name, ReadRange::ReadFromUntil(from, to)),
+        (Some(from), None) => EsStream::new(stream.name, ReadRange::ReadFrom(from)),
+        (None, Some(0)) => return eprintln!("--to must be greater than 0"),
+        (None, Some(to)) => EsStream::new(stream.name, ReadRange::ReadFromUntil(0, to)),
+        (None, None) => stream,
+    };
+
+    let stream = match offset_file.as_ref().map(|path| read_offset(path)) {
+        Some(Ok(Some(last))) => {
+            // a --from after the saved offset is kept, the events before it are skipped
+            let from = cmp::max(stream.range.from().unwrap_or(0), last.0 + 1);
+            let range = match stream.range {
+                ReadRange::ReadFromUntil(_, to) if from >= to => {
+                    return eprintln!("every event up to {} has already been inspected", to);
+                }
+                ReadRange::ReadFromUntil(_, to) => ReadRange::ReadFromUntil(from, to),
+                _otherwise => ReadRange::ReadFrom(from),
+            };
+            eprintln!("resuming from event number {}", from);
+            EsStream::new(stream.name, range)
+        }
+        Some(Ok(None)) | None => stream,
+        Some(Err(e)) => return eprintln!("error reading the offset file; {}", e),
     };
 
-    let fut = sub_connect(addr)
+    let fut = sub_connect(ServerAddr::host(hostname, port))
         .map_err(|e| eprintln!("{}", e))
         .and_then(move |(mut ctrl, msgs)| {
             ctrl.subscribe_to(stream);
+            let mut failures = 0;
+            // the offset only moves over contiguous successes
+            let mut offset_file = offset_file;
             msgs.map_err(|e| Error::new(ErrorKind::Other, e.to_string()))
-                .for_each(move |msg| match msg {
+                .map(move |msg| match msg {
                     Ok(Response::Event {
                         stream,
                         number,
                         event_name,
                         event_data,
+                        ..
                     }) => {
                         eprintln!("processing event number {}", number.0);
 
-                        let result = Command::new("/bin/bash")
-                            .arg("-c")
-                            .arg(&command)
-                            .stdin(Stdio::piped())
-                            .env("MEILIES_STREAM_NAME", stream.into_inner())
-                            .env("MEILIES_EVENT_NAME", event_name.into_inner())
-                            .env("MEILIES_EVENT_NUMBER", number.0.to_string())
-                            .spawn();
-
-                        let mut child = match result {
-                            Ok(child) => child,
-                            Err(e) => return future::err(e),
-                        };
-
-                        let data = event_data.0.as_slice();
-                        if let Err(e) = child.stdin.as_mut().unwrap().write_all(data) {
-                            return future::err(e);
-                        }
-
-                        let output = match child.wait_with_output() {
-                            Ok(output) => output,
-                            Err(e) => return future::err(e),
-                        };
-
-                        if !output.status.success() {
-                            return future_io_err("command execution was not successful");
+                        let command = command.clone();
+                        let fut = spawn_command(command, stream, number, event_name, event_data);
+                        Either::A(fut.map(move |result| Some((number, result))))
+                    }
+                    Ok(_response) => Either::B(future::ok(None)),
+                    Err(error) => Either::B(future_io_err(format!("Error: {}", error))),
+                })
+                .buffered(concurrency)
+                .for_each(move |result| match result {
+                    Some((number, Ok(()))) => match &offset_file {
+                        Some(path) => future::result(write_offset(path, number)),
+                        None => future::ok(()),
+                    },
+                    None => future::ok(()),
+                    Some((number, Err(e))) if continue_on_error => {
+                        failures += 1;
+                        offset_file = None;
+                        eprintln!("event number {} failed; {}", number.0, e);
+
+                        match max_failures {
+                            Some(max) if failures >= max => {
+                                future_io_err(format!("aborting after {} failures", failures))
+                            }
+                            _ => future::ok(()),
                         }
-
-                        future::ok(())
                     }
-                    Ok(_response) => future::ok(()),
-                    Err(error) => future_io_err(format!("Error: {}", error)),
+                    Some((_number, Err(e))) => future::err(e),
                 })
                 .map_err(|e| eprintln!("{}", e))
         })
@@ -0,0 +1,612 @@
+//! Configuring and running a server.
+
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use sled::{Config, Db};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::prelude::*;
+
+use meilies::reqresp::Response;
+use meilies::stream::StreamName as EsStreamName;
+use meilies::stream::{EventData, EventName};
+use meilies_client::ServerAddr;
+
+use super::{Error, Settings};
+use crate::handlers::ServerCtx;
+use crate::replicate::replicate;
+use crate::retention::purge_periodically;
+use crate::serve::{serve, Connections};
+use crate::snapshot::SnapshotFns;
+use crate::storage::{
+    declare_stream, flush_periodically, set_stream_compression, StreamDeclaration,
+};
+use crate::subscriptions::watch;
+
+/// Configure a server before binding its addresses and opening its database.
+#[derive(Debug)]
+pub struct ServerBuilder {
+    addrs: Vec<SocketAddr>,
+    unix_socket: Option<PathBuf>,
+    db_path: PathBuf,
+    temporary: bool,
+    compression_factor: Option<i32>,
+    replicate_from: Option<ServerAddr>,
+    nodelay: bool,
+    settings: Settings,
+    snapshot_fns: SnapshotFns,
+    uncompressed_streams: Vec<EsStreamName>,
+    declared_streams: Vec<StreamDeclaration>,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> ServerBuilder {
+        ServerBuilder {
+            addrs: Vec::new(),
+            unix_socket: None,
+            db_path: PathBuf::from("/var/lib/meilies"),
+            temporary: false,
+            compression_factor: None,
+            replicate_from: None,
+            nodelay: true,
+            settings: Settings::default(),
+            snapshot_fns: SnapshotFns::default(),
+            uncompressed_streams: Vec::new(),
+            declared_streams: Vec::new(),
+        }
+    }
+}
+
+impl ServerBuilder {
+    pub fn new() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// Add an address to listen on, the server listens on 127.0.0.1:6480 if none is given.
+    pub fn listen(mut self, addr: SocketAddr) -> ServerBuilder {
+        self.addrs.push(addr);
+        self
+    }
+
+    /// Also listen on a unix socket created at the given path.
+    pub fn unix_socket(mut self, path: impl Into<PathBuf>) -> ServerBuilder {
+        self.unix_socket = Some(path.into());
+        self
+    }
+
+    pub fn db_path(mut self, path: impl Into<PathBuf>) -> ServerBuilder {
+        self.db_path = path.into();
+        self
+    }
+
+    /// Use a database that is removed when the server is dropped, the path is ignored
+    /// and each temporary database gets its own.
+    pub fn temporary(mut self, temporary: bool) -> ServerBuilder {
+        self.temporary = temporary;
+        self
+    }
+
+    /// Compress the database using zstd with the given factor (irreversible).
+    pub fn compression_factor(mut self, factor: i32) -> ServerBuilder {
+        self.compression_factor = Some(factor);
+        self
+    }
+
+    /// Compress the data of each published event using zstd with the given level,
+    /// unlike the database compression it can be disabled for some streams.
+    pub fn event_compression(mut self, level: i32) -> ServerBuilder {
+        self.settings.event_compression = Some(level);
+        self
+    }
+
+    /// Do not compress the events published to this stream, for the streams whose data is
+    /// already compressed, it is recorded in the database and kept across restarts.
+    pub fn uncompressed_stream(mut self, stream: EsStreamName) -> ServerBuilder {
+        self.uncompressed_streams.push(stream);
+        self
+    }
+
+    /// Create a stream when the server starts, even if no event is published to it,
+    /// and record its settings, the settings of an existing stream are replaced.
+    pub fn declare_stream(mut self, declaration: StreamDeclaration) -> ServerBuilder {
+        self.declared_streams.push(declaration);
+        self
+    }
+
+    /// Maximum size in bytes of the data of a published event.
+    pub fn max_event_size(mut self, size: usize) -> ServerBuilder {
+        self.settings.max_event_size = Some(size);
+        self
+    }
+
+    /// Maximum number of events kept in each stream, the oldest ones are removed.
+    pub fn max_events(mut self, count: usize) -> ServerBuilder {
+        self.settings.max_events = Some(count);
+        self
+    }
+
+    /// Remove the events published longer than this duration ago.
+    ///
+    /// The purge runs lazily, at most every minute, in the background,
+    /// the events can outlive their TTL until the next purge.
+    pub fn retention_ttl(mut self, ttl: Duration) -> ServerBuilder {
+        self.settings.retention_ttl = Some(ttl);
+        self
+    }
+
+    /// Maximum number of streams, publishing or subscribing to a new stream is rejected
+    /// once it is reached while the existing streams keep working.
+    pub fn max_streams(mut self, count: usize) -> ServerBuilder {
+        self.settings.max_streams = Some(count);
+        self
+    }
+
+    /// Reject every request that modifies the database.
+    pub fn read_only(mut self, read_only: bool) -> ServerBuilder {
+        self.settings.read_only = read_only;
+        self
+    }
+
+    /// Close the connections that do not send a complete request during this duration,
+    /// the connections that subscribed to streams are kept open.
+    pub fn read_timeout(mut self, timeout: Duration) -> ServerBuilder {
+        self.settings.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Close the connections that do not read their responses during this duration.
+    pub fn write_timeout(mut self, timeout: Duration) -> ServerBuilder {
+        self.settings.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Send a heartbeat to the subscriptions that received no event during this interval,
+    /// no heartbeat is sent by default.
+    pub fn heartbeat_interval(mut self, interval: Duration) -> ServerBuilder {
+        self.settings.heartbeat_interval = Some(interval);
+        self
+    }
+
+    /// Flush the database to disk at this interval instead of relying on sled,
+    /// its own periodic flush is disabled.
+    pub fn flush_interval(mut self, interval: Duration) -> ServerBuilder {
+        self.settings.flush_interval = Some(interval);
+        self
+    }
+
+    /// Flush the database to disk every time this number of events have been published.
+    pub fn flush_every(mut self, count: usize) -> ServerBuilder {
+        self.settings.flush_every = Some(count);
+        self
+    }
+
+    /// Maximum number of connections open at the same time, the connections
+    /// accepted past this limit are answered with an error and closed.
+    pub fn max_connections(mut self, count: usize) -> ServerBuilder {
+        self.settings.max_connections = Some(count);
+        self
+    }
+
+    /// Maximum number of requests per second of each connection,
+    /// the requests exceeding it are answered with a "rate limited" error.
+    pub fn max_requests_per_sec(mut self, count: u32) -> ServerBuilder {
+        self.settings.max_requests_per_sec = Some(count);
+        self
+    }
+
+    /// Give every published event a global number, shared by all the streams,
+    /// that ordered `$all` subscriptions follow to receive the events in publication order.
+    ///
+    /// The global counter is written by every publication, the publications
+    /// to different streams can no longer be written concurrently and conflict
+    /// with each other, this reduces the publishing throughput of the server.
+    /// The events published while it was disabled have no global number.
+    pub fn global_order(mut self, global_order: bool) -> ServerBuilder {
+        self.settings.global_order = global_order;
+        self
+    }
+
+    /// Register the function used to fold the events of a stream
+    /// when a snapshot of it is created with `Request::CreateSnapshot`.
+    pub fn snapshot_fn<F>(mut self, stream: EsStreamName, fold: F) -> ServerBuilder
+    where
+        F: Fn(Vec<u8>, &EventName, &EventData) -> Vec<u8> + Send + Sync + 'static,
+    {
+        let snapshot_fns = Arc::get_mut(&mut self.snapshot_fns.0).unwrap();
+        snapshot_fns.insert(stream, Box::new(fold));
+        self
+    }
+
+    /// Replicate the streams of a primary server.
+    pub fn replicate_from<A: Into<ServerAddr>>(mut self, primary: A) -> ServerBuilder {
+        self.replicate_from = Some(primary.into());
+        self
+    }
+
+    /// Set `TCP_NODELAY` on the accepted TCP connections, enabled by default,
+    /// small responses are not delayed by Nagle's algorithm.
+    pub fn nodelay(mut self, nodelay: bool) -> ServerBuilder {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Open the database and bind the addresses.
+    pub fn build(self) -> Result<Server, Error> {
+        let now = Instant::now();
+
+        // a temporary database without a path is given a path of its own by sled
+        let mut config = Config::new().temporary(self.temporary);
+        if !self.temporary {
+            config = config.path(self.db_path);
+        }
+        if let Some(compression_factor) = self.compression_factor {
+            config = config
+                .use_compression(true)
+                .compression_factor(compression_factor);
+        }
+        if self.settings.flush_interval.is_some() {
+            config = config.flush_every_ms(None);
+        }
+
+        let db = config.open()?;
+        info!("kv-store loaded in {:.2?}", now.elapsed());
+
+        for stream in &self.uncompressed_streams {
+            set_stream_compression(&db, stream, false)?;
+        }
+
+        for declaration in &self.declared_streams {
+            declare_stream(&db, &declaration.stream, declaration.settings)?;
+        }
+
+        let mut addrs = self.addrs;
+        if addrs.is_empty() {
+            addrs.push(SocketAddr::from(([127, 0, 0, 1], 6480)));
+        }
+
+        let mut listeners = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            listeners.push(TcpListener::bind(&addr)?);
+        }
+
+        let unix_listener = match self.unix_socket {
+            Some(path) => Some(UnixListener::bind(path)?),
+            None => None,
+        };
+
+        Ok(Server {
+            db,
+            listeners,
+            unix_listener,
+            replicate_from: self.replicate_from,
+            nodelay: self.nodelay,
+            settings: self.settings,
+            snapshot_fns: self.snapshot_fns,
+        })
+    }
+}
+
+/// A server bound to its addresses, ready to accept connections.
+pub struct Server {
+    db: Db,
+    listeners: Vec<TcpListener>,
+    unix_listener: Option<UnixListener>,
+    replicate_from: Option<ServerAddr>,
+    nodelay: bool,
+    settings: Settings,
+    snapshot_fns: SnapshotFns,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+
+    /// The TCP addresses the server listens on, useful when binding on the port 0.
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners.iter().map(TcpListener::local_addr).collect()
+    }
+
+    /// The events published in a stream from now on, like a subscription read from its end,
+    /// the returned stream stays valid once the server is running and must be polled by
+    /// a tokio runtime.
+    ///
+    /// Each watch runs a thread waiting for the changes of the stream until it is dropped.
+    pub fn watch(
+        &self,
+        stream: &EsStreamName,
+    ) -> Result<impl Stream<Item = Response, Error = Error>, Error> {
+        watch(&self.db, stream)
+    }
+
+    /// Accept connections on every address and answer their requests,
+    /// must be run on a tokio runtime.
+    pub fn run(self) -> impl Future<Item = (), Error = ()> {
+        let Server {
+            db,
+            listeners,
+            unix_listener,
+            replicate_from,
+            nodelay,
+            settings,
+            snapshot_fns,
+        } = self;
+
+        future::lazy(move || {
+            let settings = Settings {
+                started_at: Some(Instant::now()),
+                ..settings
+            };
+            let ctx = ServerCtx {
+                snapshot_fns,
+                ..ServerCtx::new(db, settings)
+            };
+            let connections = Connections::default();
+
+            if let Some(primary) = replicate_from {
+                tokio::spawn(replicate(ctx.db.clone(), primary, ctx.settings));
+            }
+
+            if let Some(interval) = ctx.settings.flush_interval {
+                tokio::spawn(flush_periodically(ctx.db.clone(), interval));
+            }
+
+            if let Some(ttl) = ctx.settings.retention_ttl {
+                tokio::spawn(purge_periodically(ctx.db.clone(), ttl));
+            }
+
+            if let Some(listener) = unix_listener {
+                let incoming = listener.incoming();
+                tokio::spawn(serve(incoming, ctx.clone(), connections.clone()));
+            }
+
+            let servers = listeners.into_iter().map(move |listener| {
+                let connections = connections.clone();
+                let incoming = listener.incoming().map(move |socket| {
+                    if let Err(e) = socket.set_nodelay(nodelay) {
+                        warn!("set_nodelay error; {}", e);
+                    }
+                    socket
+                });
+                serve(incoming, ctx.clone(), connections)
+            });
+
+            future::join_all(servers).map(drop)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::handle_request;
+    use crate::storage::{last_event_number, save_event, stream_settings};
+    use meilies::reqresp::Request;
+    use meilies::stream::EventNumber;
+    use meilies_client::paired_connect;
+    use std::thread;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn declared_streams_exist_without_events() {
+        let server = Server::builder()
+            .listen("127.0.0.1:0".parse().unwrap())
+            .temporary(true)
+            .declare_stream("orders".parse().unwrap())
+            .declare_stream("blobs:uncompressed".parse().unwrap())
+            .build()
+            .unwrap();
+
+        let ctx = ServerCtx::new(server.db.clone(), Settings::default());
+        let (sender, receiver) = mpsc::channel(10);
+        handle_request(Request::StreamNames, &ctx, sender).unwrap();
+
+        let mut streams = match receiver.wait().next().unwrap().unwrap() {
+            Ok(Response::StreamNames { streams }) => streams,
+            otherwise => panic!("unexpected response {:?}", otherwise),
+        };
+        streams.sort();
+
+        let orders = EsStreamName::new("orders".to_owned()).unwrap();
+        let blobs = EsStreamName::new("blobs".to_owned()).unwrap();
+        assert_eq!(streams, vec![blobs.clone(), orders.clone()]);
+
+        assert_eq!(last_event_number(&server.db, &orders).unwrap(), None);
+        assert!(stream_settings(&server.db, &orders).unwrap().compressed);
+        assert!(!stream_settings(&server.db, &blobs).unwrap().compressed);
+    }
+
+    #[test]
+    fn server_builder() {
+        let server = Server::builder()
+            .listen("127.0.0.1:0".parse().unwrap())
+            .temporary(true)
+            .build()
+            .unwrap();
+
+        let addr = server.local_addrs().unwrap()[0];
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.spawn(server.run());
+
+        let stream = EsStreamName::new("builder".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let event_data = EventData(b"data".to_vec());
+
+        let fut = paired_connect(addr)
+            .map_err(|e| e.to_string())
+            .and_then(move |conn| {
+                let range = conn
+                    .publish(stream.clone(), event_name, event_data)
+                    .and_then(move |conn| conn.read_range(stream, EventNumber(0), EventNumber(1)));
+                range.map_err(|e| e.to_string())
+            });
+        let (events, _) = runtime.block_on(fut).unwrap();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].0, EventNumber(0));
+        assert_eq!(events[0].2, EventData(b"data".to_vec()));
+    }
+
+    #[test]
+    fn next_event_returns_the_event_published_after_subscribing() {
+        let server = Server::builder()
+            .listen("127.0.0.1:0".parse().unwrap())
+            .temporary(true)
+            .build()
+            .unwrap();
+
+        let addr = server.local_addrs().unwrap()[0];
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.spawn(server.run());
+
+        let stream = EsStreamName::new("next".to_owned()).unwrap();
+        let publish = |name: &str, data: &[u8]| {
+            let stream = stream.clone();
+            let event_name = EventName::new(name.to_owned()).unwrap();
+            let event_data = EventData(data.to_vec());
+            paired_connect(addr)
+                .map_err(|e| e.to_string())
+                .and_then(move |conn| {
+                    conn.publish(stream, event_name, event_data)
+                        .map_err(|e| e.to_string())
+                })
+        };
+        runtime.block_on(publish("old", b"before")).unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let next = meilies_client::next_event(addr, stream.clone())
+            .map_err(|e| e.to_string())
+            .then(move |result| tx.send(result).map_err(drop));
+        runtime.spawn(next);
+
+        std::thread::sleep(Duration::from_millis(200));
+        runtime.block_on(publish("new", b"after")).unwrap();
+        runtime.block_on(publish("newer", b"later")).unwrap();
+
+        let (number, event_name, event_data) = rx.recv().unwrap().unwrap();
+        assert_eq!(number, EventNumber(1));
+        assert_eq!(event_name, EventName::new("new".to_owned()).unwrap());
+        assert_eq!(event_data, EventData(b"after".to_vec()));
+    }
+
+    #[test]
+    fn watch_yields_the_events_published_afterward() {
+        let server = Server::builder()
+            .listen("127.0.0.1:0".parse().unwrap())
+            .temporary(true)
+            .build()
+            .unwrap();
+
+        let stream = EsStreamName::new("watched".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let event_data = EventData(b"before".to_vec());
+        save_event(
+            &server.db,
+            &stream,
+            &event_name,
+            event_data,
+            None,
+            Settings::default(),
+        )
+        .unwrap();
+
+        let watched = server.watch(&stream).unwrap();
+        let addr = server.local_addrs().unwrap()[0];
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.spawn(server.run());
+
+        let publish = |data: &[u8]| {
+            let (stream, event_name) = (stream.clone(), event_name.clone());
+            let event_data = EventData(data.to_vec());
+            paired_connect(addr)
+                .map_err(|e| e.to_string())
+                .and_then(move |conn| {
+                    conn.publish(stream, event_name, event_data)
+                        .map_err(|e| e.to_string())
+                })
+        };
+        runtime.block_on(publish(b"after")).unwrap();
+        runtime.block_on(publish(b"later")).unwrap();
+
+        let fut = watched.take(2).collect().map_err(|e| e.to_string());
+        let events: Vec<_> = runtime
+            .block_on(fut.timeout(Duration::from_secs(5)))
+            .unwrap()
+            .into_iter()
+            .map(|response| match response {
+                Response::Event {
+                    number, event_data, ..
+                } => (number, event_data),
+                otherwise => panic!("unexpected response {:?}", otherwise),
+            })
+            .collect();
+
+        assert_eq!(
+            events,
+            vec![
+                (EventNumber(1), EventData(b"after".to_vec())),
+                (EventNumber(2), EventData(b"later".to_vec())),
+            ]
+        );
+    }
+
+    #[test]
+    fn flushed_events_survive_a_reopen() {
+        fn copy_dir(from: &std::path::Path, to: &std::path::Path) -> io::Result<()> {
+            std::fs::create_dir_all(to)?;
+            for entry in std::fs::read_dir(from)? {
+                let entry = entry?;
+                let target = to.join(entry.file_name());
+                if entry.file_type()?.is_dir() {
+                    copy_dir(&entry.path(), &target)?;
+                } else {
+                    std::fs::copy(entry.path(), target)?;
+                }
+            }
+            Ok(())
+        }
+
+        let path = std::env::temp_dir().join(format!("meilies-flush-{}", std::process::id()));
+        let copy = path.with_extension("copy");
+        let _ = std::fs::remove_dir_all(&path);
+        let _ = std::fs::remove_dir_all(&copy);
+
+        let server = Server::builder()
+            .listen("127.0.0.1:0".parse().unwrap())
+            .db_path(&path)
+            .flush_interval(Duration::from_millis(20))
+            .build()
+            .unwrap();
+
+        let addr = server.local_addrs().unwrap()[0];
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.spawn(server.run());
+
+        let stream = EsStreamName::new("flushed".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let event_data = EventData(b"data".to_vec());
+
+        let publish = paired_connect(addr)
+            .map_err(|e| e.to_string())
+            .and_then(move |conn| {
+                conn.publish(stream, event_name, event_data)
+                    .map_err(|e| e.to_string())
+            });
+        runtime.block_on(publish).unwrap();
+        thread::sleep(Duration::from_millis(200));
+
+        // the files are copied while the server is running, as if it had crashed
+        copy_dir(&path, &copy).unwrap();
+        let db = Config::new().path(&copy).open().unwrap();
+        let tree = db.open_tree("flushed").unwrap();
+        assert_eq!(tree.len(), 1);
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&path);
+        let _ = std::fs::remove_dir_all(&copy);
+    }
+}
@@ -0,0 +1,172 @@
+//! The deduplication window of the events published with an id.
+
+use std::convert::TryFrom;
+
+use sled::Tree;
+
+use meilies::stream::StreamName as EsStreamName;
+
+/// The number of dedup ids remembered for each stream, the oldest ones are forgotten first.
+const DEDUP_WINDOW: usize = 1024;
+
+pub const DEDUP_ID_PREFIX: &[u8] = b"id:";
+
+pub const DEDUP_NUMBER_PREFIX: &[u8] = b"number:";
+
+/// The number of remembered dedup ids is kept next to them, they are never counted.
+pub const DEDUP_COUNT_KEY: &[u8] = b"count";
+
+/// The dedup ids of a stream are stored in their own tree.
+pub fn dedup_tree_name(stream: &EsStreamName) -> Vec<u8> {
+    format!("dedup:{}", stream).into_bytes()
+}
+
+pub fn dedup_key(prefix: &[u8], suffix: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(prefix.len() + suffix.len());
+    key.extend_from_slice(prefix);
+    key.extend_from_slice(suffix);
+    key
+}
+
+/// Decode the value stored under `DEDUP_COUNT_KEY`, a missing value counts no id.
+pub fn dedup_count(value: Option<&[u8]>) -> u64 {
+    value
+        .and_then(|value| <[u8; 8]>::try_from(value).ok())
+        .map_or(0, u64::from_be_bytes)
+}
+
+/// Forget the oldest dedup ids until at most `DEDUP_WINDOW` of them are remembered.
+pub fn trim_dedup_ids(dedup: &Tree) -> sled::Result<()> {
+    let count = dedup_count(dedup.get(DEDUP_COUNT_KEY)?.as_deref());
+    let excess = count.saturating_sub(DEDUP_WINDOW as u64) as usize;
+
+    for result in dedup.scan_prefix(DEDUP_NUMBER_PREFIX).take(excess) {
+        let (key, dedup_id) = result?;
+
+        // a concurrent publication may have forgotten the same id already
+        if dedup.remove(key)?.is_some() {
+            dedup.remove(dedup_key(DEDUP_ID_PREFIX, &dedup_id))?;
+            dedup.update_and_fetch(DEDUP_COUNT_KEY, |count| {
+                let count = dedup_count(count).saturating_sub(1);
+                Some(count.to_be_bytes().to_vec())
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::{handle_request, ServerCtx};
+    use crate::storage::{save_event, stream_names};
+    use crate::Settings;
+    use meilies::reqresp::{Request, Response};
+    use meilies::stream::{EventData, EventName, EventNumber};
+    use sled::Config;
+    use std::collections::HashMap;
+    use tokio::prelude::*;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn publish_dedup_id_appends_once() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("dedup".to_owned()).unwrap();
+
+        let publish = |dedup_id: &str| {
+            let (sender, receiver) = mpsc::channel(10);
+            let request = Request::Publish {
+                stream: stream.clone(),
+                event_name: EventName::new("event".to_owned()).unwrap(),
+                event_data: EventData(b"data".to_vec()),
+                dedup_id: Some(dedup_id.to_owned()),
+                headers: HashMap::new(),
+            };
+            let settings = Settings::default();
+            handle_request(request, &ServerCtx::new(db.clone(), settings), sender).unwrap();
+            receiver.wait().next().unwrap().unwrap()
+        };
+
+        let first = publish("order-42");
+        let retry = publish("order-42");
+        let other = publish("order-43");
+
+        let published = |number| {
+            Ok(Response::Published {
+                stream: stream.clone(),
+                number: EventNumber(number),
+            })
+        };
+        assert_eq!(first, published(0));
+        assert_eq!(retry, published(0));
+        assert_eq!(other, published(1));
+
+        let tree = db.open_tree(stream.clone().into_bytes()).unwrap();
+        assert_eq!(tree.len(), 2);
+        assert_eq!(stream_names(&db), vec![stream]);
+    }
+
+    #[test]
+    fn dedup_window_forgets_oldest_ids() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("dedup-window".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        for i in 0..=DEDUP_WINDOW {
+            let event_data = EventData(b"data".to_vec());
+            let dedup_id = i.to_string();
+            let settings = Settings::default();
+            save_event(
+                &db,
+                &stream,
+                &event_name,
+                event_data,
+                Some(&dedup_id),
+                settings,
+            )
+            .unwrap();
+        }
+
+        // the first id was forgotten, publishing it again appends a new event
+        let event_data = EventData(b"data".to_vec());
+        let number = save_event(
+            &db,
+            &stream,
+            &event_name,
+            event_data,
+            Some("0"),
+            Settings::default(),
+        );
+        assert_eq!(number.unwrap(), EventNumber(DEDUP_WINDOW as u64 + 1));
+
+        let dedup = db.open_tree(dedup_tree_name(&stream)).unwrap();
+        let count = dedup.get(DEDUP_COUNT_KEY).unwrap();
+        assert_eq!(dedup_count(count.as_deref()), DEDUP_WINDOW as u64);
+        assert_eq!(dedup.scan_prefix(DEDUP_NUMBER_PREFIX).count(), DEDUP_WINDOW);
+    }
+
+    #[test]
+    fn publish_without_dedup_id_opens_no_dedup_tree() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("no-dedup".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let event_data = EventData(b"data".to_vec());
+        save_event(
+            &db,
+            &stream,
+            &event_name,
+            event_data,
+            None,
+            Settings::default(),
+        )
+        .unwrap();
+
+        let dedup = dedup_tree_name(&stream);
+        let tree_names = db.tree_names();
+        assert!(!tree_names
+            .iter()
+            .any(|name| name.as_slice() == dedup.as_slice()));
+        assert!(!tree_names.iter().any(|name| name.starts_with(b"global:")));
+    }
+}
@@ -0,0 +1,247 @@
+//! Exporting and importing the events of a stream as blobs.
+
+use std::cmp;
+use std::convert::TryFrom;
+
+use sled::{Db, IVec, Tree};
+
+use meilies::stream::{EventNumber, RawEvent, StreamName as EsStreamName};
+
+use super::Error;
+
+/// The number of bytes of events an export blob is filled with before being sent.
+pub const EXPORT_BATCH_SIZE: usize = 1024 * 1024;
+
+/// Encode the events of a stream as blobs of about `batch_size` bytes,
+/// each event is written as its number, the length of the stored event and the stored event.
+pub fn export_blobs(tree: &Tree, batch_size: usize) -> impl Iterator<Item = sled::Result<Vec<u8>>> {
+    let mut iter = tree.iter();
+    let mut failed = false;
+
+    std::iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+
+        let mut blob = Vec::new();
+        while blob.len() < batch_size {
+            match iter.next() {
+                Some(Ok((key, value))) => {
+                    blob.extend_from_slice(&key);
+                    blob.extend_from_slice(&(value.len() as u64).to_be_bytes());
+                    blob.extend_from_slice(&value);
+                }
+                Some(Err(e)) => {
+                    failed = true;
+                    return Some(Err(e));
+                }
+                None => break,
+            }
+        }
+
+        if blob.is_empty() {
+            None
+        } else {
+            Some(Ok(blob))
+        }
+    })
+}
+
+/// Decode the events of an export blob, returns `None` if the blob is truncated
+/// or if one of its events is corrupted.
+pub fn decode_blob(mut blob: &[u8]) -> Option<Vec<(EventNumber, &[u8])>> {
+    let mut events = Vec::new();
+
+    while !blob.is_empty() {
+        if blob.len() < 16 {
+            return None;
+        }
+
+        let number = EventNumber::try_from(&blob[..8]).ok()?;
+        let length = u64::from_be_bytes(TryFrom::try_from(&blob[8..16]).ok()?) as usize;
+        let end = 16usize
+            .checked_add(length)
+            .filter(|end| *end <= blob.len())?;
+
+        let raw_event = &blob[16..end];
+        RawEvent::new(raw_event).verify().ok()?;
+
+        events.push((number, raw_event));
+        blob = &blob[end..];
+    }
+
+    Some(events)
+}
+
+/// Insert the decoded events of an export blob in a stream and move its counter forward.
+///
+/// The events keep their numbers, importing the same blob twice has no effect.
+pub fn import_events(
+    db: &Db,
+    stream: &EsStreamName,
+    events: Vec<(EventNumber, &[u8])>,
+) -> Result<(), Error> {
+    let last = match events.iter().map(|(number, _)| *number).max() {
+        Some(last) => last,
+        None => return Ok(()),
+    };
+
+    let tree = db.open_tree(stream.clone().into_bytes())?;
+    let mut batch = sled::Batch::default();
+    for (number, raw_event) in events {
+        batch.insert(&number.to_be_bytes()[..], raw_event);
+    }
+    tree.apply_batch(batch)?;
+
+    db.update_and_fetch(stream, |previous| {
+        let new = match previous.map(EventNumber::try_from) {
+            Some(Ok(previous)) => cmp::max(previous, last),
+            _ => last,
+        };
+        let slice = &new.to_be_bytes()[..];
+        Some(IVec::from(slice))
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::{handle_request, ServerCtx};
+    use crate::storage::{last_event_number, save_event};
+    use crate::Settings;
+    use meilies::reqresp::{ErrorCode, Request, Response};
+    use meilies::stream::{EventData, EventName};
+    use sled::Config;
+    use tokio::prelude::*;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn export_import_round_trip() {
+        let source = Config::new().temporary(true).open().unwrap();
+        let target = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("export".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        for i in 0..100u64 {
+            let event_data = EventData(i.to_be_bytes().to_vec());
+            save_event(
+                &source,
+                &stream,
+                &event_name,
+                event_data,
+                None,
+                Settings::default(),
+            )
+            .unwrap();
+        }
+
+        let (sender, receiver) = mpsc::channel(100);
+        let request = Request::Export {
+            stream: stream.clone(),
+        };
+        let settings = Settings::default();
+        handle_request(request, &ServerCtx::new(source.clone(), settings), sender).unwrap();
+
+        // small batches are used to check that blobs can be concatenated
+        let tree = source.open_tree(stream.clone().into_bytes()).unwrap();
+        let blobs: Vec<_> = export_blobs(&tree, 64).map(Result::unwrap).collect();
+        assert!(blobs.len() > 1);
+
+        let mut responses: Vec<_> = receiver.wait().map(Result::unwrap).collect();
+        assert_eq!(responses.pop(), Some(Ok(Response::Ok)));
+        let blob: Vec<u8> = responses
+            .into_iter()
+            .flat_map(|response| match response {
+                Ok(Response::Exported { blob, .. }) => blob,
+                otherwise => panic!("unexpected response {:?}", otherwise),
+            })
+            .collect();
+        assert_eq!(blob, blobs.concat());
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Import {
+            stream: stream.clone(),
+            blob,
+        };
+        handle_request(request, &ServerCtx::new(target.clone(), settings), sender).unwrap();
+        let response = receiver.wait().next().unwrap().unwrap();
+        assert_eq!(response, Ok(Response::Ok));
+
+        let source_events: Vec<_> = tree.iter().map(Result::unwrap).collect();
+        let target_tree = target.open_tree(stream.clone().into_bytes()).unwrap();
+        let target_events: Vec<_> = target_tree.iter().map(Result::unwrap).collect();
+        assert_eq!(source_events, target_events);
+        assert_eq!(
+            last_event_number(&target, &stream).unwrap(),
+            Some(EventNumber(99))
+        );
+
+        assert!(decode_blob(&blobs[0][..10]).is_none());
+    }
+
+    #[test]
+    fn import_rejects_corrupted_events_and_excess_streams() {
+        let source = Config::new().temporary(true).open().unwrap();
+        let target = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("imported".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let event_data = EventData(b"data".to_vec());
+        save_event(
+            &source,
+            &stream,
+            &event_name,
+            event_data,
+            None,
+            Settings::default(),
+        )
+        .unwrap();
+
+        let tree = source.open_tree(stream.clone().into_bytes()).unwrap();
+        let blob = export_blobs(&tree, EXPORT_BATCH_SIZE)
+            .next()
+            .unwrap()
+            .unwrap();
+        let mut corrupted = blob.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0xff;
+        assert!(decode_blob(&corrupted).is_none());
+
+        // the target server already holds its single allowed stream
+        let settings = Settings {
+            max_streams: Some(1),
+            ..Settings::default()
+        };
+        let ctx = ServerCtx::new(target.clone(), settings);
+        let other = EsStreamName::new("other".to_owned()).unwrap();
+        save_event(
+            &target,
+            &other,
+            &event_name,
+            EventData(b"data".to_vec()),
+            None,
+            settings,
+        )
+        .unwrap();
+
+        let cases = vec![
+            (corrupted, ErrorCode::InvalidRequest),
+            (blob, ErrorCode::TooManyStreams),
+        ];
+        for (blob, code) in cases {
+            let (sender, receiver) = mpsc::channel(10);
+            let request = Request::Import {
+                stream: stream.clone(),
+                blob,
+            };
+            handle_request(request, &ctx, sender).unwrap();
+            match receiver.wait().next().unwrap().unwrap() {
+                Err(error) => assert_eq!(error.code, Some(code)),
+                otherwise => panic!("unexpected response {:?}", otherwise),
+            }
+        }
+
+        assert_eq!(last_event_number(&target, &stream).unwrap(), None);
+    }
+}
@@ -0,0 +1,2327 @@
+//! The server state and the handling of each kind of request.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+
+use log::info;
+use sled::{Db, Event, Tree};
+use tokio::prelude::*;
+
+use meilies::reqresp::{ErrorCode, Request, Response, ServerError};
+use meilies::stream::{EventData, EventName, EventNumber, RawEvent};
+use meilies::stream::{ReadRange, StreamSettings};
+use meilies::stream::{Stream as EsStream, StreamName as EsStreamName};
+
+use super::{Error, ResponseSender, Settings};
+use crate::dedup::dedup_key;
+use crate::export::{decode_blob, export_blobs, import_events, EXPORT_BATCH_SIZE};
+use crate::snapshot::{
+    compact_stream, create_snapshot, latest_snapshot, save_snapshot, SnapshotFns,
+};
+use crate::storage::{
+    declare_stream, decode_event, event_response, flush, global_event_response, group_not_found,
+    group_tree_name, last_event_number, open_group, parse_event_number, save_event_with_headers,
+    save_events, stream_names, stream_not_found, stream_settings, tree_size, verify_stream,
+    GLOBAL_LOG_TREE, GROUP_NEXT_KEY, GROUP_PENDING_PREFIX, PUBLISH_MULTI_MAX_STREAMS,
+};
+use crate::subscriptions::{
+    is_closed, send_prefix_events, send_stream_events, spawn_stream_events, CaughtUpGuard,
+    PausedStreams, SubscriptionGuard, Subscriptions, Watcher, CLOSED_CHECK_INTERVAL,
+};
+
+/// The optional features the server was compiled with.
+fn enabled_features() -> Vec<String> {
+    let mut features = Vec::new();
+    if cfg!(feature = "sentry") {
+        features.push(String::from("sentry"));
+    }
+    if cfg!(feature = "vigil") {
+        features.push(String::from("vigil"));
+    }
+    features
+}
+
+/// The events a connection is publishing in chunks, by stream, with their name and data.
+pub type PendingChunks = Arc<Mutex<HashMap<EsStreamName, (EventName, Vec<u8>)>>>;
+
+/// The events claimed by the consumers of the groups, keyed by the name of the group tree
+/// and the event number, an event stays claimed as long as its connection is open.
+type GroupClaims = Arc<Mutex<HashMap<(Vec<u8>, EventNumber), Weak<()>>>>;
+
+/// The number of streams, it is only counted when a stream is first created
+/// with a maximum number of streams and then kept up to date.
+type StreamCount = Arc<Mutex<Option<usize>>>;
+
+/// What the requests of every connection are handled with.
+#[derive(Clone)]
+pub struct ServerCtx {
+    pub db: Db,
+    pub settings: Settings,
+    pub subscriptions: Subscriptions,
+    pub snapshot_fns: SnapshotFns,
+    pub chunks: PendingChunks,
+    pub paused: PausedStreams,
+    pub stream_count: StreamCount,
+    pub unflushed: Arc<AtomicUsize>,
+    pub claims: GroupClaims,
+    /// Identifies the connection that claims events, it is dropped with the connection.
+    pub consumer: Arc<()>,
+}
+
+impl ServerCtx {
+    pub fn new(db: Db, settings: Settings) -> ServerCtx {
+        ServerCtx {
+            db,
+            settings,
+            subscriptions: Subscriptions::default(),
+            snapshot_fns: SnapshotFns::default(),
+            chunks: PendingChunks::default(),
+            paused: PausedStreams::default(),
+            stream_count: StreamCount::default(),
+            unflushed: Arc::default(),
+            claims: GroupClaims::default(),
+            consumer: Arc::default(),
+        }
+    }
+
+    /// Count a publication and flush the database once the number
+    /// of publications not flushed yet reaches the threshold.
+    fn maybe_flush(&self) -> Result<(), Error> {
+        let threshold = match self.settings.flush_every {
+            Some(threshold) => threshold,
+            None => return Ok(()),
+        };
+
+        if self.unflushed.fetch_add(1, Ordering::SeqCst) + 1 >= threshold {
+            self.unflushed.store(0, Ordering::SeqCst);
+            flush(&self.db)?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether the data of an event exceeds the max event size of the server
+    /// or the one of the stream it is published to.
+    fn event_too_large(&self, stream: &EsStreamName, size: usize) -> Result<bool, Error> {
+        if self.settings.max_event_size.map_or(false, |max| size > max) {
+            return Ok(true);
+        }
+
+        let max = stream_settings(&self.db, stream)?.max_event_size;
+        Ok(max.map_or(false, |max| size as u64 > max))
+    }
+
+    /// Create the tree of a stream if it does not exist yet, returns `false`
+    /// if the stream is new and the maximum number of streams is reached.
+    fn create_stream(&self, stream: &EsStreamName) -> Result<bool, Error> {
+        let max_streams = match self.settings.max_streams {
+            Some(max) => max,
+            None => return Ok(true),
+        };
+
+        // a stream that has events is known without listing the trees
+        if last_event_number(&self.db, stream)?.is_some() {
+            return Ok(true);
+        }
+
+        let mut stream_count = self.stream_count.lock().unwrap();
+        let name = stream.as_str().as_bytes();
+        if self.db.tree_names().iter().any(|n| n.as_slice() == name) {
+            return Ok(true);
+        }
+
+        let count = stream_count.get_or_insert_with(|| stream_names(&self.db).len());
+        if *count >= max_streams {
+            return Ok(false);
+        }
+
+        self.db.open_tree(name)?;
+        *count += 1;
+
+        Ok(true)
+    }
+}
+
+/// Errors that are not caused by the request itself, like database errors.
+pub fn internal_error<E: fmt::Display>(error: E) -> ServerError {
+    ServerError::new(ErrorCode::Internal, error.to_string())
+}
+
+fn send_too_many_streams(stream: &EsStreamName, sender: ResponseSender) {
+    let message = format!("stream {} can not be created, too many streams", stream);
+    let error = ServerError::new(ErrorCode::TooManyStreams, message);
+    if sender.send(Err(error)).wait().is_err() {
+        info!("encountered closed channel");
+    }
+}
+
+/// A command answered by sending its responses through the sender,
+/// the subscriptions keep sending events from their own threads.
+trait Handle {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error>;
+}
+
+/// Subscribe to every existing stream, an ordered subscription
+/// follows the global log instead, in a single thread.
+struct SubscribeAll {
+    range: ReadRange,
+    ordered: bool,
+}
+
+impl Handle for SubscribeAll {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let heartbeat = ctx.settings.heartbeat_interval;
+
+        if self.ordered {
+            if !ctx.settings.global_order {
+                let error =
+                    ServerError::new(ErrorCode::NotSupported, "global ordering is not enabled");
+                if sender.send(Err(error)).wait().is_err() {
+                    info!("encountered closed channel");
+                }
+                return Ok(());
+            }
+
+            let stream = EsStream::all(self.range);
+            let tree = ctx.db.open_tree(GLOBAL_LOG_TREE)?;
+            let guard = SubscriptionGuard::new(ctx.subscriptions.clone(), stream.name.clone());
+            let db = ctx.db.clone();
+            let pauses = ctx.paused.clone();
+
+            thread::Builder::new().spawn(move || {
+                let _guard = guard;
+                let decode = |global, value: &[u8]| global_event_response(&db, global, value);
+                let caught_up = CaughtUpGuard::none();
+                let result = send_stream_events(
+                    stream,
+                    tree,
+                    sender.clone(),
+                    heartbeat,
+                    decode,
+                    caught_up,
+                    pauses,
+                );
+                if let Err(e) = result {
+                    if sender.send(Err(internal_error(e))).wait().is_err() {
+                        info!("encountered closed channel");
+                    }
+                }
+            })?;
+
+            return Ok(());
+        }
+        let all_streams = stream_names(&ctx.db);
+        if all_streams.is_empty() {
+            if sender.send(Ok(Response::AllCaughtUp)).wait().is_err() {
+                info!("encountered closed channel");
+            }
+            return Ok(());
+        }
+
+        // The streams share a counter, the last one to catch up sends `AllCaughtUp`.
+        let remaining = Arc::new(AtomicUsize::new(all_streams.len()));
+        for name in all_streams {
+            let caught_up = CaughtUpGuard(Some((remaining.clone(), sender.clone())));
+            spawn_stream_events(
+                EsStream::new(name, self.range),
+                &ctx.db,
+                &ctx.subscriptions,
+                sender.clone(),
+                heartbeat,
+                caught_up,
+                &ctx.paused,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Subscribe to the given streams, the missing ones are
+/// answered with an error if they must already exist.
+struct Subscribe {
+    streams: Vec<EsStream>,
+    require_existing: bool,
+}
+
+impl Handle for Subscribe {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let heartbeat = ctx.settings.heartbeat_interval;
+
+        for stream in self.streams {
+            if self.require_existing && last_event_number(&ctx.db, &stream.name)?.is_none() {
+                let error = stream_not_found(&stream.name);
+                if sender.clone().send(Err(error)).wait().is_err() {
+                    info!("encountered closed channel");
+                }
+                continue;
+            }
+
+            if !self.require_existing && !ctx.create_stream(&stream.name)? {
+                send_too_many_streams(&stream.name, sender.clone());
+                continue;
+            }
+
+            spawn_stream_events(
+                stream,
+                &ctx.db,
+                &ctx.subscriptions,
+                sender.clone(),
+                heartbeat,
+                CaughtUpGuard::none(),
+                &ctx.paused,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Append an event to a stream, the event number is only
+/// answered when the event is published with a dedup id.
+struct Publish {
+    stream: EsStreamName,
+    event_name: EventName,
+    event_data: EventData,
+    dedup_id: Option<String>,
+    headers: HashMap<String, String>,
+}
+
+impl Handle for Publish {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let Publish {
+            stream,
+            event_name,
+            event_data,
+            dedup_id,
+            headers,
+        } = self;
+
+        if ctx.event_too_large(&stream, event_data.0.len())? {
+            let error = ServerError::new(ErrorCode::EventTooLarge, "event exceeds max size");
+            if sender.send(Err(error)).wait().is_err() {
+                info!("encountered closed channel");
+            }
+            return Ok(());
+        }
+
+        if !ctx.create_stream(&stream)? {
+            send_too_many_streams(&stream, sender);
+            return Ok(());
+        }
+
+        let event_number = save_event_with_headers(
+            &ctx.db,
+            &stream,
+            &event_name,
+            event_data,
+            &headers,
+            dedup_id.as_ref().map(String::as_str),
+            ctx.settings,
+        )?;
+        ctx.maybe_flush()?;
+
+        info!("{:?} {:?} {:?}", stream, event_name, event_number);
+
+        let response = match dedup_id {
+            Some(_) => Response::Published {
+                stream,
+                number: event_number,
+            },
+            None => Response::Ok,
+        };
+
+        if sender.send(Ok(response)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// Publish events to several streams, the events are all appended or none of them is.
+struct PublishMulti {
+    events: Vec<(EsStreamName, EventName, EventData)>,
+}
+
+impl Handle for PublishMulti {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let PublishMulti { events } = self;
+
+        let mut streams = HashSet::new();
+        streams.extend(events.iter().map(|(stream, _, _)| stream));
+        if streams.len() > PUBLISH_MULTI_MAX_STREAMS {
+            let message = format!("at most {} streams per request", PUBLISH_MULTI_MAX_STREAMS);
+            let error = ServerError::new(ErrorCode::InvalidRequest, message);
+            if sender.send(Err(error)).wait().is_err() {
+                info!("encountered closed channel");
+            }
+            return Ok(());
+        }
+
+        for (stream, _, event_data) in &events {
+            if ctx.event_too_large(stream, event_data.0.len())? {
+                let error = ServerError::new(ErrorCode::EventTooLarge, "event exceeds max size");
+                if sender.send(Err(error)).wait().is_err() {
+                    info!("encountered closed channel");
+                }
+                return Ok(());
+            }
+        }
+
+        for (stream, _, _) in &events {
+            if !ctx.create_stream(stream)? {
+                send_too_many_streams(stream, sender);
+                return Ok(());
+            }
+        }
+
+        let numbers = save_events(&ctx.db, &events, ctx.settings)?;
+        ctx.maybe_flush()?;
+
+        let events = events
+            .into_iter()
+            .zip(numbers)
+            .map(|((stream, event_name, _), number)| {
+                info!("{:?} {:?} {:?}", stream, event_name, number);
+                (stream, number)
+            })
+            .collect();
+
+        let response = Response::PublishedMulti { events };
+        if sender.send(Ok(response)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// Replace the settings of a stream, the stream is created if it does not exist yet.
+struct ConfigureStream {
+    stream: EsStreamName,
+    settings: StreamSettings,
+}
+
+impl Handle for ConfigureStream {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let ConfigureStream { stream, settings } = self;
+
+        if !ctx.create_stream(&stream)? {
+            send_too_many_streams(&stream, sender);
+            return Ok(());
+        }
+
+        declare_stream(&ctx.db, &stream, settings)?;
+        info!("{:?} configured with {:?}", stream, settings);
+
+        if sender.send(Ok(Response::Ok)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// A part of an event, the chunks are accumulated by the connection
+/// and the event is published with the last one.
+struct PublishChunk {
+    stream: EsStreamName,
+    event_name: EventName,
+    chunk: Vec<u8>,
+    last: bool,
+}
+
+impl Handle for PublishChunk {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let PublishChunk {
+            stream,
+            event_name,
+            chunk,
+            last,
+        } = self;
+
+        let mut chunks = ctx.chunks.lock().unwrap();
+        let data = match chunks.remove(&stream) {
+            Some((name, _)) if name != event_name => {
+                let message = format!(
+                    "a chunk of {} was sent while {} was published in stream {}",
+                    event_name, name, stream
+                );
+                let error = ServerError::new(ErrorCode::InvalidRequest, message);
+                if sender.send(Err(error)).wait().is_err() {
+                    info!("encountered closed channel");
+                }
+                return Ok(());
+            }
+            Some((_, mut data)) => {
+                data.extend_from_slice(&chunk);
+                data
+            }
+            None => chunk,
+        };
+
+        if ctx.event_too_large(&stream, data.len())? {
+            let error = ServerError::new(ErrorCode::EventTooLarge, "event exceeds max size");
+            if sender.send(Err(error)).wait().is_err() {
+                info!("encountered closed channel");
+            }
+            return Ok(());
+        }
+
+        if !last {
+            chunks.insert(stream, (event_name, data));
+            if sender.send(Ok(Response::Ok)).wait().is_err() {
+                info!("encountered closed channel");
+            }
+            return Ok(());
+        }
+
+        drop(chunks);
+        let publish = Publish {
+            stream,
+            event_name,
+            event_data: EventData(data),
+            dedup_id: None,
+            headers: HashMap::new(),
+        };
+        publish.handle(ctx, sender)
+    }
+}
+
+struct LastEventNumber {
+    stream: EsStreamName,
+}
+
+impl Handle for LastEventNumber {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let number = last_event_number(&ctx.db, &self.stream)?;
+
+        let last_event_number = Response::LastEventNumber {
+            stream: self.stream,
+            number,
+        };
+        if sender.send(Ok(last_event_number)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+struct StreamNames;
+
+impl Handle for StreamNames {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let streams = Response::StreamNames {
+            streams: stream_names(&ctx.db),
+        };
+
+        if sender.send(Ok(streams)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// The maximum number of events sent in a single page, larger limits are capped.
+pub const PAGE_MAX_EVENTS: u64 = 1000;
+
+/// Read at most `limit` events of a stream in a single response,
+/// the number of the event that follows them is sent along.
+struct Page {
+    stream: EsStreamName,
+    from: EventNumber,
+    limit: u64,
+}
+
+impl Handle for Page {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let Page {
+            stream,
+            from,
+            limit,
+        } = self;
+
+        // reading a page must not create the stream
+        let (events, next) = match last_event_number(&ctx.db, &stream)? {
+            Some(_) => {
+                let tree = ctx.db.open_tree(stream.clone().into_bytes())?;
+                read_page(&stream, &tree, from, cmp::min(limit, PAGE_MAX_EVENTS))?
+            }
+            None => (Vec::new(), None),
+        };
+
+        let response = Response::Page {
+            stream,
+            events,
+            next,
+        };
+        if sender.send(Ok(response)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// Answer once the last event number of a stream reaches `number`,
+/// a thread watches the stream counter until it does.
+struct WaitFor {
+    stream: EsStreamName,
+    number: EventNumber,
+}
+
+impl WaitFor {
+    fn reached(&self, last: Option<EventNumber>) -> bool {
+        last.map_or(false, |last| last >= self.number)
+    }
+}
+
+impl Handle for WaitFor {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        // The counter is watched before it is read, this way an event published
+        // in between can not be missed.
+        let key = self.stream.clone().into_bytes();
+        let watcher = Watcher::new(&ctx.db, &key);
+
+        if self.reached(last_event_number(&ctx.db, &self.stream)?) {
+            if sender.send(Ok(Response::Ok)).wait().is_err() {
+                info!("encountered closed channel");
+            }
+            return Ok(());
+        }
+
+        thread::Builder::new().spawn(move || loop {
+            let event = match watcher.next_timeout(CLOSED_CHECK_INTERVAL) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Disconnected) => return,
+                Err(RecvTimeoutError::Timeout) if is_closed(&sender) => {
+                    info!("encountered closed channel");
+                    return;
+                }
+                Err(RecvTimeoutError::Timeout) => continue,
+            };
+
+            let response = match event {
+                Event::Insert(changed, value) if changed.as_ref() == &key[..] => {
+                    match parse_event_number(&self.stream, &value) {
+                        Ok(last) if self.reached(Some(last)) => Ok(Response::Ok),
+                        Ok(_) => continue,
+                        Err(e) => Err(internal_error(e)),
+                    }
+                }
+                _ => continue,
+            };
+
+            if sender.send(response).wait().is_err() {
+                info!("encountered closed channel");
+            }
+            return;
+        })?;
+
+        Ok(())
+    }
+}
+
+/// Create a consumer group that starts at the first event of the stream.
+struct CreateGroup {
+    stream: EsStreamName,
+    group: String,
+}
+
+impl Handle for CreateGroup {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let _claims = ctx.claims.lock().unwrap();
+        let tree = ctx
+            .db
+            .open_tree(group_tree_name(&self.stream, &self.group))?;
+        if tree.get(GROUP_NEXT_KEY)?.is_none() {
+            tree.insert(GROUP_NEXT_KEY, &EventNumber::zero().to_be_bytes()[..])?;
+        }
+
+        if sender.send(Ok(Response::Ok)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// Claim an event for a consumer of a group, the pending events released
+/// by closed connections are claimed before the events never claimed.
+struct Claim {
+    stream: EsStreamName,
+    group: String,
+}
+
+impl Claim {
+    fn claim(&self, ctx: &ServerCtx) -> Result<Result<Response, ServerError>, Error> {
+        let Claim { stream, group } = self;
+        let mut claims = ctx.claims.lock().unwrap();
+        claims.retain(|_, consumer| consumer.strong_count() > 0);
+
+        let name = group_tree_name(stream, group);
+        let tree = match open_group(&ctx.db, &name)? {
+            Some(tree) => tree,
+            None => return Ok(Err(group_not_found(stream, group))),
+        };
+
+        if last_event_number(&ctx.db, stream)?.is_none() {
+            return Ok(Ok(Response::Nil));
+        }
+        let events = ctx.db.open_tree(stream.clone().into_bytes())?;
+
+        for result in tree.scan_prefix(GROUP_PENDING_PREFIX) {
+            let (key, _) = result?;
+            let number = &key[GROUP_PENDING_PREFIX.len()..];
+            let number = parse_event_number(stream, number)?;
+            if claims.contains_key(&(name.clone(), number)) {
+                continue;
+            }
+
+            let event = events.get(number.to_be_bytes())?;
+            match event.and_then(|value| event_response(stream, number, &value)) {
+                Some(response) => {
+                    claims.insert((name, number), Arc::downgrade(&ctx.consumer));
+                    return Ok(Ok(response));
+                }
+                // the event was removed by the retention or is corrupted
+                None => tree.remove(key).map(drop)?,
+            }
+        }
+
+        let next = match tree.get(GROUP_NEXT_KEY)? {
+            Some(bytes) => parse_event_number(stream, &bytes)?,
+            None => EventNumber::zero(),
+        };
+
+        // the cursor moves past the corrupted events, they are never claimed
+        for result in events.range(next.to_be_bytes()..) {
+            let (key, value) = result?;
+            let number = parse_event_number(stream, &key)?;
+
+            let mut batch = sled::Batch::default();
+            batch.insert(GROUP_NEXT_KEY, &number.next().to_be_bytes()[..]);
+            let response = event_response(stream, number, &value);
+            if response.is_some() {
+                let pending = dedup_key(GROUP_PENDING_PREFIX, &number.to_be_bytes());
+                batch.insert(pending, &b""[..]);
+            }
+            tree.apply_batch(batch)?;
+
+            if let Some(response) = response {
+                claims.insert((name, number), Arc::downgrade(&ctx.consumer));
+                return Ok(Ok(response));
+            }
+        }
+
+        Ok(Ok(Response::Nil))
+    }
+}
+
+impl Handle for Claim {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let response = self.claim(ctx)?;
+        if sender.send(response).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// Acknowledge an event claimed by this connection, it is no more pending.
+struct Ack {
+    stream: EsStreamName,
+    group: String,
+    number: EventNumber,
+}
+
+impl Ack {
+    fn ack(&self, ctx: &ServerCtx) -> Result<Result<Response, ServerError>, Error> {
+        let Ack {
+            stream,
+            group,
+            number,
+        } = self;
+        let mut claims = ctx.claims.lock().unwrap();
+
+        let name = group_tree_name(stream, group);
+        let tree = match open_group(&ctx.db, &name)? {
+            Some(tree) => tree,
+            None => return Ok(Err(group_not_found(stream, group))),
+        };
+
+        let claim = (name, *number);
+        let consumer = claims.get(&claim).and_then(Weak::upgrade);
+        if !consumer.map_or(false, |consumer| Arc::ptr_eq(&consumer, &ctx.consumer)) {
+            let message = format!("event {} is not claimed by this connection", number.0);
+            return Ok(Err(ServerError::new(ErrorCode::InvalidRequest, message)));
+        }
+
+        tree.remove(dedup_key(GROUP_PENDING_PREFIX, &number.to_be_bytes()))?;
+        claims.remove(&claim);
+
+        Ok(Ok(Response::Ok))
+    }
+}
+
+impl Handle for Ack {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let response = self.ack(ctx)?;
+        if sender.send(response).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// Replace an event by a tombstone that keeps its name and its timestamp.
+///
+/// The live subscriptions already sent the event and skip the tombstone
+/// like any event they already sent, the reads that follow return it.
+struct RedactEvent {
+    stream: EsStreamName,
+    number: EventNumber,
+}
+
+impl RedactEvent {
+    fn redact(&self, db: &Db) -> Result<Result<Response, ServerError>, Error> {
+        let RedactEvent { stream, number } = self;
+        let not_found = || {
+            let message = format!("event {} of stream {} does not exist", number.0, stream);
+            ServerError::new(ErrorCode::EventNotFound, message)
+        };
+
+        if last_event_number(db, stream)?.is_none() {
+            return Ok(Err(not_found()));
+        }
+
+        let tree = db.open_tree(stream.clone().into_bytes())?;
+        let raw_event = match tree.get(number.to_be_bytes())? {
+            Some(value) => RawEvent::new(value),
+            None => return Ok(Err(not_found())),
+        };
+
+        let (event_name, timestamp) = match (raw_event.name(), raw_event.timestamp()) {
+            (Ok(event_name), Ok(timestamp)) => (event_name, timestamp.unwrap_or(0)),
+            (Err(e), _) | (_, Err(e)) => {
+                let message = format!("event {} is corrupted; {}", number.0, e);
+                return Ok(Err(ServerError::new(ErrorCode::CorruptedEvent, message)));
+            }
+        };
+
+        let tombstone = RawEvent::encode_redacted_at(&event_name, timestamp);
+        tree.insert(number.to_be_bytes(), tombstone.into_inner())?;
+
+        Ok(Ok(Response::Ok))
+    }
+}
+
+impl Handle for RedactEvent {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let response = self.redact(&ctx.db)?;
+        if sender.send(response).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// Subscribe to every stream whose name starts with the prefix,
+/// the streams created later are subscribed to as well.
+struct SubscribePrefix {
+    prefix: String,
+    range: ReadRange,
+}
+
+impl Handle for SubscribePrefix {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let SubscribePrefix { prefix, range } = self;
+        let (db, subscriptions) = (ctx.db.clone(), ctx.subscriptions.clone());
+        let heartbeat = ctx.settings.heartbeat_interval;
+        let pauses = ctx.paused.clone();
+
+        thread::Builder::new().spawn(move || {
+            let result = send_prefix_events(
+                prefix,
+                range,
+                db,
+                subscriptions,
+                sender.clone(),
+                heartbeat,
+                pauses,
+            );
+            if let Err(e) = result {
+                if sender.send(Err(internal_error(e))).wait().is_err() {
+                    info!("encountered closed channel");
+                }
+            }
+        })?;
+
+        Ok(())
+    }
+}
+
+/// Answer the last event of a stream, `Nil` if the stream is empty.
+struct LastEvent {
+    stream: EsStreamName,
+}
+
+impl Handle for LastEvent {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let stream = self.stream;
+
+        let response = match last_event_number(&ctx.db, &stream)? {
+            Some(_) => {
+                let tree = ctx.db.open_tree(stream.clone().into_bytes())?;
+                match tree.iter().next_back() {
+                    Some(result) => {
+                        let (key, value) = result?;
+                        let number = parse_event_number(&stream, &key)?;
+                        match decode_event(&stream, number, &value) {
+                            Ok(response) => Ok(response),
+                            Err(e) => {
+                                let message = format!("event {} is corrupted; {}", number.0, e);
+                                Err(ServerError::new(ErrorCode::CorruptedEvent, message))
+                            }
+                        }
+                    }
+                    None => Ok(Response::Nil),
+                }
+            }
+            None => Ok(Response::Nil),
+        };
+
+        if sender.send(response).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// Stop sending the events of a stream to the subscriptions of this connection,
+/// the events are kept until the stream is resumed.
+struct Pause {
+    stream: EsStreamName,
+}
+
+impl Handle for Pause {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        ctx.paused.pause(self.stream);
+        if sender.send(Ok(Response::Ok)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+struct Resume {
+    stream: EsStreamName,
+}
+
+impl Handle for Resume {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        // acknowledged first, the events kept during the pause follow the response
+        if sender.send(Ok(Response::Ok)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+        ctx.paused.resume(&self.stream);
+
+        Ok(())
+    }
+}
+
+struct StreamSize {
+    stream: EsStreamName,
+}
+
+impl Handle for StreamSize {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        // measuring a stream must not create it
+        if last_event_number(&ctx.db, &self.stream)?.is_none() {
+            if sender
+                .send(Err(stream_not_found(&self.stream)))
+                .wait()
+                .is_err()
+            {
+                info!("encountered closed channel");
+            }
+            return Ok(());
+        }
+
+        let tree = ctx.db.open_tree(self.stream.clone().into_bytes())?;
+        let bytes = tree_size(&tree)?;
+
+        let stream_size = Response::StreamSize {
+            stream: self.stream,
+            bytes,
+        };
+        if sender.send(Ok(stream_size)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+struct TotalSize;
+
+impl Handle for TotalSize {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let tree_names = ctx
+            .db
+            .tree_names()
+            .into_iter()
+            .filter(|n| n != b"__sled__default");
+
+        let mut bytes = 0;
+        for name in tree_names {
+            let tree = ctx.db.open_tree(name)?;
+            bytes += tree_size(&tree)?;
+        }
+
+        let total_size = Response::TotalSize { bytes };
+        if sender.send(Ok(total_size)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+struct ListSubscriptions;
+
+impl Handle for ListSubscriptions {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let mut subscriptions: Vec<_> = ctx
+            .subscriptions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(stream, count)| (stream.clone(), *count))
+            .collect();
+        subscriptions.sort();
+
+        let response = Response::Subscriptions { subscriptions };
+        if sender.send(Ok(response)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// Send the events of a stream in blobs of `EXPORT_BATCH_SIZE` events,
+/// an `Ok` response follows the last blob.
+struct Export {
+    stream: EsStreamName,
+}
+
+impl Handle for Export {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        // exporting a stream must not create it
+        if last_event_number(&ctx.db, &self.stream)?.is_none() {
+            if sender
+                .send(Err(stream_not_found(&self.stream)))
+                .wait()
+                .is_err()
+            {
+                info!("encountered closed channel");
+            }
+            return Ok(());
+        }
+
+        let tree = ctx.db.open_tree(self.stream.clone().into_bytes())?;
+
+        let mut sender = sender;
+        for result in export_blobs(&tree, EXPORT_BATCH_SIZE) {
+            let response = Response::Exported {
+                stream: self.stream.clone(),
+                blob: result?,
+            };
+            sender = match sender.send(Ok(response)).wait() {
+                Ok(sender) => sender,
+                Err(_) => {
+                    info!("encountered closed channel");
+                    return Ok(());
+                }
+            };
+        }
+
+        if sender.send(Ok(Response::Ok)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// Append the events of a blob produced by `Export` to a stream,
+/// a blob that can not be decoded or that contains a corrupted event is answered with an error.
+struct Import {
+    stream: EsStreamName,
+    blob: Vec<u8>,
+}
+
+impl Handle for Import {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let events = match decode_blob(&self.blob) {
+            Some(events) => events,
+            None => {
+                let message = Error::InvalidBlob.to_string();
+                let error = ServerError::new(ErrorCode::InvalidRequest, message);
+                if sender.send(Err(error)).wait().is_err() {
+                    info!("encountered closed channel");
+                }
+                return Ok(());
+            }
+        };
+
+        if !events.is_empty() && !ctx.create_stream(&self.stream)? {
+            send_too_many_streams(&self.stream, sender);
+            return Ok(());
+        }
+
+        import_events(&ctx.db, &self.stream, events)?;
+
+        if sender.send(Ok(Response::Ok)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+struct SaveSnapshot {
+    stream: EsStreamName,
+    number: EventNumber,
+    data: Vec<u8>,
+}
+
+impl Handle for SaveSnapshot {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let SaveSnapshot {
+            stream,
+            number,
+            data,
+        } = self;
+
+        let response = save_snapshot(&ctx.db, &stream, number, &data)?.map(|()| Response::Ok);
+        if sender.send(response).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+struct GetSnapshot {
+    stream: EsStreamName,
+}
+
+impl Handle for GetSnapshot {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let response = match latest_snapshot(&ctx.db, &self.stream)? {
+            Some((number, data)) => Response::Snapshot {
+                stream: self.stream,
+                number,
+                data: data.to_vec(),
+            },
+            None => Response::Nil,
+        };
+        if sender.send(Ok(response)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// Fold the events of a stream with the snapshot function registered for it.
+struct CreateSnapshot {
+    stream: EsStreamName,
+}
+
+impl Handle for CreateSnapshot {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let stream = self.stream;
+
+        let response = match ctx.snapshot_fns.get(&stream) {
+            Some(fold) => create_snapshot(&ctx.db, &stream, fold)?,
+            None => {
+                let message = format!("no snapshot function registered for stream {}", stream);
+                Err(ServerError::new(ErrorCode::NotSupported, message))
+            }
+        };
+        if sender.send(response).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// Delete the events of a stream that its latest snapshot includes.
+struct Compact {
+    stream: EsStreamName,
+}
+
+impl Handle for Compact {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let response = compact_stream(&ctx.db, &self.stream)?;
+        if sender.send(response).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+struct Ping;
+
+impl Handle for Ping {
+    fn handle(self, _ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        if sender.send(Ok(Response::Pong)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// Report the version of the server and a few statistics, the number of published events
+/// is computed from the stream counters and includes the events removed since.
+struct Info;
+
+impl Handle for Info {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let streams = stream_names(&ctx.db);
+
+        let mut published_events = 0;
+        for name in &streams {
+            if let Some(number) = last_event_number(&ctx.db, name)? {
+                published_events += number.0 + 1;
+            }
+        }
+
+        let started_at = ctx.settings.started_at;
+        let response = Response::Info {
+            version: String::from(env!("CARGO_PKG_VERSION")),
+            uptime: started_at.map_or(0, |at| at.elapsed().as_secs()),
+            streams: streams.len() as u64,
+            published_events,
+            features: enabled_features(),
+        };
+        if sender.send(Ok(response)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// Check that every event of a stream can be decoded and that no number is missing.
+struct Verify {
+    stream: EsStreamName,
+}
+
+impl Handle for Verify {
+    fn handle(self, ctx: &ServerCtx, sender: ResponseSender) -> Result<(), Error> {
+        let response = match verify_stream(&ctx.db, &self.stream)? {
+            Some(report) => Ok(Response::Verified {
+                stream: self.stream,
+                events: report.events,
+                corrupted: report.corrupted,
+                missing: report.missing,
+            }),
+            None => Err(stream_not_found(&self.stream)),
+        };
+        if sender.send(response).wait().is_err() {
+            info!("encountered closed channel");
+        }
+
+        Ok(())
+    }
+}
+
+/// The events of a page with the number of the following event if any.
+type PageEvents = (Vec<Response>, Option<EventNumber>);
+
+/// Returns at most `limit` events starting at `from` and the number of the
+/// following event if any, the corrupted events are skipped.
+fn read_page(
+    stream: &EsStreamName,
+    tree: &Tree,
+    from: EventNumber,
+    limit: u64,
+) -> Result<PageEvents, Error> {
+    let mut events = Vec::new();
+
+    for result in tree.range(from.to_be_bytes()..) {
+        let (key, value) = result?;
+        let number = parse_event_number(stream, &key)?;
+
+        if events.len() as u64 >= limit {
+            return Ok((events, Some(number)));
+        }
+
+        events.extend(event_response(stream, number, &value));
+    }
+
+    Ok((events, None))
+}
+
+pub fn handle_request(
+    request: Request,
+    ctx: &ServerCtx,
+    sender: ResponseSender,
+) -> Result<(), Error> {
+    if ctx.settings.read_only {
+        if let Request::Publish { .. }
+        | Request::PublishMulti { .. }
+        | Request::PublishChunk { .. }
+        | Request::Import { .. }
+        | Request::SaveSnapshot { .. }
+        | Request::CreateSnapshot { .. }
+        | Request::Compact { .. }
+        | Request::ConfigureStream { .. }
+        | Request::CreateGroup { .. }
+        | Request::Claim { .. }
+        | Request::Ack { .. }
+        | Request::RedactEvent { .. } = request
+        {
+            let error = ServerError::new(ErrorCode::ReadOnly, "server is read-only");
+            if sender.send(Err(error)).wait().is_err() {
+                info!("encountered closed channel");
+            }
+            return Ok(());
+        }
+    }
+
+    match request {
+        Request::SubscribeAll { range, ordered } => {
+            SubscribeAll { range, ordered }.handle(ctx, sender)
+        }
+        Request::Subscribe {
+            streams,
+            require_existing,
+        } => Subscribe {
+            streams,
+            require_existing,
+        }
+        .handle(ctx, sender),
+        Request::SubscribePrefix { prefix, range } => {
+            SubscribePrefix { prefix, range }.handle(ctx, sender)
+        }
+        Request::Publish {
+            stream,
+            event_name,
+            event_data,
+            dedup_id,
+            headers,
+        } => Publish {
+            stream,
+            event_name,
+            event_data,
+            dedup_id,
+            headers,
+        }
+        .handle(ctx, sender),
+        Request::PublishMulti { events } => PublishMulti { events }.handle(ctx, sender),
+        Request::PublishChunk {
+            stream,
+            event_name,
+            chunk,
+            last,
+        } => PublishChunk {
+            stream,
+            event_name,
+            chunk,
+            last,
+        }
+        .handle(ctx, sender),
+        Request::LastEventNumber { stream } => LastEventNumber { stream }.handle(ctx, sender),
+        Request::LastEvent { stream } => LastEvent { stream }.handle(ctx, sender),
+        Request::StreamNames => StreamNames.handle(ctx, sender),
+        Request::ConfigureStream { stream, settings } => {
+            ConfigureStream { stream, settings }.handle(ctx, sender)
+        }
+        Request::Page {
+            stream,
+            from,
+            limit,
+        } => Page {
+            stream,
+            from,
+            limit,
+        }
+        .handle(ctx, sender),
+        Request::WaitFor { stream, number } => WaitFor { stream, number }.handle(ctx, sender),
+        Request::RedactEvent { stream, number } => {
+            RedactEvent { stream, number }.handle(ctx, sender)
+        }
+        Request::Pause { stream } => Pause { stream }.handle(ctx, sender),
+        Request::Resume { stream } => Resume { stream }.handle(ctx, sender),
+        Request::CreateGroup { stream, group } => CreateGroup { stream, group }.handle(ctx, sender),
+        Request::Claim { stream, group } => Claim { stream, group }.handle(ctx, sender),
+        Request::Ack {
+            stream,
+            group,
+            number,
+        } => Ack {
+            stream,
+            group,
+            number,
+        }
+        .handle(ctx, sender),
+        Request::StreamSize { stream } => StreamSize { stream }.handle(ctx, sender),
+        Request::TotalSize => TotalSize.handle(ctx, sender),
+        Request::ListSubscriptions => ListSubscriptions.handle(ctx, sender),
+        Request::Export { stream } => Export { stream }.handle(ctx, sender),
+        Request::SaveSnapshot {
+            stream,
+            number,
+            data,
+        } => SaveSnapshot {
+            stream,
+            number,
+            data,
+        }
+        .handle(ctx, sender),
+        Request::GetSnapshot { stream } => GetSnapshot { stream }.handle(ctx, sender),
+        Request::CreateSnapshot { stream } => CreateSnapshot { stream }.handle(ctx, sender),
+        Request::Compact { stream } => Compact { stream }.handle(ctx, sender),
+        Request::Ping => Ping.handle(ctx, sender),
+        Request::Info => Info.handle(ctx, sender),
+        Request::Verify { stream } => Verify { stream }.handle(ctx, sender),
+        Request::Import { stream, blob } => Import { stream, blob }.handle(ctx, sender),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::Server;
+    use crate::storage::save_event;
+    use meilies_client::{paired_connect, PairedConnectionError};
+    use sled::Config;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn read_only_rejects_publish() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("read-only".to_owned()).unwrap();
+        let settings = Settings {
+            read_only: true,
+            ..Settings::default()
+        };
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Publish {
+            stream: stream.clone(),
+            event_name: EventName::new("event".to_owned()).unwrap(),
+            event_data: EventData(b"data".to_vec()),
+            dedup_id: None,
+            headers: HashMap::new(),
+        };
+        handle_request(request, &ServerCtx::new(db.clone(), settings), sender).unwrap();
+
+        let response = receiver.wait().next().unwrap().unwrap();
+        let error = ServerError::new(ErrorCode::ReadOnly, "server is read-only");
+        assert_eq!(response, Err(error));
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Subscribe {
+            streams: vec![EsStream::from(stream.clone())],
+            require_existing: false,
+        };
+        handle_request(request, &ServerCtx::new(db, settings), sender).unwrap();
+
+        let response = receiver.wait().next().unwrap().unwrap();
+        assert_eq!(response, Ok(Response::Subscribed { stream }));
+    }
+
+    #[test]
+    fn last_event_number_follows_publish() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("last-event-number".to_owned()).unwrap();
+
+        let last_event_number = |db: &Db| {
+            let (sender, receiver) = mpsc::channel(10);
+            let request = Request::LastEventNumber {
+                stream: stream.clone(),
+            };
+            handle_request(
+                request,
+                &ServerCtx::new(db.clone(), Settings::default()),
+                sender,
+            )
+            .unwrap();
+            receiver.wait().next().unwrap().unwrap()
+        };
+
+        let response = last_event_number(&db);
+        let expected = Response::LastEventNumber {
+            stream: stream.clone(),
+            number: None,
+        };
+        assert_eq!(response, Ok(expected));
+
+        for _ in 0..3 {
+            let (sender, receiver) = mpsc::channel(10);
+            let request = Request::Publish {
+                stream: stream.clone(),
+                event_name: EventName::new("event".to_owned()).unwrap(),
+                event_data: EventData(b"data".to_vec()),
+                dedup_id: None,
+                headers: HashMap::new(),
+            };
+            handle_request(
+                request,
+                &ServerCtx::new(db.clone(), Settings::default()),
+                sender,
+            )
+            .unwrap();
+            assert_eq!(receiver.wait().next().unwrap().unwrap(), Ok(Response::Ok));
+        }
+
+        let response = last_event_number(&db);
+        let expected = Response::LastEventNumber {
+            stream: stream.clone(),
+            number: Some(EventNumber(2)),
+        };
+        assert_eq!(response, Ok(expected));
+    }
+
+    #[test]
+    fn info_reports_version_and_counts() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let settings = Settings::default();
+
+        for (stream, count) in &[("first", 2), ("second", 3)] {
+            let stream = EsStreamName::new(stream.to_string()).unwrap();
+            for _ in 0..*count {
+                let event_data = EventData(b"data".to_vec());
+                save_event(&db, &stream, &event_name, event_data, None, settings).unwrap();
+            }
+        }
+
+        // the events removed by the retention are still counted as published
+        let second = db.open_tree("second").unwrap();
+        crate::retention::apply_retention(&second, 1).unwrap();
+
+        let (sender, receiver) = mpsc::channel(10);
+        handle_request(Request::Info, &ServerCtx::new(db, settings), sender).unwrap();
+
+        match receiver.wait().next().unwrap().unwrap() {
+            Ok(Response::Info {
+                version,
+                streams,
+                published_events,
+                ..
+            }) => {
+                assert_eq!(version, env!("CARGO_PKG_VERSION"));
+                assert_eq!(streams, 2);
+                assert_eq!(published_events, 5);
+            }
+            otherwise => panic!("unexpected response {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn max_streams_rejects_new_streams() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let settings = Settings {
+            max_streams: Some(2),
+            ..Settings::default()
+        };
+        let ctx = ServerCtx::new(db.clone(), settings);
+
+        let publish = |name: &str| {
+            let (sender, receiver) = mpsc::channel(10);
+            let request = Request::Publish {
+                stream: EsStreamName::new(name.to_owned()).unwrap(),
+                event_name: EventName::new("event".to_owned()).unwrap(),
+                event_data: EventData(b"data".to_vec()),
+                dedup_id: None,
+                headers: HashMap::new(),
+            };
+            handle_request(request, &ctx, sender).unwrap();
+            receiver.wait().next().unwrap().unwrap()
+        };
+
+        assert_eq!(publish("first"), Ok(Response::Ok));
+        assert_eq!(publish("second"), Ok(Response::Ok));
+
+        let error = "stream third can not be created, too many streams";
+        let error = ServerError::new(ErrorCode::TooManyStreams, error);
+        assert_eq!(publish("third"), Err(error));
+        assert_eq!(publish("first"), Ok(Response::Ok));
+
+        let stream = EsStreamName::new("fourth".to_owned()).unwrap();
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Subscribe {
+            streams: vec![EsStream::from(stream)],
+            require_existing: false,
+        };
+        handle_request(request, &ctx, sender).unwrap();
+
+        let response = receiver.wait().next().unwrap().unwrap();
+        let error = "stream fourth can not be created, too many streams";
+        let error = ServerError::new(ErrorCode::TooManyStreams, error);
+        assert_eq!(response, Err(error));
+        assert_eq!(stream_names(&db).len(), 2);
+    }
+
+    #[test]
+    fn publish_multi_is_atomic() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let ctx = ServerCtx::new(db.clone(), Settings::default());
+        let orders = EsStreamName::new("orders".to_owned()).unwrap();
+        let audit = EsStreamName::new("audit".to_owned()).unwrap();
+        let broken = EsStreamName::new("broken".to_owned()).unwrap();
+        let event = |stream: &EsStreamName, name: &str| {
+            let event_name = EventName::new(name.to_owned()).unwrap();
+            (stream.clone(), event_name, EventData(b"data".to_vec()))
+        };
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::PublishMulti {
+            events: vec![
+                event(&orders, "created"),
+                event(&audit, "order-created"),
+                event(&orders, "paid"),
+            ],
+        };
+        handle_request(request, &ctx, sender).unwrap();
+
+        let response = receiver.wait().next().unwrap().unwrap();
+        let expected = Response::PublishedMulti {
+            events: vec![
+                (orders.clone(), EventNumber(0)),
+                (audit.clone(), EventNumber(0)),
+                (orders.clone(), EventNumber(1)),
+            ],
+        };
+        assert_eq!(response, Ok(expected));
+
+        // the counter of the last stream is corrupted, the whole batch must be rejected
+        db.insert(broken.as_str(), &b"abc"[..]).unwrap();
+        let (sender, _receiver) = mpsc::channel(10);
+        let request = Request::PublishMulti {
+            events: vec![
+                event(&orders, "created"),
+                event(&audit, "order-created"),
+                event(&broken, "created"),
+            ],
+        };
+        match handle_request(request, &ctx, sender) {
+            Err(Error::CorruptedEventNumber(name)) => assert_eq!(name, broken),
+            otherwise => panic!("unexpected result {:?}", otherwise),
+        }
+
+        assert_eq!(
+            last_event_number(&db, &orders).unwrap(),
+            Some(EventNumber(1))
+        );
+        assert_eq!(
+            last_event_number(&db, &audit).unwrap(),
+            Some(EventNumber(0))
+        );
+        assert_eq!(db.open_tree("orders").unwrap().len(), 2);
+        assert_eq!(db.open_tree("audit").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn publish_multi_rejects_too_many_streams() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let ctx = ServerCtx::new(db.clone(), Settings::default());
+
+        let events = (0..=PUBLISH_MULTI_MAX_STREAMS)
+            .map(|i| {
+                let stream = EsStreamName::new(format!("stream-{}", i)).unwrap();
+                let event_name = EventName::new("created".to_owned()).unwrap();
+                (stream, event_name, EventData(b"data".to_vec()))
+            })
+            .collect();
+
+        let (sender, receiver) = mpsc::channel(10);
+        handle_request(Request::PublishMulti { events }, &ctx, sender).unwrap();
+
+        match receiver.wait().next().unwrap().unwrap() {
+            Err(error) => assert_eq!(error.code, Some(ErrorCode::InvalidRequest)),
+            Ok(response) => panic!("unexpected response {:?}", response),
+        }
+        assert!(db
+            .tree_names()
+            .iter()
+            .all(|name| !name.starts_with(b"stream-")));
+    }
+
+    #[test]
+    fn last_event() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("last-event".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        let last_event = |db: &Db| {
+            let (sender, receiver) = mpsc::channel(10);
+            let request = Request::LastEvent {
+                stream: stream.clone(),
+            };
+            handle_request(
+                request,
+                &ServerCtx::new(db.clone(), Settings::default()),
+                sender,
+            )
+            .unwrap();
+            receiver.wait().next().unwrap().unwrap()
+        };
+
+        assert_eq!(last_event(&db), Ok(Response::Nil));
+
+        for data in &[&b"first"[..], &b"second"[..]] {
+            let event_data = EventData(data.to_vec());
+            save_event(
+                &db,
+                &stream,
+                &event_name,
+                event_data,
+                None,
+                Settings::default(),
+            )
+            .unwrap();
+        }
+
+        let response = last_event(&db);
+        let timestamp = match &response {
+            Ok(Response::Event { timestamp, .. }) => *timestamp,
+            otherwise => panic!("unexpected response {:?}", otherwise),
+        };
+        assert!(timestamp.is_some());
+
+        let expected = Response::Event {
+            stream: stream.clone(),
+            number: EventNumber(1),
+            event_name,
+            event_data: EventData(b"second".to_vec()),
+            global: None,
+            headers: HashMap::new(),
+            redacted: false,
+            timestamp,
+        };
+        assert_eq!(response, Ok(expected));
+    }
+
+    #[test]
+    fn stream_settings_are_honored() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let ctx = ServerCtx::new(db.clone(), Settings::default());
+        let limited = EsStreamName::new("limited".to_owned()).unwrap();
+        let unlimited = EsStreamName::new("unlimited".to_owned()).unwrap();
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::ConfigureStream {
+            stream: limited.clone(),
+            settings: StreamSettings {
+                compressed: true,
+                max_events: Some(3),
+                max_event_size: Some(4),
+            },
+        };
+        handle_request(request, &ctx, sender).unwrap();
+        assert_eq!(receiver.wait().next().unwrap().unwrap(), Ok(Response::Ok));
+
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        for stream in &[&limited, &unlimited] {
+            for _ in 0..5 {
+                let event_data = EventData(b"data".to_vec());
+                save_event(
+                    &db,
+                    stream,
+                    &event_name,
+                    event_data,
+                    None,
+                    Settings::default(),
+                )
+                .unwrap();
+            }
+        }
+
+        assert_eq!(db.open_tree("limited").unwrap().len(), 3);
+        assert_eq!(db.open_tree("unlimited").unwrap().len(), 5);
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Publish {
+            stream: limited,
+            event_name,
+            event_data: EventData(b"large".to_vec()),
+            dedup_id: None,
+            headers: HashMap::new(),
+        };
+        handle_request(request, &ctx, sender).unwrap();
+
+        let response = receiver.wait().next().unwrap().unwrap();
+        let error = ServerError::new(ErrorCode::EventTooLarge, "event exceeds max size");
+        assert_eq!(response, Err(error));
+    }
+
+    #[test]
+    fn max_event_size_is_inclusive() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let settings = Settings {
+            max_event_size: Some(4),
+            ..Settings::default()
+        };
+        let ctx = ServerCtx::new(db.clone(), settings);
+        let stream = EsStreamName::new("sized".to_owned()).unwrap();
+
+        let publish = |data: &[u8]| {
+            let (sender, receiver) = mpsc::channel(10);
+            let request = Request::Publish {
+                stream: stream.clone(),
+                event_name: EventName::new("event".to_owned()).unwrap(),
+                event_data: EventData(data.to_vec()),
+                dedup_id: None,
+                headers: HashMap::new(),
+            };
+            handle_request(request, &ctx, sender).unwrap();
+            receiver.wait().next().unwrap().unwrap()
+        };
+
+        assert_eq!(publish(b"abc"), Ok(Response::Ok));
+        assert_eq!(publish(b"abcd"), Ok(Response::Ok));
+
+        let error = publish(b"abcde").unwrap_err();
+        assert_eq!(error.code, Some(ErrorCode::EventTooLarge));
+
+        assert_eq!(db.open_tree("sized").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn maybe_flush_resets_after_the_threshold() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let settings = Settings {
+            flush_every: Some(2),
+            ..Settings::default()
+        };
+        let ctx = ServerCtx::new(db, settings);
+
+        ctx.maybe_flush().unwrap();
+        assert_eq!(ctx.unflushed.load(Ordering::SeqCst), 1);
+        ctx.maybe_flush().unwrap();
+        assert_eq!(ctx.unflushed.load(Ordering::SeqCst), 0);
+        ctx.maybe_flush().unwrap();
+        assert_eq!(ctx.unflushed.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn wait_for_completes_once_published() {
+        let server = Server::builder()
+            .listen("127.0.0.1:0".parse().unwrap())
+            .temporary(true)
+            .build()
+            .unwrap();
+
+        let addr = server.local_addrs().unwrap()[0];
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.spawn(server.run());
+
+        let stream = EsStreamName::new("awaited".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let event_data = EventData(b"data".to_vec());
+
+        let waiter = runtime.block_on(paired_connect(addr)).unwrap();
+        let (done, reached) = std::sync::mpsc::channel();
+        let wait = waiter
+            .wait_for(stream.clone(), EventNumber(1), Some(Duration::from_secs(5)))
+            .then(move |result| {
+                done.send(result.is_ok()).unwrap();
+                Ok(())
+            });
+        runtime.spawn(wait);
+
+        let mut conn = runtime.block_on(paired_connect(addr)).unwrap();
+        let fut = conn.publish(stream.clone(), event_name.clone(), event_data.clone());
+        conn = runtime.block_on(fut).unwrap();
+        assert!(reached.recv_timeout(Duration::from_millis(200)).is_err());
+
+        let fut = conn.publish(stream.clone(), event_name, event_data);
+        let conn = runtime.block_on(fut).unwrap();
+        assert_eq!(reached.recv_timeout(Duration::from_secs(5)), Ok(true));
+
+        // the number is already reached, the server answers right away
+        let fut = conn.wait_for(stream.clone(), EventNumber(0), None);
+        let conn = runtime.block_on(fut).unwrap();
+
+        let fut = conn.wait_for(stream, EventNumber(10), Some(Duration::from_millis(100)));
+        match runtime.block_on(fut) {
+            Err(PairedConnectionError::TimedOut(_)) => (),
+            otherwise => panic!("unexpected result {:?}", otherwise.map(drop)),
+        }
+    }
+
+    #[test]
+    fn publish_big_event_in_chunks() {
+        let server = Server::builder()
+            .listen("127.0.0.1:0".parse().unwrap())
+            .temporary(true)
+            .build()
+            .unwrap();
+
+        let addr = server.local_addrs().unwrap()[0];
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.spawn(server.run());
+
+        let stream = EsStreamName::new("chunked".to_owned()).unwrap();
+        let event_name = EventName::new("uploaded".to_owned()).unwrap();
+        let data: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let event_data = EventData(data.clone());
+
+        let fut = paired_connect(addr)
+            .map_err(|e| e.to_string())
+            .and_then(move |conn| {
+                let range = conn
+                    .publish_chunked(stream.clone(), event_name, event_data, 64 * 1024)
+                    .and_then(move |conn| conn.read_range(stream, EventNumber(0), EventNumber(1)));
+                range.map_err(|e| e.to_string())
+            });
+        let (events, _) = runtime.block_on(fut).unwrap();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].2, EventData(data));
+    }
+
+    #[test]
+    fn ping_answers_pong() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let (sender, receiver) = mpsc::channel(10);
+
+        handle_request(
+            Request::Ping,
+            &ServerCtx::new(db, Settings::default()),
+            sender,
+        )
+        .unwrap();
+
+        let responses: Vec<_> = receiver.wait().map(|r| r.unwrap().unwrap()).collect();
+        assert_eq!(responses, vec![Response::Pong]);
+    }
+
+    #[test]
+    fn handlers_answer_without_dispatch() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let ctx = ServerCtx::new(db, Settings::default());
+        let stream = EsStreamName::new("handled".to_owned()).unwrap();
+        let (sender, receiver) = mpsc::channel(10);
+
+        let publish = Publish {
+            stream: stream.clone(),
+            event_name: EventName::new("event".to_owned()).unwrap(),
+            event_data: EventData(b"data".to_vec()),
+            dedup_id: None,
+            headers: HashMap::new(),
+        };
+        publish.handle(&ctx, sender.clone()).unwrap();
+
+        let last_event_number = LastEventNumber {
+            stream: stream.clone(),
+        };
+        last_event_number.handle(&ctx, sender.clone()).unwrap();
+        StreamNames.handle(&ctx, sender).unwrap();
+
+        let responses: Vec<_> = receiver.wait().map(|r| r.unwrap().unwrap()).collect();
+        let expected = vec![
+            Response::Ok,
+            Response::LastEventNumber {
+                stream: stream.clone(),
+                number: Some(EventNumber(0)),
+            },
+            Response::StreamNames {
+                streams: vec![stream],
+            },
+        ];
+        assert_eq!(responses, expected);
+    }
+
+    #[test]
+    fn page_reads_full_and_partial_pages() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let ctx = ServerCtx::new(db.clone(), Settings::default());
+        let stream = EsStreamName::new("paged".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        for i in 0..5u8 {
+            let data = EventData(vec![i]);
+            save_event(&db, &stream, &event_name, data, None, Settings::default()).unwrap();
+        }
+
+        let page = |from, limit| {
+            let (sender, receiver) = mpsc::channel(10);
+            let request = Request::Page {
+                stream: stream.clone(),
+                from: EventNumber(from),
+                limit,
+            };
+            handle_request(request, &ctx, sender).unwrap();
+            match receiver.wait().next().unwrap().unwrap() {
+                Ok(Response::Page { events, next, .. }) => {
+                    let numbers = events.iter().map(|event| match event {
+                        Response::Event { number, .. } => number.0,
+                        otherwise => panic!("unexpected event {:?}", otherwise),
+                    });
+                    (numbers.collect::<Vec<_>>(), next)
+                }
+                otherwise => panic!("unexpected response {:?}", otherwise),
+            }
+        };
+
+        assert_eq!(page(0, 3), (vec![0, 1, 2], Some(EventNumber(3))));
+        assert_eq!(page(3, 3), (vec![3, 4], None));
+        assert_eq!(page(1, 4), (vec![1, 2, 3, 4], None));
+        assert_eq!(page(5, 3), (vec![], None));
+
+        let missing = EsStreamName::new("missing".to_owned()).unwrap();
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Page {
+            stream: missing.clone(),
+            from: EventNumber(0),
+            limit: 10,
+        };
+        handle_request(request, &ctx, sender).unwrap();
+        let expected = Response::Page {
+            stream: missing,
+            events: Vec::new(),
+            next: None,
+        };
+        assert_eq!(receiver.wait().next().unwrap().unwrap(), Ok(expected));
+        assert!(db.tree_names().iter().all(|n| n != b"missing"));
+    }
+
+    #[test]
+    fn page_limit_is_capped_and_events_are_fully_decoded() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let ctx = ServerCtx::new(db.clone(), Settings::default());
+        let stream = EsStreamName::new("paged".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let mut headers = HashMap::new();
+        headers.insert("correlation-id".to_owned(), "42".to_owned());
+
+        for i in 0..=PAGE_MAX_EVENTS {
+            let data = EventData(i.to_be_bytes().to_vec());
+            let settings = Settings::default();
+            save_event_with_headers(&db, &stream, &event_name, data, &headers, None, settings)
+                .unwrap();
+        }
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Page {
+            stream: stream.clone(),
+            from: EventNumber(0),
+            limit: u64::MAX,
+        };
+        handle_request(request, &ctx, sender).unwrap();
+        match receiver.wait().next().unwrap().unwrap() {
+            Ok(Response::Page { events, next, .. }) => {
+                assert_eq!(events.len() as u64, PAGE_MAX_EVENTS);
+                assert_eq!(next, Some(EventNumber(PAGE_MAX_EVENTS)));
+                match &events[0] {
+                    Response::Event {
+                        headers: event_headers,
+                        timestamp,
+                        redacted,
+                        ..
+                    } => {
+                        assert_eq!(event_headers, &headers);
+                        assert!(timestamp.is_some());
+                        assert!(!redacted);
+                    }
+                    otherwise => panic!("unexpected event {:?}", otherwise),
+                }
+            }
+            otherwise => panic!("unexpected response {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn headers_round_trip_through_publish_and_subscribe() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let ctx = ServerCtx::new(db.clone(), Settings::default());
+        let stream = EsStreamName::new("orders".to_owned()).unwrap();
+        let mut headers = HashMap::new();
+        headers.insert("correlation-id".to_owned(), "42".to_owned());
+        headers.insert("content-type".to_owned(), "text/plain".to_owned());
+
+        for headers in [headers.clone(), HashMap::new()] {
+            let (sender, receiver) = mpsc::channel(10);
+            let request = Request::Publish {
+                stream: stream.clone(),
+                event_name: EventName::new("created".to_owned()).unwrap(),
+                event_data: EventData(b"data".to_vec()),
+                dedup_id: None,
+                headers,
+            };
+            handle_request(request, &ctx, sender).unwrap();
+            assert_eq!(receiver.wait().next().unwrap().unwrap(), Ok(Response::Ok));
+        }
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Subscribe {
+            streams: vec![EsStream::new(stream.clone(), ReadRange::ReadFrom(0))],
+            require_existing: false,
+        };
+        handle_request(request, &ctx, sender).unwrap();
+
+        let received: Vec<_> = receiver
+            .wait()
+            .map(|r| r.unwrap().unwrap())
+            .take_while(|r| match r {
+                Response::CaughtUp { .. } => false,
+                _ => true,
+            })
+            .filter_map(|r| match r {
+                Response::Event { headers, .. } => Some(headers),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(received, vec![headers, HashMap::new()]);
+    }
+
+    fn respond(ctx: &ServerCtx, request: Request) -> Result<Response, ServerError> {
+        let (sender, receiver) = mpsc::channel(10);
+        handle_request(request, ctx, sender).unwrap();
+        receiver.wait().next().unwrap().unwrap()
+    }
+
+    fn claimed_number(response: Result<Response, ServerError>) -> Option<u64> {
+        match response {
+            Ok(Response::Event { number, .. }) => Some(number.0),
+            Ok(Response::Nil) => None,
+            otherwise => panic!("unexpected response {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn consumers_of_a_group_do_not_share_claimed_events() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let first = ServerCtx::new(db.clone(), Settings::default());
+        let second = ServerCtx {
+            consumer: Arc::default(),
+            ..first.clone()
+        };
+        let stream = EsStreamName::new("jobs".to_owned()).unwrap();
+        let group = String::from("workers");
+        let claim = Request::Claim {
+            stream: stream.clone(),
+            group: group.clone(),
+        };
+        let ack = |number| Request::Ack {
+            stream: stream.clone(),
+            group: group.clone(),
+            number: EventNumber(number),
+        };
+
+        let error = respond(&first, claim.clone()).unwrap_err();
+        assert_eq!(error.code, Some(ErrorCode::GroupNotFound));
+
+        let event_name = EventName::new("job".to_owned()).unwrap();
+        for _ in 0..2 {
+            let data = EventData(b"data".to_vec());
+            save_event(&db, &stream, &event_name, data, None, Settings::default()).unwrap();
+        }
+        let create = Request::CreateGroup {
+            stream: stream.clone(),
+            group: group.clone(),
+        };
+        assert_eq!(respond(&first, create), Ok(Response::Ok));
+
+        assert_eq!(claimed_number(respond(&first, claim.clone())), Some(0));
+        assert_eq!(claimed_number(respond(&second, claim.clone())), Some(1));
+        assert_eq!(claimed_number(respond(&first, claim.clone())), None);
+
+        let error = respond(&second, ack(0)).unwrap_err();
+        assert_eq!(error.code, Some(ErrorCode::InvalidRequest));
+        assert_eq!(respond(&first, ack(0)), Ok(Response::Ok));
+        assert_eq!(respond(&second, ack(1)), Ok(Response::Ok));
+        assert_eq!(claimed_number(respond(&second, claim)), None);
+    }
+
+    #[test]
+    fn unacknowledged_events_are_claimed_again() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let ctx = ServerCtx::new(db.clone(), Settings::default());
+        let stream = EsStreamName::new("jobs".to_owned()).unwrap();
+        let group = String::from("workers");
+        let claim = Request::Claim {
+            stream: stream.clone(),
+            group: group.clone(),
+        };
+
+        let event_name = EventName::new("job".to_owned()).unwrap();
+        for _ in 0..2 {
+            let data = EventData(b"data".to_vec());
+            save_event(&db, &stream, &event_name, data, None, Settings::default()).unwrap();
+        }
+        let create = Request::CreateGroup {
+            stream: stream.clone(),
+            group: group.clone(),
+        };
+        assert_eq!(respond(&ctx, create), Ok(Response::Ok));
+
+        // the connection that claimed the event crashes before acknowledging it
+        let crashed = ServerCtx {
+            consumer: Arc::default(),
+            ..ctx.clone()
+        };
+        assert_eq!(claimed_number(respond(&crashed, claim.clone())), Some(0));
+        drop(crashed);
+        assert_eq!(claimed_number(respond(&ctx, claim.clone())), Some(0));
+
+        // the pending events are persisted, a restarted server delivers them again
+        let restarted = ServerCtx::new(db, Settings::default());
+        assert_eq!(claimed_number(respond(&restarted, claim.clone())), Some(0));
+        let ack = Request::Ack {
+            stream,
+            group,
+            number: EventNumber(0),
+        };
+        assert_eq!(respond(&restarted, ack), Ok(Response::Ok));
+        assert_eq!(claimed_number(respond(&restarted, claim)), Some(1));
+    }
+
+    #[test]
+    fn historical_reads_return_redacted_events() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let ctx = ServerCtx::new(db.clone(), Settings::default());
+        let stream = EsStreamName::new("users".to_owned()).unwrap();
+        let event_name = EventName::new("registered".to_owned()).unwrap();
+        for data in &[&b"alice@example.com"[..], &b"bob@example.com"[..]] {
+            let data = EventData(data.to_vec());
+            save_event(&db, &stream, &event_name, data, None, Settings::default()).unwrap();
+        }
+
+        let redact = |number| Request::RedactEvent {
+            stream: stream.clone(),
+            number: EventNumber(number),
+        };
+        assert_eq!(respond(&ctx, redact(0)), Ok(Response::Ok));
+        let error = respond(&ctx, redact(2)).unwrap_err();
+        assert_eq!(error.code, Some(ErrorCode::EventNotFound));
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Subscribe {
+            streams: vec![EsStream::new(stream.clone(), ReadRange::ReadFrom(0))],
+            require_existing: false,
+        };
+        handle_request(request, &ctx, sender).unwrap();
+
+        let events: Vec<_> = receiver
+            .wait()
+            .map(|r| r.unwrap().unwrap())
+            .take_while(|r| match r {
+                Response::CaughtUp { .. } => false,
+                _ => true,
+            })
+            .filter_map(|r| match r {
+                Response::Event {
+                    event_name,
+                    event_data,
+                    redacted,
+                    ..
+                } => Some((event_name, event_data.0, redacted)),
+                _ => None,
+            })
+            .collect();
+
+        let expected = vec![
+            (event_name.clone(), Vec::new(), true),
+            (event_name, b"bob@example.com".to_vec(), false),
+        ];
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn live_subscription_skips_redacted_events() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let ctx = ServerCtx::new(db.clone(), Settings::default());
+        let stream = EsStreamName::new("users".to_owned()).unwrap();
+        let event_name = EventName::new("registered".to_owned()).unwrap();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Subscribe {
+            streams: vec![EsStream::from(stream.clone())],
+            require_existing: false,
+        };
+        let subscribe = {
+            let ctx = ctx.clone();
+            future::lazy(move || handle_request(request, &ctx, sender))
+        };
+        runtime.block_on(subscribe).unwrap();
+
+        let mut next = |receiver: mpsc::Receiver<Result<Response, ServerError>>| {
+            let next = receiver.into_future().map_err(|(e, _)| e);
+            let (response, receiver) = runtime.block_on(next).unwrap();
+            (response.unwrap().unwrap(), receiver)
+        };
+        let number = |response| match response {
+            Response::Event { number, .. } => number,
+            otherwise => panic!("unexpected response {:?}", otherwise),
+        };
+
+        let (_subscribed, receiver) = next(receiver);
+        let data = EventData(b"alice@example.com".to_vec());
+        save_event(&db, &stream, &event_name, data, None, Settings::default()).unwrap();
+        let (event, receiver) = next(receiver);
+        assert_eq!(number(event), EventNumber(0));
+
+        // the tombstone of the event already sent is not sent again
+        let request = Request::RedactEvent {
+            stream: stream.clone(),
+            number: EventNumber(0),
+        };
+        assert_eq!(respond(&ctx, request), Ok(Response::Ok));
+        let data = EventData(b"bob@example.com".to_vec());
+        save_event(&db, &stream, &event_name, data, None, Settings::default()).unwrap();
+        let (event, _receiver) = next(receiver);
+        assert_eq!(number(event), EventNumber(1));
+    }
+
+    #[test]
+    fn stream_size_and_export_of_a_missing_stream() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let ctx = ServerCtx::new(db.clone(), Settings::default());
+        let stream = EsStreamName::new("missing".to_owned()).unwrap();
+
+        let requests = vec![
+            Request::StreamSize {
+                stream: stream.clone(),
+            },
+            Request::Export {
+                stream: stream.clone(),
+            },
+        ];
+        for request in requests {
+            match respond(&ctx, request) {
+                Err(error) => assert_eq!(error.code, Some(ErrorCode::StreamNotFound)),
+                otherwise => panic!("unexpected response {:?}", otherwise),
+            }
+        }
+
+        assert!(stream_names(&db).is_empty());
+    }
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::io::Error as IoError;
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+
+use meilies::reqresp::RequestMsgError;
+use meilies::reqresp::{Response, ServerError};
+use meilies::resp::{RespBytesConvertError, RespMsgError, RespVecConvertError};
+use meilies::stream::StreamName as EsStreamName;
+
+mod builder;
+mod dedup;
+mod export;
+mod handlers;
+mod replicate;
+mod retention;
+mod serve;
+mod snapshot;
+mod storage;
+mod subscriptions;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+
+pub use self::builder::{Server, ServerBuilder};
+pub use self::storage::{ParseStreamDeclarationError, StreamDeclaration};
+
+/// The settings of the server that are used when handling requests.
+#[derive(Debug, Default, Clone, Copy)]
+struct Settings {
+    max_event_size: Option<usize>,
+    max_events: Option<usize>,
+    retention_ttl: Option<Duration>,
+    max_streams: Option<usize>,
+    read_only: bool,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    heartbeat_interval: Option<Duration>,
+    max_connections: Option<usize>,
+    max_requests_per_sec: Option<u32>,
+    global_order: bool,
+    event_compression: Option<i32>,
+    flush_interval: Option<Duration>,
+    flush_every: Option<usize>,
+    started_at: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    RequestMsgError(RequestMsgError),
+    InvalidRequest,
+    InternalError(sled::Error),
+    IoError(IoError),
+    CorruptedEventNumber(EsStreamName),
+    InvalidBlob,
+    ReadTimeout(Duration),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::RequestMsgError(e) => write!(f, "invalid request message; {}", e),
+            Error::InvalidRequest => write!(f, "invalid request"),
+            Error::InternalError(e) => write!(f, "internal error; {}", e),
+            Error::IoError(e) => write!(f, "io error; {}", e),
+            Error::CorruptedEventNumber(stream) => {
+                write!(f, "corrupted event number of stream {}", stream)
+            }
+            Error::InvalidBlob => write!(f, "invalid import blob"),
+            Error::ReadTimeout(timeout) => write!(f, "no request received since {:.2?}", timeout),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::RequestMsgError(e) => Some(e),
+            Error::InternalError(e) => Some(e),
+            Error::IoError(e) => Some(e),
+            Error::InvalidRequest
+            | Error::CorruptedEventNumber(_)
+            | Error::InvalidBlob
+            | Error::ReadTimeout(_) => None,
+        }
+    }
+}
+
+impl From<sled::Error> for Error {
+    fn from(error: sled::Error) -> Error {
+        Error::InternalError(error)
+    }
+}
+
+impl From<RespVecConvertError<RespBytesConvertError>> for Error {
+    fn from(_: RespVecConvertError<RespBytesConvertError>) -> Error {
+        Error::InvalidRequest
+    }
+}
+
+impl From<IoError> for Error {
+    fn from(error: IoError) -> Error {
+        Error::IoError(error)
+    }
+}
+
+type ResponseSender = mpsc::Sender<Result<Response, ServerError>>;
@@ -1,36 +1,13 @@
-use std::convert::TryFrom;
-use std::fmt;
-use std::io::{Error as IoError, ErrorKind};
 use std::net::SocketAddr;
 use std::path::PathBuf;
-use std::thread;
-use std::time::Instant;
+use std::time::Duration;
 
-use log::{error, info};
-use sled::{Config, Db, Event, IVec, Tree};
+use log::{error, LevelFilter};
 use structopt::StructOpt;
-use tokio::codec::Decoder;
-use tokio::net::TcpListener;
-use tokio::prelude::*;
-use tokio::sync::mpsc;
-
-use meilies::reqresp::{Request, Response, ServerCodec};
-use meilies::reqresp::{RequestMsgError, ResponseMsgError};
-use meilies::resp::{RespBytesConvertError, RespMsgError, RespVecConvertError};
-use meilies::stream::{
-    EventNumber, RawEvent, ReadRange, Stream as EsStream, StreamName as EsStreamName,
-};
-
-fn new_event_number(numbers: &Tree, name: &EsStreamName) -> sled::Result<EventNumber> {
-    let new_value = numbers.update_and_fetch(name, |previous| {
-        let previous = previous.map(|s| EventNumber::try_from(s).unwrap());
-        let new = previous.map_or(EventNumber::zero(), EventNumber::next);
-        let slice = &new.to_be_bytes()[..];
-        Some(IVec::from(slice))
-    })?;
-
-    Ok(EventNumber::try_from(new_value.unwrap().as_ref()).unwrap())
-}
+
+use meilies::stream::StreamName;
+use meilies_client::ServerAddr;
+use meilies_server::{Server, StreamDeclaration};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "meilies-server", about = "Start the server", author)]
@@ -43,10 +20,110 @@ struct Opt {
     #[structopt(short = "p", long = "port", default_value = "6480")]
     port: u16,
 
+    /// Address to listen on (i.e. 127.0.0.1:6480 or [::1]:6480), can be repeated,
+    /// replaces the hostname and port options when specified.
+    #[structopt(long = "listen")]
+    listen: Vec<SocketAddr>,
+
+    /// Path of a unix socket to listen on, in addition to the TCP addresses.
+    #[structopt(long = "unix-socket", parse(from_os_str))]
+    unix_socket: Option<PathBuf>,
+
     /// Specify the zstd compression factor (irreversible)
     #[structopt(long = "compression-factor")]
     compression_factor: Option<i32>,
 
+    /// Compress the data of each event using zstd with this level.
+    #[structopt(long = "event-compression")]
+    event_compression: Option<i32>,
+
+    /// Do not compress the events of this stream, can be repeated.
+    #[structopt(long = "uncompressed-stream")]
+    uncompressed_streams: Vec<StreamName>,
+
+    /// Create a stream at startup even if no event is published to it, can be repeated, written
+    /// `name[:option,...]` where the options are `uncompressed`, `max-events=N` and `max-event-size=N`.
+    #[structopt(long = "declare-stream")]
+    declared_streams: Vec<StreamDeclaration>,
+
+    /// Maximum size in bytes of the data of a published event.
+    #[structopt(long = "max-event-size")]
+    max_event_size: Option<usize>,
+
+    /// Maximum number of events kept in each stream, the oldest ones are removed.
+    #[structopt(long = "max-events")]
+    max_events: Option<usize>,
+
+    /// Remove the events published more than this number of seconds ago,
+    /// the purge runs periodically and the events can outlive it briefly.
+    #[structopt(long = "retention-ttl")]
+    retention_ttl: Option<u64>,
+
+    /// Maximum number of streams, publishing or subscribing to a new stream is rejected
+    /// once it is reached while the existing streams keep working.
+    #[structopt(long = "max-streams")]
+    max_streams: Option<usize>,
+
+    /// Reject every request that modifies the database.
+    #[structopt(long = "read-only")]
+    read_only: bool,
+
+    /// Give every event a global number that ordered `$all` subscriptions follow,
+    /// every publication then writes the same counter which slows down publishing.
+    #[structopt(long = "global-order")]
+    global_order: bool,
+
+    /// Address of a primary server (i.e. localhost:6480) to replicate the streams from.
+    #[structopt(long = "replicate-from")]
+    replicate_from: Option<ServerAddr>,
+
+    /// Maximum number of connections open at the same time, the others are rejected.
+    #[structopt(long = "max-connections")]
+    max_connections: Option<usize>,
+
+    /// Maximum number of requests per second of each connection, the others are rejected.
+    #[structopt(long = "max-requests-per-sec")]
+    max_requests_per_sec: Option<u32>,
+
+    /// Close the connections that do not send a complete request during this number of seconds,
+    /// the subscribed connections are kept open.
+    #[structopt(long = "read-timeout")]
+    read_timeout: Option<u64>,
+
+    /// Close the connections that do not read their responses during this number of seconds.
+    #[structopt(long = "write-timeout")]
+    write_timeout: Option<u64>,
+
+    /// Flush the database to disk every this number of milliseconds instead of relying on sled.
+    #[structopt(long = "flush-interval-ms")]
+    flush_interval_ms: Option<u64>,
+
+    /// Flush the database to disk every time this number of events have been published.
+    #[structopt(long = "flush-every")]
+    flush_every: Option<usize>,
+
+    /// Send a heartbeat to the subscriptions that received no event during this number of seconds,
+    /// no heartbeat is sent by default.
+    #[structopt(long = "heartbeat-interval")]
+    heartbeat_interval: Option<u64>,
+
+    /// Do not set TCP_NODELAY on the accepted connections, responses may be delayed by Nagle's algorithm.
+    #[structopt(long = "no-tcp-nodelay")]
+    no_tcp_nodelay: bool,
+
+    /// Maximum level of the logs (off, error, warn, info, debug or trace),
+    /// it replaces the global level of RUST_LOG, the module levels are kept.
+    #[structopt(long = "log-level")]
+    log_level: Option<LevelFilter>,
+
+    /// Log more, each occurrence raises the level by one starting from error.
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: u8,
+
+    /// Log less, each occurrence lowers the level by one starting from error.
+    #[structopt(short = "q", long = "quiet", parse(from_occurrences))]
+    quiet: u8,
+
     /// Disable vigil initialization.
     #[structopt(long = "no-vigil")]
     no_vigil: bool,
@@ -64,322 +141,39 @@ struct Opt {
     db_path: PathBuf,
 }
 
-#[derive(Debug)]
-enum Error {
-    RequestMsgError(RequestMsgError),
-    InvalidRequest,
-    InternalError(sled::Error),
-    IoError(IoError),
-}
-
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Error::RequestMsgError(e) => write!(f, "invalid request message; {}", e),
-            Error::InvalidRequest => write!(f, "invalid request"),
-            Error::InternalError(e) => write!(f, "internal error; {}", e),
-            Error::IoError(e) => write!(f, "io error; {}", e),
-        }
+/// The level specified by the log options, `None` lets RUST_LOG decide.
+fn log_level(opt: &Opt) -> Option<LevelFilter> {
+    if opt.log_level.is_some() {
+        return opt.log_level;
     }
-}
-
-impl From<sled::Error> for Error {
-    fn from(error: sled::Error) -> Error {
-        Error::InternalError(error)
+    if opt.verbose == 0 && opt.quiet == 0 {
+        return None;
     }
-}
 
-impl From<RespVecConvertError<RespBytesConvertError>> for Error {
-    fn from(_: RespVecConvertError<RespBytesConvertError>) -> Error {
-        Error::InvalidRequest
-    }
+    let levels = [
+        LevelFilter::Off,
+        LevelFilter::Error,
+        LevelFilter::Warn,
+        LevelFilter::Info,
+        LevelFilter::Debug,
+        LevelFilter::Trace,
+    ];
+    let index = 1 + i32::from(opt.verbose) - i32::from(opt.quiet);
+    let index = index.max(0).min(levels.len() as i32 - 1);
+    Some(levels[index as usize])
 }
 
-impl From<IoError> for Error {
-    fn from(error: IoError) -> Error {
-        Error::IoError(error)
-    }
-}
-
-fn send_stream_events(
-    stream: EsStream,
-    tree: Tree,
-    mut sender: mpsc::Sender<Result<Response, String>>,
-) -> sled::Result<()> {
-    info!("blocking subscription on {} spawned", stream);
-
-    match stream.range {
-        ReadRange::ReadFrom(from) => {
-            let mut next_number = EventNumber(from);
-            let mut watcher = tree.watch_prefix(vec![]);
-
-            for result in tree.scan_prefix(next_number.to_be_bytes()) {
-                let (key, value) = result?;
-                let number = EventNumber::try_from(key.as_ref()).unwrap();
-
-                let raw_event = RawEvent::new(value);
-                let event = Response::Event {
-                    stream: stream.name.clone(),
-                    number,
-                    event_name: raw_event.name().unwrap(),
-                    event_data: raw_event.data(),
-                };
-
-                match sender.send(Ok(event)).wait() {
-                    Ok(s) => sender = s,
-                    Err(_) => {
-                        info!("encountered closed channel");
-                        return Ok(());
-                    }
-                }
-
-                next_number = number.next();
-                watcher = tree.watch_prefix(vec![]);
-            }
-
-            for event in watcher {
-                if let Event::Insert(key, value) = event {
-                    let number = EventNumber::try_from(key.as_ref()).unwrap();
-                    if number >= next_number {
-                        let raw_event = RawEvent::new(value);
-                        let event = Response::Event {
-                            stream: stream.name.clone(),
-                            number,
-                            event_name: raw_event.name().unwrap(),
-                            event_data: raw_event.data(),
-                        };
-
-                        match sender.send(Ok(event)).wait() {
-                            Ok(s) => sender = s,
-                            Err(_) => {
-                                info!("encountered closed channel");
-                                return Ok(());
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        ReadRange::ReadFromUntil(from, to) => {
-            let mut next_number = EventNumber(from);
-            let to_event_number = EventNumber(to);
-            let mut watcher = tree.watch_prefix(vec![]);
-
-            for result in tree.range(next_number.to_be_bytes()..to_event_number.to_be_bytes()) {
-                let (key, value) = result?;
-                let number = EventNumber::try_from(key.as_ref()).unwrap();
-
-                let raw_event = RawEvent::new(value);
-                let event = Response::Event {
-                    stream: stream.name.clone(),
-                    number,
-                    event_name: raw_event.name().unwrap(),
-                    event_data: raw_event.data(),
-                };
-
-                match sender.send(Ok(event)).wait() {
-                    Ok(s) => sender = s,
-                    Err(_) => {
-                        info!("encountered closed channel");
-                        return Ok(());
-                    }
-                }
-
-                next_number = number.next();
-                if next_number >= to_event_number {
-                    return Ok(());
-                }
-                watcher = tree.watch_prefix(vec![]);
-            }
-
-            for event in watcher {
-                if let Event::Insert(key, value) = event {
-                    let number = EventNumber::try_from(key.as_ref()).unwrap();
-                    if number >= to_event_number {
-                        return Ok(());
-                    }
-                    if number >= next_number {
-                        let raw_event = RawEvent::new(value);
-                        let event = Response::Event {
-                            stream: stream.name.clone(),
-                            number,
-                            event_name: raw_event.name().unwrap(),
-                            event_data: raw_event.data(),
-                        };
-
-                        match sender.send(Ok(event)).wait() {
-                            Ok(s) => sender = s,
-                            Err(_) => {
-                                info!("encountered closed channel");
-                                return Ok(());
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        ReadRange::ReadFromEnd => {
-            let watcher = tree.watch_prefix(vec![]);
-
-            for event in watcher {
-                if let Event::Insert(key, value) = event {
-                    let raw_event = RawEvent::new(value);
-                    let event = Response::Event {
-                        stream: stream.name.clone(),
-                        number: EventNumber::try_from(key.as_ref()).unwrap(),
-                        event_name: raw_event.name().unwrap(),
-                        event_data: raw_event.data(),
-                    };
-
-                    match sender.send(Ok(event)).wait() {
-                        Ok(s) => sender = s,
-                        Err(_) => {
-                            info!("encountered closed channel");
-                            return Ok(());
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    Ok(())
-}
-
-fn handle_request(
-    request: Request,
-    db: Db,
-    sender: mpsc::Sender<Result<Response, String>>,
-) -> Result<(), Error> {
-    match request {
-        Request::SubscribeAll { range } => {
-            let tree_names = db
-                .tree_names()
-                .into_iter()
-                .filter(|n| n != b"__sled__default");
-            let stream_strings = tree_names
-                .into_iter()
-                .map(|b| String::from_utf8(b).unwrap());
-            let stream_names = stream_strings.map(|s| EsStreamName::new(s).unwrap());
-            let all_streams: Vec<_> = stream_names.map(|n| EsStream::new(n, range)).collect();
-
-            for stream in all_streams {
-                let sender = sender.clone();
-                let tree = db.open_tree(stream.name.clone().into_bytes())?;
-
-                thread::Builder::new().spawn(|| {
-                    let mut sender = sender;
-
-                    let subscribed = Response::Subscribed {
-                        stream: stream.name.clone(),
-                    };
-                    match sender.send(Ok(subscribed)).wait() {
-                        Ok(s) => sender = s,
-                        Err(_) => {
-                            info!("encountered closed channel");
-                            return;
-                        }
-                    }
-
-                    if let Err(e) = send_stream_events(stream, tree, sender.clone()) {
-                        if let Err(_) = sender.send(Err(e.to_string())).wait() {
-                            info!("encountered closed channel");
-                            return;
-                        }
-                    }
-                })?;
-            }
-        }
-        Request::Subscribe { streams } => {
-            for stream in streams {
-                let sender = sender.clone();
-                let tree = db.open_tree(stream.name.clone().into_bytes())?;
-
-                thread::Builder::new().spawn(|| {
-                    let mut sender = sender;
-
-                    let subscribed = Response::Subscribed {
-                        stream: stream.name.clone(),
-                    };
-                    match sender.send(Ok(subscribed)).wait() {
-                        Ok(s) => sender = s,
-                        Err(_) => {
-                            info!("encountered closed channel");
-                            return;
-                        }
-                    }
-
-                    if let Err(e) = send_stream_events(stream, tree, sender.clone()) {
-                        if let Err(_) = sender.send(Err(e.to_string())).wait() {
-                            info!("encountered closed channel");
-                            return;
-                        }
-                    }
-                })?;
-            }
-        }
-        Request::Publish {
-            stream,
-            event_name,
-            event_data,
-        } => {
-            let tree = db.open_tree(stream.clone().into_bytes())?;
-
-            let event_number = new_event_number(&db, &stream)?;
-            let raw_length = event_name.as_str().len().to_be_bytes();
-            let raw_name = event_name.as_str().as_bytes();
-            let raw_data = event_data.0;
-
-            let mut raw_event = Vec::new();
-            raw_event.extend_from_slice(&raw_length);
-            raw_event.extend_from_slice(&raw_name);
-            raw_event.extend_from_slice(&raw_data);
-
-            if let Err(e) = tree.insert(event_number.to_be_bytes(), raw_event) {
-                return Err(Error::InternalError(e));
-            }
-
-            info!("{:?} {:?} {:?}", stream, event_name, event_number);
-
-            if sender.send(Ok(Response::Ok)).wait().is_err() {
-                info!("encountered closed channel");
-            }
-        }
-        Request::LastEventNumber { stream } => {
-            let key = db.get(&stream)?;
-            let number = key.map(|k| EventNumber::try_from(k.as_ref()).unwrap());
-
-            let last_event_number = Response::LastEventNumber { stream, number };
-            if sender.send(Ok(last_event_number)).wait().is_err() {
-                info!("encountered closed channel");
-            }
-        }
-        Request::StreamNames => {
-            let tree_names = db
-                .tree_names()
-                .into_iter()
-                .filter(|n| n != b"__sled__default");
-            let stream_strings = tree_names
-                .into_iter()
-                .map(|b| String::from_utf8(b).unwrap());
-            let stream_names = stream_strings
-                .map(|s| EsStreamName::new(s).unwrap())
-                .collect();
-            let streams = Response::StreamNames {
-                streams: stream_names,
-            };
-
-            if sender.send(Ok(streams)).wait().is_err() {
-                info!("encountered closed channel");
-            }
-        }
+/// A logger configured by RUST_LOG, the level replaces its global level.
+fn logger(level: Option<LevelFilter>) -> env_logger::Builder {
+    let mut builder = env_logger::Builder::from_default_env();
+    if let Some(level) = level {
+        builder.filter_level(level);
     }
-
-    Ok(())
+    builder
 }
 
 #[cfg(feature = "sentry")]
-fn init_sentry() {
+fn init_sentry(level: Option<LevelFilter>) {
     let guard = sentry::init(sentry::ClientOptions::default());
 
     if guard.is_enabled() {
@@ -387,7 +181,8 @@ fn init_sentry() {
     }
 
     sentry::integrations::panic::register_panic_handler();
-    sentry::integrations::env_logger::init(None, Default::default());
+    let logger = logger(level).build();
+    sentry::integrations::env_logger::init(Some(logger), Default::default());
 }
 
 #[cfg(feature = "vigil")]
@@ -415,11 +210,12 @@ fn init_vigil() {
 
 fn main() {
     let opt = Opt::from_args();
+    let level = log_level(&opt);
 
     #[cfg(feature = "sentry")]
     {
         if !opt.no_sentry {
-            init_sentry();
+            init_sentry(level);
         }
     }
 
@@ -431,7 +227,7 @@ fn main() {
     }
 
     if !cfg!(feature = "sentry") || opt.no_sentry {
-        let _ = env_logger::init();
+        let _ = logger(level).try_init();
     }
 
     let addr = match opt.hostname.parse() {
@@ -441,79 +237,116 @@ fn main() {
 
     let addr = SocketAddr::new(addr, opt.port);
 
-    let now = Instant::now();
+    let addrs = if opt.listen.is_empty() {
+        vec![addr]
+    } else {
+        opt.listen
+    };
+
+    let mut builder = Server::builder()
+        .db_path(opt.db_path)
+        .read_only(opt.read_only)
+        .global_order(opt.global_order)
+        .nodelay(!opt.no_tcp_nodelay);
 
-    let mut config = Config::new().path(opt.db_path);
+    if let Some(secs) = opt.heartbeat_interval {
+        builder = builder.heartbeat_interval(Duration::from_secs(secs));
+    }
 
+    for addr in addrs {
+        builder = builder.listen(addr);
+    }
+    if let Some(path) = &opt.unix_socket {
+        builder = builder.unix_socket(path.clone());
+    }
     if let Some(compression_factor) = opt.compression_factor {
-        config = config
-            .use_compression(true)
-            .compression_factor(compression_factor);
+        builder = builder.compression_factor(compression_factor);
+    }
+    if let Some(level) = opt.event_compression {
+        builder = builder.event_compression(level);
+    }
+    for stream in opt.uncompressed_streams {
+        builder = builder.uncompressed_stream(stream);
+    }
+    for declaration in opt.declared_streams {
+        builder = builder.declare_stream(declaration);
+    }
+    if let Some(size) = opt.max_event_size {
+        builder = builder.max_event_size(size);
+    }
+    if let Some(count) = opt.max_events {
+        builder = builder.max_events(count);
+    }
+    if let Some(secs) = opt.retention_ttl {
+        builder = builder.retention_ttl(Duration::from_secs(secs));
+    }
+    if let Some(count) = opt.max_streams {
+        builder = builder.max_streams(count);
+    }
+    if let Some(count) = opt.max_connections {
+        builder = builder.max_connections(count);
+    }
+    if let Some(count) = opt.max_requests_per_sec {
+        builder = builder.max_requests_per_sec(count);
+    }
+    if let Some(secs) = opt.read_timeout {
+        builder = builder.read_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = opt.write_timeout {
+        builder = builder.write_timeout(Duration::from_secs(secs));
+    }
+    if let Some(millis) = opt.flush_interval_ms {
+        builder = builder.flush_interval(Duration::from_millis(millis));
+    }
+    if let Some(count) = opt.flush_every {
+        builder = builder.flush_every(count);
+    }
+    if let Some(primary) = opt.replicate_from {
+        builder = builder.replicate_from(primary);
     }
 
-    let db = match config.open() {
-        Ok(db) => db,
-        Err(e) => return error!("error opening database; {}", e),
+    let server = match builder.build() {
+        Ok(server) => server,
+        Err(e) => return error!("error starting the server; {}", e),
     };
-    info!("kv-store loaded in {:.2?}", now.elapsed());
 
-    let listener = match TcpListener::bind(&addr) {
-        Ok(listener) => listener,
-        Err(e) => return error!("error binding address; {}", e),
-    };
-    println!("server is listening on {}", addr);
-
-    let server = listener
-        .incoming()
-        .map_err(|e| error!("error accepting socket; {}", e))
-        .for_each(move |socket| {
-            let framed = ServerCodec::default().framed(socket);
-            let (writer, reader) = framed.split();
-            let (sender, receiver) = mpsc::channel(10);
-
-            let error_sender = sender.clone();
-
-            let db = db.clone();
-            let requests = reader
-                .map_err(Error::RequestMsgError)
-                .for_each(move |request| {
-                    let db = db.clone();
-                    let sender = sender.clone();
-                    future::result(handle_request(request, db, sender))
-                })
-                .or_else(move |error| {
-                    error!("error; {}", error);
-                    if error_sender.send(Err(error.to_string())).wait().is_err() {
-                        info!("encountered closed channel");
-                    }
-
-                    future::ok(())
-                });
-
-            let responses = receiver
-                .map_err(|e| {
-                    let error = RespMsgError::IoError(IoError::new(ErrorKind::BrokenPipe, e));
-                    ResponseMsgError::RespMsgError(error)
-                })
-                .forward(writer)
-                .map_err(|error| {
-                    use crate::RespMsgError::IoError;
-                    use ResponseMsgError::RespMsgError;
-
-                    match error {
-                        RespMsgError(IoError(ref e)) if e.kind() == ErrorKind::BrokenPipe => {
-                            info!("{}", e);
-                        }
-                        other => error!("{}", other),
-                    }
-                })
-                .map(drop);
-
-            tokio::spawn(requests);
-            tokio::spawn(responses);
-
-            future::ok(())
-        });
-
-    tokio::run(server)
+    match server.local_addrs() {
+        Ok(addrs) => addrs
+            .iter()
+            .for_each(|addr| println!("server is listening on {}", addr)),
+        Err(e) => return error!("error reading the listening addresses; {}", e),
+    }
+    if let Some(path) = opt.unix_socket {
+        println!("server is listening on {}", path.display());
+    }
+
+    tokio::run(server.run())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_level_options() {
+        let level = |args: &[&str]| {
+            let mut argv = vec!["meilies-server"];
+            argv.extend_from_slice(args);
+            log_level(&Opt::from_iter(argv))
+        };
+
+        assert_eq!(level(&[]), None);
+        assert_eq!(level(&["-v"]), Some(LevelFilter::Warn));
+        assert_eq!(level(&["-vvv"]), Some(LevelFilter::Debug));
+        assert_eq!(level(&["-vvvvvvv"]), Some(LevelFilter::Trace));
+        assert_eq!(level(&["-q"]), Some(LevelFilter::Off));
+        assert_eq!(level(&["-vv", "-q"]), Some(LevelFilter::Warn));
+        assert_eq!(
+            level(&["--log-level", "info", "-q"]),
+            Some(LevelFilter::Info)
+        );
+
+        let logger = logger(Some(LevelFilter::Debug)).build();
+        assert_eq!(logger.filter(), LevelFilter::Debug);
+    }
 }
@@ -0,0 +1,260 @@
+//! Following the events of a primary server.
+
+use std::cmp;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+use log::{error, info};
+use sled::{Db, IVec};
+use tokio::prelude::*;
+use tokio::timer::Interval;
+
+use meilies::reqresp::Response;
+use meilies::stream::ReadRange;
+use meilies::stream::Stream as EsStream;
+use meilies::stream::{EventNumber, RawEvent};
+use meilies_client::{paired_connect, sub_connect, PairedConnection, ServerAddr, SubController};
+
+use super::{Error, Settings};
+use crate::retention::{apply_retention, retention};
+use crate::storage::{last_event_number, stream_settings};
+
+/// How often the stream names of the primary are listed to follow the new streams.
+const STREAM_NAMES_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Save an event received from a primary server, keeping the event number and the
+/// timestamp it was given, the retention of the replica is applied to its stream.
+///
+/// Saving the same event twice has no effect, which makes resuming a replication idempotent.
+/// The events the primary stored without timestamp are stamped with the current time.
+fn replicate_event(db: &Db, settings: Settings, response: Response) -> Result<(), Error> {
+    let (stream, number, event_name, event_data, headers, redacted, timestamp) = match response {
+        Response::Event {
+            stream,
+            number,
+            event_name,
+            event_data,
+            headers,
+            redacted,
+            timestamp,
+            ..
+        } => (
+            stream, number, event_name, event_data, headers, redacted, timestamp,
+        ),
+        _ => return Ok(()),
+    };
+
+    let tree = db.open_tree(stream.clone().into_bytes())?;
+    let raw_event = match (redacted, timestamp) {
+        (true, Some(timestamp)) => RawEvent::encode_redacted_at(&event_name, timestamp),
+        (true, None) => RawEvent::encode_redacted(&event_name),
+        (false, Some(timestamp)) => {
+            RawEvent::encode_with_headers_at(&event_name, &event_data, &headers, timestamp)
+        }
+        (false, None) => RawEvent::encode_with_headers(&event_name, &event_data, &headers),
+    };
+    tree.insert(number.to_be_bytes(), raw_event.into_inner())?;
+
+    db.update_and_fetch(&stream, |previous| {
+        let new = match previous.map(EventNumber::try_from) {
+            Some(Ok(previous)) => cmp::max(previous, number),
+            Some(Err(_)) => {
+                error!(
+                    "corrupted event number of {}, reset to {}",
+                    stream, number.0
+                );
+                number
+            }
+            None => number,
+        };
+        let slice = &new.to_be_bytes()[..];
+        Some(IVec::from(slice))
+    })?;
+
+    if let Some(max_events) = retention(settings, stream_settings(db, &stream)?) {
+        apply_retention(&tree, max_events)?;
+    }
+
+    Ok(())
+}
+
+/// Subscribe to the streams of the primary server that are not followed yet, its stream
+/// names are listed at every interval. Each stream is followed from the event following
+/// the last one replicated.
+fn follow_streams(
+    db: Db,
+    conn: PairedConnection,
+    ctrl: SubController,
+    interval: Duration,
+) -> impl Future<Item = (), Error = ()> {
+    Interval::new(Instant::now(), interval)
+        .map_err(|e| error!("replication error; {}", e))
+        .fold(
+            (conn, ctrl, HashSet::new()),
+            move |(conn, mut ctrl, mut followed), _| {
+                let db = db.clone();
+                conn.stream_names()
+                    .map_err(|e| error!("replication error; {}", e))
+                    .map(move |(streams, conn)| {
+                        for name in streams {
+                            if !followed.insert(name.clone()) {
+                                continue;
+                            }
+
+                            let from = match last_event_number(&db, &name) {
+                                Ok(number) => number.map_or(0, |n| n.next().0),
+                                Err(e) => {
+                                    error!("replication error; {}", e);
+                                    0
+                                }
+                            };
+
+                            info!("replicating {} from {}", name, from);
+                            ctrl.subscribe_to(EsStream::new(name, ReadRange::ReadFrom(from)));
+                        }
+
+                        (conn, ctrl, followed)
+                    })
+            },
+        )
+        .map(drop)
+}
+
+/// Follow every stream of a primary server and save its events in the local database,
+/// the streams created on the primary while replicating are followed too.
+pub fn replicate(
+    db: Db,
+    primary: ServerAddr,
+    settings: Settings,
+) -> impl Future<Item = (), Error = ()> {
+    replicate_every(db, primary, settings, STREAM_NAMES_INTERVAL)
+}
+
+/// Replicate a primary server, listing its stream names at every interval.
+fn replicate_every(
+    db: Db,
+    primary: ServerAddr,
+    settings: Settings,
+    interval: Duration,
+) -> impl Future<Item = (), Error = ()> {
+    let paired = paired_connect(primary.clone()).map_err(|e| error!("replication error; {}", e));
+    let sub = sub_connect(primary).map_err(|e| error!("replication error; {}", e));
+
+    paired.join(sub).and_then(move |(conn, (ctrl, msgs))| {
+        let follow = follow_streams(db.clone(), conn, ctrl, interval);
+
+        let events = msgs
+            .map_err(|e| error!("replication error; {}", e))
+            .for_each(move |msg| {
+                match msg {
+                    Ok(response) => {
+                        if let Err(e) = replicate_event(&db, settings, response) {
+                            error!("replication error; {}", e);
+                            return Err(());
+                        }
+                    }
+                    Err(e) => error!("replication error; {}", e),
+                }
+
+                Ok(())
+            });
+
+        // the replication stops as soon as one of the connections is lost
+        events.select(follow).map(drop).map_err(drop)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::ServerCtx;
+    use crate::serve::{serve, Connections};
+    use crate::storage::save_event;
+    use meilies::stream::{EventData, EventName, StreamName as EsStreamName};
+    use sled::{Config, Tree};
+    use std::thread;
+    use tokio::net::TcpListener;
+
+    fn timestamps(tree: &Tree) -> Vec<Option<u64>> {
+        tree.iter()
+            .values()
+            .map(|value| RawEvent::new(value.unwrap()).timestamp().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn replica_keeps_timestamps_and_follows_new_streams() {
+        let primary = Config::new().temporary(true).open().unwrap();
+        let replica = Config::new().temporary(true).open().unwrap();
+        let first = EsStreamName::new("first".to_owned()).unwrap();
+        let second = EsStreamName::new("second".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        let publish = |stream: &EsStreamName| {
+            let event_data = EventData(b"data".to_vec());
+            save_event(
+                &primary,
+                stream,
+                &event_name,
+                event_data,
+                None,
+                Settings::default(),
+            )
+            .unwrap();
+        };
+        let replicated = |stream: &EsStreamName, number: u64| {
+            for _ in 0..100 {
+                if last_event_number(&replica, stream).unwrap() == Some(EventNumber(number)) {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+            panic!("event {} of {} was not replicated", number, stream);
+        };
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        runtime.spawn(serve(
+            listener.incoming(),
+            ServerCtx::new(primary.clone(), Settings::default()),
+            Connections::default(),
+        ));
+
+        publish(&first);
+        publish(&first);
+
+        // an event stamped again by the replica would have another timestamp
+        thread::sleep(Duration::from_millis(10));
+
+        // the replica only keeps the last event of each stream
+        let settings = Settings {
+            max_events: Some(1),
+            ..Settings::default()
+        };
+        let interval = Duration::from_millis(50);
+        runtime.spawn(replicate_every(
+            replica.clone(),
+            addr.into(),
+            settings,
+            interval,
+        ));
+        replicated(&first, 1);
+
+        publish(&second);
+        replicated(&second, 0);
+
+        let primary_first = primary.open_tree(first.clone().into_bytes()).unwrap();
+        let replica_first = replica.open_tree(first.clone().into_bytes()).unwrap();
+        assert_eq!(replica_first.len(), 1);
+        assert_eq!(
+            timestamps(&replica_first),
+            timestamps(&primary_first)[1..].to_vec()
+        );
+
+        let primary_second = primary.open_tree(second.clone().into_bytes()).unwrap();
+        let replica_second = replica.open_tree(second.clone().into_bytes()).unwrap();
+        assert_eq!(timestamps(&replica_second), timestamps(&primary_second));
+    }
+}
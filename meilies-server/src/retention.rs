@@ -0,0 +1,214 @@
+//! Dropping the events that are over the retention limits of their stream.
+
+use std::cmp;
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{debug, error};
+use sled::{Db, Tree};
+use tokio::prelude::*;
+use tokio::timer::Interval;
+
+use meilies::stream::{EventNumber, RawEvent, StreamSettings};
+
+use super::Settings;
+use crate::storage::iter_stream_names;
+
+/// Remove the oldest events of a stream, keeping only the last `max_events` event numbers.
+///
+/// The cutoff is computed from the number of the last event, the event numbers of a stream
+/// being contiguous, so that the stream does not have to be counted at every publication.
+pub fn apply_retention(tree: &Tree, max_events: usize) -> sled::Result<()> {
+    let last = match tree.iter().keys().next_back() {
+        Some(key) => match EventNumber::try_from(key?.as_ref()) {
+            Ok(last) => last,
+            Err(_) => return Ok(()),
+        },
+        None => return Ok(()),
+    };
+
+    let cutoff = (last.0 + 1).saturating_sub(max_events as u64);
+    for key in tree.range(..EventNumber(cutoff).to_be_bytes()).keys() {
+        tree.remove(key?)?;
+    }
+
+    Ok(())
+}
+
+/// Remove the events of every stream that were published before `ttl` ago,
+/// returns the number of removed events.
+///
+/// The events of a stream are read from the oldest one until an event recent enough
+/// is found, the events stored without a timestamp and the corrupted ones are kept.
+fn purge_expired(db: &Db, ttl: Duration) -> sled::Result<usize> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+    let cutoff = now.saturating_sub(ttl.as_millis() as u64);
+
+    let mut purged = 0;
+    for name in iter_stream_names(db) {
+        let tree = db.open_tree(name.as_str().as_bytes())?;
+
+        for result in tree.iter() {
+            let (key, value) = result?;
+            match RawEvent::new(value).timestamp() {
+                Ok(Some(timestamp)) if timestamp >= cutoff => break,
+                Ok(Some(_)) => {
+                    tree.remove(key)?;
+                    purged += 1;
+                }
+                Ok(None) | Err(_) => (),
+            }
+        }
+    }
+
+    Ok(purged)
+}
+
+/// The maximum number of events kept in a stream, the setting
+/// of the stream takes precedence over the one of the server.
+pub fn retention(settings: Settings, stream_settings: StreamSettings) -> Option<usize> {
+    stream_settings
+        .max_events
+        .map(|count| count as usize)
+        .or(settings.max_events)
+}
+
+/// The longest time between two purges of the expired events.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Purge the expired events at every tick of the interval, until the runtime shuts down.
+pub fn purge_periodically(db: Db, ttl: Duration) -> impl Future<Item = (), Error = ()> {
+    Interval::new_interval(cmp::min(ttl, PURGE_INTERVAL))
+        .map_err(|e| error!("purge timer error; {}", e))
+        .for_each(move |_| {
+            match purge_expired(&db, ttl) {
+                Ok(0) => (),
+                Ok(purged) => debug!("purged {} expired events", purged),
+                Err(e) => error!("purge error; {}", e),
+            }
+            Ok(())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{last_event_number, save_event};
+    use meilies::stream::{EventData, EventName, StreamName as EsStreamName};
+    use sled::Config;
+
+    #[test]
+    fn retention_caps_stream_length() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("retention".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let settings = Settings {
+            max_events: Some(5),
+            ..Settings::default()
+        };
+
+        for i in 0..20u64 {
+            let event_data = EventData(i.to_be_bytes().to_vec());
+            save_event(&db, &stream, &event_name, event_data, None, settings).unwrap();
+
+            let tree = db.open_tree(stream.clone().into_bytes()).unwrap();
+            assert!(tree.len() <= 5);
+        }
+
+        let tree = db.open_tree(stream.clone().into_bytes()).unwrap();
+        let (first, _) = tree.iter().next().unwrap().unwrap();
+        let first = EventNumber::try_from(first.as_ref()).unwrap();
+        assert_eq!(first, EventNumber(15));
+    }
+
+    #[test]
+    fn retention_keeps_the_last_event_numbers() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree("numbered").unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        // the events 2 and 3 were purged, only the last numbers count
+        for i in &[0u64, 1, 4, 5, 6, 7] {
+            let event_data = EventData(i.to_be_bytes().to_vec());
+            let raw_event = RawEvent::encode(&event_name, &event_data);
+            tree.insert(EventNumber(*i).to_be_bytes(), raw_event.into_inner())
+                .unwrap();
+        }
+
+        apply_retention(&tree, 5).unwrap();
+
+        let numbers: Vec<_> = tree
+            .iter()
+            .keys()
+            .map(|key| EventNumber::try_from(key.unwrap().as_ref()).unwrap())
+            .collect();
+        assert_eq!(
+            numbers,
+            vec![
+                EventNumber(4),
+                EventNumber(5),
+                EventNumber(6),
+                EventNumber(7)
+            ]
+        );
+
+        apply_retention(&tree, 10).unwrap();
+        assert_eq!(tree.len(), 4);
+    }
+
+    #[test]
+    fn purge_expired_removes_old_events() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("expiring".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        for i in 0..4u64 {
+            let event_data = EventData(i.to_be_bytes().to_vec());
+            save_event(
+                &db,
+                &stream,
+                &event_name,
+                event_data,
+                None,
+                Settings::default(),
+            )
+            .unwrap();
+        }
+
+        // the first event was stored without a timestamp, the second one and the last
+        // one were published long ago, the last one is kept as it follows a recent one
+        let tree = db.open_tree(stream.clone().into_bytes()).unwrap();
+        let name = event_name.as_str().as_bytes();
+        let mut unversioned = (name.len() as u64).to_be_bytes().to_vec();
+        unversioned.extend_from_slice(name);
+        unversioned.extend_from_slice(b"data");
+        tree.insert(EventNumber(0).to_be_bytes(), unversioned)
+            .unwrap();
+        for i in &[1u64, 3] {
+            let event_data = EventData(i.to_be_bytes().to_vec());
+            let raw_event = RawEvent::encode_at(&event_name, &event_data, 1_000);
+            tree.insert(EventNumber(*i).to_be_bytes(), raw_event.into_inner())
+                .unwrap();
+        }
+
+        let ttl = Duration::from_secs(3600);
+        assert_eq!(purge_expired(&db, ttl).unwrap(), 1);
+        assert_eq!(purge_expired(&db, ttl).unwrap(), 0);
+
+        let numbers: Vec<_> = tree
+            .iter()
+            .keys()
+            .map(|key| EventNumber::try_from(key.unwrap().as_ref()).unwrap())
+            .collect();
+        assert_eq!(
+            numbers,
+            vec![EventNumber(0), EventNumber(2), EventNumber(3)]
+        );
+        assert_eq!(
+            last_event_number(&db, &stream).unwrap(),
+            Some(EventNumber(3))
+        );
+    }
+}
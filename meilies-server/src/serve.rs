@@ -0,0 +1,739 @@
+//! Serving the requests of a connection.
+
+use std::io::{Error as IoError, ErrorKind};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{AsyncSink, StartSend};
+use log::{error, info, warn};
+use tokio::codec::Decoder;
+use tokio::prelude::*;
+use tokio::sync::mpsc;
+use tokio::timer::Delay;
+
+use meilies::reqresp::ResponseMsgError;
+use meilies::reqresp::{ErrorCode, Request, ServerCodec, ServerError};
+use meilies::resp::RespMsgError;
+
+use super::Error;
+use crate::handlers::{handle_request, internal_error, PendingChunks, ServerCtx};
+use crate::subscriptions::PausedStreams;
+
+/// A sink that fails when it stays blocked by a slow reader for too long,
+/// the timer only runs while the underlying sink is not ready.
+struct WriteTimeout<S> {
+    sink: S,
+    timeout: Option<Duration>,
+    delay: Option<Delay>,
+}
+
+impl<S> WriteTimeout<S> {
+    fn new(sink: S, timeout: Option<Duration>) -> WriteTimeout<S> {
+        WriteTimeout {
+            sink,
+            timeout,
+            delay: None,
+        }
+    }
+
+    fn poll_delay(&mut self) -> Result<(), ResponseMsgError> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Ok(()),
+        };
+
+        let delay = self
+            .delay
+            .get_or_insert_with(|| Delay::new(Instant::now() + timeout));
+
+        let error = match delay.poll() {
+            Ok(Async::NotReady) => return Ok(()),
+            Ok(Async::Ready(())) => {
+                let message = format!("no response written since {:.2?}", timeout);
+                IoError::new(ErrorKind::TimedOut, message)
+            }
+            Err(e) => IoError::new(ErrorKind::Other, e),
+        };
+
+        Err(ResponseMsgError::RespMsgError(RespMsgError::IoError(error)))
+    }
+}
+
+impl<S> Sink for WriteTimeout<S>
+where
+    S: Sink<SinkError = ResponseMsgError>,
+{
+    type SinkItem = S::SinkItem;
+    type SinkError = ResponseMsgError;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        match self.sink.start_send(item)? {
+            AsyncSink::Ready => {
+                self.delay = None;
+                Ok(AsyncSink::Ready)
+            }
+            AsyncSink::NotReady(item) => {
+                self.poll_delay()?;
+                Ok(AsyncSink::NotReady(item))
+            }
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        match self.sink.poll_complete()? {
+            Async::Ready(()) => {
+                self.delay = None;
+                Ok(Async::Ready(()))
+            }
+            Async::NotReady => {
+                self.poll_delay()?;
+                Ok(Async::NotReady)
+            }
+        }
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.sink.close()
+    }
+}
+
+/// A stream of requests that fails when no request is received during the timeout.
+///
+/// A subscribed connection can stay quiet for as long as its streams receive
+/// no event, the timer stops once a subscription request has been received.
+pub struct ReadTimeout<S> {
+    stream: S,
+    timeout: Duration,
+    delay: Delay,
+    subscribed: bool,
+}
+
+impl<S> ReadTimeout<S> {
+    fn new(stream: S, timeout: Duration) -> ReadTimeout<S> {
+        ReadTimeout {
+            stream,
+            timeout,
+            delay: Delay::new(Instant::now() + timeout),
+            subscribed: false,
+        }
+    }
+}
+
+impl<S> Stream for ReadTimeout<S>
+where
+    S: Stream<Item = Result<Request, Error>, Error = Error>,
+{
+    type Item = S::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Async::Ready(item) = self.stream.poll()? {
+            match &item {
+                Some(Ok(Request::Subscribe { .. }))
+                | Some(Ok(Request::SubscribeAll { .. }))
+                | Some(Ok(Request::SubscribePrefix { .. })) => self.subscribed = true,
+                _otherwise => (),
+            }
+            self.delay.reset(Instant::now() + self.timeout);
+            return Ok(Async::Ready(item));
+        }
+
+        if self.subscribed {
+            return Ok(Async::NotReady);
+        }
+
+        match self.delay.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(())) => Err(Error::ReadTimeout(self.timeout)),
+            Err(e) => Err(Error::IoError(IoError::new(ErrorKind::Other, e))),
+        }
+    }
+}
+
+/// A token bucket limiting the number of requests of a connection,
+/// it holds at most one second worth of requests and is refilled continuously.
+struct RateLimiter {
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    fn new(requests_per_sec: u32) -> RateLimiter {
+        let rate = f64::from(requests_per_sec);
+        RateLimiter {
+            rate,
+            tokens: rate,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Returns `false` if the request exceeds the rate and must be rejected.
+    fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The number of open connections, shared by all the listeners.
+pub type Connections = Arc<AtomicUsize>;
+
+/// Counts a connection as long as its reader or its writer is alive.
+struct ConnectionGuard {
+    connections: Connections,
+}
+
+impl ConnectionGuard {
+    /// Returns `None` if the maximum number of connections is already reached.
+    fn acquire(connections: &Connections, max: Option<usize>) -> Option<ConnectionGuard> {
+        let previous = connections.fetch_add(1, Ordering::SeqCst);
+        let guard = ConnectionGuard {
+            connections: connections.clone(),
+        };
+
+        match max {
+            Some(max) if previous >= max => None,
+            _ => Some(guard),
+        }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Accept the incoming connections and answer the requests they send.
+///
+/// The connections accepted past the maximum number of connections
+/// are answered with an error and closed.
+///
+/// A connection that does not send a complete request during the read timeout
+/// is closed, as is one that does not read its responses during the write timeout.
+/// The subscribed connections are not closed by the read timeout.
+///
+/// A frame that is not a valid request is answered with an error,
+/// a connection sending bytes that are not a RESP frame is closed.
+pub fn serve<S>(
+    incoming: S,
+    ctx: ServerCtx,
+    connections: Connections,
+) -> impl Future<Item = (), Error = ()>
+where
+    S: Stream<Error = IoError>,
+    S::Item: AsyncRead + AsyncWrite + Send + 'static,
+{
+    incoming
+        .map_err(|e| error!("error accepting socket; {}", e))
+        .for_each(move |socket| {
+            let settings = ctx.settings;
+            let codec = match settings.max_event_size {
+                Some(size) => ServerCodec::with_max_event_size(size),
+                None => ServerCodec::default(),
+            };
+            let framed = codec.framed(socket);
+
+            let guard = match ConnectionGuard::acquire(&connections, settings.max_connections) {
+                Some(guard) => Arc::new(guard),
+                None => {
+                    warn!("too many connections, rejecting a new one");
+                    let rejection = framed
+                        .send(Err(ServerError::new(
+                            ErrorCode::TooManyConnections,
+                            "too many connections",
+                        )))
+                        .map(drop)
+                        .map_err(|e| error!("{}", e));
+                    tokio::spawn(rejection);
+                    return future::ok(());
+                }
+            };
+            let reader_guard = guard.clone();
+
+            let (writer, reader) = framed.split();
+            let writer = WriteTimeout::new(writer, settings.write_timeout);
+            let (sender, receiver) = mpsc::channel(10);
+
+            let error_sender = sender.clone();
+
+            // a frame that is not a valid request is answered with an error, but a frame
+            // that can not be decoded desynchronizes the stream and closes the connection
+            let reader = reader.then(|result| match result {
+                Ok(request) => Ok(Ok(request)),
+                Err(e) if e.is_recoverable() => Ok(Err(Error::RequestMsgError(e))),
+                Err(e) => Err(Error::RequestMsgError(e)),
+            });
+            let reader: Box<dyn Stream<Item = Result<Request, Error>, Error = Error> + Send> =
+                match settings.read_timeout {
+                    Some(timeout) => Box::new(ReadTimeout::new(reader, timeout)),
+                    None => Box::new(reader),
+                };
+
+            // the events published in chunks, the paused streams and
+            // the claimed events only concern this connection
+            let ctx = ServerCtx {
+                chunks: PendingChunks::default(),
+                paused: PausedStreams::default(),
+                consumer: Arc::default(),
+                ..ctx.clone()
+            };
+            let mut rate_limiter = settings.max_requests_per_sec.map(RateLimiter::new);
+            let requests = reader
+                .for_each(move |request| {
+                    let request = match request {
+                        Ok(request) => request,
+                        Err(error) => {
+                            warn!("{}", error);
+                            let error =
+                                ServerError::new(ErrorCode::InvalidRequest, error.to_string());
+                            if sender.clone().send(Err(error)).wait().is_err() {
+                                info!("encountered closed channel");
+                            }
+                            return future::ok(());
+                        }
+                    };
+
+                    if let Some(limiter) = &mut rate_limiter {
+                        if !limiter.try_acquire() {
+                            warn!("rate limited a request");
+                            let error = ServerError::new(ErrorCode::RateLimited, "rate limited");
+                            if sender.clone().send(Err(error)).wait().is_err() {
+                                info!("encountered closed channel");
+                            }
+                            return future::ok(());
+                        }
+                    }
+
+                    // a request that failed is answered with an error, the connection stays open
+                    if let Err(error) = handle_request(request, &ctx, sender.clone()) {
+                        error!("error; {}", error);
+                        let error = internal_error(error);
+                        if sender.clone().send(Err(error)).wait().is_err() {
+                            info!("encountered closed channel");
+                        }
+                    }
+
+                    future::ok(())
+                })
+                // only the errors of the reader end the connection
+                .or_else(move |error| {
+                    error!("error; {}", error);
+                    let error = internal_error(error);
+                    if error_sender.send(Err(error)).wait().is_err() {
+                        info!("encountered closed channel");
+                    }
+
+                    future::ok(())
+                })
+                .then(move |result| {
+                    drop(reader_guard);
+                    result
+                });
+
+            let responses = receiver
+                .map_err(|e| {
+                    let error = RespMsgError::IoError(IoError::new(ErrorKind::BrokenPipe, e));
+                    ResponseMsgError::RespMsgError(error)
+                })
+                .forward(writer)
+                .map_err(|error| {
+                    use crate::RespMsgError::IoError;
+                    use ResponseMsgError::RespMsgError;
+
+                    match error {
+                        RespMsgError(IoError(ref e)) if e.kind() == ErrorKind::BrokenPipe => {
+                            info!("{}", e);
+                        }
+                        other => error!("{}", other),
+                    }
+                })
+                .then(move |_| {
+                    drop(guard);
+                    Ok(())
+                });
+
+            tokio::spawn(requests);
+            tokio::spawn(responses);
+
+            future::ok(())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::save_event;
+    use crate::Settings;
+    use meilies::reqresp::{ClientCodec, Response};
+    use meilies::stream::{
+        EventData, EventName, EventNumber, Stream as EsStream, StreamName as EsStreamName,
+    };
+    use meilies_client::{paired_connect, PairedConnection};
+    use sled::Config;
+    use tokio::net::{TcpListener, UnixListener};
+
+    #[test]
+    fn read_timeout_closes_stalled_connection() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let settings = Settings {
+            read_timeout: Some(Duration::from_millis(100)),
+            ..Settings::default()
+        };
+        let connections = Connections::default();
+        runtime.spawn(serve(
+            listener.incoming(),
+            ServerCtx::new(db, settings),
+            connections,
+        ));
+
+        // a partial frame is sent and the client never sends the rest of it
+        let client = tokio::net::TcpStream::connect(&addr)
+            .and_then(|socket| tokio::io::write_all(socket, &b"*1\r\n$11\r\nstream"[..]))
+            .and_then(|(socket, _)| tokio::io::read_to_end(socket, Vec::new()))
+            .timeout(Duration::from_secs(5));
+
+        let (_, data) = runtime.block_on(client).unwrap();
+        let message = String::from_utf8_lossy(&data);
+        assert!(message.contains("no request received"), "{}", message);
+    }
+
+    #[test]
+    fn read_timeout_keeps_idle_subscriber() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let settings = Settings {
+            read_timeout: Some(Duration::from_millis(100)),
+            ..Settings::default()
+        };
+        let connections = Connections::default();
+        runtime.spawn(serve(
+            listener.incoming(),
+            ServerCtx::new(db, settings),
+            connections,
+        ));
+
+        let stream = EsStreamName::new("idle".to_owned()).unwrap();
+        let request = Request::Subscribe {
+            streams: vec![EsStream::from(stream.clone())],
+            require_existing: false,
+        };
+
+        // the subscriber sends nothing more and receives nothing for a few timeouts
+        let subscriber = tokio::net::TcpStream::connect(&addr)
+            .map_err(|e| e.to_string())
+            .and_then(|socket| {
+                let framed = ClientCodec.framed(socket);
+                framed.send(request).map_err(|e| e.to_string())
+            })
+            .and_then(|framed| {
+                Delay::new(Instant::now() + Duration::from_millis(300))
+                    .map(|_| framed)
+                    .map_err(|e| e.to_string())
+            });
+        let subscriber = runtime.block_on(subscriber).unwrap();
+
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let publish = paired_connect(addr)
+            .map_err(|e| e.to_string())
+            .and_then(move |conn| {
+                conn.publish(stream, event_name, EventData(b"data".to_vec()))
+                    .map_err(|e| e.to_string())
+            });
+        runtime.block_on(publish).unwrap();
+
+        let event = subscriber
+            .map_err(|e| e.to_string())
+            .filter(|response| match response {
+                Ok(Response::Subscribed { .. }) => false,
+                _otherwise => true,
+            })
+            .into_future()
+            .map(|(response, _)| response)
+            .map_err(|(e, _)| e)
+            .timeout(Duration::from_secs(5));
+
+        match runtime.block_on(event).unwrap() {
+            Some(Ok(Response::Event { number, .. })) => assert_eq!(number, EventNumber(0)),
+            otherwise => panic!("unexpected response {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn undecodable_frame_closes_connection() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Connections::default();
+        runtime.spawn(serve(
+            listener.incoming(),
+            ServerCtx::new(db, Settings::default()),
+            connections,
+        ));
+
+        // the unknown command is answered and the connection is kept,
+        // the garbage byte that follows can not be skipped and closes it
+        let frames = &b"*1\r\n$7\r\nunknown\r\n?garbage\r\n"[..];
+        let client = tokio::net::TcpStream::connect(&addr)
+            .and_then(move |socket| tokio::io::write_all(socket, frames))
+            .and_then(|(socket, _)| tokio::io::read_to_end(socket, Vec::new()))
+            .timeout(Duration::from_secs(5));
+
+        let (_, data) = runtime.block_on(client).unwrap();
+        let message = String::from_utf8_lossy(&data);
+        assert!(message.contains("Unknown command name"), "{}", message);
+        assert!(message.contains("invalid prefix byte"), "{}", message);
+    }
+
+    #[test]
+    fn failed_request_keeps_connection() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("corrupted".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let event_data = EventData(b"data".to_vec());
+        save_event(
+            &db,
+            &stream,
+            &event_name,
+            event_data,
+            None,
+            Settings::default(),
+        )
+        .unwrap();
+
+        // a key that is not an event number makes the last event request fail
+        let tree = db.open_tree(stream.clone().into_bytes()).unwrap();
+        tree.insert(b"corrupted", b"data".to_vec()).unwrap();
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        runtime.spawn(serve(
+            listener.incoming(),
+            ServerCtx::new(db, Settings::default()),
+            Connections::default(),
+        ));
+
+        let responses = tokio::net::TcpStream::connect(&addr)
+            .map_err(|e| e.to_string())
+            .and_then(|socket| {
+                let framed = ClientCodec.framed(socket);
+                framed
+                    .send(Request::LastEvent { stream })
+                    .and_then(|framed| framed.send(Request::StreamNames))
+                    .map_err(|e| e.to_string())
+            })
+            .and_then(|framed| framed.take(2).collect().map_err(|e| e.to_string()))
+            .timeout(Duration::from_secs(5));
+
+        let responses = runtime.block_on(responses).unwrap();
+        match &responses[0] {
+            Err(error) => assert_eq!(error.code, Some(ErrorCode::Internal)),
+            otherwise => panic!("unexpected response {:?}", otherwise),
+        }
+        match &responses[1] {
+            Ok(Response::StreamNames { streams }) => assert_eq!(streams.len(), 1),
+            otherwise => panic!("unexpected response {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn max_connections_rejects_excess_connections() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let settings = Settings {
+            max_connections: Some(1),
+            ..Settings::default()
+        };
+        let connections = Connections::default();
+        runtime.spawn(serve(
+            listener.incoming(),
+            ServerCtx::new(db, settings),
+            connections,
+        ));
+
+        // the first connection is accepted and kept open
+        let first = paired_connect(addr)
+            .map_err(|e| e.to_string())
+            .and_then(|conn| conn.stream_names().map_err(|e| e.to_string()));
+        let (_, _first) = runtime.block_on(first).unwrap();
+
+        let second = tokio::net::TcpStream::connect(&addr)
+            .and_then(|socket| tokio::io::read_to_end(socket, Vec::new()))
+            .timeout(Duration::from_secs(5));
+
+        let (_, data) = runtime.block_on(second).unwrap();
+        let message = String::from_utf8_lossy(&data);
+        assert!(message.contains("too many connections"), "{}", message);
+    }
+
+    #[test]
+    fn rate_limit_rejects_excess_requests() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let settings = Settings {
+            max_requests_per_sec: Some(2),
+            ..Settings::default()
+        };
+        let connections = Connections::default();
+        runtime.spawn(serve(
+            listener.incoming(),
+            ServerCtx::new(db, settings),
+            connections,
+        ));
+
+        let mut conn = runtime.block_on(paired_connect(addr)).unwrap();
+        for _ in 0..2 {
+            let (_, paired) = runtime.block_on(conn.stream_names()).unwrap();
+            conn = paired;
+        }
+
+        match runtime.block_on(conn.stream_names()) {
+            Err(error) => assert_eq!(error.code(), Some(ErrorCode::RateLimited)),
+            otherwise => panic!("unexpected result {:?}", otherwise.map(|(names, _)| names)),
+        }
+    }
+
+    #[test]
+    fn serve_multiple_listeners() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let mut addrs = Vec::new();
+        for _ in 0..2 {
+            let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+            addrs.push(listener.local_addr().unwrap());
+            runtime.spawn(serve(
+                listener.incoming(),
+                ServerCtx::new(db.clone(), Settings::default()),
+                Connections::default(),
+            ));
+        }
+
+        let stream = EsStreamName::new("listeners".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let event_data = EventData(b"data".to_vec());
+
+        let publish = {
+            let stream = stream.clone();
+            paired_connect(addrs[0])
+                .map_err(|e| e.to_string())
+                .and_then(move |conn| {
+                    conn.publish(stream, event_name, event_data)
+                        .map_err(|e| e.to_string())
+                })
+        };
+        runtime.block_on(publish).unwrap();
+
+        let last_event_number = paired_connect(addrs[1])
+            .map_err(|e| e.to_string())
+            .and_then(move |conn| conn.last_event_number(stream).map_err(|e| e.to_string()));
+        let (_, number, _) = runtime.block_on(last_event_number).unwrap();
+
+        assert_eq!(number, Some(EventNumber(0)));
+    }
+
+    #[test]
+    fn read_range_ends_on_a_corrupted_last_event() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("corrupted".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        for i in 0..3u64 {
+            let event_data = EventData(i.to_be_bytes().to_vec());
+            save_event(
+                &db,
+                &stream,
+                &event_name,
+                event_data,
+                None,
+                Settings::default(),
+            )
+            .unwrap();
+        }
+
+        // the last event of the range is never delivered
+        let tree = db.open_tree(stream.clone().into_bytes()).unwrap();
+        let key = EventNumber(2).to_be_bytes();
+        let mut value = tree.get(key).unwrap().unwrap().to_vec();
+        value[10] ^= 0x01;
+        tree.insert(key, value).unwrap();
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        runtime.spawn(serve(
+            listener.incoming(),
+            ServerCtx::new(db, Settings::default()),
+            Connections::default(),
+        ));
+
+        let fut = paired_connect(addr)
+            .map_err(|e| e.to_string())
+            .and_then(move |conn| {
+                conn.read_range(stream, EventNumber(0), EventNumber(3))
+                    .map_err(|e| e.to_string())
+            })
+            .timeout(Duration::from_secs(5));
+        let (events, _) = runtime.block_on(fut).unwrap();
+
+        let numbers: Vec<_> = events.into_iter().map(|(number, _, _)| number).collect();
+        assert_eq!(numbers, vec![EventNumber(0), EventNumber(1)]);
+    }
+
+    #[test]
+    fn serve_unix_socket() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let path = std::env::temp_dir().join(format!("meilies-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        runtime.spawn(serve(
+            listener.incoming(),
+            ServerCtx::new(db, Settings::default()),
+            Connections::default(),
+        ));
+
+        let stream = EsStreamName::new("unix".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let event_data = EventData(b"data".to_vec());
+
+        let fut = PairedConnection::connect_unix(path.clone())
+            .map_err(|e| e.to_string())
+            .and_then(move |conn| {
+                conn.publish(stream.clone(), event_name, event_data)
+                    .and_then(move |conn| conn.last_event_number(stream))
+                    .map_err(|e| e.to_string())
+            });
+        let (_, number, _) = runtime.block_on(fut).unwrap();
+
+        assert_eq!(number, Some(EventNumber(0)));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,334 @@
+//! Saving and folding the snapshots of a stream.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use sled::{Db, IVec};
+
+use meilies::reqresp::{ErrorCode, Response, ServerError};
+use meilies::stream::StreamName as EsStreamName;
+use meilies::stream::{EventData, EventName, EventNumber, RawEvent};
+
+use super::Error;
+use crate::storage::{last_event_number, parse_event_number, snapshot_tree_name, stream_not_found};
+
+/// Store a snapshot of a stream and remove the previous ones,
+/// the snapshot must not include events that were not published yet.
+pub fn save_snapshot(
+    db: &Db,
+    stream: &EsStreamName,
+    number: EventNumber,
+    data: &[u8],
+) -> Result<Result<(), ServerError>, Error> {
+    match last_event_number(db, stream)? {
+        Some(last) if number <= last => (),
+        _ => {
+            let message = format!("event {} of stream {} does not exist", number.0, stream);
+            return Ok(Err(ServerError::new(ErrorCode::EventNotFound, message)));
+        }
+    }
+
+    let tree = db.open_tree(snapshot_tree_name(stream))?;
+    let key = number.to_be_bytes();
+    tree.insert(key, data)?;
+
+    for result in tree.range(..key) {
+        let (key, _) = result?;
+        tree.remove(key)?;
+    }
+
+    Ok(Ok(()))
+}
+
+/// Returns the latest snapshot of a stream and the number of the last event it includes.
+pub fn latest_snapshot(
+    db: &Db,
+    stream: &EsStreamName,
+) -> Result<Option<(EventNumber, IVec)>, Error> {
+    let tree = db.open_tree(snapshot_tree_name(stream))?;
+    match tree.iter().next_back() {
+        Some(result) => {
+            let (key, value) = result?;
+            let number = parse_event_number(stream, &key)?;
+            Ok(Some((number, value)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// A function folding an event into the data of a snapshot, the first event
+/// of a stream without snapshot is folded into an empty vector.
+type SnapshotFn = dyn Fn(Vec<u8>, &EventName, &EventData) -> Vec<u8> + Send + Sync;
+
+/// The snapshot functions registered on the server, by stream.
+#[derive(Clone, Default)]
+pub struct SnapshotFns(pub Arc<HashMap<EsStreamName, Box<SnapshotFn>>>);
+
+impl SnapshotFns {
+    pub fn get(&self, stream: &EsStreamName) -> Option<&SnapshotFn> {
+        self.0.get(stream).map(AsRef::as_ref)
+    }
+}
+
+impl fmt::Debug for SnapshotFns {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// Fold the events published since the latest snapshot of a stream and save the result,
+/// a stream without snapshot is folded from its first event.
+pub fn create_snapshot(
+    db: &Db,
+    stream: &EsStreamName,
+    fold: &SnapshotFn,
+) -> Result<Result<Response, ServerError>, Error> {
+    let last = match last_event_number(db, stream)? {
+        Some(last) => last,
+        None => return Ok(Err(stream_not_found(stream))),
+    };
+
+    let (from, mut data) = match latest_snapshot(db, stream)? {
+        Some((number, data)) => (EventNumber(number.0 + 1), data.to_vec()),
+        None => (EventNumber(0), Vec::new()),
+    };
+
+    if from <= last {
+        let tree = db.open_tree(stream.clone().into_bytes())?;
+        for result in tree.range(from.to_be_bytes()..=last.to_be_bytes()) {
+            let (key, value) = result?;
+            let number = parse_event_number(stream, &key)?;
+            match RawEvent::new(value).decode() {
+                Ok((event_name, event_data)) => data = fold(data, &event_name, &event_data),
+                Err(e) => {
+                    let message = format!(
+                        "event {} of stream {} is corrupted; {}",
+                        number.0, stream, e
+                    );
+                    return Ok(Err(ServerError::new(ErrorCode::CorruptedEvent, message)));
+                }
+            }
+        }
+    }
+
+    let response = save_snapshot(db, stream, last, &data)?.map(|()| Response::Snapshot {
+        stream: stream.clone(),
+        number: last,
+        data,
+    });
+
+    Ok(response)
+}
+
+/// Delete the events of a stream included in its latest snapshot, they can be
+/// recovered from the snapshot. The events published after it are kept.
+///
+/// Returns the number of events deleted and the bytes they were using,
+/// an error if the stream does not exist or has no snapshot.
+pub fn compact_stream(
+    db: &Db,
+    stream: &EsStreamName,
+) -> Result<Result<Response, ServerError>, Error> {
+    if last_event_number(db, stream)?.is_none() {
+        return Ok(Err(stream_not_found(stream)));
+    }
+
+    let number = match latest_snapshot(db, stream)? {
+        Some((number, _)) => number,
+        None => {
+            let message = format!("stream {} has no snapshot to compact from", stream);
+            return Ok(Err(ServerError::new(ErrorCode::SnapshotNotFound, message)));
+        }
+    };
+
+    let tree = db.open_tree(stream.clone().into_bytes())?;
+    let (mut events, mut bytes) = (0, 0);
+
+    for result in tree.range(..=number.to_be_bytes()) {
+        let (key, value) = result?;
+        if tree.remove(&key)?.is_some() {
+            events += 1;
+            bytes += (key.len() + value.len()) as u64;
+        }
+    }
+
+    Ok(Ok(Response::Compacted {
+        stream: stream.clone(),
+        events,
+        bytes,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+    use crate::builder::Server;
+    use crate::storage::{save_event, stream_names, tree_size};
+    use crate::Settings;
+    use meilies_client::{paired_connect, PairedConnectionError};
+    use sled::Config;
+
+    #[test]
+    fn snapshot_replaces_the_previous_one() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("snapshotted".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        for _ in 0..3 {
+            let event_data = EventData(b"data".to_vec());
+            save_event(
+                &db,
+                &stream,
+                &event_name,
+                event_data,
+                None,
+                Settings::default(),
+            )
+            .unwrap();
+        }
+
+        // the event 3 has not been published yet
+        let result = save_snapshot(&db, &stream, EventNumber(3), b"ahead").unwrap();
+        assert!(result.is_err());
+        assert!(latest_snapshot(&db, &stream).unwrap().is_none());
+
+        save_snapshot(&db, &stream, EventNumber(0), b"first")
+            .unwrap()
+            .unwrap();
+        save_snapshot(&db, &stream, EventNumber(2), b"second")
+            .unwrap()
+            .unwrap();
+
+        let (number, data) = latest_snapshot(&db, &stream).unwrap().unwrap();
+        assert_eq!(number, EventNumber(2));
+        assert_eq!(data.as_ref(), b"second");
+
+        let tree = db.open_tree(snapshot_tree_name(&stream)).unwrap();
+        assert_eq!(tree.len(), 1);
+        assert_eq!(stream_names(&db), vec![stream]);
+    }
+
+    #[test]
+    fn create_snapshot_with_a_summing_fold() {
+        let stream = EsStreamName::new("summed".to_owned()).unwrap();
+        let sum = |data: Vec<u8>, _: &EventName, event_data: &EventData| {
+            let total = <[u8; 8]>::try_from(data.as_slice()).map_or(0, u64::from_be_bytes);
+            let value = <[u8; 8]>::try_from(event_data.0.as_slice()).unwrap();
+            (total + u64::from_be_bytes(value)).to_be_bytes().to_vec()
+        };
+
+        let server = Server::builder()
+            .listen("127.0.0.1:0".parse().unwrap())
+            .temporary(true)
+            .snapshot_fn(stream.clone(), sum)
+            .build()
+            .unwrap();
+
+        let addr = server.local_addrs().unwrap()[0];
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.spawn(server.run());
+
+        let mut conn = runtime.block_on(paired_connect(addr)).unwrap();
+        let event_name = EventName::new("added".to_owned()).unwrap();
+        let event_data = |value: u64| EventData(value.to_be_bytes().to_vec());
+
+        for value in 1..=3 {
+            let fut = conn.publish(stream.clone(), event_name.clone(), event_data(value));
+            conn = runtime.block_on(fut).unwrap();
+        }
+
+        let fut = conn.create_snapshot(stream.clone());
+        let ((number, data), conn) = runtime.block_on(fut).unwrap();
+        assert_eq!(number, EventNumber(2));
+        assert_eq!(data, 6u64.to_be_bytes().to_vec());
+
+        // only the events published since the previous snapshot are folded
+        let fut = conn.publish(stream.clone(), event_name, event_data(4));
+        let conn = runtime.block_on(fut).unwrap();
+        let fut = conn.create_snapshot(stream.clone());
+        let ((number, data), conn) = runtime.block_on(fut).unwrap();
+        assert_eq!(number, EventNumber(3));
+        assert_eq!(data, 10u64.to_be_bytes().to_vec());
+
+        let fut = conn.get_snapshot(stream);
+        let (snapshot, conn) = runtime.block_on(fut).unwrap();
+        assert_eq!(
+            snapshot,
+            Some((EventNumber(3), 10u64.to_be_bytes().to_vec()))
+        );
+
+        let other = EsStreamName::new("unregistered".to_owned()).unwrap();
+        match runtime.block_on(conn.create_snapshot(other)) {
+            Err(PairedConnectionError::ServerSide(_)) => (),
+            otherwise => panic!("unexpected result {:?}", otherwise.map(|(s, _)| s)),
+        }
+    }
+
+    #[test]
+    fn compact_deletes_the_snapshotted_events() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("compacted".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        for _ in 0..5 {
+            let event_data = EventData(b"data".to_vec());
+            save_event(
+                &db,
+                &stream,
+                &event_name,
+                event_data,
+                None,
+                Settings::default(),
+            )
+            .unwrap();
+        }
+
+        let error = compact_stream(&db, &stream).unwrap().unwrap_err();
+        assert_eq!(error.code, Some(ErrorCode::SnapshotNotFound));
+
+        let missing = EsStreamName::new("missing".to_owned()).unwrap();
+        let error = compact_stream(&db, &missing).unwrap().unwrap_err();
+        assert_eq!(error.code, Some(ErrorCode::StreamNotFound));
+
+        save_snapshot(&db, &stream, EventNumber(2), b"state")
+            .unwrap()
+            .unwrap();
+
+        let tree = db.open_tree(stream.clone().into_bytes()).unwrap();
+        let before = tree_size(&tree).unwrap();
+
+        let response = compact_stream(&db, &stream).unwrap().unwrap();
+        let after = tree_size(&tree).unwrap();
+        assert_eq!(
+            response,
+            Response::Compacted {
+                stream: stream.clone(),
+                events: 3,
+                bytes: before - after,
+            }
+        );
+
+        let numbers: Vec<_> = tree
+            .iter()
+            .keys()
+            .map(|key| parse_event_number(&stream, &key.unwrap()).unwrap())
+            .collect();
+        assert_eq!(numbers, vec![EventNumber(3), EventNumber(4)]);
+
+        // the stream keeps its numbering and its snapshot
+        assert_eq!(
+            last_event_number(&db, &stream).unwrap(),
+            Some(EventNumber(4))
+        );
+        assert!(latest_snapshot(&db, &stream).unwrap().is_some());
+
+        // compacting again deletes nothing more
+        match compact_stream(&db, &stream).unwrap().unwrap() {
+            Response::Compacted { events, bytes, .. } => assert_eq!((events, bytes), (0, 0)),
+            otherwise => panic!("unexpected response {:?}", otherwise),
+        }
+    }
+}
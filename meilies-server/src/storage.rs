@@ -0,0 +1,1018 @@
+//! The layout of the streams in the database and the functions reading and appending events.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use log::{debug, error};
+use sled::{ConflictableTransactionError, TransactionError, Transactional};
+use sled::{Db, TransactionalTree, Tree};
+use tokio::prelude::*;
+use tokio::timer::Interval;
+
+use meilies::reqresp::{ErrorCode, Response, ServerError};
+use meilies::stream::StreamSettings;
+use meilies::stream::{EventData, EventName, EventNumber, RawEvent, RawEventError};
+use meilies::stream::{StreamName as EsStreamName, StreamNameError};
+
+use super::{Error, Settings};
+use crate::dedup::{
+    dedup_count, dedup_key, dedup_tree_name, trim_dedup_ids, DEDUP_COUNT_KEY, DEDUP_ID_PREFIX,
+    DEDUP_NUMBER_PREFIX,
+};
+use crate::retention::{apply_retention, retention};
+
+pub fn last_event_number(
+    numbers: &Tree,
+    name: &EsStreamName,
+) -> Result<Option<EventNumber>, Error> {
+    match numbers.get(name)? {
+        Some(bytes) => parse_event_number(name, &bytes).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Read a stream counter, an error is returned if the stored value is not a valid number.
+pub fn parse_event_number(name: &EsStreamName, bytes: &[u8]) -> Result<EventNumber, Error> {
+    EventNumber::try_from(bytes).map_err(|_| Error::CorruptedEventNumber(name.clone()))
+}
+
+pub fn raw_event(
+    event_name: &EventName,
+    event_data: EventData,
+    headers: &HashMap<String, String>,
+) -> Vec<u8> {
+    RawEvent::encode_with_headers(event_name, &event_data, headers).into_inner()
+}
+
+/// Decode a stored event, the tombstones of the redacted events are flagged as such.
+pub fn decode_event(
+    stream: &EsStreamName,
+    number: EventNumber,
+    value: &[u8],
+) -> Result<Response, RawEventError> {
+    let raw_event = RawEvent::new(value);
+    let (event_name, event_data, headers) = raw_event.decode_with_headers()?;
+
+    Ok(Response::Event {
+        stream: stream.clone(),
+        number,
+        event_name,
+        event_data,
+        global: None,
+        headers,
+        redacted: raw_event.is_redacted()?,
+        timestamp: raw_event.timestamp()?,
+    })
+}
+
+/// Decode a stored event, a corrupted event is logged and `None` is returned
+/// to let subscriptions skip it instead of crashing.
+pub fn event_response(
+    stream: &EsStreamName,
+    number: EventNumber,
+    value: &[u8],
+) -> Option<Response> {
+    match decode_event(stream, number, value) {
+        Ok(response) => Some(response),
+        Err(e) => {
+            error!("skipping event {} of stream {}; {}", number.0, stream, e);
+            None
+        }
+    }
+}
+
+/// Returns the number of bytes used by the keys and values of a tree.
+pub fn tree_size(tree: &Tree) -> sled::Result<u64> {
+    let mut bytes = 0;
+
+    for result in tree.iter() {
+        let (key, value) = result?;
+        bytes += (key.len() + value.len()) as u64;
+    }
+
+    Ok(bytes)
+}
+
+/// The snapshots of a stream are stored in their own tree, keyed by the number
+/// of the last event they include, only the latest one is kept.
+pub fn snapshot_tree_name(stream: &EsStreamName) -> Vec<u8> {
+    format!("snapshot:{}", stream).into_bytes()
+}
+
+/// The settings of a stream are stored in their own tree.
+fn info_tree_name(stream: &EsStreamName) -> Vec<u8> {
+    format!("info:{}", stream).into_bytes()
+}
+
+/// The cursor of a consumer group is stored in its own tree.
+pub fn group_tree_name(stream: &EsStreamName, group: &str) -> Vec<u8> {
+    format!("group:{}:{}", stream, group).into_bytes()
+}
+
+/// The number of the first event the group never claimed.
+pub const GROUP_NEXT_KEY: &[u8] = b"next";
+
+/// The events claimed but not acknowledged yet, their number follows the prefix.
+pub const GROUP_PENDING_PREFIX: &[u8] = b"pending:";
+
+/// Open the tree of a consumer group, `None` is returned if the group was never created.
+pub fn open_group(db: &Db, name: &[u8]) -> sled::Result<Option<Tree>> {
+    if db.tree_names().iter().all(|n| &n[..] != name) {
+        return Ok(None);
+    }
+
+    db.open_tree(name).map(Some)
+}
+
+pub fn group_not_found(stream: &EsStreamName, group: &str) -> ServerError {
+    let message = format!("group {} of stream {} does not exist", group, stream);
+    ServerError::new(ErrorCode::GroupNotFound, message)
+}
+
+pub fn stream_not_found(stream: &EsStreamName) -> ServerError {
+    let message = format!("stream {} does not exist", stream);
+    ServerError::new(ErrorCode::StreamNotFound, message)
+}
+
+const COMPRESSION_KEY: &[u8] = b"compression";
+
+/// Record whether the events published to a stream are compressed,
+/// when the server compresses the events.
+pub fn set_stream_compression(db: &Db, stream: &EsStreamName, compress: bool) -> sled::Result<()> {
+    let info = db.open_tree(info_tree_name(stream))?;
+    info.insert(COMPRESSION_KEY, &[compress as u8][..])?;
+    Ok(())
+}
+
+const MAX_EVENTS_KEY: &[u8] = b"max-events";
+
+const MAX_EVENT_SIZE_KEY: &[u8] = b"max-event-size";
+
+/// Record the settings of a stream in its info tree, each setting is stored under its own key.
+fn set_stream_settings(
+    db: &Db,
+    stream: &EsStreamName,
+    settings: StreamSettings,
+) -> sled::Result<()> {
+    let info = db.open_tree(info_tree_name(stream))?;
+    info.insert(COMPRESSION_KEY, &[settings.compressed as u8][..])?;
+
+    let numbers = [
+        (MAX_EVENTS_KEY, settings.max_events),
+        (MAX_EVENT_SIZE_KEY, settings.max_event_size),
+    ];
+    for (key, number) in &numbers {
+        match number {
+            Some(number) => info.insert(key, &number.to_be_bytes()[..])?,
+            None => info.remove(key)?,
+        };
+    }
+
+    Ok(())
+}
+
+/// Read the settings of a stream, the settings that were never recorded have their default value.
+pub fn stream_settings(db: &Db, stream: &EsStreamName) -> sled::Result<StreamSettings> {
+    let info = db.open_tree(info_tree_name(stream))?;
+    let number = |key| -> sled::Result<Option<u64>> {
+        let value = info.get(key)?;
+        let bytes = value.and_then(|v| <[u8; 8]>::try_from(v.as_ref()).ok());
+        Ok(bytes.map(u64::from_be_bytes))
+    };
+
+    let compressed = info.get(COMPRESSION_KEY)?;
+    Ok(StreamSettings {
+        compressed: compressed.map_or(true, |v| v.as_ref() != [0]),
+        max_events: number(MAX_EVENTS_KEY)?,
+        max_event_size: number(MAX_EVENT_SIZE_KEY)?,
+    })
+}
+
+/// A stream created when the server starts along with its settings, written
+/// `name[:option,...]` where the options are `uncompressed`, `max-events=N` and `max-event-size=N`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamDeclaration {
+    pub stream: EsStreamName,
+    pub settings: StreamSettings,
+}
+
+#[derive(Debug)]
+pub enum ParseStreamDeclarationError {
+    InvalidStreamName(StreamNameError),
+    InvalidOption(String),
+}
+
+impl fmt::Display for ParseStreamDeclarationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseStreamDeclarationError::InvalidStreamName(e) => write!(f, "{}", e),
+            ParseStreamDeclarationError::InvalidOption(option) => {
+                write!(f, "invalid stream option {:?}", option)
+            }
+        }
+    }
+}
+
+impl FromStr for StreamDeclaration {
+    type Err = ParseStreamDeclarationError;
+
+    fn from_str(s: &str) -> Result<StreamDeclaration, Self::Err> {
+        // a colon is not allowed in stream names, it always starts the options
+        let mut split = s.splitn(2, ':');
+        let name = split.next().unwrap_or("");
+        let stream =
+            EsStreamName::from_str(name).map_err(ParseStreamDeclarationError::InvalidStreamName)?;
+
+        let mut settings = StreamSettings::default();
+        for option in split.next().into_iter().flat_map(|o| o.split(',')) {
+            let invalid = || ParseStreamDeclarationError::InvalidOption(option.to_owned());
+            let mut split = option.splitn(2, '=');
+            match (split.next(), split.next()) {
+                (Some("uncompressed"), None) => settings.compressed = false,
+                (Some("max-events"), Some(count)) => {
+                    settings.max_events = Some(count.parse().map_err(|_| invalid())?)
+                }
+                (Some("max-event-size"), Some(size)) => {
+                    settings.max_event_size = Some(size.parse().map_err(|_| invalid())?)
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(StreamDeclaration { stream, settings })
+    }
+}
+
+/// Create the trees of a stream and record its settings.
+pub fn declare_stream(
+    db: &Db,
+    stream: &EsStreamName,
+    settings: StreamSettings,
+) -> sled::Result<()> {
+    db.open_tree(stream.as_str().as_bytes())?;
+    set_stream_settings(db, stream, settings)
+}
+
+/// The global counter and the global log are stored in their own trees.
+const GLOBAL_COUNTER_TREE: &[u8] = b"global:counter";
+
+const GLOBAL_COUNTER_KEY: &[u8] = b"counter";
+
+/// The global log maps the global number of an event to its
+/// stream event number followed by the name of its stream.
+pub const GLOBAL_LOG_TREE: &[u8] = b"global:log";
+
+fn global_log_value(stream: &EsStreamName, number: EventNumber) -> Vec<u8> {
+    let mut value = Vec::with_capacity(8 + stream.as_str().len());
+    value.extend_from_slice(&number.to_be_bytes());
+    value.extend_from_slice(stream.as_str().as_bytes());
+    value
+}
+
+/// Read the event a global log entry refers to, `None` is returned if the entry
+/// is invalid or if the event was removed from its stream by the retention.
+pub fn global_event_response(db: &Db, global: EventNumber, value: &[u8]) -> Option<Response> {
+    if value.len() < 8 {
+        error!("skipping invalid global event {}", global.0);
+        return None;
+    }
+
+    let (number, name) = value.split_at(8);
+    let number = EventNumber::try_from(number).ok()?;
+    let stream = String::from_utf8(name.to_vec()).ok()?;
+    let stream = EsStreamName::new(stream).ok()?;
+
+    let raw = match db.open_tree(stream.clone().into_bytes()) {
+        Ok(tree) => tree.get(number.to_be_bytes()),
+        Err(e) => Err(e),
+    };
+
+    match raw {
+        Ok(Some(raw)) => match event_response(&stream, number, &raw)? {
+            Response::Event {
+                stream,
+                number,
+                event_name,
+                event_data,
+                headers,
+                redacted,
+                timestamp,
+                ..
+            } => Some(Response::Event {
+                stream,
+                number,
+                event_name,
+                event_data,
+                global: Some(global),
+                headers,
+                redacted,
+                timestamp,
+            }),
+            _ => None,
+        },
+        Ok(None) => None,
+        Err(e) => {
+            error!("skipping global event {}; {}", global.0, e);
+            None
+        }
+    }
+}
+
+/// Write the database to disk and log the time it took.
+pub fn flush(db: &Db) -> sled::Result<()> {
+    let now = Instant::now();
+    let bytes = db.flush()?;
+    debug!("flushed {} bytes in {:.2?}", bytes, now.elapsed());
+    Ok(())
+}
+
+/// Flush the database at every tick of the interval, until the runtime shuts down.
+pub fn flush_periodically(db: Db, interval: Duration) -> impl Future<Item = (), Error = ()> {
+    Interval::new_interval(interval)
+        .map_err(|e| error!("flush timer error; {}", e))
+        .for_each(move |_| {
+            if let Err(e) = flush(&db) {
+                error!("flush error; {}", e);
+            }
+            Ok(())
+        })
+}
+
+/// Returns the names of all the streams, the trees that are not streams are ignored.
+pub fn stream_names(db: &Db) -> Vec<EsStreamName> {
+    iter_stream_names(db).collect()
+}
+
+/// Iterates over the names of the streams one at a time,
+/// the names are validated lazily so callers can stop or skip without collecting them all.
+///
+/// Every tree of the database that is not a stream (dedup ids, snapshots, settings,
+/// consumer groups and the global order) has a name containing a colon, colons are
+/// not allowed in stream names so these trees are never listed as streams.
+pub fn iter_stream_names(db: &Db) -> impl Iterator<Item = EsStreamName> {
+    db.tree_names()
+        .into_iter()
+        .filter(|n| n != b"__sled__default")
+        .filter_map(|n| String::from_utf8(n).ok())
+        .filter_map(|s| EsStreamName::new(s).ok())
+}
+
+/// Returns the names of the streams that start with the given prefix.
+pub fn stream_names_with_prefix(db: &Db, prefix: &str) -> Vec<EsStreamName> {
+    iter_stream_names(db)
+        .filter(|name| name.as_str().starts_with(prefix))
+        .collect()
+}
+
+/// Run a transaction over all the trees of a slice, sled only implements
+/// `Transactional` for tuples of trees so each possible length is listed.
+macro_rules! transaction_over {
+    ($trees:expr, $f:expr, $($len:literal => ($($i:tt),+);)+) => {
+        match $trees.len() {
+            $($len => ($(&$trees[$i],)+).transaction(|view| $f(&[$(&view.$i),+])),)+
+            len => panic!("no transaction over {} trees", len),
+        }
+    };
+}
+
+/// Append an event and the headers it was published with to a stream.
+pub fn save_event_with_headers(
+    db: &Db,
+    stream: &EsStreamName,
+    event_name: &EventName,
+    event_data: EventData,
+    headers: &HashMap<String, String>,
+    dedup_id: Option<&str>,
+    settings: Settings,
+) -> Result<EventNumber, Error> {
+    let stream_settings = stream_settings(db, stream)?;
+    let raw_event = match settings.event_compression {
+        Some(level) if stream_settings.compressed => {
+            RawEvent::encode_compressed_with_headers(event_name, &event_data, headers, level)?
+                .into_inner()
+        }
+        _ => raw_event(event_name, event_data, headers),
+    };
+
+    // The stream counter and the event are written in the same transaction,
+    // a reader can never see an event number that does not have its event.
+    //
+    // The default tree holds the stream counters, it is followed by the tree of the stream,
+    // by the dedup tree when a dedup id is given and by the global trees when the global
+    // ordering is enabled, the global counter is then shared by all the streams
+    // and the publications conflict with each other.
+    let mut trees = vec![Tree::clone(db), db.open_tree(stream.as_str().as_bytes())?];
+    if dedup_id.is_some() {
+        trees.push(db.open_tree(dedup_tree_name(stream))?);
+    }
+    if settings.global_order {
+        trees.push(db.open_tree(GLOBAL_COUNTER_TREE)?);
+        trees.push(db.open_tree(GLOBAL_LOG_TREE)?);
+    }
+
+    let append = |trees: &[&TransactionalTree]| {
+        let (numbers, events) = (trees[0], trees[1]);
+        let (dedup, globals) = match dedup_id {
+            Some(id) => {
+                let key = dedup_key(DEDUP_ID_PREFIX, id.as_bytes());
+                (Some((trees[2], id, key)), &trees[3..])
+            }
+            None => (None, &trees[2..]),
+        };
+
+        if let Some((dedup, _, key)) = &dedup {
+            if let Some(number) = dedup.get(key)? {
+                // The event was already published, the previous number is returned.
+                match EventNumber::try_from(number.as_ref()) {
+                    Ok(number) => return Ok(number),
+                    Err(_) => return Err(ConflictableTransactionError::Abort(())),
+                }
+            }
+        }
+
+        let number = match numbers.get(stream.as_str().as_bytes())? {
+            Some(previous) => match EventNumber::try_from(previous.as_ref()) {
+                Ok(previous) => previous.next(),
+                // The counter is corrupted, the transaction is aborted
+                // and the error is reported to the client.
+                Err(_) => return Err(ConflictableTransactionError::Abort(())),
+            },
+            None => EventNumber::zero(),
+        };
+
+        numbers.insert(stream.as_str().as_bytes(), &number.to_be_bytes()[..])?;
+        events.insert(&number.to_be_bytes()[..], raw_event.clone())?;
+
+        if let [global_counter, global_log] = globals {
+            let global = match global_counter.get(GLOBAL_COUNTER_KEY)? {
+                Some(previous) => match EventNumber::try_from(previous.as_ref()) {
+                    Ok(previous) => previous.next(),
+                    Err(_) => return Err(ConflictableTransactionError::Abort(())),
+                },
+                None => EventNumber::zero(),
+            };
+
+            global_counter.insert(GLOBAL_COUNTER_KEY, &global.to_be_bytes()[..])?;
+            global_log.insert(&global.to_be_bytes()[..], global_log_value(stream, number))?;
+        }
+
+        if let Some((dedup, id, key)) = &dedup {
+            let number_key = dedup_key(DEDUP_NUMBER_PREFIX, &number.to_be_bytes());
+            dedup.insert(key.as_slice(), &number.to_be_bytes()[..])?;
+            dedup.insert(number_key, id.as_bytes())?;
+
+            let count = dedup_count(dedup.get(DEDUP_COUNT_KEY)?.as_deref()) + 1;
+            dedup.insert(DEDUP_COUNT_KEY, &count.to_be_bytes()[..])?;
+        }
+
+        Ok(number)
+    };
+
+    let result = transaction_over!(trees, append,
+        2 => (0, 1);
+        3 => (0, 1, 2);
+        4 => (0, 1, 2, 3);
+        5 => (0, 1, 2, 3, 4);
+    );
+
+    let event_number = match result {
+        Ok(number) => number,
+        Err(TransactionError::Abort(())) => {
+            return Err(Error::CorruptedEventNumber(stream.clone()))
+        }
+        Err(TransactionError::Storage(e)) => return Err(Error::from(e)),
+    };
+
+    if let Some(max_events) = retention(settings, stream_settings) {
+        apply_retention(&trees[1], max_events)?;
+    }
+
+    if dedup_id.is_some() {
+        trim_dedup_ids(&trees[2])?;
+    }
+
+    Ok(event_number)
+}
+
+/// Append an event published without headers.
+#[cfg(test)]
+pub fn save_event(
+    db: &Db,
+    stream: &EsStreamName,
+    event_name: &EventName,
+    event_data: EventData,
+    dedup_id: Option<&str>,
+    settings: Settings,
+) -> Result<EventNumber, Error> {
+    let headers = HashMap::new();
+    save_event_with_headers(
+        db, stream, event_name, event_data, &headers, dedup_id, settings,
+    )
+}
+
+/// The maximum number of different streams a `PublishMulti` can append to.
+pub const PUBLISH_MULTI_MAX_STREAMS: usize = 8;
+
+/// Append events to several streams in a single transaction, either all the events
+/// are appended or none of them is, returns the numbers given to the events in order.
+///
+/// The events must not target more than `PUBLISH_MULTI_MAX_STREAMS` different streams.
+pub fn save_events(
+    db: &Db,
+    events: &[(EsStreamName, EventName, EventData)],
+    settings: Settings,
+) -> Result<Vec<EventNumber>, Error> {
+    let mut streams: Vec<&EsStreamName> = Vec::new();
+    let mut streams_settings = Vec::new();
+    for (stream, _, _) in events {
+        if !streams.contains(&stream) {
+            streams.push(stream);
+            streams_settings.push(stream_settings(db, stream)?);
+        }
+    }
+
+    let mut raw_events = Vec::with_capacity(events.len());
+    for (stream, event_name, event_data) in events {
+        let index = streams.iter().position(|s| *s == stream).unwrap();
+        let raw_event = match settings.event_compression {
+            Some(level) if streams_settings[index].compressed => {
+                RawEvent::encode_compressed(event_name, event_data, level)?.into_inner()
+            }
+            _ => RawEvent::encode(event_name, event_data).into_inner(),
+        };
+        raw_events.push(raw_event);
+    }
+
+    // The default tree holds the stream counters, it is followed by the global trees when
+    // the global ordering is enabled and by the tree of each stream, in the order the
+    // streams first appear. Without global ordering the publications to different
+    // streams do not conflict with each other, like in `save_event_with_headers`.
+    let mut trees = vec![Tree::clone(db)];
+    if settings.global_order {
+        trees.push(db.open_tree(GLOBAL_COUNTER_TREE)?);
+        trees.push(db.open_tree(GLOBAL_LOG_TREE)?);
+    }
+    let first_stream = trees.len();
+    for stream in &streams {
+        trees.push(db.open_tree(stream.as_str().as_bytes())?);
+    }
+
+    let corrupted = Cell::new(None);
+    let append = |trees: &[&TransactionalTree]| {
+        let numbers = trees[0];
+        let globals = &trees[1..first_stream];
+        let mut assigned = Vec::with_capacity(events.len());
+
+        for ((stream, _, _), raw_event) in events.iter().zip(&raw_events) {
+            let index = streams.iter().position(|s| *s == stream).unwrap();
+            let stream_events = trees[first_stream + index];
+
+            let number = match numbers.get(stream.as_str().as_bytes())? {
+                Some(previous) => match EventNumber::try_from(previous.as_ref()) {
+                    Ok(previous) => previous.next(),
+                    Err(_) => {
+                        corrupted.set(Some(stream.clone()));
+                        return Err(ConflictableTransactionError::Abort(()));
+                    }
+                },
+                None => EventNumber::zero(),
+            };
+
+            numbers.insert(stream.as_str().as_bytes(), &number.to_be_bytes()[..])?;
+            stream_events.insert(&number.to_be_bytes()[..], raw_event.clone())?;
+
+            if let [global_counter, global_log] = globals {
+                let global = match global_counter.get(GLOBAL_COUNTER_KEY)? {
+                    Some(previous) => match EventNumber::try_from(previous.as_ref()) {
+                        Ok(previous) => previous.next(),
+                        Err(_) => {
+                            corrupted.set(Some(stream.clone()));
+                            return Err(ConflictableTransactionError::Abort(()));
+                        }
+                    },
+                    None => EventNumber::zero(),
+                };
+
+                global_counter.insert(GLOBAL_COUNTER_KEY, &global.to_be_bytes()[..])?;
+                global_log.insert(&global.to_be_bytes()[..], global_log_value(stream, number))?;
+            }
+
+            assigned.push(number);
+        }
+
+        Ok(assigned)
+    };
+
+    let result = transaction_over!(trees, append,
+        2 => (0, 1);
+        3 => (0, 1, 2);
+        4 => (0, 1, 2, 3);
+        5 => (0, 1, 2, 3, 4);
+        6 => (0, 1, 2, 3, 4, 5);
+        7 => (0, 1, 2, 3, 4, 5, 6);
+        8 => (0, 1, 2, 3, 4, 5, 6, 7);
+        9 => (0, 1, 2, 3, 4, 5, 6, 7, 8);
+        10 => (0, 1, 2, 3, 4, 5, 6, 7, 8, 9);
+        11 => (0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10);
+    );
+
+    let numbers = match result {
+        Ok(numbers) => numbers,
+        Err(TransactionError::Abort(())) => {
+            let stream = corrupted
+                .take()
+                .expect("aborted without a corrupted stream");
+            return Err(Error::CorruptedEventNumber(stream));
+        }
+        Err(TransactionError::Storage(e)) => return Err(Error::from(e)),
+    };
+
+    for (tree, stream_settings) in trees[first_stream..].iter().zip(streams_settings) {
+        if let Some(max_events) = retention(settings, stream_settings) {
+            apply_retention(tree, max_events)?;
+        }
+    }
+
+    Ok(numbers)
+}
+
+/// The health of the events of a stream.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub events: u64,
+    pub corrupted: Vec<EventNumber>,
+    pub missing: Vec<(EventNumber, EventNumber)>,
+}
+
+/// Read every event of a stream, checking its checksum and that the event numbers
+/// are contiguous up to the stream counter.
+///
+/// The events removed by the retention are not reported, only the numbers
+/// following the first stored event must be present.
+///
+/// Returns `None` if the stream does not exist, without creating it.
+pub fn verify_stream(db: &Db, stream: &EsStreamName) -> Result<Option<VerifyReport>, Error> {
+    let last = match last_event_number(db, stream)? {
+        Some(last) => last,
+        None => return Ok(None),
+    };
+
+    let tree = db.open_tree(stream.clone().into_bytes())?;
+    let mut report = VerifyReport::default();
+    let mut expected = None;
+
+    for result in tree.iter() {
+        let (key, value) = result?;
+        let number = parse_event_number(stream, &key)?;
+
+        if let Some(expected) = expected.filter(|expected| *expected < number) {
+            report.missing.push((expected, number));
+        }
+
+        if RawEvent::new(value).verify().is_err() {
+            report.corrupted.push(number);
+        }
+
+        report.events += 1;
+        expected = Some(number.next());
+    }
+
+    if let Some(expected) = expected.filter(|expected| *expected <= last) {
+        report.missing.push((expected, last.next()));
+    }
+
+    Ok(Some(report))
+}
+
+/// Returns the number of the next event that will be appended to a stream.
+pub fn next_event_number(stream: &EsStreamName, tree: &Tree) -> Result<EventNumber, Error> {
+    match tree.iter().next_back() {
+        Some(result) => Ok(parse_event_number(stream, &result?.0)?.next()),
+        None => Ok(EventNumber(0)),
+    }
+}
+
+/// Returns the number of the first event of a stream published at or after the given time,
+/// the number of the next event if there is none. The timestamps only grow along the stream,
+/// the events are binary searched, those without a timestamp are considered older.
+pub fn first_event_since(
+    stream: &EsStreamName,
+    tree: &Tree,
+    time: u64,
+) -> Result<EventNumber, Error> {
+    let mut low = match tree.iter().next() {
+        Some(result) => parse_event_number(stream, &result?.0)?.0,
+        None => return Ok(EventNumber(0)),
+    };
+    let mut high = next_event_number(stream, tree)?.0;
+
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let timestamp = match tree.get(EventNumber(middle).to_be_bytes())? {
+            Some(value) => RawEvent::new(value).timestamp().ok().and_then(|t| t),
+            None => None,
+        };
+
+        if timestamp.map_or(true, |t| t < time) {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+
+    Ok(EventNumber(low))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::{handle_request, ServerCtx};
+    use meilies::reqresp::Request;
+    use sled::Config;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn compress_events_except_opted_out_streams() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let compressed = EsStreamName::new("compressed".to_owned()).unwrap();
+        let uncompressed = EsStreamName::new("uncompressed".to_owned()).unwrap();
+        set_stream_compression(&db, &uncompressed, false).unwrap();
+
+        let settings = Settings {
+            event_compression: Some(3),
+            ..Settings::default()
+        };
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let event_data = EventData(b"data data data data data data".to_vec());
+
+        for stream in &[&compressed, &uncompressed] {
+            let data = event_data.clone();
+            save_event(&db, stream, &event_name, data, None, settings).unwrap();
+
+            let tree = db.open_tree(stream.as_str()).unwrap();
+            let (_, value) = tree.iter().next().unwrap().unwrap();
+            let raw_event = RawEvent::new(value);
+
+            let expected = *stream == &compressed;
+            assert_eq!(raw_event.is_compressed().unwrap(), expected);
+            let decoded = raw_event.decode().unwrap();
+            assert_eq!(decoded, (event_name.clone(), event_data.clone()));
+        }
+    }
+
+    #[test]
+    fn filter_stream_names_by_prefix() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let settings = Settings::default();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        for name in &["orders-eu", "orders-us", "users", "ordersless"] {
+            let stream = EsStreamName::new(name.to_string()).unwrap();
+            let data = EventData(b"data".to_vec());
+            save_event(&db, &stream, &event_name, data, None, settings).unwrap();
+        }
+
+        let mut names: Vec<_> = stream_names_with_prefix(&db, "orders-")
+            .iter()
+            .map(|name| name.as_str().to_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["orders-eu", "orders-us"]);
+
+        assert_eq!(stream_names_with_prefix(&db, "").len(), 4);
+        assert!(stream_names_with_prefix(&db, "missing").is_empty());
+        assert_eq!(iter_stream_names(&db).take(2).count(), 2);
+    }
+
+    #[test]
+    fn verify_reports_corrupted_and_missing_events() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("verify".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        for i in 0..6u64 {
+            let event_data = EventData(i.to_be_bytes().to_vec());
+            save_event(
+                &db,
+                &stream,
+                &event_name,
+                event_data,
+                None,
+                Settings::default(),
+            )
+            .unwrap();
+        }
+
+        let missing = EsStreamName::new("missing".to_owned()).unwrap();
+        assert_eq!(verify_stream(&db, &missing).unwrap(), None);
+        assert_eq!(stream_names(&db), vec![stream.clone()]);
+
+        let report = verify_stream(&db, &stream).unwrap();
+        assert_eq!(
+            report,
+            Some(VerifyReport {
+                events: 6,
+                ..VerifyReport::default()
+            })
+        );
+
+        let tree = db.open_tree(stream.clone().into_bytes()).unwrap();
+        let key = EventNumber(1).to_be_bytes();
+        let mut value = tree.get(key).unwrap().unwrap().to_vec();
+        let last = value.len() - 1;
+        value[last] ^= 0xff;
+        tree.insert(key, value).unwrap();
+        tree.remove(EventNumber(3).to_be_bytes()).unwrap();
+        tree.remove(EventNumber(5).to_be_bytes()).unwrap();
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Verify {
+            stream: stream.clone(),
+        };
+        handle_request(request, &ServerCtx::new(db, Settings::default()), sender).unwrap();
+
+        let response = receiver.wait().next().unwrap().unwrap();
+        let expected = Response::Verified {
+            stream,
+            events: 4,
+            corrupted: vec![EventNumber(1)],
+            missing: vec![
+                (EventNumber(3), EventNumber(4)),
+                (EventNumber(5), EventNumber(6)),
+            ],
+        };
+        assert_eq!(response, Ok(expected));
+    }
+
+    #[test]
+    fn event_numbers_always_have_their_event() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("atomic".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let tree = db.open_tree(stream.clone().into_bytes()).unwrap();
+
+        let publishers: Vec<_> = (0..2)
+            .map(|_| {
+                let db = db.clone();
+                let stream = stream.clone();
+                let event_name = event_name.clone();
+                thread::spawn(move || {
+                    for i in 0..100u64 {
+                        let event_data = EventData(i.to_be_bytes().to_vec());
+                        save_event(
+                            &db,
+                            &stream,
+                            &event_name,
+                            event_data,
+                            None,
+                            Settings::default(),
+                        )
+                        .unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        // the reader checks the counter until every publisher has been joined
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let db = db.clone();
+            let stream = stream.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    if let Some(number) = last_event_number(&db, &stream).unwrap() {
+                        assert!(tree.get(number.to_be_bytes()).unwrap().is_some());
+                    }
+                    thread::yield_now();
+                }
+            })
+        };
+
+        for publisher in publishers {
+            publisher.join().unwrap();
+        }
+
+        done.store(true, Ordering::SeqCst);
+        reader.join().unwrap();
+
+        let last = last_event_number(&db, &stream).unwrap();
+        assert_eq!(last, Some(EventNumber(199)));
+    }
+
+    #[test]
+    fn parse_stream_declarations() {
+        let declaration: StreamDeclaration = "blobs:uncompressed,max-events=10".parse().unwrap();
+        assert_eq!(declaration.stream.as_str(), "blobs");
+        assert!(!declaration.settings.compressed);
+        assert_eq!(declaration.settings.max_events, Some(10));
+
+        let declaration: StreamDeclaration = "orders".parse().unwrap();
+        assert_eq!(declaration.settings, StreamSettings::default());
+
+        assert!("orders:gzip".parse::<StreamDeclaration>().is_err());
+        assert!("orders:max-events=ten"
+            .parse::<StreamDeclaration>()
+            .is_err());
+    }
+
+    #[test]
+    fn stream_settings_persist_across_reopen() {
+        let path = std::env::temp_dir().join(format!("meilies-settings-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let stream = EsStreamName::new("configured".to_owned()).unwrap();
+        let settings = StreamSettings {
+            compressed: false,
+            max_events: Some(3),
+            max_event_size: Some(1024),
+        };
+
+        // without a flusher thread the database is unlocked as soon as it is dropped
+        let config = Config::new().path(&path).flush_every_ms(None);
+        let db = config.open().unwrap();
+        assert_eq!(
+            stream_settings(&db, &stream).unwrap(),
+            StreamSettings::default()
+        );
+        set_stream_settings(&db, &stream, settings).unwrap();
+        db.flush().unwrap();
+        drop(db);
+
+        // the background work of sled can keep the lock for a moment after the drop
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let db = loop {
+            match config.open() {
+                Ok(db) => break db,
+                Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+                Err(e) => panic!("{}", e),
+            }
+        };
+        assert_eq!(stream_settings(&db, &stream).unwrap(), settings);
+        drop(db);
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupted_event_number() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("corrupted".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        db.insert(stream.as_str(), &b"abc"[..]).unwrap();
+
+        let event_data = EventData(b"data".to_vec());
+        match save_event(
+            &db,
+            &stream,
+            &event_name,
+            event_data,
+            None,
+            Settings::default(),
+        ) {
+            Err(Error::CorruptedEventNumber(name)) => assert_eq!(name, stream),
+            otherwise => panic!("unexpected result {:?}", otherwise),
+        }
+
+        match last_event_number(&db, &stream) {
+            Err(Error::CorruptedEventNumber(name)) => assert_eq!(name, stream),
+            otherwise => panic!("unexpected result {:?}", otherwise),
+        }
+
+        // the stream is left untouched
+        let tree = db.open_tree(stream.clone().into_bytes()).unwrap();
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn save_events_only_touches_global_trees_with_global_order() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let events: Vec<_> = ["first", "second"]
+            .iter()
+            .map(|name| {
+                let stream = EsStreamName::new(name.to_string()).unwrap();
+                (stream, event_name.clone(), EventData(b"data".to_vec()))
+            })
+            .collect();
+
+        save_events(&db, &events, Settings::default()).unwrap();
+        let tree_names = db.tree_names();
+        assert!(!tree_names.iter().any(|name| name.starts_with(b"global:")));
+
+        let settings = Settings {
+            global_order: true,
+            ..Settings::default()
+        };
+        save_events(&db, &events, settings).unwrap();
+        let global_log = db.open_tree(GLOBAL_LOG_TREE).unwrap();
+        assert_eq!(global_log.len(), 2);
+    }
+}
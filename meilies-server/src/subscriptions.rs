@@ -0,0 +1,1621 @@
+//! Sending the history and the live events of the subscribed streams.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::executor::{self, Notify};
+use log::{error, info, warn};
+use sled::{Db, Event, Subscriber, Tree};
+use tokio::executor::{DefaultExecutor, Executor};
+use tokio::prelude::*;
+use tokio::sync::mpsc;
+use tokio::timer::Delay;
+
+use meilies::reqresp::{Response, ServerError};
+use meilies::stream::{EventNumber, ReadRange};
+use meilies::stream::{Stream as EsStream, StreamName as EsStreamName};
+
+use super::{Error, ResponseSender};
+use crate::handlers::internal_error;
+use crate::storage::{
+    event_response, first_event_since, next_event_number, parse_event_number,
+    stream_names_with_prefix,
+};
+
+/// How often a subscription thread waiting for a change checks that its connection is open.
+pub const CLOSED_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Does nothing when notified, the futures polled with it are polled again later.
+struct NoopNotify;
+
+impl Notify for NoopNotify {
+    fn notify(&self, _id: usize) {}
+}
+
+/// Returns `true` once the receiver of the responses has been dropped, without blocking.
+///
+/// A subscription thread waiting for the changes of a quiet stream would otherwise
+/// only notice that its connection is closed when sending the next event.
+pub fn is_closed(sender: &ResponseSender) -> bool {
+    // the slot reserved by the probe is released when it is dropped
+    let mut probe = sender.clone();
+    let mut ready = executor::spawn(future::poll_fn(move || probe.poll_ready()));
+    ready.poll_future_notify(&Arc::new(NoopNotify), 0).is_err()
+}
+
+/// Appended to a watched prefix to build a key that is never written, colons are neither
+/// allowed in stream names nor part of the 8 bytes event numbers.
+const WAKE_UP_SUFFIX: &[u8] = b":wake-up";
+
+/// Stops the thread of `forward_changes` when dropped, the receiver of the changes must
+/// have been dropped before.
+///
+/// sled 0.29 only exposes the changes of a tree as a blocking iterator, removing a key
+/// that does not exist notifies the subscribers without changing the tree, the thread
+/// wakes up and ends instead of waiting for the next write to a quiet stream.
+pub struct Forwarder {
+    tree: Tree,
+    wake_up_key: Vec<u8>,
+}
+
+impl Drop for Forwarder {
+    fn drop(&mut self) {
+        if let Err(e) = self.tree.remove(&self.wake_up_key) {
+            error!("impossible to stop a watcher; {}", e);
+        }
+    }
+}
+
+/// Forward the changes of the keys of a tree starting with `prefix` from a dedicated thread.
+/// `send` returns `false` once the changes are not wanted anymore, the thread then ends,
+/// at the latest when the returned `Forwarder` is dropped.
+fn forward_changes<F>(tree: &Tree, prefix: &[u8], mut send: F) -> Forwarder
+where
+    F: FnMut(Event) -> bool + Send + 'static,
+{
+    let subscriber: Subscriber = tree.watch_prefix(prefix);
+    thread::spawn(move || {
+        for event in subscriber {
+            if !send(event) {
+                break;
+            }
+        }
+    });
+
+    Forwarder {
+        tree: tree.clone(),
+        wake_up_key: [prefix, WAKE_UP_SUFFIX].concat(),
+    }
+}
+
+/// The changes of a watched tree that can be waited for with a timeout.
+///
+/// The fields are dropped in order, the receiver is closed before the forwarder is woken up.
+pub struct Watcher {
+    receiver: std::sync::mpsc::Receiver<Event>,
+    _forwarder: Forwarder,
+}
+
+impl Watcher {
+    pub fn new(tree: &Tree, prefix: &[u8]) -> Watcher {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let forwarder = forward_changes(tree, prefix, move |event| sender.send(event).is_ok());
+        Watcher {
+            receiver,
+            _forwarder: forwarder,
+        }
+    }
+
+    pub fn next_timeout(&self, timeout: Duration) -> Result<Event, RecvTimeoutError> {
+        self.receiver.recv_timeout(timeout)
+    }
+}
+
+/// Wait for the next change of a watched stream, a heartbeat is sent each time
+/// the interval elapses without any change so that consumers can compute their lag.
+///
+/// Returns `None` when the watcher or the channel is closed.
+fn next_watched_event(
+    watcher: &Watcher,
+    stream: &EsStreamName,
+    tree: &Tree,
+    sender: &mut mpsc::Sender<Result<Response, ServerError>>,
+    heartbeat: Option<Duration>,
+) -> Result<Option<Event>, Error> {
+    let timeout = heartbeat.map_or(CLOSED_CHECK_INTERVAL, |h| {
+        cmp::min(h, CLOSED_CHECK_INTERVAL)
+    });
+    let mut last_change = Instant::now();
+
+    loop {
+        match watcher.next_timeout(timeout) {
+            Ok(event) => return Ok(Some(event)),
+            Err(RecvTimeoutError::Disconnected) => return Ok(None),
+            Err(RecvTimeoutError::Timeout) => {
+                if is_closed(sender) {
+                    info!("encountered closed channel");
+                    return Ok(None);
+                }
+
+                match heartbeat {
+                    Some(interval) if last_change.elapsed() >= interval => {
+                        last_change = Instant::now();
+                    }
+                    _ => continue,
+                }
+
+                let heartbeat = Response::Heartbeat {
+                    stream: stream.clone(),
+                    number: next_event_number(stream, tree)?,
+                };
+                if sender.clone().send(Ok(heartbeat)).wait().is_err() {
+                    info!("encountered closed channel");
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+/// Send the events of a tree, the stored values are turned into responses by `decode`,
+/// the values for which it returns `None` are skipped.
+pub fn send_stream_events<F>(
+    stream: EsStream,
+    tree: Tree,
+    mut sender: mpsc::Sender<Result<Response, ServerError>>,
+    heartbeat: Option<Duration>,
+    decode: F,
+    mut caught_up: CaughtUpGuard,
+    pauses: PausedStreams,
+) -> Result<(), Error>
+where
+    F: Fn(EventNumber, &[u8]) -> Option<Response>,
+{
+    info!("blocking subscription on {} spawned", stream);
+
+    // The watcher is installed once, before the subscription is acknowledged and
+    // before the historical events are read, this way no event can be missed between
+    // the end of the scan and the live events, the duplicates are skipped using numbers.
+    let watcher = Watcher::new(&tree, &[]);
+
+    let subscribed = Response::Subscribed {
+        stream: stream.name.clone(),
+    };
+    match sender.send(Ok(subscribed)).wait() {
+        Ok(s) => sender = s,
+        Err(_) => {
+            info!("encountered closed channel");
+            return Ok(());
+        }
+    }
+
+    // A subscription from a time starts at the first event published at or after it,
+    // the events published after the subscription are all sent.
+    let since = match stream.from_time {
+        Some(time) => first_event_since(&stream.name, &tree, time)?,
+        None => EventNumber(0),
+    };
+
+    // A history only subscription reads until the tail of the stream at the time of the
+    // subscription, the events published during the scan are not sent. A bounded range
+    // stops at its end when the tail is further.
+    if stream.history_only {
+        let tail = next_event_number(&stream.name, &tree)?;
+        let end = match stream.range.to() {
+            Some(to) => cmp::min(tail, EventNumber(to)),
+            None => tail,
+        };
+        let from = cmp::max(EventNumber(stream.range.from().unwrap_or(0)), since);
+
+        if from < end {
+            for result in tree.range(from.to_be_bytes()..end.to_be_bytes()) {
+                let (key, value) = result?;
+                let number = parse_event_number(&stream.name, &key)?;
+
+                if let Some(event) = decode(number, &value) {
+                    match send_event(sender, event, &stream.name, &pauses) {
+                        Some(s) => sender = s,
+                        None => return Ok(()),
+                    }
+                }
+            }
+        }
+
+        let end_of_stream = Response::EndOfStream {
+            stream: stream.name.clone(),
+            number: end,
+        };
+        if sender.send(Ok(end_of_stream)).wait().is_err() {
+            info!("encountered closed channel");
+        }
+        caught_up.caught_up();
+
+        return Ok(());
+    }
+
+    match stream.range {
+        ReadRange::ReadFrom(from) => {
+            let mut next_number = cmp::max(EventNumber(from), since);
+
+            for result in tree.range(next_number.to_be_bytes()..) {
+                let (key, value) = result?;
+                let number = parse_event_number(&stream.name, &key)?;
+
+                if let Some(event) = decode(number, &value) {
+                    match send_event(sender, event, &stream.name, &pauses) {
+                        Some(s) => sender = s,
+                        None => return Ok(()),
+                    }
+                }
+
+                next_number = number.next();
+            }
+
+            let caught_up_response = Response::CaughtUp {
+                stream: stream.name.clone(),
+                number: next_number,
+            };
+            match sender.send(Ok(caught_up_response)).wait() {
+                Ok(s) => sender = s,
+                Err(_) => {
+                    info!("encountered closed channel");
+                    return Ok(());
+                }
+            }
+            caught_up.caught_up();
+
+            while let Some(event) =
+                next_watched_event(&watcher, &stream.name, &tree, &mut sender, heartbeat)?
+            {
+                if let Event::Insert(key, value) = event {
+                    let number = parse_event_number(&stream.name, &key)?;
+                    if number >= next_number {
+                        if let Some(event) = decode(number, &value) {
+                            match send_event(sender, event, &stream.name, &pauses) {
+                                Some(s) => sender = s,
+                                None => return Ok(()),
+                            }
+                        }
+
+                        next_number = number.next();
+                    }
+                }
+            }
+        }
+        ReadRange::ReadFromUntil(from, to) => {
+            let mut next_number = cmp::max(EventNumber(from), since);
+            let to_event_number = EventNumber(to);
+
+            if next_number >= to_event_number {
+                return Ok(());
+            }
+
+            for result in tree.range(next_number.to_be_bytes()..to_event_number.to_be_bytes()) {
+                let (key, value) = result?;
+                let number = parse_event_number(&stream.name, &key)?;
+
+                if let Some(event) = decode(number, &value) {
+                    match send_event(sender, event, &stream.name, &pauses) {
+                        Some(s) => sender = s,
+                        None => return Ok(()),
+                    }
+                }
+
+                next_number = number.next();
+                if next_number >= to_event_number {
+                    return Ok(());
+                }
+            }
+            caught_up.caught_up();
+
+            while let Some(event) =
+                next_watched_event(&watcher, &stream.name, &tree, &mut sender, heartbeat)?
+            {
+                if let Event::Insert(key, value) = event {
+                    let number = parse_event_number(&stream.name, &key)?;
+                    if number >= to_event_number {
+                        return Ok(());
+                    }
+                    if number >= next_number {
+                        if let Some(event) = decode(number, &value) {
+                            match send_event(sender, event, &stream.name, &pauses) {
+                                Some(s) => sender = s,
+                                None => return Ok(()),
+                            }
+                        }
+
+                        next_number = number.next();
+                    }
+                }
+            }
+        }
+        ReadRange::ReadFromEnd => {
+            // The tail is read after the watcher has been installed, the events that were
+            // inserted in between are seen by the watcher but must not be sent.
+            let mut next_number = next_event_number(&stream.name, &tree)?;
+            caught_up.caught_up();
+
+            while let Some(event) =
+                next_watched_event(&watcher, &stream.name, &tree, &mut sender, heartbeat)?
+            {
+                if let Event::Insert(key, value) = event {
+                    let number = parse_event_number(&stream.name, &key)?;
+                    if number >= next_number {
+                        if let Some(event) = decode(number, &value) {
+                            match send_event(sender, event, &stream.name, &pauses) {
+                                Some(s) => sender = s,
+                                None => return Ok(()),
+                            }
+                        }
+
+                        next_number = number.next();
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Counts the streams of a `$all` subscription that are still sending their historical
+/// events, the last one to catch up or to reach the end of its range sends `AllCaughtUp`.
+///
+/// A stream that stops early, because its channel is closed or because of an error,
+/// is counted when the guard is dropped.
+pub struct CaughtUpGuard(pub Option<(Arc<AtomicUsize>, ResponseSender)>);
+
+impl CaughtUpGuard {
+    /// A guard for a subscription that is not part of a `$all` subscription.
+    pub fn none() -> CaughtUpGuard {
+        CaughtUpGuard(None)
+    }
+
+    /// Count the stream as caught up, it is only counted once.
+    fn caught_up(&mut self) {
+        if let Some((remaining, sender)) = self.0.take() {
+            if remaining.fetch_sub(1, Ordering::SeqCst) == 1
+                && sender.send(Ok(Response::AllCaughtUp)).wait().is_err()
+            {
+                info!("encountered closed channel");
+            }
+        }
+    }
+}
+
+impl Drop for CaughtUpGuard {
+    fn drop(&mut self) {
+        self.caught_up();
+    }
+}
+
+/// The number of active subscriptions of each stream, shared by all the connections.
+pub type Subscriptions = Arc<Mutex<HashMap<EsStreamName, u64>>>;
+
+/// Counts a subscription to a stream as long as it is alive.
+pub struct SubscriptionGuard {
+    subscriptions: Subscriptions,
+    stream: EsStreamName,
+}
+
+impl SubscriptionGuard {
+    pub fn new(subscriptions: Subscriptions, stream: EsStreamName) -> SubscriptionGuard {
+        *subscriptions
+            .lock()
+            .unwrap()
+            .entry(stream.clone())
+            .or_insert(0) += 1;
+        SubscriptionGuard {
+            subscriptions,
+            stream,
+        }
+    }
+}
+
+impl Drop for SubscriptionGuard {
+    fn drop(&mut self) {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        if let Some(count) = subscriptions.get_mut(&self.stream) {
+            *count -= 1;
+            if *count == 0 {
+                subscriptions.remove(&self.stream);
+            }
+        }
+    }
+}
+
+/// The streams whose delivery is paused on a connection, the subscriptions wait
+/// before sending the next event of a paused stream until it is resumed.
+///
+/// The events are not read in the meantime, the subscription resumes where it stopped.
+#[derive(Clone, Default)]
+pub struct PausedStreams(Arc<(Mutex<PauseState>, Condvar)>);
+
+#[derive(Default)]
+struct PauseState {
+    paused: HashSet<EsStreamName>,
+    tasks: Vec<futures::task::Task>,
+}
+
+impl PausedStreams {
+    pub fn pause(&self, stream: EsStreamName) {
+        let (state, _) = &*self.0;
+        state.lock().unwrap().paused.insert(stream);
+    }
+
+    pub fn resume(&self, stream: &EsStreamName) {
+        let (state, resumed) = &*self.0;
+        let mut state = state.lock().unwrap();
+        if state.paused.remove(stream) {
+            state.tasks.drain(..).for_each(|task| task.notify());
+            resumed.notify_all();
+        }
+    }
+
+    /// Block the thread while the stream is paused, returns `false`
+    /// if the connection is closed before the stream is resumed.
+    fn wait_resumed(&self, stream: &EsStreamName, sender: &ResponseSender) -> bool {
+        let (state, resumed) = &*self.0;
+        let mut state = state.lock().unwrap();
+        while state.paused.contains(stream) {
+            let (guard, _) = resumed.wait_timeout(state, CLOSED_CHECK_INTERVAL).unwrap();
+            state = guard;
+            if is_closed(sender) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Returns `true` if the stream is paused, the current task is then notified on resume.
+    fn poll_paused(&self, stream: &EsStreamName) -> bool {
+        let (state, _) = &*self.0;
+        let mut state = state.lock().unwrap();
+        if state.paused.contains(stream) {
+            state.tasks.push(futures::task::current());
+            return true;
+        }
+        false
+    }
+}
+
+/// Send an event once its stream is not paused, returns the sender
+/// or `None` if the channel has been closed.
+fn send_event(
+    sender: ResponseSender,
+    event: Response,
+    stream: &EsStreamName,
+    pauses: &PausedStreams,
+) -> Option<ResponseSender> {
+    if pauses.wait_resumed(stream, &sender) {
+        if let Ok(sender) = sender.send(Ok(event)).wait() {
+            return Some(sender);
+        }
+    }
+
+    info!("encountered closed channel");
+    None
+}
+
+/// The changes of a sled watcher as a Stream, polling it never blocks the thread.
+///
+/// The subscriber is still iterated by the thread of `forward_changes`, sled 0.29 can not
+/// be polled, so each watch costs a thread until the stream is dropped, the receiver is
+/// dropped first.
+struct WatchStream {
+    receiver: futures::sync::mpsc::UnboundedReceiver<Event>,
+    _forwarder: Forwarder,
+}
+
+impl WatchStream {
+    fn new(tree: &Tree) -> WatchStream {
+        let (sender, receiver) = futures::sync::mpsc::unbounded();
+        let forwarder =
+            forward_changes(tree, &[], move |event| sender.unbounded_send(event).is_ok());
+        WatchStream {
+            receiver,
+            _forwarder: forwarder,
+        }
+    }
+}
+
+impl Stream for WatchStream {
+    type Item = Event;
+    type Error = sled::Error;
+
+    fn poll(&mut self) -> Poll<Option<Event>, sled::Error> {
+        // the receiver of an unbounded channel never fails
+        Ok(self.receiver.poll().unwrap_or(Async::Ready(None)))
+    }
+}
+
+/// The events inserted in a stream after its `tail`, a heartbeat is returned each time
+/// the interval elapses without any change, like `next_watched_event` does.
+struct LiveEvents {
+    stream: EsStreamName,
+    tree: Tree,
+    watcher: WatchStream,
+    next_number: EventNumber,
+    heartbeat: Option<(Duration, Delay)>,
+    pauses: PausedStreams,
+}
+
+impl LiveEvents {
+    /// The watcher is installed before the tail is read, as in `send_stream_events`,
+    /// the events inserted in between are not missed.
+    fn new(
+        stream: EsStreamName,
+        tree: Tree,
+        heartbeat: Option<Duration>,
+        pauses: PausedStreams,
+    ) -> Result<LiveEvents, Error> {
+        let watcher = WatchStream::new(&tree);
+        let next_number = next_event_number(&stream, &tree)?;
+
+        Ok(LiveEvents {
+            stream,
+            tree,
+            watcher,
+            next_number,
+            heartbeat: heartbeat.map(|interval| (interval, Delay::new(Instant::now() + interval))),
+            pauses,
+        })
+    }
+}
+
+impl Stream for LiveEvents {
+    type Item = Response;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Response>, Error> {
+        // the changes are kept by the watcher while the stream is paused
+        if self.pauses.poll_paused(&self.stream) {
+            return Ok(Async::NotReady);
+        }
+
+        while let Async::Ready(event) = self.watcher.poll()? {
+            let (key, value) = match event {
+                Some(Event::Insert(key, value)) => (key, value),
+                Some(_) => continue,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            if let Some((interval, delay)) = &mut self.heartbeat {
+                delay.reset(Instant::now() + *interval);
+            }
+
+            // an event rewritten after it was sent, like a redacted one, is not sent again
+            let number = parse_event_number(&self.stream, &key)?;
+            if number < self.next_number {
+                continue;
+            }
+            self.next_number = number.next();
+
+            if let Some(event) = event_response(&self.stream, number, &value) {
+                return Ok(Async::Ready(Some(event)));
+            }
+        }
+
+        if let Some((interval, delay)) = &mut self.heartbeat {
+            if let Ok(Async::Ready(())) = delay.poll() {
+                delay.reset(Instant::now() + *interval);
+                let heartbeat = Response::Heartbeat {
+                    stream: self.stream.clone(),
+                    number: next_event_number(&self.stream, &self.tree)?,
+                };
+                return Ok(Async::Ready(Some(heartbeat)));
+            }
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+/// Send the live events of a stream read from its end without blocking a thread of the runtime,
+/// the watcher is polled by the runtime and the events are forwarded to the sender.
+///
+/// Only the subscription thread is saved, the changes are still forwarded by a thread.
+fn send_live_events(
+    name: EsStreamName,
+    tree: Tree,
+    sender: ResponseSender,
+    heartbeat: Option<Duration>,
+    mut caught_up: CaughtUpGuard,
+    guard: SubscriptionGuard,
+    pauses: PausedStreams,
+) -> Result<impl Future<Item = (), Error = ()>, Error> {
+    info!("subscription on {} spawned on the runtime", name);
+
+    let live = LiveEvents::new(name.clone(), tree, heartbeat, pauses)?;
+
+    let subscribed = Response::Subscribed { stream: name };
+    let fut = sender
+        .send(Ok(subscribed))
+        .and_then(move |sender| {
+            caught_up.caught_up();
+            live.map(Ok)
+                .or_else(|e| Ok::<_, mpsc::error::SendError>(Err(internal_error(e))))
+                .forward(sender)
+        })
+        .then(move |result| {
+            drop(guard);
+            if result.is_err() {
+                info!("encountered closed channel");
+            }
+            Ok(())
+        });
+
+    Ok(fut)
+}
+
+/// The events published in a stream from now on, the stream is polled without blocking
+/// and never ends. The events rewritten after they were published are not returned again.
+///
+/// A thread forwards the changes of the stream until the returned stream is dropped.
+pub fn watch(
+    db: &Db,
+    stream: &EsStreamName,
+) -> Result<impl Stream<Item = Response, Error = Error>, Error> {
+    let tree = db.open_tree(stream.clone().into_bytes())?;
+    LiveEvents::new(stream.clone(), tree, None, PausedStreams::default())
+}
+
+/// Send the events of a stream in a dedicated thread, the subscriptions read from
+/// the end of a stream are polled by the runtime when there is one.
+pub fn spawn_stream_events(
+    stream: EsStream,
+    db: &Db,
+    subscriptions: &Subscriptions,
+    sender: mpsc::Sender<Result<Response, ServerError>>,
+    heartbeat: Option<Duration>,
+    caught_up: CaughtUpGuard,
+    pauses: &PausedStreams,
+) -> Result<(), Error> {
+    let tree = db.open_tree(stream.name.clone().into_bytes())?;
+    let guard = SubscriptionGuard::new(subscriptions.clone(), stream.name.clone());
+    let pauses = pauses.clone();
+
+    let mut executor = DefaultExecutor::current();
+    if stream.range == ReadRange::ReadFromEnd && executor.status().is_ok() {
+        let name = stream.name;
+        let live = send_live_events(name, tree, sender, heartbeat, caught_up, guard, pauses)?;
+        if let Err(e) = executor.spawn(Box::new(live)) {
+            error!("subscription could not be spawned; {}", e);
+        }
+        return Ok(());
+    }
+
+    thread::Builder::new().spawn(move || {
+        let _guard = guard;
+        let name = stream.name.clone();
+        let decode = move |number, value: &[u8]| event_response(&name, number, value);
+        let result = send_stream_events(
+            stream,
+            tree,
+            sender.clone(),
+            heartbeat,
+            decode,
+            caught_up,
+            pauses,
+        );
+        if let Err(e) = result {
+            if sender.send(Err(internal_error(e))).wait().is_err() {
+                info!("encountered closed channel");
+            }
+        }
+    })?;
+
+    Ok(())
+}
+
+/// Subscribe to every stream whose name starts with the prefix,
+/// the streams created after the subscription are sent from their first event.
+pub fn send_prefix_events(
+    prefix: String,
+    range: ReadRange,
+    db: Db,
+    subscriptions: Subscriptions,
+    sender: mpsc::Sender<Result<Response, ServerError>>,
+    heartbeat: Option<Duration>,
+    pauses: PausedStreams,
+) -> Result<(), Error> {
+    // The stream counters are watched before listing the streams,
+    // this way a stream created in between can not be missed.
+    let watcher = Watcher::new(&db, prefix.as_bytes());
+
+    let mut subscribed = HashSet::new();
+    for name in stream_names_with_prefix(&db, &prefix) {
+        subscribed.insert(name.clone());
+        spawn_stream_events(
+            EsStream::new(name, range),
+            &db,
+            &subscriptions,
+            sender.clone(),
+            heartbeat,
+            CaughtUpGuard::none(),
+            &pauses,
+        )?;
+    }
+
+    let new_range = match range {
+        ReadRange::ReadFromEnd => ReadRange::ReadFrom(0),
+        otherwise => otherwise,
+    };
+
+    loop {
+        let event = match watcher.next_timeout(CLOSED_CHECK_INTERVAL) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) if is_closed(&sender) => {
+                info!("encountered closed channel");
+                break;
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+        };
+
+        if let Event::Insert(key, _) = event {
+            let name = match String::from_utf8(key.to_vec()).map(EsStreamName::new) {
+                Ok(Ok(name)) => name,
+                _ => {
+                    warn!("skipping the invalid stream name {:?}", key);
+                    continue;
+                }
+            };
+            if subscribed.insert(name.clone()) {
+                let stream = EsStream::new(name, new_range);
+                let caught_up = CaughtUpGuard::none();
+                let sender = sender.clone();
+                spawn_stream_events(
+                    stream,
+                    &db,
+                    &subscriptions,
+                    sender,
+                    heartbeat,
+                    caught_up,
+                    &pauses,
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::{handle_request, ServerCtx};
+    use crate::storage::{save_event, stream_names};
+    use crate::Settings;
+    use meilies::reqresp::{ErrorCode, Request};
+    use meilies::stream::{EventData, EventName, RawEvent};
+    use sled::Config;
+
+    #[test]
+    fn subscription_skips_corrupted_events() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("corrupted".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        for i in 0..3u64 {
+            let event_data = EventData(i.to_be_bytes().to_vec());
+            save_event(
+                &db,
+                &stream,
+                &event_name,
+                event_data,
+                None,
+                Settings::default(),
+            )
+            .unwrap();
+        }
+
+        let tree = db.open_tree(stream.clone().into_bytes()).unwrap();
+        let key = EventNumber(1).to_be_bytes();
+        let mut value = tree.get(key).unwrap().unwrap().to_vec();
+        value[10] ^= 0x01;
+        tree.insert(key, value).unwrap();
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Subscribe {
+            streams: vec![EsStream::new(
+                stream.clone(),
+                ReadRange::ReadFromUntil(0, 3),
+            )],
+            require_existing: false,
+        };
+        let settings = Settings::default();
+        handle_request(request, &ServerCtx::new(db, settings), sender).unwrap();
+
+        let numbers: Vec<_> = receiver
+            .wait()
+            .filter_map(|response| match response.unwrap() {
+                Ok(Response::Event { number, .. }) => Some(number),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(numbers, vec![EventNumber(0), EventNumber(2)]);
+    }
+
+    #[test]
+    fn read_from_end_does_not_miss_or_repeat_events() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("read-from-end".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        let publisher = {
+            let db = db.clone();
+            let stream = stream.clone();
+            let event_name = event_name.clone();
+            thread::spawn(move || {
+                for i in 0..200u64 {
+                    let event_data = EventData(i.to_be_bytes().to_vec());
+                    save_event(
+                        &db,
+                        &stream,
+                        &event_name,
+                        event_data,
+                        None,
+                        Settings::default(),
+                    )
+                    .unwrap();
+                }
+            })
+        };
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Subscribe {
+            streams: vec![EsStream::from(stream.clone())],
+            require_existing: false,
+        };
+        handle_request(
+            request,
+            &ServerCtx::new(db.clone(), Settings::default()),
+            sender,
+        )
+        .unwrap();
+
+        publisher.join().unwrap();
+        let event_data = EventData(b"last".to_vec());
+        save_event(
+            &db,
+            &stream,
+            &event_name,
+            event_data,
+            None,
+            Settings::default(),
+        )
+        .unwrap();
+
+        let mut numbers = Vec::new();
+        for response in receiver.wait() {
+            match response.unwrap() {
+                Ok(Response::Subscribed { .. }) => (),
+                Ok(Response::Event { number, .. }) => {
+                    numbers.push(number.0);
+                    if number.0 == 200 {
+                        break;
+                    }
+                }
+                otherwise => panic!("unexpected response {:?}", otherwise),
+            }
+        }
+
+        let first = numbers[0];
+        assert_eq!(numbers, (first..=200).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn read_from_does_not_miss_or_repeat_events() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("read-from".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        let publisher = {
+            let db = db.clone();
+            let stream = stream.clone();
+            let event_name = event_name.clone();
+            thread::spawn(move || {
+                for i in 0..300u64 {
+                    let event_data = EventData(i.to_be_bytes().to_vec());
+                    save_event(
+                        &db,
+                        &stream,
+                        &event_name,
+                        event_data,
+                        None,
+                        Settings::default(),
+                    )
+                    .unwrap();
+                }
+            })
+        };
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Subscribe {
+            streams: vec![EsStream::new(stream, ReadRange::ReadFrom(0))],
+            require_existing: false,
+        };
+        handle_request(request, &ServerCtx::new(db, Settings::default()), sender).unwrap();
+
+        let mut numbers = Vec::new();
+        for response in receiver.wait() {
+            match response.unwrap() {
+                Ok(Response::Subscribed { .. }) => (),
+                Ok(Response::CaughtUp { .. }) => (),
+                Ok(Response::Event { number, .. }) => {
+                    numbers.push(number.0);
+                    if number.0 == 299 {
+                        break;
+                    }
+                }
+                otherwise => panic!("unexpected response {:?}", otherwise),
+            }
+        }
+
+        publisher.join().unwrap();
+        assert_eq!(numbers, (0..300).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn quiet_subscription_receives_heartbeats() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("quiet".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let settings = Settings {
+            heartbeat_interval: Some(Duration::from_millis(50)),
+            ..Settings::default()
+        };
+
+        for _ in 0..2 {
+            let event_data = EventData(b"data".to_vec());
+            save_event(&db, &stream, &event_name, event_data, None, settings).unwrap();
+        }
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Subscribe {
+            streams: vec![EsStream::new(stream.clone(), ReadRange::ReadFromEnd)],
+            require_existing: false,
+        };
+        handle_request(request, &ServerCtx::new(db, settings), sender).unwrap();
+
+        let mut responses = receiver.wait();
+        let subscribed = responses.next().unwrap().unwrap();
+        assert_eq!(
+            subscribed,
+            Ok(Response::Subscribed {
+                stream: stream.clone()
+            })
+        );
+
+        let heartbeat = responses.next().unwrap().unwrap();
+        let expected = Response::Heartbeat {
+            stream,
+            number: EventNumber(2),
+        };
+        assert_eq!(heartbeat, Ok(expected));
+    }
+
+    #[test]
+    fn subscribe_require_existing() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("missing".to_owned()).unwrap();
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Subscribe {
+            streams: vec![EsStream::from(stream.clone())],
+            require_existing: true,
+        };
+        handle_request(
+            request,
+            &ServerCtx::new(db.clone(), Settings::default()),
+            sender,
+        )
+        .unwrap();
+
+        let response = receiver.wait().next().unwrap().unwrap();
+        let error = ServerError::new(ErrorCode::StreamNotFound, "stream missing does not exist");
+        assert_eq!(response, Err(error));
+        assert!(db.tree_names().iter().all(|n| n != b"missing"));
+    }
+
+    #[test]
+    fn subscribe_from_time_starts_at_first_later_event() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("since".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        // events published every second starting at 1000 milliseconds
+        let tree = db.open_tree(stream.clone().into_bytes()).unwrap();
+        for i in 0..5u64 {
+            let event_data = EventData(i.to_be_bytes().to_vec());
+            let raw_event = RawEvent::encode_at(&event_name, &event_data, (i + 1) * 1000);
+            let key = EventNumber(i).to_be_bytes();
+            tree.insert(key, raw_event.into_inner()).unwrap();
+        }
+
+        assert_eq!(
+            first_event_since(&stream, &tree, 0).unwrap(),
+            EventNumber(0)
+        );
+        assert_eq!(
+            first_event_since(&stream, &tree, 3000).unwrap(),
+            EventNumber(2)
+        );
+        assert_eq!(
+            first_event_since(&stream, &tree, 3001).unwrap(),
+            EventNumber(3)
+        );
+        assert_eq!(
+            first_event_since(&stream, &tree, 9000).unwrap(),
+            EventNumber(5)
+        );
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Subscribe {
+            streams: vec![EsStream::since(stream.clone(), 2500)],
+            require_existing: false,
+        };
+        let ctx = ServerCtx::new(db.clone(), Settings::default());
+        handle_request(request, &ctx, sender).unwrap();
+
+        let numbers: Vec<_> = receiver
+            .wait()
+            .map(|r| r.unwrap().unwrap())
+            .take_while(|r| match r {
+                Response::CaughtUp { .. } => false,
+                _ => true,
+            })
+            .filter_map(|r| match r {
+                Response::Event { number, .. } => Some(number.0),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(numbers, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn paused_stream_resumes_where_it_stopped() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("paused".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let publish = |i: u64| {
+            let event_data = EventData(i.to_be_bytes().to_vec());
+            save_event(
+                &db,
+                &stream,
+                &event_name,
+                event_data,
+                None,
+                Settings::default(),
+            )
+            .unwrap();
+        };
+
+        // the responses are forwarded to be received with a timeout
+        let (sender, receiver) = mpsc::channel::<Result<Response, ServerError>>(10);
+        let (forward, responses) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            for response in receiver.wait() {
+                if forward.send(response.unwrap().unwrap()).is_err() {
+                    break;
+                }
+            }
+        });
+        let next = || responses.recv_timeout(Duration::from_secs(5)).unwrap();
+        let event_number = |response| match response {
+            Response::Event { number, .. } => number.0,
+            otherwise => panic!("unexpected response {:?}", otherwise),
+        };
+
+        publish(0);
+        let ctx = ServerCtx::new(db.clone(), Settings::default());
+        let request = Request::Subscribe {
+            streams: vec![EsStream::new(stream.clone(), ReadRange::ReadFrom(0))],
+            require_existing: false,
+        };
+        handle_request(request, &ctx, sender.clone()).unwrap();
+        assert_eq!(
+            next(),
+            Response::Subscribed {
+                stream: stream.clone()
+            }
+        );
+        assert_eq!(event_number(next()), 0);
+        match next() {
+            Response::CaughtUp { .. } => (),
+            otherwise => panic!("unexpected response {:?}", otherwise),
+        }
+
+        let request = Request::Pause {
+            stream: stream.clone(),
+        };
+        handle_request(request, &ctx, sender.clone()).unwrap();
+        assert_eq!(next(), Response::Ok);
+
+        publish(1);
+        publish(2);
+        let pending = responses.recv_timeout(Duration::from_millis(300));
+        assert!(pending.is_err(), "unexpected response {:?}", pending);
+
+        let request = Request::Resume {
+            stream: stream.clone(),
+        };
+        handle_request(request, &ctx, sender).unwrap();
+        assert_eq!(next(), Response::Ok);
+        assert_eq!(event_number(next()), 1);
+        assert_eq!(event_number(next()), 2);
+    }
+
+    #[test]
+    fn history_only_ignores_later_events() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("history".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let publish = |i: u64| {
+            let event_data = EventData(i.to_be_bytes().to_vec());
+            save_event(
+                &db,
+                &stream,
+                &event_name,
+                event_data,
+                None,
+                Settings::default(),
+            )
+            .unwrap();
+        };
+
+        for i in 0..3 {
+            publish(i);
+        }
+
+        let (sender, receiver) = mpsc::channel(1);
+        let request = Request::Subscribe {
+            streams: vec![EsStream::history(stream.clone(), 0)],
+            require_existing: false,
+        };
+        let ctx = ServerCtx::new(db.clone(), Settings::default());
+        handle_request(request, &ctx, sender).unwrap();
+
+        // the tail has been read once the first event is received
+        let mut responses = receiver.wait().map(|r| r.unwrap().unwrap());
+        let subscribed = Response::Subscribed {
+            stream: stream.clone(),
+        };
+        assert_eq!(responses.next(), Some(subscribed));
+        let mut numbers = Vec::new();
+        if let Some(Response::Event { number, .. }) = responses.next() {
+            numbers.push(number.0);
+        }
+
+        for i in 3..5 {
+            publish(i);
+        }
+
+        for response in responses {
+            match response {
+                Response::Event { number, .. } => numbers.push(number.0),
+                Response::EndOfStream { number, .. } => {
+                    assert_eq!(number, EventNumber(3));
+                    break;
+                }
+                otherwise => panic!("unexpected response {:?}", otherwise),
+            }
+        }
+        assert_eq!(numbers, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn bounded_history_stops_at_the_end_of_the_range() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("history".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        for i in 0..10u64 {
+            let event_data = EventData(i.to_be_bytes().to_vec());
+            let settings = Settings::default();
+            save_event(&db, &stream, &event_name, event_data, None, settings).unwrap();
+        }
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Subscribe {
+            streams: vec![EsStream::history_until(stream.clone(), 2, 5)],
+            require_existing: false,
+        };
+        let ctx = ServerCtx::new(db, Settings::default());
+        handle_request(request, &ctx, sender).unwrap();
+
+        let mut numbers = Vec::new();
+        for response in receiver.wait().map(|r| r.unwrap().unwrap()) {
+            match response {
+                Response::Subscribed { .. } => (),
+                Response::Event { number, .. } => numbers.push(number.0),
+                Response::EndOfStream { number, .. } => {
+                    assert_eq!(number, EventNumber(5));
+                    break;
+                }
+                otherwise => panic!("unexpected response {:?}", otherwise),
+            }
+        }
+        assert_eq!(numbers, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn read_from_sends_caught_up_once() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let stream = EsStreamName::new("caught-up".to_owned()).unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+
+        for i in 0..5u64 {
+            let event_data = EventData(i.to_be_bytes().to_vec());
+            save_event(
+                &db,
+                &stream,
+                &event_name,
+                event_data,
+                None,
+                Settings::default(),
+            )
+            .unwrap();
+        }
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Subscribe {
+            streams: vec![EsStream::new(stream.clone(), ReadRange::ReadFrom(0))],
+            require_existing: false,
+        };
+        handle_request(
+            request,
+            &ServerCtx::new(db.clone(), Settings::default()),
+            sender,
+        )
+        .unwrap();
+        let mut responses = receiver.wait().map(|r| r.unwrap().unwrap());
+
+        let subscribed = Response::Subscribed {
+            stream: stream.clone(),
+        };
+        assert_eq!(responses.next(), Some(subscribed));
+
+        for i in 0..5 {
+            match responses.next() {
+                Some(Response::Event { number, .. }) => assert_eq!(number, EventNumber(i)),
+                otherwise => panic!("unexpected response {:?}", otherwise),
+            }
+        }
+
+        let caught_up = Response::CaughtUp {
+            stream: stream.clone(),
+            number: EventNumber(5),
+        };
+        assert_eq!(responses.next(), Some(caught_up));
+
+        let event_data = EventData(b"live".to_vec());
+        save_event(
+            &db,
+            &stream,
+            &event_name,
+            event_data,
+            None,
+            Settings::default(),
+        )
+        .unwrap();
+
+        match responses.next() {
+            Some(Response::Event { number, .. }) => assert_eq!(number, EventNumber(5)),
+            otherwise => panic!("unexpected response {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn subscribe_prefix_follows_new_streams() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let publish = |name: &str| {
+            let stream = EsStreamName::new(name.to_owned()).unwrap();
+            let event_data = EventData(b"data".to_vec());
+            save_event(
+                &db,
+                &stream,
+                &event_name,
+                event_data,
+                None,
+                Settings::default(),
+            )
+            .unwrap();
+        };
+
+        publish("orders-eu");
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::SubscribePrefix {
+            prefix: String::from("orders-"),
+            range: ReadRange::ReadFrom(0),
+        };
+        handle_request(
+            request,
+            &ServerCtx::new(db.clone(), Settings::default()),
+            sender,
+        )
+        .unwrap();
+
+        publish("invoices");
+        publish("orders-us");
+
+        // each stream sends a subscription, its event and a caught-up marker
+        let responses: Vec<_> = receiver
+            .wait()
+            .take(6)
+            .map(|r| r.unwrap().unwrap())
+            .collect();
+
+        for name in &["orders-eu", "orders-us"] {
+            let stream = EsStreamName::new(name.to_string()).unwrap();
+            assert!(responses.iter().any(|r| match r {
+                Response::Event {
+                    stream: s, number, ..
+                } => s == &stream && number.0 == 0,
+                _ => false,
+            }));
+        }
+
+        assert!(responses.iter().all(|r| match r {
+            Response::Subscribed { stream } | Response::Event { stream, .. } => {
+                stream.as_str().starts_with("orders-")
+            }
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn ordered_subscription_follows_global_order() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let settings = Settings {
+            global_order: true,
+            ..Settings::default()
+        };
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let publish = |name: &str| {
+            let stream = EsStreamName::new(name.to_owned()).unwrap();
+            let event_data = EventData(b"data".to_vec());
+            save_event(&db, &stream, &event_name, event_data, None, settings).unwrap();
+        };
+
+        let order = ["b", "a", "c", "a", "b", "c", "c", "a"];
+        for name in &order[..4] {
+            publish(name);
+        }
+
+        let (sender, receiver) = mpsc::channel(20);
+        let request = Request::SubscribeAll {
+            range: ReadRange::ReadFrom(0),
+            ordered: true,
+        };
+        handle_request(request, &ServerCtx::new(db.clone(), settings), sender).unwrap();
+
+        for name in &order[4..] {
+            publish(name);
+        }
+
+        // a subscription, the historical events, a caught-up marker and the live events
+        let events: Vec<_> = receiver
+            .wait()
+            .take(order.len() + 2)
+            .filter_map(|r| match r.unwrap().unwrap() {
+                Response::Event { stream, global, .. } => Some((stream, global)),
+                _ => None,
+            })
+            .collect();
+
+        let expected: Vec<_> = order
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let stream = EsStreamName::new(name.to_string()).unwrap();
+                (stream, Some(EventNumber(i as u64)))
+            })
+            .collect();
+        assert_eq!(events, expected);
+
+        // the global log is not a stream
+        assert_eq!(stream_names(&db).len(), 3);
+
+        let (sender, receiver) = mpsc::channel(1);
+        let request = Request::SubscribeAll {
+            range: ReadRange::ReadFrom(0),
+            ordered: true,
+        };
+        let ctx = ServerCtx::new(db, Settings::default());
+        handle_request(request, &ctx, sender).unwrap();
+        assert!(receiver.wait().next().unwrap().unwrap().is_err());
+    }
+
+    #[test]
+    fn bounded_subscribe_all_sends_all_caught_up_once() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let publish = |name: &str, count: u64| {
+            let stream = EsStreamName::new(name.to_owned()).unwrap();
+            for _ in 0..count {
+                let event_data = EventData(b"data".to_vec());
+                let settings = Settings::default();
+                save_event(&db, &stream, &event_name, event_data, None, settings).unwrap();
+            }
+        };
+
+        // the first stream reaches its bound, the second one its tail
+        publish("long", 5);
+        publish("short", 1);
+
+        let (sender, receiver) = mpsc::channel(20);
+        let request = Request::SubscribeAll {
+            range: ReadRange::ReadFromUntil(0, 3),
+            ordered: false,
+        };
+        let ctx = ServerCtx::new(db.clone(), Settings::default());
+        handle_request(request, &ctx, sender).unwrap();
+
+        let mut responses = receiver.wait().map(|r| r.unwrap().unwrap());
+        let before: Vec<_> = responses
+            .by_ref()
+            .take_while(|r| r != &Response::AllCaughtUp)
+            .collect();
+
+        let events = before.iter().filter(|r| match r {
+            Response::Event { .. } => true,
+            _ => false,
+        });
+        assert_eq!(events.count(), 3 + 1);
+
+        // the marker is only sent once, the short stream then follows its new events
+        publish("short", 1);
+        match responses.next() {
+            Some(Response::Event { stream, number, .. }) => {
+                assert_eq!(stream.as_str(), "short");
+                assert_eq!(number, EventNumber(1));
+            }
+            otherwise => panic!("unexpected response {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn live_subscription_is_polled_by_the_runtime() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let ctx = ServerCtx::new(db.clone(), Settings::default());
+        let stream = EsStreamName::new("live".to_owned()).unwrap();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Subscribe {
+            streams: vec![EsStream::from(stream.clone())],
+            require_existing: false,
+        };
+        let subscribe = future::lazy(move || handle_request(request, &ctx, sender));
+        runtime.block_on(subscribe).unwrap();
+
+        let mut next = |receiver: mpsc::Receiver<Result<Response, ServerError>>| {
+            let next = receiver.into_future().map_err(|(e, _)| e);
+            let (response, receiver) = runtime.block_on(next).unwrap();
+            (response.unwrap().unwrap(), receiver)
+        };
+
+        let (subscribed, receiver) = next(receiver);
+        assert_eq!(
+            subscribed,
+            Response::Subscribed {
+                stream: stream.clone()
+            }
+        );
+
+        let event_name = EventName::new("event".to_owned()).unwrap();
+        let data = EventData(b"live".to_vec());
+        save_event(&db, &stream, &event_name, data, None, Settings::default()).unwrap();
+
+        let (event, _receiver) = next(receiver);
+        let timestamp = match &event {
+            Response::Event { timestamp, .. } => *timestamp,
+            otherwise => panic!("unexpected response {:?}", otherwise),
+        };
+        assert!(timestamp.is_some());
+
+        let expected = Response::Event {
+            stream,
+            number: EventNumber(0),
+            event_name,
+            event_data: EventData(b"live".to_vec()),
+            global: None,
+            headers: HashMap::new(),
+            redacted: false,
+            timestamp,
+        };
+        assert_eq!(event, expected);
+    }
+
+    #[test]
+    fn list_subscriptions_counts_subscribers() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let ctx = ServerCtx::new(db, Settings::default());
+        let stream = EsStreamName::new("watched".to_owned()).unwrap();
+
+        let (sender, receiver) = mpsc::channel(10);
+        for _ in 0..2 {
+            let request = Request::Subscribe {
+                streams: vec![EsStream::from(stream.clone())],
+                require_existing: false,
+            };
+            handle_request(request, &ctx, sender.clone()).unwrap();
+        }
+
+        let request = Request::ListSubscriptions;
+        handle_request(request, &ctx, sender).unwrap();
+
+        let expected = Response::Subscriptions {
+            subscriptions: vec![(stream, 2)],
+        };
+        let mut responses = receiver.wait().map(|r| r.unwrap().unwrap());
+        assert!(responses.any(|r| r == expected));
+    }
+
+    #[test]
+    fn dropped_subscriber_frees_quiet_subscriptions() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let ctx = ServerCtx::new(db, Settings::default());
+        let stream = EsStreamName::new("quiet".to_owned()).unwrap();
+
+        let (sender, receiver) = mpsc::channel(10);
+        let request = Request::Subscribe {
+            streams: vec![EsStream::new(stream.clone(), ReadRange::ReadFrom(0))],
+            require_existing: false,
+        };
+        handle_request(request, &ctx, sender).unwrap();
+
+        // wait for the thread to be blocked on the quiet stream
+        let mut responses = receiver.wait().map(|r| r.unwrap().unwrap());
+        assert!(responses.any(|r| match r {
+            Response::CaughtUp { .. } => true,
+            _ => false,
+        }));
+        assert_eq!(ctx.subscriptions.lock().unwrap().get(&stream), Some(&1));
+        drop(responses);
+
+        // no event is ever published, the thread notices the closed connection by itself
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while ctx.subscriptions.lock().unwrap().contains_key(&stream) {
+            assert!(Instant::now() < deadline, "the subscription is still alive");
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn dropped_forwarder_releases_its_thread() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree("quiet").unwrap();
+
+        // the closure is owned by the thread, it is dropped when the thread ends
+        let alive = Arc::new(());
+        let owned = alive.clone();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let forwarder = forward_changes(&tree, &[], move |event| {
+            let _owned = &owned;
+            sender.send(event).is_ok()
+        });
+
+        drop(receiver);
+        drop(forwarder);
+
+        // nothing is ever written to the stream
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Arc::strong_count(&alive) > 1 {
+            assert!(
+                Instant::now() < deadline,
+                "the forwarder thread is still alive"
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(tree.is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+//! Connect to a server running in the same process without any socket,
+//! the requests go through the same codec and handlers as on a TCP connection.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use futures::stream;
+use futures::task::{self, Task};
+use meilies::reqresp::ClientCodec;
+use sled::Db;
+use tokio::codec::{Decoder, Framed};
+use tokio::prelude::*;
+
+use super::Settings;
+use crate::handlers::ServerCtx;
+use crate::serve::{serve, Connections};
+
+/// The bytes written in one direction of a pipe and not read yet.
+#[derive(Default)]
+struct Buffer {
+    bytes: VecDeque<u8>,
+    closed: bool,
+    reader: Option<Task>,
+}
+
+impl Buffer {
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(task) = self.reader.take() {
+            task.notify();
+        }
+    }
+}
+
+/// One end of an in-process connection, the bytes written to it are read from the other end.
+pub struct Pipe {
+    read: Arc<Mutex<Buffer>>,
+    write: Arc<Mutex<Buffer>>,
+}
+
+/// Create the two ends of an in-process connection.
+pub fn pipe() -> (Pipe, Pipe) {
+    let (a, b) = (Arc::default(), Arc::default());
+    let left = Pipe {
+        read: Arc::clone(&a),
+        write: Arc::clone(&b),
+    };
+    let right = Pipe { read: b, write: a };
+    (left, right)
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buffer = self.read.lock().unwrap();
+        if buffer.bytes.is_empty() {
+            if buffer.closed {
+                return Ok(0);
+            }
+            buffer.reader = Some(task::current());
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let len = cmp::min(buf.len(), buffer.bytes.len());
+        for (dst, src) in buf.iter_mut().zip(buffer.bytes.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut buffer = self.write.lock().unwrap();
+        if buffer.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        buffer.bytes.extend(buf);
+        if let Some(task) = buffer.reader.take() {
+            task.notify();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for Pipe {}
+
+impl AsyncWrite for Pipe {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.write.lock().unwrap().close();
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        self.write.lock().unwrap().close();
+        self.read.lock().unwrap().close();
+    }
+}
+
+/// Serve a database on a new in-process connection and return the client side of it,
+/// must be called on a tokio runtime.
+pub fn connect_in_process(db: Db) -> Framed<Pipe, ClientCodec> {
+    let (client, server) = pipe();
+    let ctx = ServerCtx::new(db, Settings::default());
+    let incoming = stream::once(Ok(server));
+    tokio::spawn(serve(incoming, ctx, Connections::default()));
+
+    ClientCodec::default().framed(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use meilies::reqresp::{Request, Response};
+    use meilies::stream::{EventData, EventName, EventNumber, ReadRange};
+    use meilies::stream::{Stream as EsStream, StreamName};
+    use sled::Config;
+
+    type Connection = Framed<Pipe, ClientCodec>;
+
+    fn request(
+        runtime: &mut tokio::runtime::Runtime,
+        conn: Connection,
+        request: Request,
+    ) -> (Response, Connection) {
+        let fut = conn
+            .send(request)
+            .map_err(|e| e.to_string())
+            .and_then(|conn| conn.into_future().map_err(|(e, _)| e.to_string()));
+        let (response, conn) = runtime.block_on(fut).unwrap();
+        (response.unwrap().unwrap(), conn)
+    }
+
+    fn publish(name: &StreamName, data: &[u8]) -> Request {
+        Request::Publish {
+            stream: name.clone(),
+            event_name: EventName::new("event".to_owned()).unwrap(),
+            event_data: EventData(data.to_vec()),
+            dedup_id: None,
+            headers: Default::default(),
+        }
+    }
+
+    #[test]
+    fn publish_then_last_event_number() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let conn = runtime
+            .block_on(future::lazy(|| Ok::<_, ()>(connect_in_process(db))))
+            .unwrap();
+
+        let stream = StreamName::new("loopback".to_owned()).unwrap();
+        let (response, conn) = request(&mut runtime, conn, publish(&stream, b"first"));
+        assert_eq!(response, Response::Ok);
+        let (response, conn) = request(&mut runtime, conn, publish(&stream, b"second"));
+        assert_eq!(response, Response::Ok);
+
+        let last = Request::LastEventNumber {
+            stream: stream.clone(),
+        };
+        let (response, _conn) = request(&mut runtime, conn, last);
+        let expected = Response::LastEventNumber {
+            stream,
+            number: Some(EventNumber(1)),
+        };
+        assert_eq!(response, expected);
+    }
+
+    #[test]
+    fn publish_then_subscribe() {
+        let db = Config::new().temporary(true).open().unwrap();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let conn = runtime
+            .block_on(future::lazy(|| Ok::<_, ()>(connect_in_process(db))))
+            .unwrap();
+
+        let stream = StreamName::new("loopback".to_owned()).unwrap();
+        let (response, conn) = request(&mut runtime, conn, publish(&stream, b"data"));
+        assert_eq!(response, Response::Ok);
+
+        let subscribe = Request::Subscribe {
+            streams: vec![EsStream::new(stream.clone(), ReadRange::ReadFrom(0))],
+            require_existing: false,
+        };
+        let fut = conn
+            .send(subscribe)
+            .map_err(|e| e.to_string())
+            .and_then(|conn| conn.take(3).collect().map_err(|e| e.to_string()));
+        let responses: Vec<_> = runtime
+            .block_on(fut)
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+
+        // the event is stamped with the time it was published at
+        let timestamp = match &responses[1] {
+            Response::Event { timestamp, .. } => *timestamp,
+            otherwise => panic!("unexpected response {:?}", otherwise),
+        };
+        assert!(timestamp.is_some());
+
+        let expected = vec![
+            Response::Subscribed {
+                stream: stream.clone(),
+            },
+            Response::Event {
+                stream: stream.clone(),
+                number: EventNumber(0),
+                event_name: EventName::new("event".to_owned()).unwrap(),
+                event_data: EventData(b"data".to_vec()),
+                global: None,
+                headers: Default::default(),
+                redacted: false,
+                timestamp,
+            },
+            Response::CaughtUp {
+                stream,
+                number: EventNumber(1),
+            },
+        ];
+        assert_eq!(responses, expected);
+    }
+}
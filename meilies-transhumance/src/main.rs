@@ -1,11 +1,9 @@
-use std::net::ToSocketAddrs;
-
 use futures::future::Either;
 use futures::{future, Future, Stream};
 use log::{error, info};
 use meilies::reqresp::Response;
 use meilies::stream::Stream as EsStream;
-use meilies_client::{paired_connect, sub_connect};
+use meilies_client::{paired_connect, sub_connect, ServerAddr};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -17,11 +15,11 @@ use structopt::StructOpt;
 struct Opt {
     /// Source server address (i.e. localhost:6480).
     #[structopt(long = "src-server")]
-    src_server: String,
+    src_server: ServerAddr,
 
     /// Destination server address (i.e. localhost:6481).
     #[structopt(long = "dst-server")]
-    dst_server: String,
+    dst_server: ServerAddr,
 
     /// List of streams to migrate from the source server to the destination one
     /// (i.e. hello:10, super-stream).
@@ -34,37 +32,11 @@ struct Opt {
 fn main() {
     let _ = stderrlog::new().verbosity(2).init();
 
-    let opt = Opt::from_args();
-
-    let src_server = match opt
-        .src_server
-        .to_socket_addrs()
-        .map(|addrs| addrs.filter(|a| a.is_ipv4()).next())
-    {
-        Ok(Some(addr)) => addr,
-        Ok(None) => {
-            return error!(
-                "impossible to dns resolve the source addr; {:?}",
-                opt.src_server
-            )
-        }
-        Err(e) => return error!("error parsing addr; {}", e),
-    };
-
-    let dst_server = match opt
-        .dst_server
-        .to_socket_addrs()
-        .map(|addrs| addrs.filter(|a| a.is_ipv4()).next())
-    {
-        Ok(Some(addr)) => addr,
-        Ok(None) => {
-            return error!(
-                "impossible to dns resolve the destination addr; {:?}",
-                opt.dst_server
-            )
-        }
-        Err(e) => return error!("error parsing addr; {}", e),
-    };
+    let Opt {
+        src_server,
+        dst_server,
+        streams,
+    } = Opt::from_args();
 
     if src_server == dst_server {
         return error!("the source and destination can not be the same");
@@ -73,7 +45,7 @@ fn main() {
     let fut = sub_connect(src_server)
         .map_err(|e| error!("{}", e))
         .and_then(move |(mut ctrl, msgs)| {
-            for stream in opt.streams {
+            for stream in streams {
                 ctrl.subscribe_to(stream);
             }
 
@@ -87,11 +59,15 @@ fn main() {
                                 number,
                                 event_name,
                                 event_data,
+                                headers,
+                                ..
                             }) => {
                                 info!("{:?} {:?} {:?}", stream, event_name, number);
                                 Either::A(
                                     dst_conn
-                                        .publish(stream, event_name, event_data)
+                                        .publish_with_headers(
+                                            stream, event_name, event_data, headers,
+                                        )
                                         .map_err(|e| error!("{}", e)),
                                 )
                             }
@@ -0,0 +1,19 @@
+#![no_main]
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use tokio::codec::Decoder;
+
+use meilies::reqresp::ServerCodec;
+use meilies::resp::RespCodec;
+
+// The decoders must never panic on untrusted bytes, they either return
+// a value, wait for more bytes or return an error.
+fuzz_target!(|data: &[u8]| {
+    // every decoded value consumes at least one byte, the loops always end
+    let mut buf = BytesMut::from(data);
+    while let Ok(Some(_)) = RespCodec.decode(&mut buf) {}
+
+    let mut buf = BytesMut::from(data);
+    let mut codec = ServerCodec::with_max_event_size(1024);
+    while let Ok(Some(_)) = codec.decode(&mut buf) {}
+});
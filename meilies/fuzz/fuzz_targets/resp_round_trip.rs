@@ -0,0 +1,52 @@
+#![no_main]
+use bytes::BytesMut;
+use libfuzzer_sys::arbitrary::{Arbitrary, Result, Unstructured};
+use libfuzzer_sys::fuzz_target;
+use tokio::codec::{Decoder, Encoder};
+
+use meilies::resp::{RespCodec, RespValue};
+
+const MAX_DEPTH: usize = 4;
+
+fn arbitrary_value(u: &mut Unstructured, depth: usize) -> Result<RespValue> {
+    let kind = u8::arbitrary(u)? % if depth < MAX_DEPTH { 7 } else { 6 };
+    let value = match kind {
+        0 => RespValue::SimpleString(String::arbitrary(u)?),
+        1 => RespValue::Error(String::arbitrary(u)?),
+        2 => RespValue::Integer(i64::arbitrary(u)?),
+        3 => RespValue::BulkString(Vec::arbitrary(u)?),
+        4 => RespValue::Nil,
+        5 => RespValue::NullArray,
+        _ => {
+            let len = u8::arbitrary(u)? % 8;
+            let mut array = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                array.push(arbitrary_value(u, depth + 1)?);
+            }
+            RespValue::Array(array)
+        }
+    };
+    Ok(value)
+}
+
+// Any value that can be encoded must be decoded back to the same value
+// and consume exactly the encoded bytes.
+fuzz_target!(|data: &[u8]| {
+    let mut u = Unstructured::new(data);
+    let value = match arbitrary_value(&mut u, 0) {
+        Ok(value) => value,
+        Err(_) => return,
+    };
+
+    // simple strings and errors containing a CRLF can not be encoded
+    let mut buf = BytesMut::new();
+    if RespCodec.encode(value.clone(), &mut buf).is_err() {
+        return;
+    }
+
+    let decoded = RespCodec
+        .decode(&mut buf)
+        .expect("encoded value must decode");
+    assert_eq!(decoded, Some(value));
+    assert!(buf.is_empty());
+});
@@ -1,3 +1,56 @@
 pub mod reqresp;
 pub mod resp;
 pub mod stream;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use crate::reqresp::{RespRequestConvertError, RespResponseConvertError};
+    use crate::resp::{
+        RespArrayConvertError, RespBytesConvertError, RespIntConvertError, RespStringConvertError,
+        RespTupleConvertError, RespVecConvertError,
+    };
+    use crate::stream::{
+        RespEventNameConvertError, RespStreamConvertError, RespStreamNameConvertError,
+        StreamNameError,
+    };
+
+    fn boxed<E: Error + Send + Sync + 'static>(error: E) -> Box<dyn Error + Send + Sync> {
+        Box::new(error)
+    }
+
+    #[test]
+    fn convert_errors_are_boxable() {
+        let errors = vec![
+            boxed(RespRequestConvertError::MissingArgument),
+            boxed(RespResponseConvertError::MissingArgument),
+            boxed(RespStringConvertError::InvalidRespType),
+            boxed(RespIntConvertError::InvalidRespType),
+            boxed(RespBytesConvertError::InvalidRespType),
+            boxed(RespArrayConvertError::InvalidRespType),
+            boxed(RespTupleConvertError::InvalidRespType),
+            boxed(RespVecConvertError::InnerRespConvertError(
+                RespIntConvertError::InvalidRespType,
+            )),
+            boxed(RespStreamNameConvertError::InvalidRespType),
+            boxed(RespEventNameConvertError::InvalidRespType),
+            boxed(RespStreamConvertError::InvalidRespType),
+        ];
+
+        for error in errors {
+            assert!(!error.to_string().is_empty());
+        }
+    }
+
+    #[test]
+    fn convert_errors_expose_their_source() {
+        let error =
+            RespStreamNameConvertError::InnerStreamNameConvertError(StreamNameError::ContainColon);
+        let source = error.source().expect("missing error source");
+        assert_eq!(
+            source.to_string(),
+            StreamNameError::ContainColon.to_string()
+        );
+    }
+}
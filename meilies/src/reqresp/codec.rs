@@ -4,19 +4,22 @@ use bytes::BytesMut;
 use tokio::codec::{Decoder, Encoder};
 use tokio::io;
 
-use super::{Request, RespRequestConvertError, RespResponseConvertError, Response};
+use super::{Request, RespRequestConvertError, RespResponseConvertError, Response, ServerError};
 use crate::resp::{FromResp, RespCodec, RespMsgError, RespValue};
 
 #[derive(Debug, Default)]
 pub struct ClientCodec;
 
 impl Decoder for ClientCodec {
-    type Item = Result<Response, String>;
+    type Item = Result<Response, ServerError>;
     type Error = ResponseMsgError;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         match RespCodec.decode(buf)? {
-            Some(value) => Ok(Some(FromResp::from_resp(value)?)),
+            Some(value) => {
+                let response: Result<Response, String> = FromResp::from_resp(value)?;
+                Ok(Some(response.map_err(ServerError::from)))
+            }
             None => Ok(None),
         }
     }
@@ -31,8 +34,34 @@ impl Encoder for ClientCodec {
     }
 }
 
+/// The room left in a frame for the command, the stream and the event names
+/// when the frame size is derived from the max event size.
+const MAX_FRAME_OVERHEAD: usize = 4096;
+
 #[derive(Debug, Default)]
-pub struct ServerCodec;
+pub struct ServerCodec {
+    max_frame_size: Option<usize>,
+    max_event_size: Option<usize>,
+}
+
+impl ServerCodec {
+    /// Create a codec that rejects any incomplete frame bigger than the given size.
+    pub fn with_max_frame_size(max_frame_size: usize) -> ServerCodec {
+        ServerCodec {
+            max_frame_size: Some(max_frame_size),
+            max_event_size: None,
+        }
+    }
+
+    /// Create a codec that rejects published events with data bigger than the given size,
+    /// frames that can not contain such an event are rejected before being fully received.
+    pub fn with_max_event_size(max_event_size: usize) -> ServerCodec {
+        ServerCodec {
+            max_frame_size: Some(max_event_size + MAX_FRAME_OVERHEAD),
+            max_event_size: Some(max_event_size),
+        }
+    }
+}
 
 impl Decoder for ServerCodec {
     type Item = Request;
@@ -40,20 +69,26 @@ impl Decoder for ServerCodec {
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         match RespCodec.decode(buf)? {
-            Some(value) => Ok(Some(FromResp::from_resp(value)?)),
-            None => Ok(None),
+            Some(value) => {
+                let request = Request::from_resp_with_max_event_size(value, self.max_event_size)?;
+                Ok(Some(request))
+            }
+            None => match self.max_frame_size {
+                Some(max) if buf.len() > max => Err(RequestMsgError::FrameTooLarge(max)),
+                _ => Ok(None),
+            },
         }
     }
 }
 
 impl Encoder for ServerCodec {
-    type Item = Result<Response, String>;
+    type Item = Result<Response, ServerError>;
     type Error = ResponseMsgError;
 
     fn encode(&mut self, msg: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
-        let msg = match msg {
+        let msg: RespValue = match msg {
             Ok(item) => item.into(),
-            Err(error) => RespValue::Error(error),
+            Err(error) => error.into(),
         };
 
         Ok(RespCodec.encode(msg, buf)?)
@@ -64,6 +99,7 @@ impl Encoder for ServerCodec {
 pub enum RequestMsgError {
     RequestMsgError(RespRequestConvertError),
     RespMsgError(RespMsgError),
+    FrameTooLarge(usize),
 }
 
 impl fmt::Display for RequestMsgError {
@@ -71,6 +107,33 @@ impl fmt::Display for RequestMsgError {
         match self {
             RequestMsgError::RequestMsgError(error) => write!(f, "{}", error),
             RequestMsgError::RespMsgError(error) => write!(f, "{}", error),
+            RequestMsgError::FrameTooLarge(max) => {
+                write!(f, "frame exceeds max size of {} bytes", max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RequestMsgError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RequestMsgError::RequestMsgError(error) => Some(error),
+            RequestMsgError::RespMsgError(error) => Some(error),
+            RequestMsgError::FrameTooLarge(_) => None,
+        }
+    }
+}
+
+impl RequestMsgError {
+    /// Whether the connection can still be read after this error.
+    ///
+    /// A valid frame that is not a valid request has been consumed and the next
+    /// frame can be decoded, but a frame that can not be decoded stays in the buffer
+    /// and every following decode fails the same way.
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            RequestMsgError::RequestMsgError(_) => true,
+            RequestMsgError::RespMsgError(_) | RequestMsgError::FrameTooLarge(_) => false,
         }
     }
 }
@@ -108,6 +171,15 @@ impl fmt::Display for ResponseMsgError {
     }
 }
 
+impl std::error::Error for ResponseMsgError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ResponseMsgError::ResponseMsgError(error) => Some(error),
+            ResponseMsgError::RespMsgError(error) => Some(error),
+        }
+    }
+}
+
 impl From<RespMsgError> for ResponseMsgError {
     fn from(error: RespMsgError) -> ResponseMsgError {
         ResponseMsgError::RespMsgError(error)
@@ -125,3 +197,95 @@ impl From<io::Error> for ResponseMsgError {
         ResponseMsgError::from(RespMsgError::from(error))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reqresp::ErrorCode;
+    use crate::stream::{EventData, EventName, StreamName};
+
+    fn publish_request(size: usize) -> Request {
+        Request::Publish {
+            stream: StreamName::new("stream".to_owned()).unwrap(),
+            event_name: EventName::new("event".to_owned()).unwrap(),
+            event_data: EventData(vec![42; size]),
+            dedup_id: None,
+            headers: Default::default(),
+        }
+    }
+
+    #[test]
+    fn frame_size_limit() {
+        let mut buf = BytesMut::new();
+        ClientCodec.encode(publish_request(100), &mut buf).unwrap();
+        let frame_size = buf.len();
+
+        let mut codec = ServerCodec::with_max_frame_size(frame_size);
+        let request = codec.decode(&mut buf).unwrap();
+        assert_eq!(request, Some(publish_request(100)));
+        assert!(buf.is_empty());
+
+        // an incomplete frame just under the limit must wait for more bytes
+        ClientCodec.encode(publish_request(100), &mut buf).unwrap();
+        let mut rest = buf.split_off(frame_size - 1);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        buf.unsplit(rest.split_to(1));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(publish_request(100)));
+
+        // an incomplete frame just over the limit must be rejected
+        let mut codec = ServerCodec::with_max_frame_size(frame_size);
+        ClientCodec.encode(publish_request(110), &mut buf).unwrap();
+        buf.truncate(frame_size + 1);
+        match codec.decode(&mut buf) {
+            Err(RequestMsgError::FrameTooLarge(max)) => assert_eq!(max, frame_size),
+            otherwise => panic!("unexpected decode result {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn classify_decode_errors() {
+        let mut codec = ServerCodec::default();
+
+        // an unknown command is consumed, the following request can be decoded
+        let mut buf = BytesMut::from(&b"*1\r\n$7\r\nunknown\r\n"[..]);
+        ClientCodec.encode(publish_request(10), &mut buf).unwrap();
+        let error = codec.decode(&mut buf).unwrap_err();
+        assert!(error.is_recoverable());
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(publish_request(10)));
+
+        // a garbage byte is never consumed
+        let mut buf = BytesMut::from(&b"?garbage\r\n"[..]);
+        let error = codec.decode(&mut buf).unwrap_err();
+        assert!(!error.is_recoverable());
+        assert!(!codec.decode(&mut buf).unwrap_err().is_recoverable());
+    }
+
+    #[test]
+    fn request_msg_error_source() {
+        use std::error::Error;
+
+        let mut buf = BytesMut::from(&b":abc\r\n"[..]);
+        let error = ServerCodec::default().decode(&mut buf).unwrap_err();
+
+        let source = error.source().expect("a resp message error");
+        assert!(source.is::<RespMsgError>());
+        let source = source.source().expect("a parse int error");
+        assert!(source.is::<std::num::ParseIntError>());
+    }
+
+    #[test]
+    fn coded_error_round_trip() {
+        let error = ServerError::new(ErrorCode::StreamNotFound, "stream orders does not exist");
+        let mut buf = BytesMut::new();
+        ServerCodec::default()
+            .encode(Err(error.clone()), &mut buf)
+            .unwrap();
+        assert_eq!(
+            &buf[..],
+            &b"-STREAM_NOT_FOUND stream orders does not exist\r\n"[..]
+        );
+
+        let response = ClientCodec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(response, Err(error));
+    }
+}
@@ -0,0 +1,159 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::resp::RespValue;
+
+/// A machine readable reason sent in front of the message of an error,
+/// like the `WRONGTYPE` prefix of Redis errors.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    StreamNotFound,
+    EventNotFound,
+    GroupNotFound,
+    SnapshotNotFound,
+    CorruptedEvent,
+    EventTooLarge,
+    TooManyStreams,
+    TooManyConnections,
+    RateLimited,
+    ReadOnly,
+    NotSupported,
+    InvalidRequest,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::StreamNotFound => "STREAM_NOT_FOUND",
+            ErrorCode::EventNotFound => "EVENT_NOT_FOUND",
+            ErrorCode::GroupNotFound => "GROUP_NOT_FOUND",
+            ErrorCode::SnapshotNotFound => "SNAPSHOT_NOT_FOUND",
+            ErrorCode::CorruptedEvent => "CORRUPTED_EVENT",
+            ErrorCode::EventTooLarge => "EVENT_TOO_LARGE",
+            ErrorCode::TooManyStreams => "TOO_MANY_STREAMS",
+            ErrorCode::TooManyConnections => "TOO_MANY_CONNECTIONS",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::ReadOnly => "READ_ONLY",
+            ErrorCode::NotSupported => "NOT_SUPPORTED",
+            ErrorCode::InvalidRequest => "INVALID_REQUEST",
+            ErrorCode::Internal => "INTERNAL",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseErrorCodeError(String);
+
+impl fmt::Display for ParseErrorCodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown error code {:?}", self.0)
+    }
+}
+
+impl FromStr for ErrorCode {
+    type Err = ParseErrorCodeError;
+
+    fn from_str(s: &str) -> Result<ErrorCode, Self::Err> {
+        match s {
+            "STREAM_NOT_FOUND" => Ok(ErrorCode::StreamNotFound),
+            "EVENT_NOT_FOUND" => Ok(ErrorCode::EventNotFound),
+            "GROUP_NOT_FOUND" => Ok(ErrorCode::GroupNotFound),
+            "SNAPSHOT_NOT_FOUND" => Ok(ErrorCode::SnapshotNotFound),
+            "CORRUPTED_EVENT" => Ok(ErrorCode::CorruptedEvent),
+            "EVENT_TOO_LARGE" => Ok(ErrorCode::EventTooLarge),
+            "TOO_MANY_STREAMS" => Ok(ErrorCode::TooManyStreams),
+            "TOO_MANY_CONNECTIONS" => Ok(ErrorCode::TooManyConnections),
+            "RATE_LIMITED" => Ok(ErrorCode::RateLimited),
+            "READ_ONLY" => Ok(ErrorCode::ReadOnly),
+            "NOT_SUPPORTED" => Ok(ErrorCode::NotSupported),
+            "INVALID_REQUEST" => Ok(ErrorCode::InvalidRequest),
+            "INTERNAL" => Ok(ErrorCode::Internal),
+            _ => Err(ParseErrorCodeError(s.to_owned())),
+        }
+    }
+}
+
+/// An error sent by the server in place of a response.
+///
+/// It is encoded as a RESP error string made of the code followed by the message,
+/// errors sent by servers that do not know about codes are decoded without one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerError {
+    pub code: Option<ErrorCode>,
+    pub message: String,
+}
+
+impl ServerError {
+    pub fn new<M: Into<String>>(code: ErrorCode, message: M) -> ServerError {
+        ServerError {
+            code: Some(code),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "{} {}", code, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl From<String> for ServerError {
+    fn from(string: String) -> ServerError {
+        let (code, message) = match string.find(' ') {
+            Some(index) => (&string[..index], &string[index + 1..]),
+            None => (&string[..], ""),
+        };
+        match code.parse() {
+            Ok(code) => ServerError::new(code, message),
+            Err(_) => ServerError {
+                code: None,
+                message: string,
+            },
+        }
+    }
+}
+
+impl From<ServerError> for RespValue {
+    fn from(error: ServerError) -> RespValue {
+        RespValue::error(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_coded_and_uncoded_errors() {
+        let error = ServerError::from(String::from("RATE_LIMITED too many requests"));
+        assert_eq!(
+            error,
+            ServerError::new(ErrorCode::RateLimited, "too many requests")
+        );
+
+        let error = ServerError::from(String::from("stream orders does not exist"));
+        assert_eq!(error.code, None);
+        assert_eq!(error.message, "stream orders does not exist");
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let error = ServerError::new(ErrorCode::StreamNotFound, "stream orders does not exist");
+        assert_eq!(
+            error.to_string(),
+            "STREAM_NOT_FOUND stream orders does not exist"
+        );
+        assert_eq!(ServerError::from(error.to_string()), error);
+    }
+}
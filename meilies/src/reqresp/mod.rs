@@ -1,7 +1,9 @@
 mod codec;
+mod error;
 mod request;
 mod response;
 
 pub use self::codec::{ClientCodec, RequestMsgError, ResponseMsgError, ServerCodec};
+pub use self::error::{ErrorCode, ParseErrorCodeError, ServerError};
 pub use self::request::{Request, RespRequestConvertError};
 pub use self::response::{RespResponseConvertError, Response};
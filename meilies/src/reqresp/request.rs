@@ -1,58 +1,353 @@
 use crate::resp::{FromResp, RespValue};
-use crate::stream::ALL_STREAMS;
-use crate::stream::{EventData, EventName, ReadRange, Stream, StreamName};
+use crate::stream::{EventData, EventDataError, EventName, EventNumber};
+use crate::stream::{ReadRange, Stream, StreamName, StreamSettings};
+use crate::stream::{ALL_STREAMS, ALL_STREAMS_PREFIX};
+use std::collections::HashMap;
 use std::fmt;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Request {
+    /// Subscribe to every stream, an `ordered` subscription follows the global
+    /// numbers of the events and needs a server started with global ordering.
     SubscribeAll {
         range: ReadRange,
+        ordered: bool,
     },
     Subscribe {
         streams: Vec<Stream>,
+        require_existing: bool,
     },
+    SubscribePrefix {
+        prefix: String,
+        range: ReadRange,
+    },
+    /// Publishing twice with the same `dedup_id` appends the event only once,
+    /// the number previously given to the event is returned instead.
+    ///
+    /// The `headers` are stored along with the event and sent to the subscribers.
     Publish {
         stream: StreamName,
         event_name: EventName,
         event_data: EventData,
+        dedup_id: Option<String>,
+        #[cfg_attr(feature = "serde", serde(default))]
+        headers: HashMap<String, String>,
+    },
+    /// Publish events to several streams at once, either all the events are appended
+    /// or none of them is, the server answers with `Response::PublishedMulti`.
+    PublishMulti {
+        events: Vec<(StreamName, EventName, EventData)>,
+    },
+    /// Publish a part of an event too big to be sent at once, the chunks sent
+    /// on a connection are accumulated and the event is published with the last one.
+    PublishChunk {
+        stream: StreamName,
+        event_name: EventName,
+        chunk: Vec<u8>,
+        last: bool,
     },
     LastEventNumber {
         stream: StreamName,
     },
+    LastEvent {
+        stream: StreamName,
+    },
     StreamNames,
+    StreamSize {
+        stream: StreamName,
+    },
+    TotalSize,
+    ListSubscriptions,
+    /// Ask for all the events of a stream as a sequence of binary blobs,
+    /// the server answers with `Response::Exported` messages followed by `Response::Ok`.
+    Export {
+        stream: StreamName,
+    },
+    /// Restore the events of a blob produced by an export, events keep their numbers.
+    Import {
+        stream: StreamName,
+        blob: Vec<u8>,
+    },
+    /// Check the checksums of the events of a stream and that no event is missing.
+    Verify {
+        stream: StreamName,
+    },
+    /// Ask for the version of the server and a few statistics.
+    Info,
+    /// Check that the server is alive, it answers with `Response::Pong`.
+    Ping,
+    /// Store the state of a stream folded up to the event `number` included,
+    /// it replaces the previous snapshot of the stream.
+    SaveSnapshot {
+        stream: StreamName,
+        number: EventNumber,
+        data: Vec<u8>,
+    },
+    /// Ask for the latest snapshot of a stream, the server answers with
+    /// `Response::Snapshot` or `Response::Nil` if there is none.
+    GetSnapshot {
+        stream: StreamName,
+    },
+    /// Ask the server to fold the events published since the latest snapshot
+    /// of a stream and to save the result, it answers with `Response::Snapshot`.
+    ///
+    /// The server must have a snapshot function registered for the stream.
+    CreateSnapshot {
+        stream: StreamName,
+    },
+    /// Delete the events of a stream that are included in its latest snapshot,
+    /// the server answers with `Response::Compacted`.
+    ///
+    /// The stream must have a snapshot, the deleted events can not be read anymore.
+    Compact {
+        stream: StreamName,
+    },
+    /// Replace the settings of a stream, they are kept across restarts.
+    ConfigureStream {
+        stream: StreamName,
+        settings: StreamSettings,
+    },
+    /// Ask for at most `limit` events of a stream starting at `from`, the server
+    /// answers with a single `Response::Page`. The server caps the limit, the
+    /// page can contain less events even if the end of the stream is not reached.
+    Page {
+        stream: StreamName,
+        from: EventNumber,
+        limit: u64,
+    },
+    /// Wait until the last event number of a stream reaches `number`, the server
+    /// answers with `Response::Ok` once it does, without sending the events.
+    WaitFor {
+        stream: StreamName,
+        number: EventNumber,
+    },
+    /// Stop sending the events of a subscribed stream until it is resumed, the subscription
+    /// is kept and the server answers with `Response::Ok`.
+    Pause {
+        stream: StreamName,
+    },
+    /// Send the events of a paused stream again, starting with the first one not sent.
+    Resume {
+        stream: StreamName,
+    },
+    /// Create a consumer group that reads a stream from its first event,
+    /// nothing is changed if the group already exists.
+    CreateGroup {
+        stream: StreamName,
+        group: String,
+    },
+    /// Claim the next event of a stream that no consumer of the group has claimed or
+    /// acknowledged, the server answers with `Response::Event` or `Response::Nil`.
+    ///
+    /// The event is claimed until it is acknowledged or the connection is closed.
+    Claim {
+        stream: StreamName,
+        group: String,
+    },
+    /// Acknowledge an event claimed on this connection, it is never claimed again.
+    Ack {
+        stream: StreamName,
+        group: String,
+        number: EventNumber,
+    },
+    /// Replace an event by a tombstone that keeps its number and its name, the event
+    /// is then read as a `Response::Event` that is redacted and has no data.
+    RedactEvent {
+        stream: StreamName,
+        number: EventNumber,
+    },
 }
 
 impl Into<RespValue> for Request {
     fn into(self) -> RespValue {
         match self {
-            Request::SubscribeAll { range } => {
-                let command = RespValue::bulk_string(&"subscribe"[..]);
+            Request::SubscribeAll { range, ordered } => {
+                let command = if ordered {
+                    RespValue::bulk_string(&"subscribe-ordered"[..])
+                } else {
+                    RespValue::bulk_string(&"subscribe"[..])
+                };
                 let all = Stream::all(range).into();
                 RespValue::Array(vec![command, all])
             }
-            Request::Subscribe { streams } => {
-                let command = RespValue::bulk_string(&"subscribe"[..]);
+            Request::Subscribe {
+                streams,
+                require_existing,
+            } => {
+                let command = if require_existing {
+                    RespValue::bulk_string(&"subscribe-existing"[..])
+                } else {
+                    RespValue::bulk_string(&"subscribe"[..])
+                };
                 let streams = streams.into_iter().map(Into::into);
                 let args = Some(command).into_iter().chain(streams).collect();
                 RespValue::Array(args)
             }
+            Request::SubscribePrefix { prefix, range } => {
+                let mut args = vec![
+                    RespValue::bulk_string(&"subscribe-prefix"[..]),
+                    RespValue::bulk_string(prefix),
+                ];
+                if let Some(from) = range.from() {
+                    args.push(RespValue::bulk_string(from.to_string()));
+                }
+                if let Some(to) = range.to() {
+                    args.push(RespValue::bulk_string(to.to_string()));
+                }
+                RespValue::Array(args)
+            }
             Request::Publish {
                 stream,
                 event_name,
                 event_data,
-            } => RespValue::Array(vec![
-                RespValue::bulk_string(&"publish"[..]),
-                RespValue::bulk_string(stream.to_string()),
-                RespValue::bulk_string(event_name.to_string()),
-                RespValue::bulk_string(event_data.0),
-            ]),
+                dedup_id,
+                headers,
+            } => {
+                let mut args = vec![
+                    RespValue::bulk_string(&"publish"[..]),
+                    RespValue::bulk_string(stream.to_string()),
+                    RespValue::bulk_string(event_name.to_string()),
+                    RespValue::bulk_string(event_data.0),
+                ];
+                if let Some(dedup_id) = dedup_id {
+                    args.push(RespValue::bulk_string(dedup_id));
+                }
+                if !headers.is_empty() {
+                    args.push(RespValue::string_pairs(headers));
+                }
+                RespValue::Array(args)
+            }
+            Request::PublishMulti { events } => {
+                let mut args = vec![RespValue::bulk_string(&"publish-multi"[..])];
+                for (stream, event_name, event_data) in events {
+                    args.push(RespValue::bulk_string(stream.to_string()));
+                    args.push(RespValue::bulk_string(event_name.to_string()));
+                    args.push(RespValue::bulk_string(event_data.0));
+                }
+                RespValue::Array(args)
+            }
             Request::LastEventNumber { stream } => RespValue::Array(vec![
                 RespValue::bulk_string(&"last-event-number"[..]),
                 RespValue::bulk_string(stream.to_string()),
             ]),
+            Request::LastEvent { stream } => RespValue::Array(vec![
+                RespValue::bulk_string(&"last-event"[..]),
+                RespValue::bulk_string(stream.to_string()),
+            ]),
             Request::StreamNames => {
                 RespValue::Array(vec![RespValue::bulk_string(&"stream-names"[..])])
             }
+            Request::Ping => RespValue::Array(vec![RespValue::bulk_string(&"ping"[..])]),
+            Request::StreamSize { stream } => RespValue::Array(vec![
+                RespValue::bulk_string(&"stream-size"[..]),
+                RespValue::bulk_string(stream.to_string()),
+            ]),
+            Request::TotalSize => RespValue::Array(vec![RespValue::bulk_string(&"total-size"[..])]),
+            Request::ListSubscriptions => {
+                RespValue::Array(vec![RespValue::bulk_string(&"list-subscriptions"[..])])
+            }
+            Request::Export { stream } => RespValue::Array(vec![
+                RespValue::bulk_string(&"export"[..]),
+                RespValue::bulk_string(stream.to_string()),
+            ]),
+            Request::Import { stream, blob } => RespValue::Array(vec![
+                RespValue::bulk_string(&"import"[..]),
+                RespValue::bulk_string(stream.to_string()),
+                RespValue::bulk_string(blob),
+            ]),
+            Request::Verify { stream } => RespValue::Array(vec![
+                RespValue::bulk_string(&"verify"[..]),
+                RespValue::bulk_string(stream.to_string()),
+            ]),
+            Request::Info => RespValue::Array(vec![RespValue::bulk_string(&"info"[..])]),
+            Request::SaveSnapshot {
+                stream,
+                number,
+                data,
+            } => RespValue::Array(vec![
+                RespValue::bulk_string(&"save-snapshot"[..]),
+                RespValue::bulk_string(stream.to_string()),
+                RespValue::Integer(number.0 as i64),
+                RespValue::bulk_string(data),
+            ]),
+            Request::PublishChunk {
+                stream,
+                event_name,
+                chunk,
+                last,
+            } => RespValue::Array(vec![
+                RespValue::bulk_string(&"publish-chunk"[..]),
+                RespValue::bulk_string(stream.to_string()),
+                RespValue::bulk_string(event_name.to_string()),
+                RespValue::bulk_string(chunk),
+                RespValue::Integer(last as i64),
+            ]),
+            Request::GetSnapshot { stream } => RespValue::Array(vec![
+                RespValue::bulk_string(&"get-snapshot"[..]),
+                RespValue::bulk_string(stream.to_string()),
+            ]),
+            Request::CreateSnapshot { stream } => RespValue::Array(vec![
+                RespValue::bulk_string(&"create-snapshot"[..]),
+                RespValue::bulk_string(stream.to_string()),
+            ]),
+            Request::Compact { stream } => RespValue::Array(vec![
+                RespValue::bulk_string(&"compact"[..]),
+                RespValue::bulk_string(stream.to_string()),
+            ]),
+            Request::ConfigureStream { stream, settings } => RespValue::Array(vec![
+                RespValue::bulk_string(&"configure-stream"[..]),
+                RespValue::bulk_string(stream.to_string()),
+                settings.into(),
+            ]),
+            Request::Page {
+                stream,
+                from,
+                limit,
+            } => RespValue::Array(vec![
+                RespValue::bulk_string(&"page"[..]),
+                RespValue::bulk_string(stream.to_string()),
+                RespValue::Integer(from.0 as i64),
+                RespValue::Integer(limit as i64),
+            ]),
+            Request::WaitFor { stream, number } => RespValue::Array(vec![
+                RespValue::bulk_string(&"wait-for"[..]),
+                RespValue::bulk_string(stream.to_string()),
+                RespValue::Integer(number.0 as i64),
+            ]),
+            Request::CreateGroup { stream, group } => RespValue::Array(vec![
+                RespValue::bulk_string(&"create-group"[..]),
+                RespValue::bulk_string(stream.to_string()),
+                RespValue::bulk_string(group),
+            ]),
+            Request::Claim { stream, group } => RespValue::Array(vec![
+                RespValue::bulk_string(&"claim"[..]),
+                RespValue::bulk_string(stream.to_string()),
+                RespValue::bulk_string(group),
+            ]),
+            Request::Ack {
+                stream,
+                group,
+                number,
+            } => RespValue::Array(vec![
+                RespValue::bulk_string(&"ack"[..]),
+                RespValue::bulk_string(stream.to_string()),
+                RespValue::bulk_string(group),
+                RespValue::Integer(number.0 as i64),
+            ]),
+            Request::RedactEvent { stream, number } => RespValue::Array(vec![
+                RespValue::bulk_string(&"redact-event"[..]),
+                RespValue::bulk_string(stream.to_string()),
+                RespValue::Integer(number.0 as i64),
+            ]),
+            Request::Pause { stream } => RespValue::Array(vec![
+                RespValue::bulk_string(&"pause"[..]),
+                RespValue::bulk_string(stream.to_string()),
+            ]),
+            Request::Resume { stream } => RespValue::Array(vec![
+                RespValue::bulk_string(&"resume"[..]),
+                RespValue::bulk_string(stream.to_string()),
+            ]),
         }
     }
 }
@@ -65,6 +360,8 @@ pub enum RespRequestConvertError {
     UnknownCommandName,
     MissingArgument,
     TooManyArguments,
+    MixedPrefixSubscription,
+    EventDataTooLarge(EventDataError),
 }
 
 impl fmt::Display for RespRequestConvertError {
@@ -77,6 +374,22 @@ impl fmt::Display for RespRequestConvertError {
             UnknownCommandName => write!(f, "Unknown command name"),
             MissingArgument => write!(f, "Missing argument"),
             TooManyArguments => write!(f, "Too many arguments"),
+            MixedPrefixSubscription => {
+                write!(
+                    f,
+                    "A $all.<prefix> subscription can not include other streams"
+                )
+            }
+            EventDataTooLarge(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RespRequestConvertError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RespRequestConvertError::EventDataTooLarge(e) => Some(e),
+            _ => None,
         }
     }
 }
@@ -85,6 +398,17 @@ impl FromResp for Request {
     type Error = RespRequestConvertError;
 
     fn from_resp(value: RespValue) -> Result<Self, Self::Error> {
+        Request::from_resp_with_max_event_size(value, None)
+    }
+}
+
+impl Request {
+    /// Convert a RESP value into a request, rejecting published events
+    /// with data bigger than `max_event_size` bytes.
+    pub fn from_resp_with_max_event_size(
+        value: RespValue,
+        max_event_size: Option<usize>,
+    ) -> Result<Request, RespRequestConvertError> {
         use RespRequestConvertError::*;
 
         let mut iter = match value {
@@ -99,17 +423,85 @@ impl FromResp for Request {
             .map_err(|_| InvalidArgumentRespType)?;
 
         match command.as_str() {
-            "subscribe" => {
+            "subscribe" | "subscribe-existing" => {
+                let require_existing = command == "subscribe-existing";
                 let streams: Result<Vec<_>, _> = iter.map(Stream::from_resp).collect();
                 let streams = streams.map_err(|_| InvalidArgumentRespType)?;
 
                 if let Some(stream) = streams.iter().find(|s| s.name == ALL_STREAMS) {
                     return Ok(Request::SubscribeAll {
                         range: stream.range,
+                        ordered: false,
                     });
                 }
 
-                Ok(Request::Subscribe { streams })
+                // a prefix subscription is a request of its own, the other
+                // streams would otherwise be silently dropped
+                let is_all_prefix = |s: &&Stream| s.name.as_str().starts_with(ALL_STREAMS_PREFIX);
+                if let Some(stream) = streams.iter().find(is_all_prefix) {
+                    if streams.len() > 1 {
+                        return Err(MixedPrefixSubscription);
+                    }
+                    let prefix = stream.name.as_str()[ALL_STREAMS_PREFIX.len()..].to_owned();
+                    return Ok(Request::SubscribePrefix {
+                        prefix,
+                        range: stream.range,
+                    });
+                }
+
+                Ok(Request::Subscribe {
+                    streams,
+                    require_existing,
+                })
+            }
+            "subscribe-ordered" => {
+                let stream = iter
+                    .next()
+                    .map(Stream::from_resp)
+                    .ok_or(MissingArgument)?
+                    .map_err(|_| InvalidArgumentRespType)?;
+
+                if stream.name != ALL_STREAMS {
+                    return Err(InvalidArgumentRespType);
+                }
+
+                if iter.next().is_some() {
+                    return Err(TooManyArguments);
+                }
+
+                Ok(Request::SubscribeAll {
+                    range: stream.range,
+                    ordered: true,
+                })
+            }
+            "subscribe-prefix" => {
+                let prefix = iter
+                    .next()
+                    .map(String::from_resp)
+                    .ok_or(MissingArgument)?
+                    .map_err(|_| InvalidArgumentRespType)?;
+
+                // the bounds are sent as strings, like the ranges of the subscribe command
+                let mut bounds = iter.map(|value| {
+                    let string = String::from_resp(value).map_err(|_| InvalidArgumentRespType)?;
+                    string.parse::<u64>().map_err(|_| InvalidArgumentRespType)
+                });
+
+                let from = bounds.next().transpose()?;
+                let to = bounds.next().transpose()?;
+
+                if bounds.next().is_some() {
+                    return Err(TooManyArguments);
+                }
+
+                let range = match (from, to) {
+                    (Some(from), Some(to)) if from >= to => return Err(InvalidArgumentRespType),
+                    (Some(from), Some(to)) => ReadRange::ReadFromUntil(from, to),
+                    (Some(from), None) => ReadRange::ReadFrom(from),
+                    (_, _) => ReadRange::ReadFromEnd,
+                };
+
+                Ok(Request::SubscribePrefix { prefix, range })
             }
             "publish" => {
                 let stream = iter
@@ -130,6 +522,32 @@ impl FromResp for Request {
                     .ok_or(MissingArgument)?
                     .map_err(|_| InvalidArgumentRespType)?;
 
+                let event_data = match max_event_size {
+                    Some(max) => {
+                        EventData::with_max(event_data.0, max).map_err(EventDataTooLarge)?
+                    }
+                    None => event_data,
+                };
+
+                // The dedup id and the headers are both optional,
+                // the headers are the only argument sent as an array.
+                let mut dedup_id = None;
+                let mut headers = HashMap::new();
+                match iter.next() {
+                    Some(value @ RespValue::Array(_)) => {
+                        headers = HashMap::from_resp(value).map_err(|_| InvalidArgumentRespType)?;
+                    }
+                    Some(value) => {
+                        let id = String::from_resp(value).map_err(|_| InvalidArgumentRespType)?;
+                        dedup_id = Some(id);
+                        if let Some(value) = iter.next() {
+                            headers =
+                                HashMap::from_resp(value).map_err(|_| InvalidArgumentRespType)?;
+                        }
+                    }
+                    None => (),
+                }
+
                 if iter.next().is_some() {
                     return Err(TooManyArguments);
                 }
@@ -138,6 +556,54 @@ impl FromResp for Request {
                     stream,
                     event_name,
                     event_data,
+                    dedup_id,
+                    headers,
+                })
+            }
+            "publish-multi" => {
+                let mut events = Vec::new();
+                while let Some(stream) = iter.next() {
+                    let stream =
+                        StreamName::from_resp(stream).map_err(|_| InvalidArgumentRespType)?;
+
+                    let event_name = iter
+                        .next()
+                        .map(EventName::from_resp)
+                        .ok_or(MissingArgument)?
+                        .map_err(|_| InvalidArgumentRespType)?;
+
+                    let event_data = iter
+                        .next()
+                        .map(EventData::from_resp)
+                        .ok_or(MissingArgument)?
+                        .map_err(|_| InvalidArgumentRespType)?;
+
+                    let event_data = match max_event_size {
+                        Some(max) => {
+                            EventData::with_max(event_data.0, max).map_err(EventDataTooLarge)?
+                        }
+                        None => event_data,
+                    };
+
+                    events.push((stream, event_name, event_data));
+                }
+
+                if events.is_empty() {
+                    return Err(MissingArgument);
+                }
+
+                Ok(Request::PublishMulti { events })
+            }
+            "publish-chunk" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (stream, event_name, chunk, last): (StreamName, EventName, Vec<u8>, i64) =
+                    FromResp::from_resp(arguments).map_err(|_| InvalidArgumentRespType)?;
+
+                Ok(Request::PublishChunk {
+                    stream,
+                    event_name,
+                    chunk,
+                    last: last != 0,
                 })
             }
             "last-event-number" => {
@@ -153,8 +619,487 @@ impl FromResp for Request {
 
                 Ok(Request::LastEventNumber { stream })
             }
+            "last-event" => {
+                let stream = iter
+                    .next()
+                    .map(StreamName::from_resp)
+                    .ok_or(MissingArgument)?
+                    .map_err(|_| InvalidArgumentRespType)?;
+
+                if iter.next().is_some() {
+                    return Err(TooManyArguments);
+                }
+
+                Ok(Request::LastEvent { stream })
+            }
             "stream-names" => Ok(Request::StreamNames),
+            "ping" => Ok(Request::Ping),
+            "stream-size" => {
+                let stream = iter
+                    .next()
+                    .map(StreamName::from_resp)
+                    .ok_or(MissingArgument)?
+                    .map_err(|_| InvalidArgumentRespType)?;
+
+                if iter.next().is_some() {
+                    return Err(TooManyArguments);
+                }
+
+                Ok(Request::StreamSize { stream })
+            }
+            "total-size" => Ok(Request::TotalSize),
+            "list-subscriptions" => Ok(Request::ListSubscriptions),
+            "export" => {
+                let stream = iter
+                    .next()
+                    .map(StreamName::from_resp)
+                    .ok_or(MissingArgument)?
+                    .map_err(|_| InvalidArgumentRespType)?;
+
+                if iter.next().is_some() {
+                    return Err(TooManyArguments);
+                }
+
+                Ok(Request::Export { stream })
+            }
+            "import" => {
+                let stream = iter
+                    .next()
+                    .map(StreamName::from_resp)
+                    .ok_or(MissingArgument)?
+                    .map_err(|_| InvalidArgumentRespType)?;
+
+                let blob = iter
+                    .next()
+                    .map(Vec::<u8>::from_resp)
+                    .ok_or(MissingArgument)?
+                    .map_err(|_| InvalidArgumentRespType)?;
+
+                if iter.next().is_some() {
+                    return Err(TooManyArguments);
+                }
+
+                Ok(Request::Import { stream, blob })
+            }
+            "verify" => {
+                let stream = iter
+                    .next()
+                    .map(StreamName::from_resp)
+                    .ok_or(MissingArgument)?
+                    .map_err(|_| InvalidArgumentRespType)?;
+
+                if iter.next().is_some() {
+                    return Err(TooManyArguments);
+                }
+
+                Ok(Request::Verify { stream })
+            }
+            "info" => Ok(Request::Info),
+            "save-snapshot" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (stream, number, data): (StreamName, EventNumber, Vec<u8>) =
+                    FromResp::from_resp(arguments).map_err(|_| InvalidArgumentRespType)?;
+
+                Ok(Request::SaveSnapshot {
+                    stream,
+                    number,
+                    data,
+                })
+            }
+            "get-snapshot" | "create-snapshot" => {
+                let stream = iter
+                    .next()
+                    .map(StreamName::from_resp)
+                    .ok_or(MissingArgument)?
+                    .map_err(|_| InvalidArgumentRespType)?;
+
+                if iter.next().is_some() {
+                    return Err(TooManyArguments);
+                }
+
+                if command == "get-snapshot" {
+                    Ok(Request::GetSnapshot { stream })
+                } else {
+                    Ok(Request::CreateSnapshot { stream })
+                }
+            }
+            "compact" => {
+                let stream = iter
+                    .next()
+                    .map(StreamName::from_resp)
+                    .ok_or(MissingArgument)?
+                    .map_err(|_| InvalidArgumentRespType)?;
+
+                if iter.next().is_some() {
+                    return Err(TooManyArguments);
+                }
+
+                Ok(Request::Compact { stream })
+            }
+            "page" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (stream, from, limit): (StreamName, EventNumber, i64) =
+                    FromResp::from_resp(arguments).map_err(|_| InvalidArgumentRespType)?;
+
+                Ok(Request::Page {
+                    stream,
+                    from,
+                    limit: limit as u64,
+                })
+            }
+            "configure-stream" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (stream, settings): (StreamName, StreamSettings) =
+                    FromResp::from_resp(arguments).map_err(|_| InvalidArgumentRespType)?;
+
+                Ok(Request::ConfigureStream { stream, settings })
+            }
+            "wait-for" | "redact-event" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (stream, number): (StreamName, EventNumber) =
+                    FromResp::from_resp(arguments).map_err(|_| InvalidArgumentRespType)?;
+
+                if command == "wait-for" {
+                    Ok(Request::WaitFor { stream, number })
+                } else {
+                    Ok(Request::RedactEvent { stream, number })
+                }
+            }
+            "pause" | "resume" => {
+                let stream = iter
+                    .next()
+                    .map(StreamName::from_resp)
+                    .ok_or(MissingArgument)?
+                    .map_err(|_| InvalidArgumentRespType)?;
+
+                if iter.next().is_some() {
+                    return Err(TooManyArguments);
+                }
+
+                if command == "pause" {
+                    Ok(Request::Pause { stream })
+                } else {
+                    Ok(Request::Resume { stream })
+                }
+            }
+            "create-group" | "claim" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (stream, group): (StreamName, String) =
+                    FromResp::from_resp(arguments).map_err(|_| InvalidArgumentRespType)?;
+
+                if command == "create-group" {
+                    Ok(Request::CreateGroup { stream, group })
+                } else {
+                    Ok(Request::Claim { stream, group })
+                }
+            }
+            "ack" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (stream, group, number): (StreamName, String, EventNumber) =
+                    FromResp::from_resp(arguments).map_err(|_| InvalidArgumentRespType)?;
+
+                Ok(Request::Ack {
+                    stream,
+                    group,
+                    number,
+                })
+            }
             _otherwise => Err(UnknownCommandName),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn publish_value(size: usize) -> RespValue {
+        RespValue::Array(vec![
+            RespValue::bulk_string(&"publish"[..]),
+            RespValue::bulk_string(&"stream"[..]),
+            RespValue::bulk_string(&"event"[..]),
+            RespValue::bulk_string(vec![42; size]),
+        ])
+    }
+
+    #[test]
+    fn publish_chunk_round_trip() {
+        let request = Request::PublishChunk {
+            stream: StreamName::new(String::from("uploads")).unwrap(),
+            event_name: EventName::new(String::from("uploaded")).unwrap(),
+            chunk: vec![0, 1, 2, 3],
+            last: true,
+        };
+
+        let value: RespValue = request.clone().into();
+        assert_eq!(Request::from_resp(value).unwrap(), request);
+    }
+
+    #[test]
+    fn publish_multi_round_trip() {
+        let request = Request::PublishMulti {
+            events: vec![
+                (
+                    StreamName::new(String::from("orders")).unwrap(),
+                    EventName::new(String::from("created")).unwrap(),
+                    EventData(b"{}".to_vec()),
+                ),
+                (
+                    StreamName::new(String::from("audit")).unwrap(),
+                    EventName::new(String::from("order-created")).unwrap(),
+                    EventData(vec![0, 1, 2]),
+                ),
+            ],
+        };
+
+        let value: RespValue = request.clone().into();
+        assert_eq!(Request::from_resp(value).unwrap(), request);
+
+        let value = RespValue::Array(vec![
+            RespValue::bulk_string(&"publish-multi"[..]),
+            RespValue::bulk_string(&"orders"[..]),
+            RespValue::bulk_string(&"created"[..]),
+        ]);
+        assert!(Request::from_resp(value).is_err());
+    }
+
+    #[test]
+    fn configure_stream_round_trip() {
+        let request = Request::ConfigureStream {
+            stream: StreamName::new(String::from("orders")).unwrap(),
+            settings: StreamSettings {
+                compressed: true,
+                max_events: None,
+                max_event_size: Some(1024),
+            },
+        };
+
+        let value: RespValue = request.clone().into();
+        assert_eq!(Request::from_resp(value).unwrap(), request);
+    }
+
+    #[test]
+    fn page_round_trip() {
+        let request = Request::Page {
+            stream: StreamName::new(String::from("orders")).unwrap(),
+            from: EventNumber(10),
+            limit: 20,
+        };
+
+        let value: RespValue = request.clone().into();
+        assert_eq!(Request::from_resp(value).unwrap(), request);
+    }
+
+    #[test]
+    fn wait_for_round_trip() {
+        let request = Request::WaitFor {
+            stream: StreamName::new(String::from("orders")).unwrap(),
+            number: EventNumber(42),
+        };
+
+        let value: RespValue = request.clone().into();
+        assert_eq!(Request::from_resp(value).unwrap(), request);
+    }
+
+    #[test]
+    fn compact_round_trip() {
+        let request = Request::Compact {
+            stream: StreamName::new(String::from("orders")).unwrap(),
+        };
+
+        let value: RespValue = request.clone().into();
+        assert_eq!(Request::from_resp(value).unwrap(), request);
+    }
+
+    #[test]
+    fn redact_event_round_trip() {
+        let request = Request::RedactEvent {
+            stream: StreamName::new(String::from("orders")).unwrap(),
+            number: EventNumber(42),
+        };
+
+        let value: RespValue = request.clone().into();
+        assert_eq!(Request::from_resp(value).unwrap(), request);
+    }
+
+    #[test]
+    fn pause_resume_round_trip() {
+        let stream = StreamName::new(String::from("orders")).unwrap();
+        let requests = vec![
+            Request::Pause {
+                stream: stream.clone(),
+            },
+            Request::Resume { stream },
+        ];
+
+        for request in requests {
+            let value: RespValue = request.clone().into();
+            assert_eq!(Request::from_resp(value).unwrap(), request);
+        }
+    }
+
+    #[test]
+    fn consumer_group_round_trip() {
+        let stream = StreamName::new(String::from("orders")).unwrap();
+        let group = String::from("billing");
+        let requests = vec![
+            Request::CreateGroup {
+                stream: stream.clone(),
+                group: group.clone(),
+            },
+            Request::Claim {
+                stream: stream.clone(),
+                group: group.clone(),
+            },
+            Request::Ack {
+                stream,
+                group,
+                number: EventNumber(7),
+            },
+        ];
+
+        for request in requests {
+            let value: RespValue = request.clone().into();
+            assert_eq!(Request::from_resp(value).unwrap(), request);
+        }
+    }
+
+    #[test]
+    fn publish_max_event_size() {
+        let request = Request::from_resp_with_max_event_size(publish_value(10), Some(10));
+        match request {
+            Ok(Request::Publish { event_data, .. }) => assert_eq!(event_data.0.len(), 10),
+            otherwise => panic!("unexpected request {:?}", otherwise),
+        }
+
+        let request = Request::from_resp_with_max_event_size(publish_value(11), Some(10));
+        match request {
+            Err(RespRequestConvertError::EventDataTooLarge(error)) => {
+                assert_eq!(error, EventDataError::TooLarge { size: 11, max: 10 })
+            }
+            otherwise => panic!("unexpected request {:?}", otherwise),
+        }
+
+        let request = Request::from_resp(publish_value(11));
+        assert!(request.is_ok());
+    }
+
+    #[test]
+    fn subscribe_prefix_round_trip() {
+        let ranges = vec![
+            ReadRange::ReadFromEnd,
+            ReadRange::ReadFrom(3),
+            ReadRange::ReadFromUntil(3, 7),
+        ];
+
+        for range in ranges {
+            let request = Request::SubscribePrefix {
+                prefix: String::from("orders"),
+                range,
+            };
+            let value: RespValue = request.clone().into();
+            assert_eq!(Request::from_resp(value).unwrap(), request);
+        }
+    }
+
+    #[test]
+    fn subscribe_all_ordered_round_trip() {
+        for ordered in [false, true] {
+            let request = Request::SubscribeAll {
+                range: ReadRange::ReadFrom(7),
+                ordered,
+            };
+            let value: RespValue = request.clone().into();
+            assert_eq!(Request::from_resp(value).unwrap(), request);
+        }
+
+        let value = RespValue::Array(vec![
+            RespValue::bulk_string(&"subscribe-ordered"[..]),
+            RespValue::bulk_string(&"orders"[..]),
+        ]);
+        assert!(Request::from_resp(value).is_err());
+    }
+
+    #[test]
+    fn subscribe_all_with_prefix() {
+        let value = RespValue::Array(vec![
+            RespValue::bulk_string(&"subscribe"[..]),
+            RespValue::bulk_string(&"$all.audit-:3"[..]),
+        ]);
+
+        let expected = Request::SubscribePrefix {
+            prefix: String::from("audit-"),
+            range: ReadRange::ReadFrom(3),
+        };
+        assert_eq!(Request::from_resp(value).unwrap(), expected);
+
+        let value = RespValue::Array(vec![
+            RespValue::bulk_string(&"subscribe"[..]),
+            RespValue::bulk_string(&"$all.audit-:3"[..]),
+            RespValue::bulk_string(&"orders"[..]),
+        ]);
+        match Request::from_resp(value) {
+            Err(RespRequestConvertError::MixedPrefixSubscription) => (),
+            otherwise => panic!("unexpected result {:?}", otherwise),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let request = Request::Publish {
+            stream: StreamName::new(String::from("orders")).unwrap(),
+            event_name: EventName::new(String::from("created")).unwrap(),
+            event_data: EventData(b"{}".to_vec()),
+            dedup_id: None,
+            headers: HashMap::new(),
+        };
+
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(serde_json::from_str::<Request>(&json).unwrap(), request);
+
+        // the headers can be omitted
+        let json = r#"{"Publish":{"stream":"orders","event_name":"created","event_data":"{}"}}"#;
+        assert_eq!(serde_json::from_str::<Request>(json).unwrap(), request);
+
+        let invalid = r#"{"LastEvent":{"stream":"a:b"}}"#;
+        assert!(serde_json::from_str::<Request>(invalid).is_err());
+    }
+
+    #[test]
+    fn publish_dedup_id_round_trip() {
+        let mut value = publish_value(4);
+        if let RespValue::Array(args) = &mut value {
+            args.push(RespValue::bulk_string(&"order-42"[..]));
+        }
+
+        let request = Request::from_resp(value).unwrap();
+        match &request {
+            Request::Publish { dedup_id, .. } => assert_eq!(dedup_id.as_deref(), Some("order-42")),
+            otherwise => panic!("unexpected request {:?}", otherwise),
+        }
+
+        let value: RespValue = request.clone().into();
+        assert_eq!(Request::from_resp(value).unwrap(), request);
+    }
+
+    #[test]
+    fn publish_headers_round_trip() {
+        let mut headers = HashMap::new();
+        headers.insert(String::from("correlation-id"), String::from("42"));
+        headers.insert(String::from("content-type"), String::from("text/plain"));
+
+        for dedup_id in [None, Some(String::from("order-42"))] {
+            let request = Request::Publish {
+                stream: StreamName::new(String::from("orders")).unwrap(),
+                event_name: EventName::new(String::from("created")).unwrap(),
+                event_data: EventData(b"{}".to_vec()),
+                dedup_id,
+                headers: headers.clone(),
+            };
+
+            let value: RespValue = request.clone().into();
+            assert_eq!(Request::from_resp(value).unwrap(), request);
+        }
+    }
+}
@@ -1,10 +1,15 @@
-use crate::resp::{FromResp, RespValue};
+use crate::resp::{FromResp, RespTupleConvertError, RespValue};
 use crate::stream::{EventData, EventName, EventNumber, StreamName};
+use std::collections::HashMap;
 use std::fmt;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Response {
     Ok,
+    Nil,
+    /// The answer to `Request::Ping`.
+    Pong,
     Subscribed {
         stream: StreamName,
     },
@@ -13,6 +18,40 @@ pub enum Response {
         number: EventNumber,
         event_name: EventName,
         event_data: EventData,
+        /// The number the event was given across all the streams, only sent
+        /// to the ordered `$all` subscriptions of a server with global ordering.
+        global: Option<EventNumber>,
+        /// The headers the event was published with.
+        #[cfg_attr(feature = "serde", serde(default))]
+        headers: HashMap<String, String>,
+        /// The event was redacted, its data and its headers were removed.
+        #[cfg_attr(feature = "serde", serde(default))]
+        redacted: bool,
+        /// The time the event was published at in milliseconds since the UNIX epoch,
+        /// the events stored before the timestamps were introduced have none.
+        #[cfg_attr(feature = "serde", serde(default))]
+        timestamp: Option<u64>,
+    },
+    /// Sent once the historical events of a subscription have all been sent,
+    /// `number` is the number of the next event that will be sent.
+    CaughtUp {
+        stream: StreamName,
+        number: EventNumber,
+    },
+    /// Sent once every stream of a `$all` subscription has sent its historical events,
+    /// or has reached the end of its range.
+    AllCaughtUp,
+    /// Sent once a history only subscription has sent the events that existed when
+    /// it was opened, `number` is the number of the first event that was not sent.
+    EndOfStream {
+        stream: StreamName,
+        number: EventNumber,
+    },
+    /// Sent periodically while a live subscription receives no event,
+    /// `number` is the number of the next event that will be appended to the stream.
+    Heartbeat {
+        stream: StreamName,
+        number: EventNumber,
     },
     LastEventNumber {
         stream: StreamName,
@@ -21,12 +60,83 @@ pub enum Response {
     StreamNames {
         streams: Vec<StreamName>,
     },
+    StreamSize {
+        stream: StreamName,
+        bytes: u64,
+    },
+    TotalSize {
+        bytes: u64,
+    },
+    /// The number of active subscriptions of each stream.
+    Subscriptions {
+        subscriptions: Vec<(StreamName, u64)>,
+    },
+    /// Sent in response to a publish that specified a dedup id,
+    /// `number` is the number given to the event when it was first published.
+    Published {
+        stream: StreamName,
+        number: EventNumber,
+    },
+    /// Sent in response to a `Request::PublishMulti`, the number given
+    /// to each event in the order the events were sent.
+    PublishedMulti {
+        events: Vec<(StreamName, EventNumber)>,
+    },
+    /// A batch of events of an exported stream.
+    Exported {
+        stream: StreamName,
+        blob: Vec<u8>,
+    },
+    /// The result of a stream verification, `events` is the number of events read,
+    /// `missing` contains the ranges of event numbers that are missing (`to` is exclusive).
+    Verified {
+        stream: StreamName,
+        events: u64,
+        corrupted: Vec<EventNumber>,
+        missing: Vec<(EventNumber, EventNumber)>,
+    },
+    /// The version of the server, its uptime in seconds, its number of streams,
+    /// the number of events published in all of them and the enabled features.
+    ///
+    /// `published_events` counts every event ever published, including the ones
+    /// since removed by the retention, the expiration or a compaction.
+    Info {
+        version: String,
+        uptime: u64,
+        streams: u64,
+        published_events: u64,
+        features: Vec<String>,
+    },
+    /// The latest snapshot of a stream, it contains the state folded up to the event `number`.
+    Snapshot {
+        stream: StreamName,
+        number: EventNumber,
+        data: Vec<u8>,
+    },
+    /// The result of a `Request::Compact`, the number of events that were deleted
+    /// and the number of bytes their keys and values were using.
+    Compacted {
+        stream: StreamName,
+        events: u64,
+        bytes: u64,
+    },
+    /// The events read for a `Request::Page`, `next` is the number of the event
+    /// the next page starts at, it is `None` once the end of the stream is reached.
+    ///
+    /// The events are `Response::Event`s, like the ones sent to subscriptions.
+    Page {
+        stream: StreamName,
+        events: Vec<Response>,
+        next: Option<EventNumber>,
+    },
 }
 
 impl Into<RespValue> for Response {
     fn into(self) -> RespValue {
         match self {
             Response::Ok => RespValue::string("OK"),
+            Response::Nil => RespValue::Nil,
+            Response::Pong => RespValue::string("PONG"),
             Response::Subscribed { stream } => RespValue::Array(vec![
                 RespValue::string("subscribed"),
                 RespValue::string(stream),
@@ -36,12 +146,47 @@ impl Into<RespValue> for Response {
                 number,
                 event_name,
                 event_data,
-            } => RespValue::Array(vec![
-                RespValue::string("event"),
+                global,
+                headers,
+                redacted,
+                timestamp,
+            } => {
+                let kind = if redacted { "redacted-event" } else { "event" };
+                let mut args = vec![
+                    RespValue::string(kind),
+                    RespValue::string(stream),
+                    RespValue::Integer(number.0 as i64),
+                    RespValue::string(event_name),
+                    RespValue::bulk_string(event_data.0),
+                ];
+                let global = global.map(|global| RespValue::Integer(global.0 as i64));
+                match timestamp {
+                    Some(timestamp) => {
+                        args.push(global.unwrap_or(RespValue::Nil));
+                        args.push(RespValue::Integer(timestamp as i64));
+                    }
+                    None => args.extend(global),
+                }
+                if !headers.is_empty() {
+                    args.push(RespValue::string_pairs(headers));
+                }
+                RespValue::Array(args)
+            }
+            Response::CaughtUp { stream, number } => RespValue::Array(vec![
+                RespValue::string("caught-up"),
+                RespValue::string(stream),
+                RespValue::Integer(number.0 as i64),
+            ]),
+            Response::AllCaughtUp => RespValue::Array(vec![RespValue::string("all-caught-up")]),
+            Response::EndOfStream { stream, number } => RespValue::Array(vec![
+                RespValue::string("end-of-stream"),
+                RespValue::string(stream),
+                RespValue::Integer(number.0 as i64),
+            ]),
+            Response::Heartbeat { stream, number } => RespValue::Array(vec![
+                RespValue::string("heartbeat"),
                 RespValue::string(stream),
                 RespValue::Integer(number.0 as i64),
-                RespValue::string(event_name),
-                RespValue::bulk_string(event_data.0),
             ]),
             Response::LastEventNumber { stream, number } => {
                 let number = match number {
@@ -63,6 +208,114 @@ impl Into<RespValue> for Response {
                 let args = Some(command).into_iter().chain(streams).collect();
                 RespValue::Array(args)
             }
+            Response::StreamSize { stream, bytes } => RespValue::Array(vec![
+                RespValue::string("stream-size"),
+                RespValue::string(stream),
+                RespValue::Integer(bytes as i64),
+            ]),
+            Response::TotalSize { bytes } => RespValue::Array(vec![
+                RespValue::string("total-size"),
+                RespValue::Integer(bytes as i64),
+            ]),
+            Response::Subscriptions { subscriptions } => {
+                let mut args = vec![RespValue::string("subscriptions")];
+                for (stream, count) in subscriptions {
+                    args.push(RespValue::string(stream));
+                    args.push(RespValue::Integer(count as i64));
+                }
+                RespValue::Array(args)
+            }
+            Response::Published { stream, number } => RespValue::Array(vec![
+                RespValue::string("published"),
+                RespValue::string(stream),
+                RespValue::Integer(number.0 as i64),
+            ]),
+            Response::PublishedMulti { events } => {
+                let mut args = vec![RespValue::string("published-multi")];
+                for (stream, number) in events {
+                    args.push(RespValue::string(stream));
+                    args.push(RespValue::Integer(number.0 as i64));
+                }
+                RespValue::Array(args)
+            }
+            Response::Exported { stream, blob } => RespValue::Array(vec![
+                RespValue::string("exported"),
+                RespValue::string(stream),
+                RespValue::bulk_string(blob),
+            ]),
+            Response::Verified {
+                stream,
+                events,
+                corrupted,
+                missing,
+            } => {
+                let number = |n: EventNumber| RespValue::Integer(n.0 as i64);
+                let corrupted = corrupted.into_iter().map(number).collect();
+                let missing = missing
+                    .into_iter()
+                    .flat_map(|(from, to)| vec![number(from), number(to)])
+                    .collect();
+
+                RespValue::Array(vec![
+                    RespValue::string("verified"),
+                    RespValue::string(stream),
+                    RespValue::Integer(events as i64),
+                    RespValue::Array(corrupted),
+                    RespValue::Array(missing),
+                ])
+            }
+            Response::Info {
+                version,
+                uptime,
+                streams,
+                published_events,
+                features,
+            } => RespValue::Array(vec![
+                RespValue::string("info"),
+                RespValue::string(version),
+                RespValue::Integer(uptime as i64),
+                RespValue::Integer(streams as i64),
+                RespValue::Integer(published_events as i64),
+                RespValue::Array(features.into_iter().map(RespValue::string).collect()),
+            ]),
+            Response::Snapshot {
+                stream,
+                number,
+                data,
+            } => RespValue::Array(vec![
+                RespValue::string("snapshot"),
+                RespValue::string(stream),
+                RespValue::Integer(number.0 as i64),
+                RespValue::bulk_string(data),
+            ]),
+            Response::Compacted {
+                stream,
+                events,
+                bytes,
+            } => RespValue::Array(vec![
+                RespValue::string("compacted"),
+                RespValue::string(stream),
+                RespValue::Integer(events as i64),
+                RespValue::Integer(bytes as i64),
+            ]),
+            Response::Page {
+                stream,
+                events,
+                next,
+            } => {
+                let events = events.into_iter().map(Into::into).collect();
+                let next = match next {
+                    Some(next) => RespValue::Integer(next.0 as i64),
+                    None => RespValue::Nil,
+                };
+
+                RespValue::Array(vec![
+                    RespValue::string("page"),
+                    RespValue::string(stream),
+                    RespValue::Array(events),
+                    next,
+                ])
+            }
         }
     }
 }
@@ -91,6 +344,21 @@ impl fmt::Display for RespResponseConvertError {
     }
 }
 
+impl std::error::Error for RespResponseConvertError {}
+
+impl From<RespTupleConvertError> for RespResponseConvertError {
+    fn from(error: RespTupleConvertError) -> RespResponseConvertError {
+        use RespResponseConvertError::*;
+        match error {
+            RespTupleConvertError::InvalidArity { expected, found } if found < expected => {
+                MissingArgument
+            }
+            RespTupleConvertError::InvalidArity { .. } => TooManyArguments,
+            _otherwise => InvalidArgumentRespType,
+        }
+    }
+}
+
 impl FromResp for Response {
     type Error = RespResponseConvertError;
 
@@ -99,6 +367,8 @@ impl FromResp for Response {
 
         let mut iter = match value {
             RespValue::SimpleString(ref text) if text == "OK" => return Ok(Response::Ok),
+            RespValue::SimpleString(ref text) if text == "PONG" => return Ok(Response::Pong),
+            RespValue::Nil | RespValue::NullArray => return Ok(Response::Nil),
             RespValue::Array(array) => array.into_iter(),
             _otherwise => return Err(InvalidResponseRespType),
         };
@@ -123,7 +393,55 @@ impl FromResp for Response {
 
                 Ok(Response::Subscribed { stream })
             }
-            "event" => {
+            "event" | "redacted-event" => {
+                // The global number is an optional fifth argument, it is nil when only
+                // the timestamp follows it. The headers are an optional last argument,
+                // the only one sent as an array.
+                let mut arguments: Vec<_> = iter.collect();
+                let headers = match arguments.last() {
+                    Some(RespValue::Array(_)) => {
+                        let headers = HashMap::from_resp(arguments.pop().unwrap());
+                        headers.map_err(|_| InvalidArgumentRespType)?
+                    }
+                    _ => HashMap::new(),
+                };
+
+                let timestamp = match arguments.len() {
+                    6 => {
+                        let timestamp = i64::from_resp(arguments.pop().unwrap());
+                        Some(timestamp.map_err(|_| InvalidArgumentRespType)? as u64)
+                    }
+                    _ => None,
+                };
+
+                let global = match arguments.len() {
+                    5 => {
+                        let global = Option::<EventNumber>::from_resp(arguments.pop().unwrap());
+                        global.map_err(|_| InvalidArgumentRespType)?
+                    }
+                    _ => None,
+                };
+
+                let arguments = RespValue::Array(arguments);
+                let (stream, number, event_name, event_data): (
+                    StreamName,
+                    EventNumber,
+                    EventName,
+                    EventData,
+                ) = FromResp::from_resp(arguments)?;
+
+                Ok(Response::Event {
+                    stream,
+                    number,
+                    event_name,
+                    event_data,
+                    global,
+                    headers,
+                    redacted: response_type == "redacted-event",
+                    timestamp,
+                })
+            }
+            "caught-up" => {
                 let stream = iter
                     .next()
                     .map(StreamName::from_resp)
@@ -136,39 +454,139 @@ impl FromResp for Response {
                     .ok_or(MissingArgument)?
                     .map_err(|_| InvalidArgumentRespType)?;
 
-                let event_name = iter
-                    .next()
-                    .map(EventName::from_resp)
-                    .ok_or(MissingArgument)?
-                    .map_err(|_| InvalidArgumentRespType)?;
-
-                let event_data = iter
-                    .next()
-                    .map(EventData::from_resp)
-                    .ok_or(MissingArgument)?
-                    .map_err(|_| InvalidArgumentRespType)?;
+                if iter.next().is_some() {
+                    return Err(TooManyArguments);
+                }
 
+                Ok(Response::CaughtUp { stream, number })
+            }
+            "all-caught-up" => {
                 if iter.next().is_some() {
                     return Err(TooManyArguments);
                 }
 
-                Ok(Response::Event {
+                Ok(Response::AllCaughtUp)
+            }
+            "end-of-stream" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (stream, number): (StreamName, EventNumber) = FromResp::from_resp(arguments)?;
+
+                Ok(Response::EndOfStream { stream, number })
+            }
+            "heartbeat" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (stream, number): (StreamName, EventNumber) = FromResp::from_resp(arguments)?;
+
+                Ok(Response::Heartbeat { stream, number })
+            }
+            "last-event-number" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (stream, number): (StreamName, Option<EventNumber>) =
+                    FromResp::from_resp(arguments)?;
+
+                Ok(Response::LastEventNumber { stream, number })
+            }
+            "published" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (stream, number): (StreamName, EventNumber) = FromResp::from_resp(arguments)?;
+
+                Ok(Response::Published { stream, number })
+            }
+            "exported" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (stream, blob): (StreamName, Vec<u8>) = FromResp::from_resp(arguments)?;
+
+                Ok(Response::Exported { stream, blob })
+            }
+            "verified" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (stream, events, corrupted, missing): (
+                    StreamName,
+                    i64,
+                    Vec<EventNumber>,
+                    Vec<EventNumber>,
+                ) = FromResp::from_resp(arguments)?;
+
+                if missing.len() % 2 != 0 {
+                    return Err(InvalidArgumentRespType);
+                }
+
+                Ok(Response::Verified {
+                    stream,
+                    events: events as u64,
+                    corrupted,
+                    missing: missing.chunks(2).map(|r| (r[0], r[1])).collect(),
+                })
+            }
+            "info" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (version, uptime, streams, published_events, features): (
+                    String,
+                    i64,
+                    i64,
+                    i64,
+                    Vec<String>,
+                ) = FromResp::from_resp(arguments)?;
+
+                Ok(Response::Info {
+                    version,
+                    uptime: uptime as u64,
+                    streams: streams as u64,
+                    published_events: published_events as u64,
+                    features,
+                })
+            }
+            "snapshot" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (stream, number, data): (StreamName, EventNumber, Vec<u8>) =
+                    FromResp::from_resp(arguments)?;
+
+                Ok(Response::Snapshot {
                     stream,
                     number,
-                    event_name,
-                    event_data,
+                    data,
                 })
             }
-            "last-event-number" => {
+            "compacted" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (stream, events, bytes): (StreamName, i64, i64) =
+                    FromResp::from_resp(arguments)?;
+
+                Ok(Response::Compacted {
+                    stream,
+                    events: events as u64,
+                    bytes: bytes as u64,
+                })
+            }
+            "page" => {
+                let arguments = RespValue::Array(iter.collect());
+                let (stream, events, next): (StreamName, Vec<Response>, Option<EventNumber>) =
+                    FromResp::from_resp(arguments)?;
+
+                if events.iter().any(|e| !matches!(e, Response::Event { .. })) {
+                    return Err(InvalidArgumentRespType);
+                }
+
+                Ok(Response::Page {
+                    stream,
+                    events,
+                    next,
+                })
+            }
+            "stream-names" => match iter.map(StreamName::from_resp).collect() {
+                Ok(streams) => Ok(Response::StreamNames { streams }),
+                Err(_) => Err(InvalidArgumentRespType),
+            },
+            "stream-size" => {
                 let stream = iter
                     .next()
                     .map(StreamName::from_resp)
                     .ok_or(MissingArgument)?
                     .map_err(|_| InvalidArgumentRespType)?;
 
-                let number = iter
+                let bytes = iter
                     .next()
-                    .map(FromResp::from_resp)
+                    .map(i64::from_resp)
                     .ok_or(MissingArgument)?
                     .map_err(|_| InvalidArgumentRespType)?;
 
@@ -176,13 +594,266 @@ impl FromResp for Response {
                     return Err(TooManyArguments);
                 }
 
-                Ok(Response::LastEventNumber { stream, number })
+                Ok(Response::StreamSize {
+                    stream,
+                    bytes: bytes as u64,
+                })
+            }
+            "total-size" => {
+                let bytes = iter
+                    .next()
+                    .map(i64::from_resp)
+                    .ok_or(MissingArgument)?
+                    .map_err(|_| InvalidArgumentRespType)?;
+
+                if iter.next().is_some() {
+                    return Err(TooManyArguments);
+                }
+
+                Ok(Response::TotalSize {
+                    bytes: bytes as u64,
+                })
+            }
+            "subscriptions" => {
+                let mut subscriptions = Vec::new();
+                while let Some(stream) = iter.next() {
+                    let stream =
+                        StreamName::from_resp(stream).map_err(|_| InvalidArgumentRespType)?;
+
+                    let count = iter
+                        .next()
+                        .map(i64::from_resp)
+                        .ok_or(MissingArgument)?
+                        .map_err(|_| InvalidArgumentRespType)?;
+
+                    subscriptions.push((stream, count as u64));
+                }
+
+                Ok(Response::Subscriptions { subscriptions })
+            }
+            "published-multi" => {
+                let mut events = Vec::new();
+                while let Some(stream) = iter.next() {
+                    let stream =
+                        StreamName::from_resp(stream).map_err(|_| InvalidArgumentRespType)?;
+
+                    let number = iter
+                        .next()
+                        .map(EventNumber::from_resp)
+                        .ok_or(MissingArgument)?
+                        .map_err(|_| InvalidArgumentRespType)?;
+
+                    events.push((stream, number));
+                }
+
+                Ok(Response::PublishedMulti { events })
             }
-            "stream-names" => match iter.map(StreamName::from_resp).collect() {
-                Ok(streams) => Ok(Response::StreamNames { streams }),
-                Err(_) => Err(InvalidArgumentRespType),
-            },
             _otherwise => Err(UnknownTypeName),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn info_round_trip() {
+        let response = Response::Info {
+            version: String::from("0.2.0"),
+            uptime: 42,
+            streams: 3,
+            published_events: 1000,
+            features: vec![String::from("sentry")],
+        };
+
+        let value: RespValue = response.clone().into();
+        assert_eq!(Response::from_resp(value).unwrap(), response);
+    }
+
+    #[test]
+    fn compacted_round_trip() {
+        let response = Response::Compacted {
+            stream: StreamName::new(String::from("orders")).unwrap(),
+            events: 120,
+            bytes: 4096,
+        };
+
+        let value: RespValue = response.clone().into();
+        assert_eq!(Response::from_resp(value).unwrap(), response);
+    }
+
+    #[test]
+    fn all_caught_up_round_trip() {
+        let value: RespValue = Response::AllCaughtUp.into();
+        assert_eq!(Response::from_resp(value).unwrap(), Response::AllCaughtUp);
+    }
+
+    #[test]
+    fn end_of_stream_round_trip() {
+        let response = Response::EndOfStream {
+            stream: StreamName::new(String::from("orders")).unwrap(),
+            number: EventNumber(12),
+        };
+
+        let value: RespValue = response.clone().into();
+        assert_eq!(Response::from_resp(value).unwrap(), response);
+    }
+
+    #[test]
+    fn page_round_trip() {
+        let stream = StreamName::new(String::from("orders")).unwrap();
+        let mut headers = HashMap::new();
+        headers.insert(String::from("correlation-id"), String::from("42"));
+        let events = vec![
+            Response::Event {
+                stream: stream.clone(),
+                number: EventNumber(4),
+                event_name: EventName::new(String::from("created")).unwrap(),
+                event_data: EventData(b"{}".to_vec()),
+                global: None,
+                headers,
+                redacted: false,
+                timestamp: Some(1_571_000_000_000),
+            },
+            Response::Event {
+                stream: stream.clone(),
+                number: EventNumber(5),
+                event_name: EventName::new(String::from("paid")).unwrap(),
+                event_data: EventData(Vec::new()),
+                global: None,
+                headers: HashMap::new(),
+                redacted: true,
+                timestamp: None,
+            },
+        ];
+
+        for next in [None, Some(EventNumber(6))] {
+            let response = Response::Page {
+                stream: stream.clone(),
+                events: events.clone(),
+                next,
+            };
+
+            let value: RespValue = response.clone().into();
+            assert_eq!(Response::from_resp(value).unwrap(), response);
+        }
+
+        let value = RespValue::Array(vec![
+            RespValue::string("page"),
+            RespValue::string("orders"),
+            RespValue::Array(vec![RespValue::Array(vec![RespValue::string(
+                "all-caught-up",
+            )])]),
+            RespValue::Nil,
+        ]);
+        assert!(Response::from_resp(value).is_err());
+    }
+
+    #[test]
+    fn published_multi_round_trip() {
+        let response = Response::PublishedMulti {
+            events: vec![
+                (
+                    StreamName::new(String::from("orders")).unwrap(),
+                    EventNumber(7),
+                ),
+                (
+                    StreamName::new(String::from("audit")).unwrap(),
+                    EventNumber(0),
+                ),
+            ],
+        };
+
+        let value: RespValue = response.clone().into();
+        assert_eq!(Response::from_resp(value).unwrap(), response);
+    }
+
+    #[test]
+    fn pong_round_trip() {
+        let value: RespValue = Response::Pong.into();
+        assert_eq!(Response::from_resp(value).unwrap(), Response::Pong);
+    }
+
+    #[test]
+    fn event_global_number_round_trip() {
+        for global in [None, Some(EventNumber(42))] {
+            let response = Response::Event {
+                stream: StreamName::new(String::from("orders")).unwrap(),
+                number: EventNumber(3),
+                event_name: EventName::new(String::from("created")).unwrap(),
+                event_data: EventData(b"{}".to_vec()),
+                global,
+                headers: HashMap::new(),
+                redacted: false,
+                timestamp: None,
+            };
+
+            let value: RespValue = response.clone().into();
+            assert_eq!(Response::from_resp(value).unwrap(), response);
+        }
+    }
+
+    #[test]
+    fn event_timestamp_round_trip() {
+        let mut headers = HashMap::new();
+        headers.insert(String::from("correlation-id"), String::from("42"));
+
+        for global in [None, Some(EventNumber(42))] {
+            for headers in [HashMap::new(), headers.clone()] {
+                let response = Response::Event {
+                    stream: StreamName::new(String::from("orders")).unwrap(),
+                    number: EventNumber(3),
+                    event_name: EventName::new(String::from("created")).unwrap(),
+                    event_data: EventData(b"{}".to_vec()),
+                    global,
+                    headers,
+                    redacted: false,
+                    timestamp: Some(1_574_000_000_000),
+                };
+
+                let value: RespValue = response.clone().into();
+                assert_eq!(Response::from_resp(value).unwrap(), response);
+            }
+        }
+    }
+
+    #[test]
+    fn redacted_event_round_trip() {
+        let response = Response::Event {
+            stream: StreamName::new(String::from("orders")).unwrap(),
+            number: EventNumber(3),
+            event_name: EventName::new(String::from("created")).unwrap(),
+            event_data: EventData(Vec::new()),
+            global: None,
+            headers: HashMap::new(),
+            redacted: true,
+            timestamp: Some(1_574_000_000_000),
+        };
+
+        let value: RespValue = response.clone().into();
+        assert_eq!(Response::from_resp(value).unwrap(), response);
+    }
+
+    #[test]
+    fn event_headers_round_trip() {
+        let mut headers = HashMap::new();
+        headers.insert(String::from("correlation-id"), String::from("42"));
+
+        for global in [None, Some(EventNumber(42))] {
+            let response = Response::Event {
+                stream: StreamName::new(String::from("orders")).unwrap(),
+                number: EventNumber(3),
+                event_name: EventName::new(String::from("created")).unwrap(),
+                event_data: EventData(b"{}".to_vec()),
+                global,
+                headers: headers.clone(),
+                redacted: false,
+                timestamp: None,
+            };
+
+            let value: RespValue = response.clone().into();
+            assert_eq!(Response::from_resp(value).unwrap(), response);
+        }
+    }
+}
@@ -38,6 +38,18 @@ impl fmt::Display for RespMsgError {
     }
 }
 
+impl std::error::Error for RespMsgError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use RespMsgError::*;
+        match self {
+            InvalidInteger(error) => Some(error),
+            InvalidUtf8String(error) => Some(error),
+            IoError(error) => Some(error),
+            InvalidPrefixByte(_) | SimpleStringContainCrlf | MissingBulkStringFinalCrlf => None,
+        }
+    }
+}
+
 impl From<io::Error> for RespMsgError {
     fn from(error: io::Error) -> RespMsgError {
         RespMsgError::IoError(error)
@@ -140,7 +152,7 @@ fn decode_array(buf: &[u8]) -> Result<Option<(RespValue, usize)>, RespMsgError>
             let mut advance = bytes_string.len() + CRLF_NEWLINE.len();
 
             match length {
-                len if len < 0 => Ok(Some((RespValue::Nil, advance))),
+                len if len < 0 => Ok(Some((RespValue::NullArray, advance))),
                 _ => {
                     let mut array = Vec::with_capacity(length as usize);
                     for _ in 0..length {
@@ -281,6 +293,16 @@ impl Encoder for RespCodec {
                 buf.put(integer_string);
                 buf.put(&CRLF_NEWLINE[..]);
 
+                Ok(())
+            }
+            RespValue::NullArray => {
+                let integer_string = "-1";
+                buf.reserve(1 + integer_string.len() + CRLF_NEWLINE.len());
+
+                buf.put_u8(ARRAY_CHAR);
+                buf.put(integer_string);
+                buf.put(&CRLF_NEWLINE[..]);
+
                 Ok(())
             }
         }
@@ -401,6 +423,21 @@ mod tests {
         assert!(buf.is_empty());
     }
 
+    #[test]
+    fn null_bulk_string_and_null_array() {
+        let mut buf = BytesMut::from(&b"$-1\r\n*-1\r\n"[..]);
+
+        let nil = RespCodec.decode(&mut buf).unwrap();
+        let null_array = RespCodec.decode(&mut buf).unwrap();
+
+        assert_eq!(nil, Some(RespValue::Nil));
+        assert_eq!(null_array, Some(RespValue::NullArray));
+        assert!(buf.is_empty());
+
+        RespCodec.encode(RespValue::NullArray, &mut buf).unwrap();
+        assert_eq!(&buf[..], b"*-1\r\n");
+    }
+
     #[test]
     fn multiple_simple_string() {
         let mut buf = BytesMut::new();
@@ -1,5 +1,8 @@
 use super::RespValue;
+use std::collections::HashMap;
+use std::convert::TryInto;
 use std::fmt;
+use std::hash::Hash;
 use std::string::FromUtf8Error;
 
 pub trait FromResp: Sized {
@@ -34,6 +37,15 @@ impl fmt::Display for RespStringConvertError {
     }
 }
 
+impl std::error::Error for RespStringConvertError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RespStringConvertError::InvalidUtf8String(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 impl FromResp for String {
     type Error = RespStringConvertError;
 
@@ -63,6 +75,8 @@ impl fmt::Display for RespIntConvertError {
     }
 }
 
+impl std::error::Error for RespIntConvertError {}
+
 impl FromResp for i64 {
     type Error = RespIntConvertError;
 
@@ -90,6 +104,8 @@ impl fmt::Display for RespBytesConvertError {
     }
 }
 
+impl std::error::Error for RespBytesConvertError {}
+
 impl FromResp for Vec<u8> {
     type Error = RespBytesConvertError;
 
@@ -103,6 +119,45 @@ impl FromResp for Vec<u8> {
     }
 }
 
+#[derive(Debug)]
+pub enum RespArrayConvertError {
+    InvalidRespType,
+    InvalidLength { expected: usize, found: usize },
+}
+
+impl fmt::Display for RespArrayConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use RespArrayConvertError::*;
+        match self {
+            InvalidRespType => write!(
+                f,
+                "invalid RESP type found, expected String, Error or BulkString"
+            ),
+            InvalidLength { expected, found } => write!(
+                f,
+                "invalid number of bytes, expected {} but found {}",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RespArrayConvertError {}
+
+impl<const N: usize> FromResp for [u8; N] {
+    type Error = RespArrayConvertError;
+
+    fn from_resp(value: RespValue) -> Result<Self, Self::Error> {
+        use RespArrayConvertError::*;
+
+        let bytes = Vec::<u8>::from_resp(value).map_err(|_| InvalidRespType)?;
+        let found = bytes.len();
+        bytes
+            .try_into()
+            .map_err(|_| InvalidLength { expected: N, found })
+    }
+}
+
 #[derive(Debug)]
 pub enum RespVecConvertError<E> {
     InvalidRespType,
@@ -119,6 +174,15 @@ impl<E: fmt::Display> fmt::Display for RespVecConvertError<E> {
     }
 }
 
+impl<E: std::error::Error + 'static> std::error::Error for RespVecConvertError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RespVecConvertError::InnerRespConvertError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 impl<T: FromResp> FromResp for Vec<T> {
     type Error = RespVecConvertError<T::Error>;
 
@@ -135,12 +199,27 @@ impl<T: FromResp> FromResp for Vec<T> {
     }
 }
 
+/// A map is an array of `[key, value]` arrays, the last value of a key is kept.
+impl<K, V> FromResp for HashMap<K, V>
+where
+    K: FromResp + Eq + Hash,
+    V: FromResp,
+    K::Error: fmt::Display,
+    V::Error: fmt::Display,
+{
+    type Error = RespVecConvertError<RespTupleConvertError>;
+
+    fn from_resp(value: RespValue) -> Result<Self, Self::Error> {
+        Vec::<(K, V)>::from_resp(value).map(|pairs| pairs.into_iter().collect())
+    }
+}
+
 impl<T: FromResp> FromResp for Option<T> {
     type Error = T::Error;
 
     fn from_resp(value: RespValue) -> Result<Self, Self::Error> {
         match value {
-            RespValue::Nil => Ok(None),
+            RespValue::Nil | RespValue::NullArray => Ok(None),
             other => T::from_resp(other).map(Some),
         }
     }
@@ -156,3 +235,172 @@ impl<T: FromResp> FromResp for Result<T, String> {
         }
     }
 }
+
+#[derive(Debug)]
+pub enum RespTupleConvertError {
+    InvalidRespType,
+    InvalidArity { expected: usize, found: usize },
+    InnerRespConvertError { index: usize, error: String },
+}
+
+impl fmt::Display for RespTupleConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use RespTupleConvertError::*;
+        match self {
+            InvalidRespType => write!(f, "invalid RESP type found, expected Array"),
+            InvalidArity { expected, found } => write!(
+                f,
+                "invalid number of elements, expected {} but found {}",
+                expected, found
+            ),
+            InnerRespConvertError { index, error } => {
+                write!(
+                    f,
+                    "inner RESP type convertion error at {}: {}",
+                    index, error
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for RespTupleConvertError {}
+
+macro_rules! impl_from_resp_for_tuple {
+    ($len:expr => $($index:tt $name:ident),+) => {
+        impl<$($name: FromResp),+> FromResp for ($($name,)+)
+        where
+            $(<$name as FromResp>::Error: fmt::Display),+
+        {
+            type Error = RespTupleConvertError;
+
+            fn from_resp(value: RespValue) -> Result<Self, Self::Error> {
+                use RespTupleConvertError::*;
+
+                let array = match value {
+                    RespValue::Array(array) => array,
+                    _ => return Err(InvalidRespType),
+                };
+
+                if array.len() != $len {
+                    return Err(InvalidArity { expected: $len, found: array.len() });
+                }
+
+                let mut iter = array.into_iter();
+                Ok(($(
+                    $name::from_resp(iter.next().unwrap()).map_err(|e| InnerRespConvertError {
+                        index: $index,
+                        error: e.to_string(),
+                    })?,
+                )+))
+            }
+        }
+    };
+}
+
+impl_from_resp_for_tuple!(1 => 0 A);
+impl_from_resp_for_tuple!(2 => 0 A, 1 B);
+impl_from_resp_for_tuple!(3 => 0 A, 1 B, 2 C);
+impl_from_resp_for_tuple!(4 => 0 A, 1 B, 2 C, 3 D);
+impl_from_resp_for_tuple!(5 => 0 A, 1 B, 2 C, 3 D, 4 E);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_from_pairs() {
+        let pairs = vec![("b", "2"), ("a", "1")]
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect::<HashMap<_, _>>();
+
+        let value = RespValue::string_pairs(pairs.clone());
+        assert_eq!(HashMap::<String, String>::from_resp(value).unwrap(), pairs);
+
+        let value = RespValue::Array(vec![RespValue::bulk_string("alone")]);
+        assert!(HashMap::<String, String>::from_resp(value).is_err());
+    }
+
+    #[test]
+    fn option_from_nulls() {
+        let from_resp = |value| Option::<i64>::from_resp(value).unwrap();
+
+        assert_eq!(from_resp(RespValue::Nil), None);
+        assert_eq!(from_resp(RespValue::NullArray), None);
+        assert_eq!(from_resp(RespValue::Integer(7)), Some(7));
+    }
+
+    #[test]
+    fn tuple_from_resp() {
+        let value = RespValue::Array(vec![
+            RespValue::bulk_string(&"hello"[..]),
+            RespValue::Integer(42),
+        ]);
+
+        let (text, number) = <(String, i64)>::from_resp(value).unwrap();
+        assert_eq!(text, "hello");
+        assert_eq!(number, 42);
+    }
+
+    #[test]
+    fn tuple_from_resp_wrong_arity() {
+        let value = RespValue::Array(vec![RespValue::Integer(42)]);
+
+        match <(String, i64)>::from_resp(value) {
+            Err(RespTupleConvertError::InvalidArity { expected, found }) => {
+                assert_eq!((expected, found), (2, 1))
+            }
+            otherwise => panic!("unexpected result {:?}", otherwise),
+        }
+
+        match <(String, i64)>::from_resp(RespValue::Integer(42)) {
+            Err(RespTupleConvertError::InvalidRespType) => (),
+            otherwise => panic!("unexpected result {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn tuple_from_resp_inner_error() {
+        let value = RespValue::Array(vec![
+            RespValue::bulk_string(&"hello"[..]),
+            RespValue::bulk_string(&"world"[..]),
+        ]);
+
+        match <(String, i64)>::from_resp(value) {
+            Err(RespTupleConvertError::InnerRespConvertError { index, .. }) => assert_eq!(index, 1),
+            otherwise => panic!("unexpected result {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn byte_array_from_resp() {
+        let value = RespValue::bulk_string(&b"abcd"[..]);
+        let bytes = <[u8; 4]>::from_resp(value).unwrap();
+        assert_eq!(&bytes, b"abcd");
+    }
+
+    #[test]
+    fn byte_array_from_resp_too_short() {
+        let value = RespValue::bulk_string(&b"abc"[..]);
+
+        match <[u8; 4]>::from_resp(value) {
+            Err(RespArrayConvertError::InvalidLength { expected, found }) => {
+                assert_eq!((expected, found), (4, 3))
+            }
+            otherwise => panic!("unexpected result {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn byte_array_from_resp_too_long() {
+        let value = RespValue::bulk_string(&b"abcde"[..]);
+
+        match <[u8; 4]>::from_resp(value) {
+            Err(RespArrayConvertError::InvalidLength { expected, found }) => {
+                assert_eq!((expected, found), (4, 5))
+            }
+            otherwise => panic!("unexpected result {:?}", otherwise),
+        }
+    }
+}
@@ -4,7 +4,7 @@ mod resp_value;
 
 pub use self::codec::{RespCodec, RespMsgError};
 pub use self::from_resp::{
-    FromResp, RespBytesConvertError, RespIntConvertError, RespStringConvertError,
-    RespVecConvertError,
+    FromResp, RespArrayConvertError, RespBytesConvertError, RespIntConvertError,
+    RespStringConvertError, RespTupleConvertError, RespVecConvertError,
 };
 pub use self::resp_value::RespValue;
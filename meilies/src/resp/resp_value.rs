@@ -1,5 +1,9 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::{fmt, str};
 
+use super::{FromResp, RespStringConvertError};
+
 #[derive(Clone, PartialEq, Eq)]
 pub enum RespValue {
     SimpleString(String),
@@ -7,21 +11,79 @@ pub enum RespValue {
     Integer(i64),
     BulkString(Vec<u8>),
     Array(Vec<RespValue>),
+    /// The null bulk string (`$-1`), it is what the nil values are encoded as.
     Nil,
+    /// The null array (`*-1`), decoded as is for the clients that tell it apart
+    /// from the null bulk string, both are considered as `None` by `FromResp`.
+    NullArray,
 }
 
 impl RespValue {
+    /// A simple string, the displayed text must not contain any CR or LF character.
     pub fn string(string: impl fmt::Display) -> RespValue {
         RespValue::SimpleString(string.to_string())
     }
 
+    /// An error message, the displayed text must not contain any CR or LF character.
     pub fn error(string: impl fmt::Display) -> RespValue {
         RespValue::Error(string.to_string())
     }
 
+    /// A binary safe string, it can contain any byte.
     pub fn bulk_string(string: impl Into<Vec<u8>>) -> RespValue {
         RespValue::BulkString(string.into())
     }
+
+    /// An array of `[key, value]` arrays of bulk strings, sorted by key.
+    pub fn string_pairs(pairs: HashMap<String, String>) -> RespValue {
+        let mut pairs: Vec<_> = pairs.into_iter().collect();
+        pairs.sort();
+
+        let pairs = pairs
+            .into_iter()
+            .map(|(key, value)| {
+                RespValue::Array(vec![
+                    RespValue::bulk_string(key),
+                    RespValue::bulk_string(value),
+                ])
+            })
+            .collect();
+
+        RespValue::Array(pairs)
+    }
+
+    /// Returns the text of a simple string, an error or a UTF-8 bulk string.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            RespValue::SimpleString(string) | RespValue::Error(string) => Some(string),
+            RespValue::BulkString(bytes) => str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the bytes of a simple string, an error or a bulk string.
+    pub fn into_bytes(self) -> Option<Vec<u8>> {
+        match self {
+            RespValue::SimpleString(string) | RespValue::Error(string) => Some(string.into_bytes()),
+            RespValue::BulkString(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            RespValue::Integer(integer) => Some(*integer),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<RespValue> for String {
+    type Error = RespStringConvertError;
+
+    fn try_from(value: RespValue) -> Result<String, Self::Error> {
+        String::from_resp(value)
+    }
 }
 
 impl PartialEq<&'_ str> for RespValue {
@@ -37,7 +99,7 @@ impl PartialEq<&'_ str> for RespValue {
 
 impl PartialEq<str> for RespValue {
     fn eq(&self, other: &str) -> bool {
-        self == &other
+        *self == other
     }
 }
 
@@ -67,6 +129,52 @@ impl fmt::Debug for RespValue {
             }
             RespValue::Array(elements) => fmt.debug_tuple("Array").field(&elements).finish(),
             RespValue::Nil => fmt.debug_tuple("Nil").finish(),
+            RespValue::NullArray => fmt.debug_tuple("NullArray").finish(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn accessors() {
+        let simple = RespValue::string("hello");
+        assert_eq!(simple.as_str(), Some("hello"));
+        assert_eq!(simple.as_integer(), None);
+        assert_eq!(simple.into_bytes(), Some(b"hello".to_vec()));
+
+        let error = RespValue::error("failed");
+        assert_eq!(error.as_str(), Some("failed"));
+        assert_eq!(error.into_bytes(), Some(b"failed".to_vec()));
+
+        let bulk = RespValue::bulk_string(&b"\xffdata"[..]);
+        assert_eq!(bulk.as_str(), None);
+        assert_eq!(bulk.into_bytes(), Some(b"\xffdata".to_vec()));
+
+        let integer = RespValue::Integer(42);
+        assert_eq!(integer.as_integer(), Some(42));
+        assert_eq!(integer.as_str(), None);
+        assert_eq!(integer.into_bytes(), None);
+
+        let array = RespValue::Array(vec![RespValue::Array(vec![RespValue::Integer(1)])]);
+        assert_eq!(array.as_str(), None);
+        assert_eq!(array.as_integer(), None);
+        assert_eq!(array.into_bytes(), None);
+
+        assert_eq!(RespValue::Nil.as_str(), None);
+        assert_eq!(RespValue::Nil.as_integer(), None);
+        assert_eq!(RespValue::Nil.into_bytes(), None);
+    }
+
+    #[test]
+    fn try_into_string() {
+        let string: String = RespValue::bulk_string(&b"hello"[..]).try_into().unwrap();
+        assert_eq!(string, "hello");
+
+        let result: Result<String, _> = RespValue::Nil.try_into();
+        assert!(result.is_err());
+    }
+}
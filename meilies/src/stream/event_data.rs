@@ -1,21 +1,251 @@
 use crate::resp::{FromResp, RespBytesConvertError, RespValue};
-use std::{fmt, str};
+use std::convert::Infallible;
+use std::str::FromStr;
+use std::{ascii, fmt, str};
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct EventData(pub Vec<u8>);
 
+impl EventData {
+    /// Create an event data, returns an error if it is bigger than `max` bytes.
+    pub fn with_max(bytes: Vec<u8>, max: usize) -> Result<EventData, EventDataError> {
+        if bytes.len() > max {
+            return Err(EventDataError::TooLarge {
+                size: bytes.len(),
+                max,
+            });
+        }
+
+        Ok(EventData(bytes))
+    }
+
+    /// Returns the data as a string slice if it is valid UTF-8.
+    pub fn as_utf8(&self) -> Option<&str> {
+        str::from_utf8(&self.0).ok()
+    }
+
+    /// Returns the data as a string slice if it is valid UTF-8, same as `as_utf8`.
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_utf8()
+    }
+
+    /// The number of bytes of the data.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the data as text, the data that is not valid UTF-8 is encoded.
+    pub fn to_text(&self, encoding: BinaryEncoding) -> String {
+        if let Some(text) = self.as_utf8() {
+            return text.to_owned();
+        }
+
+        match encoding {
+            BinaryEncoding::Base64 => base64(&self.0),
+            BinaryEncoding::Hex => self.0.iter().map(|b| format!("{:02x}", b)).collect(),
+            BinaryEncoding::Escape => {
+                let escaped = self.0.iter().flat_map(|b| ascii::escape_default(*b));
+                escaped.map(char::from).collect()
+            }
+        }
+    }
+
+    /// Serialize a value as JSON to create an event data.
+    #[cfg(feature = "json")]
+    pub fn from_json<T: serde::Serialize>(value: &T) -> Result<EventData, serde_json::Error> {
+        serde_json::to_vec(value).map(EventData)
+    }
+
+    /// Deserialize the data, it must be a JSON representation of `T`.
+    #[cfg(feature = "json")]
+    pub fn as_json<T: serde::de::DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_slice(&self.0)
+    }
+}
+
 impl fmt::Debug for EventData {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        let event = &self.0;
         let mut dbg = fmt.debug_tuple("EventData");
-        match str::from_utf8(event) {
-            Ok(event) => dbg.field(&event),
-            Err(_) => dbg.field(event),
+        match self.as_utf8() {
+            Some(event) => dbg.field(&event),
+            None => dbg.field(&self.0),
         };
         dbg.finish()
     }
 }
 
+impl FromStr for EventData {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<EventData, Self::Err> {
+        Ok(EventData::from(s))
+    }
+}
+
+impl From<String> for EventData {
+    fn from(text: String) -> EventData {
+        EventData(text.into_bytes())
+    }
+}
+
+impl From<&str> for EventData {
+    fn from(text: &str) -> EventData {
+        EventData(text.as_bytes().to_vec())
+    }
+}
+
+impl From<Vec<u8>> for EventData {
+    fn from(bytes: Vec<u8>) -> EventData {
+        EventData(bytes)
+    }
+}
+
+/// The encoding used to display event data that is not valid UTF-8.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BinaryEncoding {
+    Base64,
+    Hex,
+    Escape,
+}
+
+impl FromStr for BinaryEncoding {
+    type Err = ParseBinaryEncodingError;
+
+    fn from_str(s: &str) -> Result<BinaryEncoding, Self::Err> {
+        match s {
+            "base64" => Ok(BinaryEncoding::Base64),
+            "hex" => Ok(BinaryEncoding::Hex),
+            "escape" => Ok(BinaryEncoding::Escape),
+            _otherwise => Err(ParseBinaryEncodingError),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ParseBinaryEncodingError;
+
+impl fmt::Display for ParseBinaryEncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid binary encoding, expected base64, hex or escape")
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (n >> (18 - 6 * i)) & 0b11_1111;
+                text.push(char::from(BASE64_ALPHABET[index as usize]));
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    text
+}
+
+/// Decodes padded base64 as described in RFC 4648, rejecting misplaced
+/// padding and non-zero trailing bits so that every text has one decoding.
+#[cfg(feature = "serde")]
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.as_bytes();
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let mut chunks = text.chunks(4).peekable();
+    while let Some(chunk) = chunks.next() {
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && chunks.peek().is_some()) {
+            return None;
+        }
+
+        let mut n = 0u32;
+        for (i, c) in chunk[..4 - padding].iter().enumerate() {
+            let index = BASE64_ALPHABET.iter().position(|a| a == c)? as u32;
+            n |= index << (18 - 6 * i);
+        }
+
+        let decoded = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+        let len = 3 - padding;
+        if decoded[len..].iter().any(|&b| b != 0) {
+            return None;
+        }
+        bytes.extend_from_slice(&decoded[..len]);
+    }
+
+    Some(bytes)
+}
+
+/// Event data is serialized as a string when it is valid UTF-8,
+/// otherwise as a `{ "base64": "..." }` map to keep it unambiguous.
+#[cfg(feature = "serde")]
+impl serde::Serialize for EventData {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        match self.as_utf8() {
+            Some(text) => serializer.serialize_str(text),
+            None => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("base64", &base64(&self.0))?;
+                map.end()
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for EventData {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{self, MapAccess, Visitor};
+
+        struct EventDataVisitor;
+
+        impl<'de> Visitor<'de> for EventDataVisitor {
+            type Value = EventData;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string or a map with a base64 entry")
+            }
+
+            fn visit_str<E: de::Error>(self, text: &str) -> Result<EventData, E> {
+                Ok(EventData(text.as_bytes().to_vec()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<EventData, A::Error> {
+                match map.next_entry::<String, String>()? {
+                    Some((ref key, ref text)) if key == "base64" => match decode_base64(text) {
+                        Some(bytes) => Ok(EventData(bytes)),
+                        None => Err(de::Error::custom("invalid base64 event data")),
+                    },
+                    Some((key, _)) => Err(de::Error::unknown_field(&key, &["base64"])),
+                    None => Err(de::Error::missing_field("base64")),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(EventDataVisitor)
+    }
+}
+
 impl FromResp for EventData {
     type Error = RespBytesConvertError;
 
@@ -28,3 +258,147 @@ impl FromResp for EventData {
         }
     }
 }
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventDataError {
+    TooLarge { size: usize, max: usize },
+}
+
+impl fmt::Display for EventDataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EventDataError::TooLarge { size, max } => write!(
+                f,
+                "event data of {} bytes exceeds max size of {} bytes",
+                size, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EventDataError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_encodings() {
+        let data = EventData(vec![0xff, 0x00, b'a', 0x10]);
+
+        assert_eq!(data.to_text(BinaryEncoding::Base64), "/wBhEA==");
+        assert_eq!(data.to_text(BinaryEncoding::Hex), "ff006110");
+        assert_eq!(data.to_text(BinaryEncoding::Escape), "\\xff\\x00a\\x10");
+    }
+
+    #[test]
+    fn utf8_is_not_encoded() {
+        let data = EventData(b"hello".to_vec());
+        assert_eq!(data.to_text(BinaryEncoding::Hex), "hello");
+
+        assert_eq!(EventData(b"ab".to_vec()).as_utf8(), Some("ab"));
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"abc"), "YWJj");
+    }
+
+    // The test vectors of RFC 4648, section 10.
+    const RFC_4648_VECTORS: &[(&str, &str)] = &[
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ];
+
+    #[test]
+    fn base64_rfc_4648_vectors() {
+        for (bytes, text) in RFC_4648_VECTORS {
+            assert_eq!(base64(bytes.as_bytes()), *text);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn decode_base64_rfc_4648_vectors() {
+        for (bytes, text) in RFC_4648_VECTORS {
+            assert_eq!(decode_base64(text).as_deref(), Some(bytes.as_bytes()));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn decode_base64_padding_errors() {
+        // missing padding
+        assert_eq!(decode_base64("Zg"), None);
+        assert_eq!(decode_base64("Zm8"), None);
+        // too much padding
+        assert_eq!(decode_base64("Zg==="), None);
+        assert_eq!(decode_base64("Z==="), None);
+        assert_eq!(decode_base64("===="), None);
+        // padding before the end
+        assert_eq!(decode_base64("Zg==Zg=="), None);
+        assert_eq!(decode_base64("Zm=v"), None);
+        // non-zero trailing bits
+        assert_eq!(decode_base64("Zh=="), None);
+        assert_eq!(decode_base64("Zm9="), None);
+        // outside of the alphabet
+        assert_eq!(decode_base64("Zm9-"), None);
+    }
+
+    #[test]
+    fn conversions() {
+        let data = EventData::from("hello");
+        assert_eq!(data, EventData::from(String::from("hello")));
+        assert_eq!(data, EventData::from(b"hello".to_vec()));
+        assert_eq!(data, "hello".parse().unwrap());
+        assert_eq!(data.len(), 5);
+        assert!(!data.is_empty());
+        assert!(EventData::from("").is_empty());
+    }
+
+    #[test]
+    fn as_str_utf8_and_binary() {
+        assert_eq!(EventData::from("héllo").as_str(), Some("héllo"));
+        assert_eq!(EventData::from(vec![0xff, 0x00, b'a']).as_str(), None);
+        assert_eq!(EventData::from(Vec::new()).as_str(), Some(""));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_event_data() {
+        let text = EventData(b"hello".to_vec());
+        let json = serde_json::to_string(&text).unwrap();
+        assert_eq!(json, r#""hello""#);
+        assert_eq!(serde_json::from_str::<EventData>(&json).unwrap(), text);
+
+        let binary = EventData(vec![0xff, 0x00, b'a', 0x10]);
+        let json = serde_json::to_string(&binary).unwrap();
+        assert_eq!(json, r#"{"base64":"/wBhEA=="}"#);
+        assert_eq!(serde_json::from_str::<EventData>(&json).unwrap(), binary);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_helpers() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Order {
+            id: u32,
+            item: String,
+        }
+
+        let order = Order {
+            id: 7,
+            item: String::from("coffee"),
+        };
+        let data = EventData::from_json(&order).unwrap();
+        assert_eq!(data.as_str(), Some(r#"{"id":7,"item":"coffee"}"#));
+        assert_eq!(data.as_json::<Order>().unwrap(), order);
+
+        let malformed = EventData::from(r#"{"id":7,"item":"#);
+        assert!(malformed.as_json::<Order>().is_err());
+        let mistyped = EventData::from(r#"{"id":"seven","item":"coffee"}"#);
+        assert!(mistyped.as_json::<Order>().is_err());
+    }
+}
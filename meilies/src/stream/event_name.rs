@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::fmt;
 use std::str::FromStr;
 use std::string::FromUtf8Error;
@@ -5,6 +6,11 @@ use std::string::FromUtf8Error;
 use crate::resp::{FromResp, RespStringConvertError, RespValue};
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String")
+)]
 pub struct EventName(String);
 
 impl EventName {
@@ -16,6 +22,18 @@ impl EventName {
         Ok(EventName(name))
     }
 
+    /// Create an event name from a name known at compile time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is empty, use `new` for the names that are not known in advance.
+    pub fn from_static(name: &'static str) -> EventName {
+        match EventName::new(name.to_owned()) {
+            Ok(name) => name,
+            Err(e) => panic!("invalid static event name {:?}; {}", name, e),
+        }
+    }
+
     pub fn into_inner(self) -> String {
         self.0
     }
@@ -29,6 +47,22 @@ impl EventName {
     }
 }
 
+impl TryFrom<String> for EventName {
+    type Error = EventNameError;
+
+    fn try_from(name: String) -> Result<EventName, EventNameError> {
+        EventName::new(name)
+    }
+}
+
+impl TryFrom<&str> for EventName {
+    type Error = EventNameError;
+
+    fn try_from(name: &str) -> Result<EventName, EventNameError> {
+        EventName::new(name.to_owned())
+    }
+}
+
 impl fmt::Display for EventName {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.0.fmt(f)
@@ -53,6 +87,17 @@ impl fmt::Display for RespEventNameConvertError {
     }
 }
 
+impl std::error::Error for RespEventNameConvertError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use RespEventNameConvertError::*;
+        match self {
+            InvalidRespType => None,
+            InvalidUtf8String(e) => Some(e),
+            InnerEventNameConvertError(e) => Some(e),
+        }
+    }
+}
+
 impl FromResp for EventName {
     type Error = RespEventNameConvertError;
     fn from_resp(value: RespValue) -> Result<Self, Self::Error> {
@@ -87,3 +132,32 @@ impl fmt::Display for EventNameError {
 }
 
 impl std::error::Error for EventNameError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_from_rejects_empty_names() {
+        assert_eq!(EventName::try_from("created").unwrap().as_str(), "created");
+        assert_eq!(EventName::try_from(""), Err(EventNameError::EmptyName));
+        assert_eq!(
+            EventName::try_from(String::new()),
+            Err(EventNameError::EmptyName)
+        );
+    }
+
+    #[test]
+    fn from_static() {
+        assert_eq!(
+            EventName::from_static("created"),
+            "created".parse().unwrap()
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn from_static_panics_on_empty_name() {
+        EventName::from_static("");
+    }
+}
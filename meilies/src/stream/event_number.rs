@@ -3,6 +3,11 @@ use core::array::TryFromSliceError;
 use std::convert::TryFrom;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct EventNumber(pub u64);
 
 impl EventNumber {
@@ -4,11 +4,13 @@ mod event_number;
 mod raw_event;
 mod stream;
 mod stream_name;
+mod stream_settings;
 
-pub use self::event_data::EventData;
-pub use self::event_name::EventName;
+pub use self::event_data::{BinaryEncoding, EventData, EventDataError, ParseBinaryEncodingError};
+pub use self::event_name::{EventName, EventNameError, RespEventNameConvertError};
 pub use self::event_number::EventNumber;
-pub use self::raw_event::RawEvent;
-pub use self::stream::{ParseStreamError, ReadRange, Stream};
-pub use self::stream_name::ALL_STREAMS;
-pub use self::stream_name::{StreamName, StreamNameError};
+pub use self::raw_event::{RawEvent, RawEventError};
+pub use self::stream::{ParseStreamError, ReadRange, RespStreamConvertError, Stream};
+pub use self::stream_name::{RespStreamNameConvertError, StreamName, StreamNameError};
+pub use self::stream_name::{ALL_STREAMS, ALL_STREAMS_PREFIX};
+pub use self::stream_settings::StreamSettings;
@@ -1,40 +1,506 @@
-use std::error::Error;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::io;
+use std::string::FromUtf8Error;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use super::{EventData, EventName};
+use super::{EventData, EventName, EventNameError};
 
+/// The version of the events written by this version of the server.
+///
+/// Events written before were not versioned and start with the big endian
+/// length of their name, the first byte of these events is always zero.
+const VERSION: u8 = 1;
+
+/// The data of the event is compressed using zstd.
+const COMPRESSED_FLAG: u8 = 0b0000_0001;
+
+/// The event is the tombstone of a redacted event, it has no data and no headers.
+const REDACTED_FLAG: u8 = 0b0000_0010;
+
+const KNOWN_FLAGS: u8 = COMPRESSED_FLAG | REDACTED_FLAG;
+
+/// An event as it is stored, `version|flags|timestamp|headers|length|name|data|crc32`.
+///
+/// The flags tell how the data must be read, the timestamp is the time the event
+/// was published at in milliseconds since the UNIX epoch. The headers are the big
+/// endian length of the block followed by the `length|key` and `length|value` of
+/// every header, the CRC32 covers everything that precedes it.
+///
+/// The unversioned events written before are `length|name|data`, without
+/// flags, timestamp, headers nor checksum.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct RawEvent<T>(T);
 
+#[derive(Debug)]
+pub enum RawEventError {
+    Truncated,
+    UnknownVersion(u8),
+    ChecksumMismatch { expected: u32, found: u32 },
+    InvalidUtf8Name(FromUtf8Error),
+    InvalidName(EventNameError),
+    InvalidUtf8Header(FromUtf8Error),
+    UnknownFlags(u8),
+    CompressionUnsupported,
+    Decompression(io::Error),
+}
+
+impl fmt::Display for RawEventError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use RawEventError::*;
+        match self {
+            Truncated => f.write_str("raw event is truncated"),
+            UnknownVersion(version) => write!(f, "unknown raw event version {}", version),
+            ChecksumMismatch { expected, found } => write!(
+                f,
+                "raw event checksum mismatch, expected {:08x} but found {:08x}",
+                expected, found
+            ),
+            InvalidUtf8Name(e) => write!(f, "invalid UTF8 event name; {}", e),
+            InvalidName(e) => write!(f, "invalid event name; {}", e),
+            InvalidUtf8Header(e) => write!(f, "invalid UTF8 event header; {}", e),
+            UnknownFlags(flags) => write!(f, "unknown raw event flags {:08b}", flags),
+            CompressionUnsupported => f.write_str("raw event is compressed but zstd is disabled"),
+            Decompression(e) => write!(f, "raw event decompression failed; {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RawEventError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RawEventError::InvalidUtf8Name(e) => Some(e),
+            RawEventError::InvalidName(e) => Some(e),
+            RawEventError::InvalidUtf8Header(e) => Some(e),
+            RawEventError::Decompression(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl RawEvent<Vec<u8>> {
+    /// Encode an event stamped with the current time.
+    pub fn encode(event_name: &EventName, event_data: &EventData) -> RawEvent<Vec<u8>> {
+        RawEvent::encode_at(event_name, event_data, now_millis())
+    }
+
+    /// Encode an event, the timestamp is a number of milliseconds since the UNIX epoch.
+    pub fn encode_at(
+        event_name: &EventName,
+        event_data: &EventData,
+        timestamp: u64,
+    ) -> RawEvent<Vec<u8>> {
+        RawEvent::encode_parts(0, timestamp, &HashMap::new(), event_name, &event_data.0)
+    }
+
+    /// Encode an event along with its headers, stamped with the current time.
+    pub fn encode_with_headers(
+        event_name: &EventName,
+        event_data: &EventData,
+        headers: &HashMap<String, String>,
+    ) -> RawEvent<Vec<u8>> {
+        RawEvent::encode_with_headers_at(event_name, event_data, headers, now_millis())
+    }
+
+    /// Encode an event along with its headers, the timestamp is a number
+    /// of milliseconds since the UNIX epoch.
+    pub fn encode_with_headers_at(
+        event_name: &EventName,
+        event_data: &EventData,
+        headers: &HashMap<String, String>,
+        timestamp: u64,
+    ) -> RawEvent<Vec<u8>> {
+        RawEvent::encode_parts(0, timestamp, headers, event_name, &event_data.0)
+    }
+
+    /// Encode an event whose data is compressed with zstd at the given level,
+    /// stamped with the current time.
+    #[cfg(feature = "zstd")]
+    pub fn encode_compressed(
+        event_name: &EventName,
+        event_data: &EventData,
+        level: i32,
+    ) -> io::Result<RawEvent<Vec<u8>>> {
+        RawEvent::encode_compressed_with_headers(event_name, event_data, &HashMap::new(), level)
+    }
+
+    /// Encode an event and its headers, the data is compressed with zstd at the given level.
+    #[cfg(feature = "zstd")]
+    pub fn encode_compressed_with_headers(
+        event_name: &EventName,
+        event_data: &EventData,
+        headers: &HashMap<String, String>,
+        level: i32,
+    ) -> io::Result<RawEvent<Vec<u8>>> {
+        let compressed = zstd::encode_all(&event_data.0[..], level)?;
+        let timestamp = now_millis();
+        let raw_event =
+            RawEvent::encode_parts(COMPRESSED_FLAG, timestamp, headers, event_name, &compressed);
+        Ok(raw_event)
+    }
+
+    /// Encode the tombstone of a redacted event, stamped with the current time.
+    pub fn encode_redacted(event_name: &EventName) -> RawEvent<Vec<u8>> {
+        RawEvent::encode_redacted_at(event_name, now_millis())
+    }
+
+    /// Encode the tombstone of a redacted event, it keeps the name and
+    /// the timestamp of the event but neither its data nor its headers.
+    pub fn encode_redacted_at(event_name: &EventName, timestamp: u64) -> RawEvent<Vec<u8>> {
+        RawEvent::encode_parts(REDACTED_FLAG, timestamp, &HashMap::new(), event_name, &[])
+    }
+
+    fn encode_parts(
+        flags: u8,
+        timestamp: u64,
+        headers: &HashMap<String, String>,
+        event_name: &EventName,
+        raw_data: &[u8],
+    ) -> RawEvent<Vec<u8>> {
+        let raw_name = event_name.as_str().as_bytes();
+        let raw_length = (raw_name.len() as u64).to_be_bytes();
+        let raw_headers = encode_headers(headers);
+
+        let capacity = 1 + 1 + 8 + 8 + raw_headers.len() + 8 + raw_name.len() + raw_data.len() + 4;
+        let mut raw_event = Vec::with_capacity(capacity);
+        raw_event.push(VERSION);
+        raw_event.push(flags);
+        raw_event.extend_from_slice(&timestamp.to_be_bytes());
+        raw_event.extend_from_slice(&(raw_headers.len() as u64).to_be_bytes());
+        raw_event.extend_from_slice(&raw_headers);
+        raw_event.extend_from_slice(&raw_length);
+        raw_event.extend_from_slice(raw_name);
+        raw_event.extend_from_slice(raw_data);
+
+        let checksum = crc32fast::hash(&raw_event);
+        raw_event.extend_from_slice(&checksum.to_be_bytes());
+
+        RawEvent(raw_event)
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.0
+    }
+}
+
 impl<T: AsRef<[u8]>> RawEvent<T> {
     pub fn new(content: T) -> RawEvent<T> {
         RawEvent(content)
     }
 
-    fn name_size(&self) -> usize {
-        // FIXME: prefer using TryFrom
-        let mut event_name_size: [u8; 8] = [0; 8];
-        for (i, b) in self.0.as_ref().iter().enumerate() {
-            if i == 8 {
-                break;
+    /// Returns the flags, the timestamp, the raw headers, the raw name and the raw data
+    /// of the event, the checksum is verified if there is one.
+    fn parts(&self) -> Result<Parts<'_>, RawEventError> {
+        let bytes = self.0.as_ref();
+
+        let (flags, timestamp, headers, content) = match bytes.first() {
+            Some(0) => (0, None, &[][..], bytes),
+            Some(&VERSION) => {
+                let (flags, content) = match checked_content(bytes)?.split_first() {
+                    Some((&flags, content)) => (flags, content),
+                    None => return Err(RawEventError::Truncated),
+                };
+                if flags & !KNOWN_FLAGS != 0 {
+                    return Err(RawEventError::UnknownFlags(flags));
+                }
+                let (timestamp, content) = split_timestamp(content)?;
+                let (headers, content) = split_sized(content)?;
+                (flags, Some(timestamp), headers, content)
             }
-            event_name_size[i] = *b;
+            Some(version) => return Err(RawEventError::UnknownVersion(*version)),
+            None => return Err(RawEventError::Truncated),
+        };
+
+        let (name, data) = split_sized(content)?;
+        Ok((flags, timestamp, headers, name, data))
+    }
+
+    /// Check that the event is not corrupted.
+    pub fn verify(&self) -> Result<(), RawEventError> {
+        self.decode().map(drop)
+    }
+
+    /// Returns the name and the data of the event, verifying the checksum only once.
+    pub fn decode(&self) -> Result<(EventName, EventData), RawEventError> {
+        self.decode_with_headers()
+            .map(|(name, data, _)| (name, data))
+    }
+
+    /// Returns the name, the data and the headers of the event,
+    /// the events stored without headers have none.
+    pub fn decode_with_headers(
+        &self,
+    ) -> Result<(EventName, EventData, HashMap<String, String>), RawEventError> {
+        let (flags, _, raw_headers, raw_name, raw_data) = self.parts()?;
+        let headers = decode_headers(raw_headers)?;
+        let name =
+            String::from_utf8(raw_name.to_owned()).map_err(RawEventError::InvalidUtf8Name)?;
+        let name = EventName::new(name).map_err(RawEventError::InvalidName)?;
+
+        let data = if flags & COMPRESSED_FLAG != 0 {
+            decompress(raw_data)?
+        } else {
+            raw_data.to_owned()
+        };
+
+        Ok((name, EventData(data), headers))
+    }
+
+    /// Returns `true` if the data of the event is stored compressed.
+    pub fn is_compressed(&self) -> Result<bool, RawEventError> {
+        self.parts()
+            .map(|(flags, _, _, _, _)| flags & COMPRESSED_FLAG != 0)
+    }
+
+    /// Returns `true` if the event is the tombstone of a redacted event.
+    pub fn is_redacted(&self) -> Result<bool, RawEventError> {
+        self.parts()
+            .map(|(flags, _, _, _, _)| flags & REDACTED_FLAG != 0)
+    }
+
+    pub fn name(&self) -> Result<EventName, RawEventError> {
+        self.decode().map(|(name, _)| name)
+    }
+
+    pub fn data(&self) -> Result<EventData, RawEventError> {
+        self.decode().map(|(_, data)| data)
+    }
+
+    pub fn headers(&self) -> Result<HashMap<String, String>, RawEventError> {
+        self.decode_with_headers().map(|(_, _, headers)| headers)
+    }
+
+    /// The time the event was published at, in milliseconds since the UNIX epoch,
+    /// `None` for the events stored before the timestamps were recorded.
+    pub fn timestamp(&self) -> Result<Option<u64>, RawEventError> {
+        self.parts().map(|(_, timestamp, _, _, _)| timestamp)
+    }
+}
+
+/// The flags, the timestamp, the raw headers, the raw name and the raw data of an event.
+type Parts<'a> = (u8, Option<u64>, &'a [u8], &'a [u8], &'a [u8]);
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+fn split_timestamp(content: &[u8]) -> Result<(u64, &[u8]), RawEventError> {
+    if content.len() < 8 {
+        return Err(RawEventError::Truncated);
+    }
+
+    let (timestamp, content) = content.split_at(8);
+    Ok((u64::from_be_bytes(timestamp.try_into().unwrap()), content))
+}
+
+/// Split a block prefixed by its big endian length from the bytes that follow it.
+fn split_sized(content: &[u8]) -> Result<(&[u8], &[u8]), RawEventError> {
+    if content.len() < 8 {
+        return Err(RawEventError::Truncated);
+    }
+
+    let (length, rest) = content.split_at(8);
+    let size = u64::from_be_bytes(length.try_into().unwrap());
+    if size > rest.len() as u64 {
+        return Err(RawEventError::Truncated);
+    }
+
+    Ok(rest.split_at(size as usize))
+}
+
+/// Encode the headers sorted by key, the same headers are always encoded the same way.
+fn encode_headers(headers: &HashMap<String, String>) -> Vec<u8> {
+    let mut headers: Vec<_> = headers.iter().collect();
+    headers.sort();
+
+    let mut raw_headers = Vec::new();
+    for (key, value) in headers {
+        for part in &[key, value] {
+            raw_headers.extend_from_slice(&(part.len() as u64).to_be_bytes());
+            raw_headers.extend_from_slice(part.as_bytes());
+        }
+    }
+
+    raw_headers
+}
+
+fn decode_headers(mut raw_headers: &[u8]) -> Result<HashMap<String, String>, RawEventError> {
+    let mut headers = HashMap::new();
+    while !raw_headers.is_empty() {
+        let (key, rest) = split_sized(raw_headers)?;
+        let (value, rest) = split_sized(rest)?;
+        let key = String::from_utf8(key.to_owned()).map_err(RawEventError::InvalidUtf8Header)?;
+        let value =
+            String::from_utf8(value.to_owned()).map_err(RawEventError::InvalidUtf8Header)?;
+        headers.insert(key, value);
+        raw_headers = rest;
+    }
+
+    Ok(headers)
+}
+
+#[cfg(feature = "zstd")]
+fn decompress(raw_data: &[u8]) -> Result<Vec<u8>, RawEventError> {
+    zstd::decode_all(raw_data).map_err(RawEventError::Decompression)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress(_raw_data: &[u8]) -> Result<Vec<u8>, RawEventError> {
+    Err(RawEventError::CompressionUnsupported)
+}
+
+/// Verify the CRC32 that follows a versioned event, returns the content between
+/// the version byte and the checksum.
+fn checked_content(bytes: &[u8]) -> Result<&[u8], RawEventError> {
+    if bytes.len() < 1 + 4 {
+        return Err(RawEventError::Truncated);
+    }
+
+    let (checked, checksum) = bytes.split_at(bytes.len() - 4);
+    let expected = u32::from_be_bytes(checksum.try_into().unwrap());
+    let found = crc32fast::hash(checked);
+    if expected != found {
+        return Err(RawEventError::ChecksumMismatch { expected, found });
+    }
+
+    Ok(&checked[1..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event() -> (EventName, EventData) {
+        let name = EventName::new("created".to_owned()).unwrap();
+        (name, EventData(b"hello".to_vec()))
+    }
+
+    #[test]
+    fn decode_checksummed_event() {
+        let (name, data) = event();
+        let raw_event = RawEvent::encode(&name, &data);
+        assert_eq!(raw_event.decode().unwrap(), (name, data));
+    }
+
+    #[test]
+    fn reject_unknown_version() {
+        let (name, data) = event();
+        let mut bytes = RawEvent::encode(&name, &data).into_inner();
+        bytes[0] = 2;
+
+        match RawEvent::new(bytes).decode() {
+            Err(RawEventError::UnknownVersion(2)) => (),
+            otherwise => panic!("unexpected result {:?}", otherwise),
         }
-        usize::from_be_bytes(event_name_size)
     }
 
-    // FIXME: Prefer using a typed Error
-    pub fn name(&self) -> Result<EventName, Box<Error>> {
-        let name_size = self.name_size();
-        let raw_name = &self.0.as_ref()[8..(8 + name_size)];
-        let name = String::from_utf8(raw_name.to_owned())?;
+    #[test]
+    fn encode_at_keeps_the_timestamp() {
+        let (name, data) = event();
+        let raw_event = RawEvent::encode_at(&name, &data, 1_500_000_000_000);
+        assert_eq!(raw_event.timestamp().unwrap(), Some(1_500_000_000_000));
+        assert_eq!(raw_event.decode().unwrap(), (name, data));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn decode_compressed_and_uncompressed_events() {
+        let name = EventName::new("created".to_owned()).unwrap();
+        let data = EventData(b"hello hello hello hello hello".to_vec());
+
+        let compressed = RawEvent::encode_compressed(&name, &data, 3).unwrap();
+        let uncompressed = RawEvent::encode(&name, &data);
+
+        assert!(compressed.is_compressed().unwrap());
+        assert!(!uncompressed.is_compressed().unwrap());
+        assert_eq!(compressed.decode().unwrap(), (name.clone(), data.clone()));
+        assert_eq!(uncompressed.decode().unwrap(), (name, data));
+    }
+
+    #[test]
+    fn decode_event_with_headers() {
+        let (name, data) = event();
+        let mut headers = HashMap::new();
+        headers.insert("correlation-id".to_owned(), "42".to_owned());
+        headers.insert("content-type".to_owned(), "text/plain".to_owned());
+
+        let raw_event = RawEvent::encode_with_headers(&name, &data, &headers);
+        assert!(raw_event.timestamp().unwrap().is_some());
+        assert_eq!(raw_event.decode().unwrap(), (name.clone(), data.clone()));
+        assert_eq!(raw_event.headers().unwrap(), headers);
 
-        Ok(EventName::new(name)?)
+        let raw_event = RawEvent::encode_with_headers(&name, &data, &HashMap::new());
+        assert_eq!(
+            raw_event.decode_with_headers().unwrap(),
+            (name, data, HashMap::new())
+        );
     }
 
-    pub fn data(&self) -> EventData {
-        let name_size = self.name_size();
-        let raw_name = &self.0.as_ref()[(8 + name_size)..];
+    #[test]
+    fn decode_redacted_event() {
+        let (name, data) = event();
+        let raw_event = RawEvent::encode_at(&name, &data, 1_500_000_000_000);
+        assert!(!raw_event.is_redacted().unwrap());
 
-        EventData(raw_name.to_owned())
+        let timestamp = raw_event.timestamp().unwrap().unwrap();
+        let tombstone = RawEvent::encode_redacted_at(&raw_event.name().unwrap(), timestamp);
+        assert!(tombstone.is_redacted().unwrap());
+        assert_eq!(tombstone.timestamp().unwrap(), Some(1_500_000_000_000));
+        assert_eq!(tombstone.decode().unwrap(), (name, EventData(Vec::new())));
+    }
+
+    #[test]
+    fn reject_unknown_flags() {
+        let (name, data) = event();
+        let mut bytes = RawEvent::encode(&name, &data).into_inner();
+        bytes[1] = 0b1000_0000;
+        let checksum_at = bytes.len() - 4;
+        let checksum = crc32fast::hash(&bytes[..checksum_at]);
+        bytes[checksum_at..].copy_from_slice(&checksum.to_be_bytes());
+
+        match RawEvent::new(bytes).decode() {
+            Err(RawEventError::UnknownFlags(0b1000_0000)) => (),
+            otherwise => panic!("unexpected result {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn decode_unversioned_event() {
+        let (name, data) = event();
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(name.as_str().len() as u64).to_be_bytes());
+        bytes.extend_from_slice(name.as_str().as_bytes());
+        bytes.extend_from_slice(&data.0);
+
+        let raw_event = RawEvent::new(bytes);
+        assert_eq!(raw_event.timestamp().unwrap(), None);
+        assert_eq!(raw_event.headers().unwrap(), HashMap::new());
+        assert_eq!(raw_event.decode().unwrap(), (name, data));
+    }
+
+    #[test]
+    fn detect_corrupted_byte() {
+        let (name, data) = event();
+        let mut bytes = RawEvent::encode(&name, &data).into_inner();
+        let last_data_byte = bytes.len() - 5;
+        bytes[last_data_byte] ^= 0x01;
+
+        match RawEvent::new(bytes).decode() {
+            Err(RawEventError::ChecksumMismatch { .. }) => (),
+            otherwise => panic!("unexpected result {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn detect_truncated_event() {
+        let raw_event = RawEvent::new(vec![0, 0, 0, 0, 0, 0, 0, 42, b'a']);
+        match raw_event.decode() {
+            Err(RawEventError::Truncated) => (),
+            otherwise => panic!("unexpected result {:?}", otherwise),
+        }
     }
 }
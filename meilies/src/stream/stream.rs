@@ -7,6 +7,7 @@ use crate::resp::{FromResp, RespStringConvertError, RespValue};
 use crate::stream::{StreamName, StreamNameError};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ReadRange {
     ReadFromUntil(u64, u64),
     ReadFrom(u64),
@@ -27,6 +28,11 @@ impl ReadRange {
             _ => None,
         }
     }
+
+    /// Returns `true` if the range has an end, the subscription ends once it is reached.
+    pub fn is_bounded(&self) -> bool {
+        self.to().is_some()
+    }
 }
 
 impl fmt::Display for ReadRange {
@@ -40,9 +46,19 @@ impl fmt::Display for ReadRange {
 }
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Stream {
     pub name: StreamName,
     pub range: ReadRange,
+    /// Start reading from the first event published at or after this time, in milliseconds
+    /// since the UNIX epoch, the start of the range is still respected, written `name:@time`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub from_time: Option<u64>,
+    /// Only read the events that exist when subscribing, the server replaces the end
+    /// of the range by the current end of the stream, written `name:from:$`.
+    /// A bounded range stops before the current end if it is earlier, `name:from:to:$`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub history_only: bool,
 }
 
 impl Stream {
@@ -51,7 +67,39 @@ impl Stream {
     }
 
     pub fn new(name: StreamName, range: ReadRange) -> Stream {
-        Stream { name, range }
+        Stream {
+            name,
+            range,
+            from_time: None,
+            history_only: false,
+        }
+    }
+
+    /// Read a stream from the first event published at or after the given time,
+    /// in milliseconds since the UNIX epoch, the server resolves the event number.
+    pub fn since(name: StreamName, time: u64) -> Stream {
+        Stream {
+            from_time: Some(time),
+            ..Stream::new(name, ReadRange::ReadFrom(0))
+        }
+    }
+
+    /// Read the events of a stream that exist when subscribing, starting at `from`,
+    /// the server sends `EndOfStream` once they have all been sent.
+    pub fn history(name: StreamName, from: u64) -> Stream {
+        Stream {
+            history_only: true,
+            ..Stream::new(name, ReadRange::ReadFrom(from))
+        }
+    }
+
+    /// Read the events of a stream from `from` until `to` (exclusive) that exist when
+    /// subscribing, the server sends `EndOfStream` once `to` or the end is reached.
+    pub fn history_until(name: StreamName, from: u64, to: u64) -> Stream {
+        Stream {
+            history_only: true,
+            ..Stream::new(name, ReadRange::ReadFromUntil(from, to))
+        }
     }
 
     pub fn new_from_to(name: StreamName, from: Option<u64>, to: Option<u64>) -> Stream {
@@ -60,7 +108,33 @@ impl Stream {
             (Some(from), None) => ReadRange::ReadFrom(from),
             (_, _) => ReadRange::ReadFromEnd,
         };
-        Stream { name, range }
+        Stream::new(name, range)
+    }
+
+    pub fn name(&self) -> &StreamName {
+        &self.name
+    }
+
+    pub fn range(&self) -> ReadRange {
+        self.range
+    }
+
+    pub fn from_time(&self) -> Option<u64> {
+        self.from_time
+    }
+
+    pub fn history_only(&self) -> bool {
+        self.history_only
+    }
+
+    /// Returns the same stream read with another range.
+    pub fn with_range(self, range: ReadRange) -> Stream {
+        Stream { range, ..self }
+    }
+
+    /// Returns the same stream read from its end, only the new events are read.
+    pub fn without_range(self) -> Stream {
+        self.with_range(ReadRange::ReadFromEnd)
     }
 }
 
@@ -72,6 +146,23 @@ impl fmt::Debug for Stream {
 
 impl fmt::Display for Stream {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.history_only {
+            return match (self.from_time, self.range) {
+                (Some(time), _) => write!(f, "{}:@{}:$", self.name, time),
+                (None, ReadRange::ReadFromUntil(from, to)) => {
+                    write!(f, "{}:{}:{}:$", self.name, from, to)
+                }
+                (None, range) => write!(f, "{}:{}:$", self.name, range.from().unwrap_or(0)),
+            };
+        }
+
+        if let Some(time) = self.from_time {
+            return match self.range.to() {
+                Some(to) => write!(f, "{}:@{}:{}", self.name, time, to),
+                None => write!(f, "{}:@{}", self.name, time),
+            };
+        }
+
         match self.range {
             ReadRange::ReadFromUntil(from, to) => write!(f, "{}:{}:{}", self.name, from, to),
             ReadRange::ReadFrom(from) => write!(f, "{}:{}", self.name, from),
@@ -82,13 +173,7 @@ impl fmt::Display for Stream {
 
 impl Into<RespValue> for Stream {
     fn into(self) -> RespValue {
-        let text = match self.range {
-            ReadRange::ReadFromUntil(from, to) => format!("{}:{}:{}", self.name, from, to),
-            ReadRange::ReadFrom(from) => format!("{}:{}", self.name, from),
-            ReadRange::ReadFromEnd => format!("{}", self.name),
-        };
-
-        RespValue::BulkString(text.into_bytes())
+        RespValue::BulkString(self.to_string().into_bytes())
     }
 }
 
@@ -110,6 +195,17 @@ impl fmt::Display for RespStreamConvertError {
     }
 }
 
+impl std::error::Error for RespStreamConvertError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use RespStreamConvertError::*;
+        match self {
+            InvalidRespType => None,
+            InvalidUtf8String(e) => Some(e),
+            InnerStreamConvertError(e) => Some(e),
+        }
+    }
+}
+
 impl FromResp for Stream {
     type Error = RespStreamConvertError;
     fn from_resp(value: RespValue) -> Result<Self, Self::Error> {
@@ -124,10 +220,7 @@ impl FromResp for Stream {
 
 impl From<StreamName> for Stream {
     fn from(name: StreamName) -> Stream {
-        Stream {
-            name,
-            range: ReadRange::ReadFromEnd,
-        }
+        Stream::new(name, ReadRange::ReadFromEnd)
     }
 }
 
@@ -145,23 +238,67 @@ impl FromStr for Stream {
             }
             (Some(name), Some(from), None, None) => {
                 let name = StreamName::new(name.to_owned()).map_err(StreamNameError)?;
+                if let Some(time) = from.strip_prefix('@') {
+                    let time = u64::from_str_radix(time, 10).map_err(StartTimeError)?;
+                    return Ok(Stream::since(name, time));
+                }
+
                 let number = u64::from_str_radix(from, 10).map_err(StartFromError)?;
-                Ok(Stream {
-                    name,
-                    range: ReadRange::ReadFrom(number),
-                })
+                Ok(Stream::new(name, ReadRange::ReadFrom(number)))
             }
             (Some(name), Some(from), Some(to), None) => {
                 let name = StreamName::new(name.to_owned()).map_err(StreamNameError)?;
-                let from = u64::from_str_radix(from, 10).map_err(StartFromError)?;
+
+                // `name:@time:to` reads from a time until an event number
+                if let Some(time) = from.strip_prefix('@') {
+                    let time = u64::from_str_radix(time, 10).map_err(StartTimeError)?;
+                    if to == "$" {
+                        let stream = Stream::since(name, time);
+                        return Ok(Stream {
+                            history_only: true,
+                            ..stream
+                        });
+                    }
+                    let to = u64::from_str_radix(to, 10).map_err(EndToError)?;
+                    if to == 0 {
+                        return Err(BoundsError);
+                    }
+                    let stream = Stream::since(name, time);
+                    return Ok(stream.with_range(ReadRange::ReadFromUntil(0, to)));
+                }
+
+                // `name::to` reads from the first event and `name:from:` has no end
+                let from = match from {
+                    "" => 0,
+                    from => u64::from_str_radix(from, 10).map_err(StartFromError)?,
+                };
+                // `name:from:$` reads until the end of the stream at the time of the subscription
+                let range = match to {
+                    "" => ReadRange::ReadFrom(from),
+                    "$" => return Ok(Stream::history(name, from)),
+                    to => {
+                        let to = u64::from_str_radix(to, 10).map_err(EndToError)?;
+                        if from >= to {
+                            return Err(BoundsError);
+                        }
+                        ReadRange::ReadFromUntil(from, to)
+                    }
+                };
+
+                Ok(Stream::new(name, range))
+            }
+            // `name:from:to:$` reads until `to` or the end of the stream, the earliest
+            (Some(name), Some(from), Some(to), Some("$")) if split.next().is_none() => {
+                let name = StreamName::new(name.to_owned()).map_err(StreamNameError)?;
+                let from = match from {
+                    "" => 0,
+                    from => u64::from_str_radix(from, 10).map_err(StartFromError)?,
+                };
                 let to = u64::from_str_radix(to, 10).map_err(EndToError)?;
                 if from >= to {
                     return Err(BoundsError);
                 }
-                Ok(Stream {
-                    name,
-                    range: ReadRange::ReadFromUntil(from, to),
-                })
+                Ok(Stream::history_until(name, from, to))
             }
             (_, _, _, _) => Err(FormatError),
         }
@@ -172,6 +309,7 @@ impl FromStr for Stream {
 pub enum ParseStreamError {
     StreamNameError(StreamNameError),
     StartFromError(ParseIntError),
+    StartTimeError(ParseIntError),
     EndToError(ParseIntError),
     BoundsError,
     FormatError,
@@ -184,6 +322,7 @@ impl fmt::Display for ParseStreamError {
         match self {
             StreamNameError(e) => write!(f, "stream not properly formatted; {}", e),
             StartFromError(e) => write!(f, "stream \"start from\" not properly formatted; {}", e),
+            StartTimeError(e) => write!(f, "stream \"start time\" not properly formatted; {}", e),
             EndToError(e) => write!(f, "stream \"end to\" not properly formatted; {}", e),
             BoundsError => f.write_str("The end bound must be greater than the start bound"),
             FormatError => f.write_str("stream is not properly formatted"),
@@ -191,6 +330,17 @@ impl fmt::Display for ParseStreamError {
     }
 }
 
+impl std::error::Error for ParseStreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use ParseStreamError::*;
+        match self {
+            StreamNameError(e) => Some(e),
+            StartFromError(e) | StartTimeError(e) | EndToError(e) => Some(e),
+            BoundsError | FormatError => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -241,12 +391,6 @@ mod tests {
         let result = Stream::from_str("default::0");
         assert!(result.is_err());
 
-        let result = Stream::from_str("default:0:");
-        assert!(result.is_err());
-
-        let result = Stream::from_str("default:0:");
-        assert!(result.is_err());
-
         let result = Stream::from_str("default:0:-1");
         assert!(result.is_err());
 
@@ -256,4 +400,144 @@ mod tests {
         let result = Stream::from_str("default:1:0");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn stream_accessors() {
+        let name = StreamName::new("default".to_owned()).unwrap();
+        let stream = Stream::new(name.clone(), ReadRange::ReadFrom(3));
+        assert_eq!(stream.name(), &name);
+        assert_eq!(stream.range(), ReadRange::ReadFrom(3));
+
+        let stream = stream.with_range(ReadRange::ReadFromUntil(3, 5));
+        assert_eq!(stream.range(), ReadRange::ReadFromUntil(3, 5));
+        assert_eq!(stream.name(), &name);
+
+        let stream = stream.without_range();
+        assert_eq!(stream, Stream::from(name));
+    }
+
+    #[test]
+    fn read_range_bounds() {
+        assert!(ReadRange::ReadFromUntil(0, 5).is_bounded());
+        assert!(!ReadRange::ReadFrom(0).is_bounded());
+        assert!(!ReadRange::ReadFromEnd.is_bounded());
+
+        assert_eq!(ReadRange::ReadFromUntil(1, 5).from(), Some(1));
+        assert_eq!(ReadRange::ReadFromUntil(1, 5).to(), Some(5));
+        assert_eq!(ReadRange::ReadFrom(1).to(), None);
+        assert_eq!(ReadRange::ReadFromEnd.from(), None);
+    }
+
+    #[test]
+    fn parse_display_round_trip() {
+        let streams = [
+            "default",
+            "default:5",
+            "default:1:5",
+            "$all",
+            "$all:0",
+            "$all:2:4",
+            "default:3:$",
+            "default:@1500000000000:$",
+        ];
+        for text in &streams {
+            let stream = Stream::from_str(text).unwrap();
+            assert_eq!(&stream.to_string(), text);
+            assert_eq!(Stream::from_str(&stream.to_string()).unwrap(), stream);
+        }
+
+        let all = Stream::from_str("$all:0").unwrap();
+        assert_eq!(all, Stream::all(ReadRange::ReadFrom(0)));
+    }
+
+    #[test]
+    fn parse_open_ended_ranges() {
+        let name = StreamName::new("default".to_owned()).unwrap();
+
+        let stream = Stream::from_str("default::100").unwrap();
+        assert_eq!(
+            stream,
+            Stream::new(name.clone(), ReadRange::ReadFromUntil(0, 100))
+        );
+
+        let stream = Stream::from_str("default:50:").unwrap();
+        assert_eq!(stream, Stream::new(name.clone(), ReadRange::ReadFrom(50)));
+
+        let stream = Stream::from_str("default::").unwrap();
+        assert_eq!(stream, Stream::new(name, ReadRange::ReadFrom(0)));
+
+        // the open-ended forms are displayed with their explicit bounds
+        for text in &["default::100", "default:50:", "default::"] {
+            let stream = Stream::from_str(text).unwrap();
+            assert_eq!(Stream::from_str(&stream.to_string()).unwrap(), stream);
+        }
+
+        assert_eq!(
+            Stream::from_str("default::0"),
+            Err(ParseStreamError::BoundsError)
+        );
+        assert_eq!(
+            Stream::from_str("default:5:5"),
+            Err(ParseStreamError::BoundsError)
+        );
+        assert!(Stream::from_str("default:a:").is_err());
+        assert!(Stream::from_str("default::a").is_err());
+    }
+
+    #[test]
+    fn parse_start_time() {
+        let name = StreamName::new("default".to_owned()).unwrap();
+
+        let stream = Stream::from_str("default:@1500000000000").unwrap();
+        assert_eq!(stream, Stream::since(name.clone(), 1_500_000_000_000));
+        assert_eq!(stream.range(), ReadRange::ReadFrom(0));
+
+        let stream = Stream::from_str("default:@1500000000000:10").unwrap();
+        assert_eq!(stream.from_time(), Some(1_500_000_000_000));
+        assert_eq!(stream.range(), ReadRange::ReadFromUntil(0, 10));
+
+        for text in &["default:@1500000000000", "default:@1500000000000:10"] {
+            let stream = Stream::from_str(text).unwrap();
+            assert_eq!(&stream.to_string(), text);
+        }
+
+        assert!(Stream::from_str("default:@").is_err());
+        assert!(Stream::from_str("default:@a:10").is_err());
+        assert_eq!(
+            Stream::from_str("default:@5:0"),
+            Err(ParseStreamError::BoundsError)
+        );
+    }
+
+    #[test]
+    fn parse_history_only() {
+        let name = StreamName::new("default".to_owned()).unwrap();
+
+        let stream = Stream::from_str("default:3:$").unwrap();
+        assert_eq!(stream, Stream::history(name.clone(), 3));
+        assert!(stream.history_only());
+        assert_eq!(stream.range(), ReadRange::ReadFrom(3));
+
+        let stream = Stream::from_str("default::$").unwrap();
+        assert_eq!(stream, Stream::history(name.clone(), 0));
+        assert_eq!(stream.to_string(), "default:0:$");
+
+        let stream = Stream::from_str("default:@1500000000000:$").unwrap();
+        assert!(stream.history_only());
+        assert_eq!(stream.from_time(), Some(1_500_000_000_000));
+
+        assert!(!Stream::from_str("default:3:").unwrap().history_only());
+        assert!(Stream::from_str("default:$").is_err());
+
+        let stream = Stream::from_str("default:3:10:$").unwrap();
+        assert_eq!(stream, Stream::history_until(name.clone(), 3, 10));
+        assert_eq!(stream.range(), ReadRange::ReadFromUntil(3, 10));
+        assert_eq!(stream.to_string(), "default:3:10:$");
+
+        assert_eq!(
+            Stream::from_str("default:10:3:$"),
+            Err(ParseStreamError::BoundsError)
+        );
+        assert!(Stream::from_str("default:3:10:$:").is_err());
+    }
 }
@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::fmt;
 use std::str::FromStr;
 use std::string::FromUtf8Error;
@@ -6,7 +7,16 @@ use crate::resp::{FromResp, RespStringConvertError, RespValue};
 
 pub const ALL_STREAMS: &str = "$all";
 
+/// Subscribing to `$all.audit-` sends the events of all the streams starting with `audit-`,
+/// it must be the only stream of its subscription.
+pub const ALL_STREAMS_PREFIX: &str = "$all.";
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String")
+)]
 pub struct StreamName(String);
 
 impl StreamName {
@@ -39,6 +49,14 @@ impl StreamName {
     }
 }
 
+impl TryFrom<String> for StreamName {
+    type Error = StreamNameError;
+
+    fn try_from(name: String) -> Result<StreamName, StreamNameError> {
+        StreamName::new(name)
+    }
+}
+
 impl fmt::Display for StreamName {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.0.fmt(f)
@@ -69,6 +87,17 @@ impl fmt::Display for RespStreamNameConvertError {
     }
 }
 
+impl std::error::Error for RespStreamNameConvertError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use RespStreamNameConvertError::*;
+        match self {
+            InvalidRespType => None,
+            InvalidUtf8String(e) => Some(e),
+            InnerStreamNameConvertError(e) => Some(e),
+        }
+    }
+}
+
 impl FromResp for StreamName {
     type Error = RespStreamNameConvertError;
     fn from_resp(value: RespValue) -> Result<Self, Self::Error> {
@@ -104,6 +133,8 @@ impl fmt::Display for StreamNameError {
     }
 }
 
+impl std::error::Error for StreamNameError {}
+
 impl PartialEq<&'_ str> for StreamName {
     fn eq(&self, other: &&'_ str) -> bool {
         self.0.eq(other)
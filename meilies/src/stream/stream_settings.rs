@@ -0,0 +1,67 @@
+use crate::resp::{FromResp, RespTupleConvertError, RespValue};
+
+/// The settings of a stream, they take precedence over the settings of the server.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StreamSettings {
+    /// Whether the events are compressed when the server compresses the events.
+    pub compressed: bool,
+    /// Maximum number of events kept in the stream, the oldest ones are removed.
+    pub max_events: Option<u64>,
+    /// Maximum size in bytes of the data of an event published to the stream.
+    pub max_event_size: Option<u64>,
+}
+
+impl Default for StreamSettings {
+    fn default() -> StreamSettings {
+        StreamSettings {
+            compressed: true,
+            max_events: None,
+            max_event_size: None,
+        }
+    }
+}
+
+impl From<StreamSettings> for RespValue {
+    fn from(settings: StreamSettings) -> RespValue {
+        let option =
+            |value: Option<u64>| value.map_or(RespValue::Nil, |v| RespValue::Integer(v as i64));
+        RespValue::Array(vec![
+            RespValue::Integer(settings.compressed as i64),
+            option(settings.max_events),
+            option(settings.max_event_size),
+        ])
+    }
+}
+
+impl FromResp for StreamSettings {
+    type Error = RespTupleConvertError;
+
+    fn from_resp(value: RespValue) -> Result<Self, Self::Error> {
+        let (compressed, max_events, max_event_size): (i64, Option<i64>, Option<i64>) =
+            FromResp::from_resp(value)?;
+
+        Ok(StreamSettings {
+            compressed: compressed != 0,
+            max_events: max_events.map(|n| n as u64),
+            max_event_size: max_event_size.map(|n| n as u64),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resp_round_trip() {
+        let settings = StreamSettings {
+            compressed: false,
+            max_events: Some(100),
+            max_event_size: None,
+        };
+
+        let value: RespValue = settings.into();
+        assert_eq!(StreamSettings::from_resp(value).unwrap(), settings);
+    }
+}